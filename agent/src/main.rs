@@ -0,0 +1,244 @@
+// vim: tw=80
+//! Feed ZFS datasets' I/O statistics to ztop's alerts and exporters, without
+//! the interactive display
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use nix::sys::signal::{SigSet, Signal};
+use ztop::{
+    app::{self, App, DataSource, SavedCounters},
+    config::{self, Config},
+    exec,
+    serve::Serve,
+    sink::{self, Sink},
+    warning,
+};
+
+/// Export ZFS datasets' I/O statistics without a display
+#[derive(Debug, clap::Parser)]
+struct Cli {
+    /// Register with the AgentX master agent on this socket, as a path or
+    /// HOST:PORT, to serve per-dataset statistics over SNMP.
+    #[clap(long = "agentx", value_name = "MASTER")]
+    agentx:     Option<String>,
+    /// Read settings from this file instead of the default location.
+    #[clap(long = "config")]
+    config:     Option<PathBuf>,
+    /// Include child datasets' stats with their parents'.
+    #[clap(short = 'c', long = "children")]
+    children:   bool,
+    /// Keep running if some pools' statistics aren't visible, as in a
+    /// container.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "container")]
+    container:  bool,
+    /// Read ZFS kstats from this directory instead of /proc/spl/kstat/zfs.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "kstat-path")]
+    kstat_path: Option<PathBuf>,
+    /// Never run other programs, such as alert hooks.
+    #[clap(long = "no-exec")]
+    no_exec:    bool,
+    /// Include mounted snapshots, which are excluded by default.
+    #[clap(long = "snapshots")]
+    snapshots:  bool,
+    /// Answer HTTP requests for the latest statistics on this [HOST:]PORT.
+    #[clap(long = "serve")]
+    serve:      Option<String>,
+    /// Save the raw counters to this file when stopped, and resume from them
+    /// when started.
+    #[clap(long = "state-file")]
+    state_file: Option<PathBuf>,
+    /// Send per-dataset gauges to this statsd server, as HOST:PORT.
+    #[clap(long = "statsd")]
+    statsd:     Option<String>,
+    /// update interval, in seconds or with the specified unit
+    #[clap(
+        short = 't',
+        long = "time",
+        default_value = "1",
+        value_parser = config::interval_from_str
+    )]
+    time:       Duration,
+    /// Send per-dataset metrics to this Zabbix server, as HOST:PORT.
+    #[clap(long = "zabbix")]
+    zabbix:     Option<String>,
+    /// Collect these pools and their children
+    pools:      Vec<String>,
+}
+
+impl Cli {
+    /// Check that the requested pools exist, unless in container mode
+    fn check_pools(&self) -> Result<(), Box<dyn Error>> {
+        #[cfg(target_os = "linux")]
+        if self.container {
+            return Ok(());
+        }
+        DataSource::check_pools(&self.pools)
+    }
+}
+
+/// Receive SIGINT and SIGTERM as messages instead of being killed by them.
+/// Must be called before starting any other threads, so that none of them
+/// receive the signals instead.
+fn catch_signals() -> Result<Receiver<Signal>, Box<dyn Error>> {
+    let mut set = SigSet::empty();
+    set.add(Signal::SIGINT);
+    set.add(Signal::SIGTERM);
+    set.thread_block()?;
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(signal) = set.wait() {
+            if tx.send(signal).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Save the raw counters for the next run
+fn save_counters(app: &App, path: &Path) {
+    let r = app
+        .saved_counters()
+        .and_then(|saved| saved.map_or(Ok(()), |s| s.save(path)));
+    if let Err(e) = r {
+        eprintln!("{e}");
+    }
+}
+
+/// Print the collector's errors and warnings as they change, since there's
+/// no display to show them on
+#[derive(Default)]
+struct Reporter {
+    error:    Option<String>,
+    warnings: u64,
+}
+
+impl Reporter {
+    fn report(&mut self, app: &App) {
+        if app.error() != self.error.as_deref() {
+            self.error = app.error().map(str::to_owned);
+            if let Some(e) = self.error.as_ref() {
+                eprintln!("{e}");
+            }
+        }
+        let warnings = warning::total();
+        if warnings > self.warnings {
+            self.warnings = warnings;
+            if let Some((msg, _)) = warning::recent().first() {
+                eprintln!("{msg}");
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli: Cli = Cli::parse();
+    if cli.no_exec {
+        exec::forbid("--no-exec");
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(path) = cli.kstat_path.clone() {
+        app::set_kstat_path(path);
+    }
+    #[cfg(target_os = "linux")]
+    if cli.container {
+        app::set_container_mode();
+    }
+    if let Err(e) = cli.check_pools() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    let signals = cli
+        .state_file
+        .as_ref()
+        .map(|_| catch_signals())
+        .transpose()?;
+    let mut config = Config::load(cli.config.as_deref())?;
+    let serve = cli
+        .serve
+        .as_deref()
+        .map(|addr| Serve::new(addr, cli.children))
+        .transpose()?;
+    if let Some(serve) = serve.as_ref() {
+        serve.set_openmetrics(config.openmetrics.clone());
+    }
+    let mut sinks =
+        sink::from_config(&cli.agentx, &cli.statsd, &cli.zabbix, &mut config)?;
+    sinks.extend(serve.clone().map(|s| Box::new(s) as Box<dyn Sink>));
+    let mut watcher = config::Watcher::new(cli.config.as_deref());
+    let options = app::Options {
+        children: cli.children,
+        pools: cli.pools.clone(),
+        ..Default::default()
+    };
+    let mut app = App::new(options, config, sinks);
+    if !app.has_consumers() {
+        eprintln!(
+            "Nothing to do.  Configure an alert or an exporter, like --serve."
+        );
+        std::process::exit(1);
+    }
+    if cli.snapshots {
+        app.toggle_snapshots();
+    }
+    if let Some(path) = cli.state_file.as_deref() {
+        match SavedCounters::load(path) {
+            Ok(Some(saved)) => app.resume_from(saved),
+            Ok(None) => (),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+    let mut reporter = Reporter::default();
+    let mut next_sample = Instant::now() + cli.time;
+    loop {
+        let timeout = next_sample.saturating_duration_since(Instant::now());
+        match signals.as_ref().map(|rx| rx.recv_timeout(timeout)) {
+            None => thread::sleep(timeout),
+            Some(Err(RecvTimeoutError::Timeout)) => (),
+            Some(Ok(_) | Err(RecvTimeoutError::Disconnected)) => {
+                if let Some(path) = cli.state_file.as_deref() {
+                    save_counters(&app, path);
+                }
+                return Ok(());
+            }
+        }
+        next_sample += cli.time;
+        app.poll_first_refresh();
+        app.on_tick();
+        if watcher.changed() {
+            let r =
+                Config::load(cli.config.as_deref()).and_then(|mut config| {
+                    let mut sinks = sink::from_config(
+                        &cli.agentx,
+                        &cli.statsd,
+                        &cli.zabbix,
+                        &mut config,
+                    )?;
+                    sinks.extend(
+                        serve.clone().map(|s| Box::new(s) as Box<dyn Sink>),
+                    );
+                    Ok((config, sinks))
+                });
+            match r {
+                Ok((config, sinks)) => {
+                    if let Some(serve) = serve.as_ref() {
+                        serve.set_openmetrics(config.openmetrics.clone());
+                    }
+                    app.reload_config(config, sinks)
+                }
+                Err(e) => {
+                    eprintln!("Cannot reload the configuration file: {e}")
+                }
+            }
+        }
+        reporter.report(&app);
+    }
+}
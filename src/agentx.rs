@@ -0,0 +1,892 @@
+// vim: tw=80
+//! Serve per-dataset statistics over SNMP, as an AgentX subagent (RFC 2741).
+//!
+//! ztop connects to a master agent, like Net-SNMP's snmpd, and registers the
+//! subtree described by `doc/ZTOP-MIB.txt`, under Net-SNMP's experimental
+//! `netSnmpPlaypen`.  It holds one table with a row for each dataset.  A
+//! dataset keeps its row's index for as long as ztop runs, like an
+//! interface's `ifIndex`, so pollers can follow it even as other datasets
+//! come and go.  The table is read-only.
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::net::UnixStream,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    app::{Element, Metric},
+    sink::Sink,
+    warning::warn,
+};
+
+/// ztopMIB, in Net-SNMP's `netSnmpPlaypen`
+const ZTOP_MIB: [u32; 9] = [1, 3, 6, 1, 4, 1, 8072, 9999, 9999];
+/// ztopDatasetEntry, relative to ztopMIB.  The next subidentifier is the
+/// column, and the last is the row's index.
+const ENTRY: [u32; 2] = [1, 1];
+/// ztopDatasetName
+const NAME: u32 = 2;
+/// The first of the columns holding each of `Metric::ALL`, in order
+const FIRST_METRIC: u32 = 3;
+/// ztopDatasetRead, bytes read since the pool was imported
+const NREAD: u32 = FIRST_METRIC + Metric::ALL.len() as u32;
+/// ztopDatasetWritten, bytes written since the pool was imported
+const NWRITTEN: u32 = NREAD + 1;
+
+/// Don't let an unresponsive master agent hold up the subagent for long
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How often to check whether the subagent has been stopped, while waiting
+/// for requests
+const POLL: Duration = Duration::from_secs(1);
+/// How long to wait before reconnecting to the master agent
+const RETRY: Duration = Duration::from_secs(15);
+
+/// PDU types
+const OPEN: u8 = 1;
+const CLOSE: u8 = 2;
+const REGISTER: u8 = 3;
+const GET: u8 = 5;
+const GET_NEXT: u8 = 6;
+const GET_BULK: u8 = 7;
+const TEST_SET: u8 = 8;
+const RESPONSE: u8 = 18;
+
+/// Header flags
+const NON_DEFAULT_CONTEXT: u8 = 0x08;
+const NETWORK_BYTE_ORDER: u8 = 0x10;
+
+/// The Response-PDU's error for a refused TestSet-PDU
+const NOT_WRITABLE: u16 = 17;
+/// The Close-PDU's reason when the subagent is stopped
+const REASON_SHUTDOWN: u8 = 5;
+
+/// The `[agentx]` section of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentxConfig {
+    /// The master agent's socket, as a path or `HOST:PORT`
+    pub master: Option<String>,
+}
+
+/// An object identifier
+type Oid = Vec<u32>;
+
+/// A variable's value, of the few types that the table uses
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    OctetString(Vec<u8>),
+    Counter64(u64),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+impl Value {
+    fn tag(&self) -> u16 {
+        match self {
+            Value::OctetString(_) => 4,
+            Value::Counter64(_) => 70,
+            Value::NoSuchObject => 128,
+            Value::NoSuchInstance => 129,
+            Value::EndOfMibView => 130,
+        }
+    }
+}
+
+/// One of a request's SearchRanges
+#[derive(Clone, Debug)]
+struct Range {
+    start:   Oid,
+    /// Does the range include `start` itself?
+    include: bool,
+    /// The end of the range, exclusive, or empty if it's unbounded
+    end:     Oid,
+}
+
+/// The OID of one cell of the dataset table
+fn cell_oid(column: u32, index: u32) -> Oid {
+    let mut oid = ZTOP_MIB.to_vec();
+    oid.extend(ENTRY);
+    oid.extend([column, index]);
+    oid
+}
+
+/// The dataset table, as of the latest update
+#[derive(Debug, Default)]
+struct Table {
+    /// Every dataset's row index, including those that have gone away
+    indices: HashMap<String, u32>,
+    rows:    BTreeMap<u32, Element>,
+}
+
+impl Table {
+    fn update(&mut self, elems: &[Element]) {
+        self.rows.clear();
+        for elem in elems {
+            let next = self.indices.len() as u32 + 1;
+            let index = *self.indices.entry(elem.name.clone()).or_insert(next);
+            self.rows.insert(index, elem.clone());
+        }
+    }
+
+    fn cell(elem: &Element, column: u32) -> Value {
+        match column {
+            NAME => Value::OctetString(elem.name.as_bytes().to_vec()),
+            NREAD => Value::Counter64(elem.nread),
+            NWRITTEN => Value::Counter64(elem.nwritten),
+            c => {
+                let metric = Metric::ALL[(c - FIRST_METRIC) as usize];
+                Value::Counter64(elem.get(metric).round() as u64)
+            }
+        }
+    }
+
+    /// Every cell, in lexicographic order of their OIDs
+    fn cells(&self) -> impl Iterator<Item = (Oid, Value)> + '_ {
+        (NAME..=NWRITTEN).flat_map(move |column| {
+            self.rows.iter().map(move |(index, elem)| {
+                (cell_oid(column, *index), Self::cell(elem, column))
+            })
+        })
+    }
+
+    /// Answer one variable of a Get-PDU
+    fn get(&self, oid: &[u32]) -> Value {
+        let Some(rest) = oid
+            .strip_prefix(&ZTOP_MIB[..])
+            .and_then(|rest| rest.strip_prefix(&ENTRY[..]))
+        else {
+            return Value::NoSuchObject;
+        };
+        match rest {
+            [column, index] if (NAME..=NWRITTEN).contains(column) => {
+                self.rows.get(index).map_or(Value::NoSuchInstance, |elem| {
+                    Self::cell(elem, *column)
+                })
+            }
+            [column, ..] if (NAME..=NWRITTEN).contains(column) => {
+                Value::NoSuchInstance
+            }
+            _ => Value::NoSuchObject,
+        }
+    }
+
+    /// Answer one range of a GetNext-PDU
+    fn next(&self, range: &Range) -> (Oid, Value) {
+        self.cells()
+            .find(|(oid, _)| {
+                if range.include {
+                    oid >= &range.start
+                } else {
+                    oid > &range.start
+                }
+            })
+            .filter(|(oid, _)| range.end.is_empty() || oid < &range.end)
+            .unwrap_or_else(|| (range.start.clone(), Value::EndOfMibView))
+    }
+
+    /// Answer a GetBulk-PDU.  The first `non_repeaters` ranges are answered
+    /// once, like a GetNext-PDU's, and the rest are walked together, up to
+    /// `max_repetitions` times or until all of them reach their ends.
+    fn bulk(
+        &self,
+        non_repeaters: usize,
+        max_repetitions: usize,
+        ranges: &[Range],
+    ) -> Vec<(Oid, Value)> {
+        let (fixed, repeated) =
+            ranges.split_at(non_repeaters.min(ranges.len()));
+        let mut varbinds =
+            fixed.iter().map(|r| self.next(r)).collect::<Vec<_>>();
+        let mut repeated = repeated.to_vec();
+        for _ in 0..max_repetitions {
+            let mut done = true;
+            for range in repeated.iter_mut() {
+                let (oid, value) = self.next(range);
+                if value != Value::EndOfMibView {
+                    done = false;
+                    range.start.clone_from(&oid);
+                    range.include = false;
+                }
+                varbinds.push((oid, value));
+            }
+            if done {
+                break;
+            }
+        }
+        varbinds
+    }
+
+    /// Answer a Get-, GetNext-, or GetBulk-PDU
+    fn answer(
+        &self,
+        kind: u8,
+        body: &mut Decoder,
+    ) -> io::Result<Vec<(Oid, Value)>> {
+        let (non_repeaters, max_repetitions) = if kind == GET_BULK {
+            (usize::from(body.u16()?), usize::from(body.u16()?))
+        } else {
+            (0, 0)
+        };
+        let mut ranges = Vec::new();
+        while !body.is_empty() {
+            let (start, include) = body.oid()?;
+            let (end, _) = body.oid()?;
+            ranges.push(Range {
+                start,
+                include,
+                end,
+            });
+        }
+        Ok(match kind {
+            GET => ranges
+                .into_iter()
+                .map(|r| {
+                    let value = self.get(&r.start);
+                    (r.start, value)
+                })
+                .collect(),
+            GET_NEXT => ranges.iter().map(|r| self.next(r)).collect(),
+            _ => self.bulk(non_repeaters, max_repetitions, &ranges),
+        })
+    }
+}
+
+/// The fields of a PDU's header that ztop uses
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Header {
+    kind:        u8,
+    flags:       u8,
+    session:     u32,
+    transaction: u32,
+    packet:      u32,
+}
+
+impl Header {
+    const LEN: usize = 20;
+
+    /// Frame a payload with this header.  ztop always sends in network byte
+    /// order.
+    fn frame(&self, payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN + payload.len());
+        buf.extend([1, self.kind, NETWORK_BYTE_ORDER, 0]);
+        buf.extend(self.session.to_be_bytes());
+        buf.extend(self.transaction.to_be_bytes());
+        buf.extend(self.packet.to_be_bytes());
+        buf.extend((payload.len() as u32).to_be_bytes());
+        buf.extend(payload);
+        buf
+    }
+}
+
+/// Read one PDU, returning its header and payload
+fn read_pdu(r: &mut impl Read) -> io::Result<(Header, Vec<u8>)> {
+    let mut buf = [0u8; Header::LEN];
+    r.read_exact(&mut buf)?;
+    if buf[0] != 1 {
+        let msg = format!("unsupported AgentX version {}", buf[0]);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    let mut d = Decoder::new(&buf[4..], buf[2]);
+    let header = Header {
+        kind:        buf[1],
+        flags:       buf[2],
+        session:     d.u32()?,
+        transaction: d.u32()?,
+        packet:      d.u32()?,
+    };
+    let mut payload = vec![0u8; d.u32()? as usize];
+    r.read_exact(&mut payload)?;
+    Ok((header, payload))
+}
+
+/// Builds a PDU's payload, in network byte order
+#[derive(Debug, Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u16(&mut self, n: u16) {
+        self.0.extend(n.to_be_bytes());
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.0.extend(n.to_be_bytes());
+    }
+
+    fn oid(&mut self, oid: &[u32], include: bool) {
+        self.0.extend([oid.len() as u8, 0, u8::from(include), 0]);
+        for subid in oid {
+            self.u32(*subid);
+        }
+    }
+
+    fn octets(&mut self, s: &[u8]) {
+        self.u32(s.len() as u32);
+        self.0.extend(s);
+        self.0.resize(self.0.len().next_multiple_of(4), 0);
+    }
+
+    fn varbind(&mut self, oid: &[u32], value: &Value) {
+        self.u16(value.tag());
+        self.u16(0);
+        self.oid(oid, false);
+        match value {
+            Value::OctetString(s) => self.octets(s),
+            Value::Counter64(n) => self.0.extend(n.to_be_bytes()),
+            _ => (),
+        }
+    }
+}
+
+/// Parses a PDU's payload, in the byte order given by its header's flags
+struct Decoder<'a> {
+    buf: &'a [u8],
+    big: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(buf: &'a [u8], flags: u8) -> Self {
+        let big = flags & NETWORK_BYTE_ORDER != 0;
+        Decoder { buf, big }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        if self.buf.len() < N {
+            let msg = "truncated AgentX PDU";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        let (head, tail) = self.buf.split_at(N);
+        self.buf = tail;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.take()?;
+        Ok(if self.big {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take()?;
+        Ok(if self.big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    /// An OID, and whether it's included in its range.  A prefix stands for
+    /// `1.3.6.1.<prefix>`.
+    fn oid(&mut self) -> io::Result<(Oid, bool)> {
+        let [n, prefix, include, _] = self.take()?;
+        let mut oid = Vec::with_capacity(usize::from(n) + 5);
+        if prefix != 0 {
+            oid.extend([1, 3, 6, 1, u32::from(prefix)]);
+        }
+        for _ in 0..n {
+            oid.push(self.u32()?);
+        }
+        Ok((oid, include != 0))
+    }
+
+    fn octets(&mut self) -> io::Result<()> {
+        let len = self.u32()? as usize;
+        for _ in 0..len.next_multiple_of(4) {
+            self.take::<1>()?;
+        }
+        Ok(())
+    }
+}
+
+/// The connection to the master agent
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Connect to a socket's path, or to `HOST:PORT` over TCP.  Net-SNMP's
+    /// `tcp:` prefix is accepted too.
+    fn connect(master: &str) -> io::Result<Self> {
+        let stream = if master.starts_with('/') {
+            Stream::Unix(UnixStream::connect(master)?)
+        } else {
+            let master = master.strip_prefix("tcp:").unwrap_or(master);
+            let addr = master.to_socket_addrs()?.next().ok_or_else(|| {
+                io::Error::other(format!("Cannot resolve {master}"))
+            })?;
+            Stream::Tcp(TcpStream::connect_timeout(&addr, TIMEOUT)?)
+        };
+        stream.set_timeouts(TIMEOUT)?;
+        Ok(stream)
+    }
+
+    fn set_timeouts(&self, read: Duration) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => {
+                s.set_read_timeout(Some(read))?;
+                s.set_write_timeout(Some(TIMEOUT))
+            }
+            Stream::Unix(s) => {
+                s.set_read_timeout(Some(read))?;
+                s.set_write_timeout(Some(TIMEOUT))
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// Describe one of the errors that the master agent may refuse a request
+/// with
+fn error_name(error: u16) -> String {
+    match error {
+        256 => "openFailed".to_owned(),
+        257 => "notOpen".to_owned(),
+        263 => "duplicateRegistration".to_owned(),
+        265 => "unsupportedContext".to_owned(),
+        266 => "parseError".to_owned(),
+        267 => "requestDenied".to_owned(),
+        268 => "processingError".to_owned(),
+        e => format!("error {e}"),
+    }
+}
+
+/// A registered session with the master agent
+struct Session {
+    stream: Stream,
+    id:     u32,
+    packet: u32,
+}
+
+impl Session {
+    /// Open a session and register ztop's subtree
+    fn open(master: &str) -> io::Result<Self> {
+        let mut session = Session {
+            stream: Stream::connect(master)?,
+            id:     0,
+            packet: 0,
+        };
+        // The default timeout, and no object identifying the subagent
+        let mut open = Encoder::default();
+        open.0.extend([0, 0, 0, 0]);
+        open.oid(&[], false);
+        open.octets(b"ztop");
+        session.id = session.request(OPEN, &open, "the session")?.session;
+        // The default timeout and priority, and no range
+        let mut register = Encoder::default();
+        register.0.extend([0, 127, 0, 0]);
+        register.oid(&ZTOP_MIB, false);
+        session.request(REGISTER, &register, "the registration")?;
+        session.stream.set_timeouts(POLL)?;
+        Ok(session)
+    }
+
+    fn send(&mut self, kind: u8, payload: &Encoder) -> io::Result<()> {
+        self.packet += 1;
+        let header = Header {
+            kind,
+            flags: 0,
+            session: self.id,
+            transaction: 0,
+            packet: self.packet,
+        };
+        self.stream.write_all(&header.frame(&payload.0))
+    }
+
+    /// Send a request, and wait for the master agent to accept it
+    fn request(
+        &mut self,
+        kind: u8,
+        payload: &Encoder,
+        what: &str,
+    ) -> io::Result<Header> {
+        self.send(kind, payload)?;
+        let (header, body) = read_pdu(&mut self.stream)?;
+        if header.kind != RESPONSE || header.packet != self.packet {
+            let msg = "unexpected reply from the master agent";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        let mut body = Decoder::new(&body, header.flags);
+        let _sys_up_time = body.u32()?;
+        match body.u16()? {
+            0 => Ok(header),
+            e => Err(io::Error::other(format!(
+                "the master agent refused {what}: {}",
+                error_name(e)
+            ))),
+        }
+    }
+
+    /// Wait briefly for the master agent's next request
+    fn wait(&mut self) -> io::Result<Option<(Header, Vec<u8>)>> {
+        // Only the first byte may time out, so that a timeout never leaves
+        // part of a PDU unread
+        let mut first = [0u8];
+        match self.stream.read(&mut first) {
+            Ok(0) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the master agent closed the connection",
+            )),
+            Ok(_) => {
+                read_pdu(&mut (&first[..]).chain(&mut self.stream)).map(Some)
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Answer the master agent's requests until stopped
+    fn serve(
+        &mut self,
+        table: &Mutex<Table>,
+        stop: &AtomicBool,
+    ) -> io::Result<()> {
+        while !stop.load(Ordering::Relaxed) {
+            let Some((header, body)) = self.wait()? else {
+                continue;
+            };
+            let mut body = Decoder::new(&body, header.flags);
+            // Only the default context is registered
+            if header.flags & NON_DEFAULT_CONTEXT != 0 {
+                body.octets()?;
+            }
+            let (error, varbinds) = match header.kind {
+                GET | GET_NEXT | GET_BULK => {
+                    (0, table.lock().unwrap().answer(header.kind, &mut body)?)
+                }
+                TEST_SET => (NOT_WRITABLE, Vec::new()),
+                CLOSE => {
+                    return Err(io::Error::other(
+                        "the master agent closed the session",
+                    ))
+                }
+                // A refused TestSet-PDU is followed only by a CleanupSet-PDU,
+                // which isn't answered
+                _ => continue,
+            };
+            let mut response = Encoder::default();
+            response.u32(0);
+            response.u16(error);
+            response.u16(u16::from(error != 0));
+            for (oid, value) in &varbinds {
+                response.varbind(oid, value);
+            }
+            let header = Header {
+                kind: RESPONSE,
+                ..header
+            };
+            self.stream.write_all(&header.frame(&response.0))?;
+        }
+        Ok(())
+    }
+
+    /// Tell the master agent that ztop is going away
+    fn close(mut self) {
+        let mut close = Encoder::default();
+        close.0.extend([REASON_SHUTDOWN, 0, 0, 0]);
+        // The master agent will notice when the connection closes anyway
+        let _ = self.send(CLOSE, &close);
+    }
+}
+
+/// Keep a session open with the master agent, reconnecting whenever it's
+/// lost, until stopped
+fn run(master: &str, table: &Mutex<Table>, stop: &AtomicBool) {
+    // Report each distinct error once, rather than on every retry
+    let mut last_error = None;
+    let mut report = |e: io::Error| {
+        let msg = e.to_string();
+        if last_error.as_ref() != Some(&msg) {
+            warn(format!("AgentX master agent {master}: {msg}"));
+            last_error = Some(msg);
+        }
+    };
+    while !stop.load(Ordering::Relaxed) {
+        match Session::open(master) {
+            Ok(mut session) => match session.serve(table, stop) {
+                Ok(()) => return session.close(),
+                Err(e) => report(e),
+            },
+            Err(e) => report(e),
+        }
+        let mut waited = Duration::ZERO;
+        while waited < RETRY && !stop.load(Ordering::Relaxed) {
+            thread::sleep(POLL);
+            waited += POLL;
+        }
+    }
+}
+
+/// Serves the latest update from a worker thread, which answers the master
+/// agent's requests as they come
+pub struct Agentx {
+    table: Arc<Mutex<Table>>,
+    stop:  Arc<AtomicBool>,
+}
+
+impl Agentx {
+    /// Connect to the master agent on `master`, a socket's path or
+    /// `HOST:PORT`, in the background
+    pub fn new(master: &str) -> Self {
+        let table = Arc::new(Mutex::new(Table::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let master = master.to_owned();
+        let (t, s) = (table.clone(), stop.clone());
+        thread::spawn(move || run(&master, &t, &s));
+        Agentx { table, stop }
+    }
+}
+
+/// Close the session once the Agentx is dropped, as on a configuration
+/// reload
+impl Drop for Agentx {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Sink for Agentx {
+    fn send(&mut self, elems: &[Element]) -> io::Result<()> {
+        self.table.lock().unwrap().update(elems);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use std::{net::TcpListener, sync::mpsc};
+
+    use super::*;
+
+    fn elem(name: &str) -> Element {
+        Element {
+            ops_r: 1.4,
+            r_s: 4096.0,
+            w_s: 2.6,
+            nread: 1 << 40,
+            nwritten: 7,
+            ..Element::named(name)
+        }
+    }
+
+    fn table() -> Table {
+        let mut table = Table::default();
+        table.update(&[elem("tank"), elem("tank/foo")]);
+        table.update(&[elem("tank/bar"), elem("tank")]);
+        table
+    }
+
+    fn range(start: Oid, end: Oid) -> Range {
+        Range {
+            start,
+            include: false,
+            end,
+        }
+    }
+
+    /// Datasets keep their indices, and the indices of vanished ones aren't
+    /// reused
+    #[test]
+    fn indices() {
+        let table = table();
+        assert_eq!(table.rows.keys().copied().collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(table.rows[&3].name, "tank/bar");
+    }
+
+    #[test]
+    fn get() {
+        let table = table();
+        let name = b"tank/bar".to_vec();
+        assert_eq!(table.get(&cell_oid(NAME, 3)), Value::OctetString(name));
+        assert_eq!(table.get(&cell_oid(FIRST_METRIC, 1)), Value::Counter64(1));
+        assert_eq!(table.get(&cell_oid(4, 1)), Value::Counter64(4096));
+        assert_eq!(table.get(&cell_oid(6, 1)), Value::Counter64(3));
+        assert_eq!(table.get(&cell_oid(NREAD, 1)), Value::Counter64(1 << 40));
+        assert_eq!(table.get(&cell_oid(NWRITTEN, 1)), Value::Counter64(7));
+        assert_eq!(table.get(&cell_oid(NAME, 2)), Value::NoSuchInstance);
+        assert_eq!(table.get(&cell_oid(NWRITTEN + 1, 1)), Value::NoSuchObject);
+        assert_eq!(table.get(&[1, 3, 6, 1, 2, 1]), Value::NoSuchObject);
+    }
+
+    /// GetNext walks each column in turn, and stops at the range's end
+    #[test]
+    fn next() {
+        let table = table();
+        let (oid, _) = table.next(&range(ZTOP_MIB.to_vec(), vec![]));
+        assert_eq!(oid, cell_oid(NAME, 1));
+        let (oid, _) = table.next(&range(cell_oid(NAME, 1), vec![]));
+        assert_eq!(oid, cell_oid(NAME, 3));
+        let (oid, _) = table.next(&range(cell_oid(NAME, 3), vec![]));
+        assert_eq!(oid, cell_oid(FIRST_METRIC, 1));
+        let r = Range {
+            include: true,
+            ..range(cell_oid(NAME, 3), vec![])
+        };
+        assert_eq!(table.next(&r).0, cell_oid(NAME, 3));
+        let r = range(cell_oid(NAME, 1), cell_oid(NAME, 2));
+        assert_eq!(table.next(&r), (cell_oid(NAME, 1), Value::EndOfMibView));
+        let r = range(cell_oid(NWRITTEN, 3), vec![]);
+        assert_eq!(table.next(&r).1, Value::EndOfMibView);
+    }
+
+    #[test]
+    fn bulk() {
+        let table = table();
+        let ranges = [
+            range(cell_oid(NAME, 1), vec![]),
+            range(cell_oid(NREAD, 1), vec![]),
+            range(cell_oid(NWRITTEN, 1), vec![]),
+        ];
+        let oids = table
+            .bulk(1, 3, &ranges)
+            .into_iter()
+            .map(|(oid, value)| (oid, value == Value::EndOfMibView))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            oids,
+            [
+                (cell_oid(NAME, 3), false),
+                (cell_oid(NREAD, 3), false),
+                (cell_oid(NWRITTEN, 3), false),
+                (cell_oid(NWRITTEN, 1), false),
+                (cell_oid(NWRITTEN, 3), true),
+                (cell_oid(NWRITTEN, 3), false),
+                (cell_oid(NWRITTEN, 3), true),
+            ]
+        );
+    }
+
+    /// Master agents may send in either byte order, and may abbreviate OIDs
+    /// with a prefix
+    #[test]
+    fn little_endian() {
+        let pdu = [
+            1, GET, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 12, 0, 0, 0, 2,
+            4, 1, 0, 1, 0, 0, 0, 8, 0, 0, 0,
+        ];
+        let (header, payload) = read_pdu(&mut &pdu[..]).unwrap();
+        assert_eq!((header.kind, header.session, header.packet), (GET, 9, 2));
+        let mut body = Decoder::new(&payload, header.flags);
+        assert_eq!(body.oid().unwrap(), (vec![1, 3, 6, 1, 4, 1, 8], true));
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn varbind() {
+        let mut enc = Encoder::default();
+        enc.varbind(&[1, 2], &Value::OctetString(b"tank/a".to_vec()));
+        assert_eq!(
+            enc.0,
+            [
+                0, 4, 0, 0, 2, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 6,
+                b't', b'a', b'n', b'k', b'/', b'a', 0, 0
+            ]
+        );
+    }
+
+    /// Act as a master agent: accept the session and registration, then get
+    /// a dataset's name
+    #[test]
+    fn session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let master = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let respond = |stream: &mut TcpStream, header: Header| {
+                let mut body = Encoder::default();
+                body.0.extend([0; 8]);
+                let header = Header {
+                    kind: RESPONSE,
+                    session: 42,
+                    ..header
+                };
+                stream.write_all(&header.frame(&body.0)).unwrap();
+            };
+            let (open, _) = read_pdu(&mut stream).unwrap();
+            assert_eq!(open.kind, OPEN);
+            respond(&mut stream, open);
+            let (register, payload) = read_pdu(&mut stream).unwrap();
+            assert_eq!((register.kind, register.session), (REGISTER, 42));
+            let mut body = Decoder::new(&payload, register.flags);
+            body.u32().unwrap();
+            assert_eq!(body.oid().unwrap().0, ZTOP_MIB);
+            respond(&mut stream, register);
+            rx.recv().unwrap();
+
+            let mut get = Encoder::default();
+            get.oid(&cell_oid(NAME, 1), false);
+            get.oid(&[], false);
+            let header = Header {
+                kind:        GET,
+                flags:       NETWORK_BYTE_ORDER,
+                session:     42,
+                transaction: 5,
+                packet:      6,
+            };
+            stream.write_all(&header.frame(&get.0)).unwrap();
+            read_pdu(&mut stream).unwrap()
+        });
+        let mut agentx = Agentx::new(&master);
+        agentx.send(&[elem("tank")]).unwrap();
+        tx.send(()).unwrap();
+        let (header, payload) = handle.join().unwrap();
+        assert_eq!(
+            (header.kind, header.transaction, header.packet),
+            (RESPONSE, 5, 6)
+        );
+        let mut expected = Encoder::default();
+        expected.0.extend([0; 8]);
+        expected
+            .varbind(&cell_oid(NAME, 1), &Value::OctetString(b"tank".to_vec()));
+        assert_eq!(payload, expected.0);
+    }
+}
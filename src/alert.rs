@@ -0,0 +1,650 @@
+// vim: tw=80
+//! User-defined alert rules, evaluated against every dataset on each refresh
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    io::{self, Write},
+    mem,
+    os::unix::net::UnixDatagram,
+    process::{Child, Stdio},
+    time::{Duration, Instant},
+};
+
+use cfg_if::cfg_if;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    app::{Counters, Element, Metric},
+    config::{de_duration, de_opt_regex, de_rate},
+    exec,
+    warning::warn,
+};
+
+cfg_if! {
+    if #[cfg(target_os = "freebsd")] {
+        const SYSLOG_PATH: &str = "/var/run/log";
+    } else {
+        const SYSLOG_PATH: &str = "/dev/log";
+    }
+}
+
+/// What to do when an alert fires
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Ring the terminal bell
+    Bell,
+    /// Write the recent statistics to a file, as configured by `[capture]`
+    Capture,
+    /// Highlight the dataset's row for as long as the condition holds
+    Highlight,
+    /// Run the rule's `hook` command
+    Hook,
+    /// Log a structured entry to the systemd journal
+    #[cfg(target_os = "linux")]
+    Journal,
+    /// Log a message to syslog
+    Syslog,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum Comparison {
+    #[default]
+    #[serde(rename = ">")]
+    Greater,
+    #[serde(rename = ">=")]
+    GreaterEqual,
+    #[serde(rename = "<")]
+    Less,
+    #[serde(rename = "<=")]
+    LessEqual,
+}
+
+impl Comparison {
+    fn test(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Greater => value > threshold,
+            Comparison::GreaterEqual => value >= threshold,
+            Comparison::Less => value < threshold,
+            Comparison::LessEqual => value <= threshold,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+        };
+        f.write_str(s)
+    }
+}
+
+/// One `[[alert]]` section from the config file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Used to identify the rule in notifications
+    #[serde(default)]
+    pub name:      Option<String>,
+    /// Only check datasets with names matching this regex
+    #[serde(default, deserialize_with = "de_opt_regex")]
+    pub datasets:  Option<Regex>,
+    pub metric:    Metric,
+    #[serde(default)]
+    pub op:        Comparison,
+    #[serde(deserialize_with = "de_rate")]
+    pub threshold: f64,
+    /// The condition must hold for this long before the alert fires
+    #[serde(default, deserialize_with = "de_duration")]
+    pub duration:  Duration,
+    #[serde(default = "Rule::default_actions")]
+    pub actions:   Vec<Action>,
+    /// Shell command to run for the `hook` action
+    #[serde(default)]
+    pub hook:      Option<String>,
+}
+
+impl Rule {
+    fn default_actions() -> Vec<Action> {
+        vec![Action::Highlight]
+    }
+
+    /// Check for inconsistencies that serde can't catch.  `capture` says
+    /// whether the config file has a `[capture]` section.
+    pub fn validate(&self, capture: bool) -> Result<(), Box<dyn Error>> {
+        if self.actions.contains(&Action::Hook) && self.hook.is_none() {
+            let msg = format!(
+                "alert {:?} has a hook action but no hook",
+                self.name()
+            );
+            return Err(msg.into());
+        }
+        if self.actions.contains(&Action::Capture) && !capture {
+            let msg = format!(
+                "alert {:?} has a capture action but no [capture] section",
+                self.name()
+            );
+            return Err(msg.into());
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("ztop alert")
+    }
+
+    fn matches(&self, elem: &Element) -> bool {
+        self.datasets
+            .as_ref()
+            .map(|re| re.is_match(&elem.name))
+            .unwrap_or(true)
+    }
+
+    /// Is this the same rule as `other`, from a reloaded config file?
+    fn same_as(&self, other: &Rule) -> bool {
+        self.name == other.name
+            && self.datasets.as_ref().map(Regex::as_str)
+                == other.datasets.as_ref().map(Regex::as_str)
+            && self.metric == other.metric
+            && self.op == other.op
+            && self.threshold == other.threshold
+            && self.duration == other.duration
+            && self.actions == other.actions
+            && self.hook == other.hook
+    }
+}
+
+/// An alert that has just fired
+#[derive(Clone, Debug)]
+pub struct Firing {
+    rule:    usize,
+    dataset: String,
+    value:   f64,
+}
+
+/// Progress of one rule against one dataset
+#[derive(Clone, Copy, Debug)]
+struct State {
+    /// When the condition first became true
+    since: Instant,
+    fired: bool,
+    /// The user has seen it, so stop highlighting it until it fires again
+    acked: bool,
+}
+
+#[derive(Default)]
+pub struct Alerts {
+    rules:   Vec<Rule>,
+    /// For each rule, the datasets that currently meet its condition
+    states:  Vec<HashMap<String, State>>,
+    /// For each rule, when it may fire again if it's been snoozed
+    snoozed: Vec<Option<Instant>>,
+    /// Hook processes that haven't yet been reaped
+    running: Vec<Child>,
+}
+
+impl Alerts {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        let states = rules.iter().map(|_| HashMap::new()).collect();
+        let snoozed = vec![None; rules.len()];
+        Alerts {
+            rules,
+            states,
+            snoozed,
+            running: Vec::new(),
+        }
+    }
+
+    /// Replace the rules with a reloaded config file's.  Rules that didn't
+    /// change keep their state, so they won't fire again and stay
+    /// acknowledged or snoozed.
+    pub fn reload(&mut self, rules: Vec<Rule>) {
+        let mut old = mem::take(&mut self.rules)
+            .into_iter()
+            .zip(mem::take(&mut self.states))
+            .zip(mem::take(&mut self.snoozed))
+            .map(Some)
+            .collect::<Vec<_>>();
+        for rule in rules {
+            let prev = old
+                .iter_mut()
+                .find(|o| {
+                    o.as_ref().is_some_and(|((r, _), _)| r.same_as(&rule))
+                })
+                .and_then(Option::take)
+                .map(|((_, states), snoozed)| (states, snoozed));
+            let (states, snoozed) = prev.unwrap_or_default();
+            self.rules.push(rule);
+            self.states.push(states);
+            self.snoozed.push(snoozed);
+        }
+    }
+
+    /// The rules that have fired for `dataset`, if any, or for every
+    /// dataset
+    fn fired<'a>(
+        &'a mut self,
+        dataset: Option<&'a str>,
+    ) -> impl Iterator<Item = (usize, &'a mut State)> + 'a {
+        self.states
+            .iter_mut()
+            .enumerate()
+            .flat_map(move |(i, states)| {
+                states
+                    .iter_mut()
+                    .filter(move |(name, s)| {
+                        s.fired && dataset.map_or(true, |d| d == name.as_str())
+                    })
+                    .map(move |(_, s)| (i, s))
+            })
+    }
+
+    /// Stop highlighting the alerts that have fired for `dataset`, or for
+    /// every dataset, until their conditions clear and they fire again.
+    /// Return how many there were.
+    pub fn ack(&mut self, dataset: Option<&str>) -> usize {
+        let mut n = 0;
+        for (_, state) in self.fired(dataset).filter(|(_, s)| !s.acked) {
+            state.acked = true;
+            n += 1;
+        }
+        n
+    }
+
+    /// Silence every rule that has fired for `dataset`, or for any dataset,
+    /// until `until`.  They fire again afterwards if their conditions still
+    /// hold.  Return the rules' names.
+    pub fn snooze(
+        &mut self,
+        dataset: Option<&str>,
+        until: Instant,
+    ) -> Vec<String> {
+        let mut rules = Vec::new();
+        for (i, state) in self.fired(dataset) {
+            state.fired = false;
+            if !rules.contains(&i) {
+                rules.push(i);
+            }
+        }
+        rules.sort_unstable();
+        for &i in rules.iter() {
+            self.snoozed[i] = Some(until);
+        }
+        rules
+            .into_iter()
+            .map(|i| self.rules[i].name().to_owned())
+            .collect()
+    }
+
+    /// Check every rule against the latest statistics.
+    ///
+    /// # Returns
+    ///
+    /// The alerts that fired during this evaluation.  An alert fires once
+    /// when its condition has held for the rule's duration, and won't fire
+    /// again until the condition has cleared.  Rules whose metric needs
+    /// counters missing from `counters` never fire.
+    pub fn evaluate(
+        &mut self,
+        elems: &[Element],
+        now: Instant,
+        counters: Counters,
+    ) -> Vec<Firing> {
+        let mut firings = Vec::new();
+        for (i, (rule, states)) in
+            self.rules.iter().zip(self.states.iter_mut()).enumerate()
+        {
+            let snoozed = match self.snoozed[i] {
+                Some(until) if now < until => true,
+                Some(_) => {
+                    self.snoozed[i] = None;
+                    false
+                }
+                None => false,
+            };
+            let mut next = HashMap::new();
+            let available = counters.contains(rule.metric.counters());
+            for elem in elems.iter().filter(|e| available && rule.matches(e)) {
+                let value = elem.get(rule.metric);
+                if !rule.op.test(value, rule.threshold) {
+                    continue;
+                }
+                let mut state =
+                    states.get(&elem.name).copied().unwrap_or(State {
+                        since: now,
+                        fired: false,
+                        acked: false,
+                    });
+                if !snoozed
+                    && !state.fired
+                    && now - state.since >= rule.duration
+                {
+                    state.fired = true;
+                    firings.push(Firing {
+                        rule: i,
+                        dataset: elem.name.clone(),
+                        value,
+                    });
+                }
+                next.insert(elem.name.clone(), state);
+            }
+            *states = next;
+        }
+        firings
+    }
+
+    /// Should this dataset's row be highlighted?
+    pub fn is_highlighted(&self, dataset: &str) -> bool {
+        self.rules
+            .iter()
+            .zip(self.states.iter())
+            .any(|(rule, states)| {
+                rule.actions.contains(&Action::Highlight)
+                    && states.get(dataset).is_some_and(|s| s.fired && !s.acked)
+            })
+    }
+
+    /// Has a rule with this name fired for this dataset, and is its
+    /// condition still true?
+    pub fn has_fired(&self, name: &str, dataset: &str) -> bool {
+        self.rules
+            .iter()
+            .zip(self.states.iter())
+            .any(|(rule, states)| {
+                rule.name() == name
+                    && states.get(dataset).is_some_and(|s| s.fired)
+            })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The names of the newly fired alerts that should start a capture
+    pub fn capturing(&self, firings: &[Firing]) -> Vec<String> {
+        firings
+            .iter()
+            .map(|f| &self.rules[f.rule])
+            .filter(|rule| rule.actions.contains(&Action::Capture))
+            .map(|rule| rule.name().to_owned())
+            .collect()
+    }
+
+    /// Perform the non-visual actions for newly fired alerts.
+    ///
+    /// A hook that can't be started gets a warning.  A bell, syslog message,
+    /// or journal entry that can't be delivered is simply dropped; the alert
+    /// still shows in the table.
+    pub fn notify(&mut self, firings: &[Firing]) {
+        self.running
+            .retain_mut(|c| !matches!(c.try_wait(), Ok(Some(_))));
+        for firing in firings {
+            let rule = &self.rules[firing.rule];
+            let msg = format!(
+                "{}: {} {} is {:.0} ({} {})",
+                rule.name(),
+                firing.dataset,
+                rule.metric,
+                firing.value,
+                rule.op,
+                rule.threshold
+            );
+            for action in rule.actions.iter() {
+                match action {
+                    Action::Bell => {
+                        let mut stdout = io::stdout();
+                        let _ = stdout.write_all(b"\x07");
+                        let _ = stdout.flush();
+                    }
+                    Action::Capture | Action::Highlight => (),
+                    Action::Hook => {
+                        let Some(hook) = rule.hook.as_ref() else {
+                            continue;
+                        };
+                        let mut cmd = match exec::command("/bin/sh") {
+                            Ok(cmd) => cmd,
+                            Err(e) => {
+                                warn(format!("Alert {}: {e}", rule.name()));
+                                continue;
+                            }
+                        };
+                        let child = cmd
+                            .arg("-c")
+                            .arg(hook)
+                            .env("ZTOP_RULE", rule.name())
+                            .env("ZTOP_DATASET", &firing.dataset)
+                            .env("ZTOP_METRIC", rule.metric.to_string())
+                            .env("ZTOP_VALUE", format!("{:.0}", firing.value))
+                            .env("ZTOP_THRESHOLD", rule.threshold.to_string())
+                            .stdin(Stdio::null())
+                            .stdout(Stdio::null())
+                            .stderr(Stdio::null())
+                            .spawn();
+                        if let Ok(child) = child {
+                            self.running.push(child);
+                        }
+                    }
+                    #[cfg(target_os = "linux")]
+                    Action::Journal => {
+                        let _ = crate::journal::send(&[
+                            ("PRIORITY", "4".to_owned()),
+                            ("MESSAGE", msg.clone()),
+                            ("ALERT", rule.name().to_owned()),
+                            ("DATASET", firing.dataset.clone()),
+                            ("METRIC", rule.metric.to_string()),
+                            ("VALUE", format!("{:.0}", firing.value)),
+                            ("THRESHOLD", rule.threshold.to_string()),
+                        ]);
+                    }
+                    Action::Syslog => {
+                        let _ = syslog(&msg);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Send a warning-level message to the local syslog daemon
+pub fn syslog(msg: &str) -> io::Result<()> {
+    // LOG_USER | LOG_WARNING
+    const PRI: u8 = 8 | 4;
+    let sock = UnixDatagram::unbound()?;
+    let line = format!("<{PRI}>ztop[{}]: {msg}", std::process::id());
+    sock.send_to(line.as_bytes(), SYSLOG_PATH)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn rule(duration: Duration) -> Rule {
+        Rule {
+            name: None,
+            datasets: Some(Regex::new("^tank/db").unwrap()),
+            metric: Metric::WS,
+            op: Comparison::Greater,
+            threshold: 100.0,
+            duration,
+            actions: Rule::default_actions(),
+            hook: None,
+        }
+    }
+
+    #[test]
+    fn fires_immediately() {
+        let mut alerts = Alerts::new(vec![rule(Duration::ZERO)]);
+        let now = Instant::now();
+        let elems = [
+            Element::writing("tank/db", 200.0),
+            Element::writing("tank/web", 200.0),
+        ];
+        let firings = alerts.evaluate(&elems, now, Counters::ALL);
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].dataset, "tank/db");
+        assert!(alerts.is_highlighted("tank/db"));
+        assert!(!alerts.is_highlighted("tank/web"));
+    }
+
+    #[test]
+    fn fires_once() {
+        let mut alerts = Alerts::new(vec![rule(Duration::ZERO)]);
+        let now = Instant::now();
+        let elems = [Element::writing("tank/db", 200.0)];
+        assert_eq!(alerts.evaluate(&elems, now, Counters::ALL).len(), 1);
+        let later = now + Duration::from_secs(1);
+        assert!(alerts.evaluate(&elems, later, Counters::ALL).is_empty());
+        assert!(alerts.is_highlighted("tank/db"));
+    }
+
+    /// A rule whose metric this kernel can't measure never fires
+    #[test]
+    fn missing_counter() {
+        let mut alerts = Alerts::new(vec![rule(Duration::ZERO)]);
+        let elems = [Element::writing("tank/db", 200.0)];
+        let counters = Counters::READS | Counters::NREAD;
+        assert!(alerts.evaluate(&elems, Instant::now(), counters).is_empty());
+        assert!(!alerts.is_highlighted("tank/db"));
+    }
+
+    #[test]
+    fn must_be_sustained() {
+        let mut alerts = Alerts::new(vec![rule(Duration::from_secs(10))]);
+        let t0 = Instant::now();
+        let busy = [Element::writing("tank/db", 200.0)];
+        let idle = [Element::writing("tank/db", 0.0)];
+        assert!(alerts.evaluate(&busy, t0, Counters::ALL).is_empty());
+        // The condition clears, resetting the timer
+        let t1 = t0 + Duration::from_secs(5);
+        assert!(alerts.evaluate(&idle, t1, Counters::ALL).is_empty());
+        let t2 = t0 + Duration::from_secs(10);
+        assert!(alerts.evaluate(&busy, t2, Counters::ALL).is_empty());
+        assert!(!alerts.is_highlighted("tank/db"));
+        let t3 = t0 + Duration::from_secs(20);
+        assert_eq!(alerts.evaluate(&busy, t3, Counters::ALL).len(), 1);
+        assert!(alerts.is_highlighted("tank/db"));
+        let t4 = t0 + Duration::from_secs(21);
+        assert!(alerts.evaluate(&idle, t4, Counters::ALL).is_empty());
+        assert!(!alerts.is_highlighted("tank/db"));
+    }
+
+    /// An acknowledged alert stops being highlighted until it fires again
+    #[test]
+    fn ack() {
+        let mut alerts = Alerts::new(vec![rule(Duration::ZERO)]);
+        let t0 = Instant::now();
+        let busy = [
+            Element::writing("tank/db", 200.0),
+            Element::writing("tank/db2", 200.0),
+        ];
+        let idle = [
+            Element::writing("tank/db", 0.0),
+            Element::writing("tank/db2", 200.0),
+        ];
+        alerts.evaluate(&busy, t0, Counters::ALL);
+        assert_eq!(alerts.ack(Some("tank/db")), 1);
+        assert_eq!(alerts.ack(Some("tank/db")), 0);
+        assert!(!alerts.is_highlighted("tank/db"));
+        assert!(alerts.is_highlighted("tank/db2"));
+        // Still acknowledged while the condition holds
+        let t1 = t0 + Duration::from_secs(1);
+        assert!(alerts.evaluate(&busy, t1, Counters::ALL).is_empty());
+        assert!(!alerts.is_highlighted("tank/db"));
+        let t2 = t0 + Duration::from_secs(2);
+        alerts.evaluate(&idle, t2, Counters::ALL);
+        let t3 = t0 + Duration::from_secs(3);
+        assert_eq!(alerts.evaluate(&busy, t3, Counters::ALL).len(), 1);
+        assert!(alerts.is_highlighted("tank/db"));
+        // Every dataset
+        assert_eq!(alerts.ack(None), 2);
+        assert!(!alerts.is_highlighted("tank/db2"));
+    }
+
+    /// A snoozed rule doesn't fire until the snooze ends
+    #[test]
+    fn snooze() {
+        let mut other = rule(Duration::ZERO);
+        other.name = Some("other".to_owned());
+        other.datasets = Some(Regex::new("^tank/web").unwrap());
+        let mut alerts = Alerts::new(vec![rule(Duration::ZERO), other]);
+        let t0 = Instant::now();
+        let elems = [
+            Element::writing("tank/db", 200.0),
+            Element::writing("tank/web", 200.0),
+        ];
+        assert_eq!(alerts.evaluate(&elems, t0, Counters::ALL).len(), 2);
+        let until = t0 + Duration::from_secs(60);
+        assert_eq!(alerts.snooze(Some("tank/db"), until), ["ztop alert"]);
+        assert!(!alerts.is_highlighted("tank/db"));
+        assert!(alerts.is_highlighted("tank/web"));
+        let t1 = t0 + Duration::from_secs(30);
+        assert!(alerts.evaluate(&elems, t1, Counters::ALL).is_empty());
+        assert!(!alerts.is_highlighted("tank/db"));
+        let t2 = t0 + Duration::from_secs(60);
+        let firings = alerts.evaluate(&elems, t2, Counters::ALL);
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].dataset, "tank/db");
+        assert!(alerts.is_highlighted("tank/db"));
+    }
+
+    /// Unchanged rules keep their state across a reload
+    #[test]
+    fn reload() {
+        let mut alerts = Alerts::new(vec![rule(Duration::ZERO)]);
+        let t0 = Instant::now();
+        let elems = [Element::writing("tank/db", 200.0)];
+        assert_eq!(alerts.evaluate(&elems, t0, Counters::ALL).len(), 1);
+        let mut other = rule(Duration::ZERO);
+        other.threshold = 50.0;
+        alerts.reload(vec![other, rule(Duration::ZERO)]);
+        let t1 = t0 + Duration::from_secs(1);
+        let firings = alerts.evaluate(&elems, t1, Counters::ALL);
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].rule, 0);
+        assert!(alerts.is_highlighted("tank/db"));
+    }
+
+    #[test]
+    fn parse() {
+        let s = r#"
+            name = "busy db"
+            datasets = "^tank/db"
+            metric = "w_s"
+            op = ">="
+            threshold = "100M"
+            duration = "30s"
+            actions = ["highlight", "hook"]
+            hook = "logger busy"
+        "#;
+        let rule: Rule = toml::from_str(s).unwrap();
+        assert_eq!(rule.name(), "busy db");
+        assert!(rule.datasets.unwrap().is_match("tank/db/x"));
+        assert_eq!(rule.metric, Metric::WS);
+        assert_eq!(rule.op, Comparison::GreaterEqual);
+        assert_eq!(rule.threshold, 100_000_000.0);
+        assert_eq!(rule.duration, Duration::from_secs(30));
+        assert_eq!(rule.actions, [Action::Highlight, Action::Hook]);
+        assert_eq!(rule.hook.as_deref(), Some("logger busy"));
+    }
+
+    #[test]
+    fn parse_defaults() {
+        let s = r#"
+            metric = "d_s"
+            threshold = 1000
+        "#;
+        let rule: Rule = toml::from_str(s).unwrap();
+        assert!(rule.datasets.is_none());
+        assert_eq!(rule.op, Comparison::Greater);
+        assert_eq!(rule.threshold, 1000.0);
+        assert_eq!(rule.duration, Duration::ZERO);
+        assert_eq!(rule.actions, [Action::Highlight]);
+    }
+}
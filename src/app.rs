@@ -1,37 +1,352 @@
 // vim: tw=80
 use std::{
-    collections::{btree_map, BTreeMap},
+    borrow::Cow,
+    cmp::{Ordering, Reverse},
+    collections::{btree_map, BTreeMap, HashMap, HashSet, VecDeque},
     error::Error,
+    fmt,
+    fs,
+    io,
     mem,
     num::NonZeroUsize,
-    ops::AddAssign,
+    ops::{AddAssign, BitOr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use cfg_if::cfg_if;
+use glob::Pattern;
 use nix::{
     sys::time::TimeSpec,
     time::{clock_gettime, ClockId},
 };
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_os = "linux")]
+use crate::journal::Summarizer;
+use crate::{
+    alert::{self, Alerts, Rule},
+    capture::Capture,
+    config::{format_interval, Config, DatasetSet, HotnessWeights, Relabel},
+    container::{self, Containers},
+    exec,
+    i18n::{tr, trf},
+    iostat::{
+        refresh_view,
+        sort_vdevs,
+        Latency,
+        Pools,
+        Queues,
+        VdevRow,
+        Vdevs,
+        POOL_INTERVAL,
+        VDEV_COLUMNS,
+    },
+    pressure::{self, PressureConfig},
+    sink::Sink,
+    state::SavedSort,
+    summary::Summary,
+    vm,
+    warning::{warn, warn_once},
+};
 
 cfg_if! {
     if #[cfg(target_os = "freebsd")] {
         mod freebsd;
-        use freebsd::SnapshotIter;
+        use freebsd::{
+            arcstats, dbufstats, is_permission_denied, mount_table, pools,
+            txgs, zfetchstats, SnapshotIter, ACCESS_HINT,
+        };
         const CLOCK_UPTIME: ClockId = ClockId::CLOCK_UPTIME;
     } else if #[cfg(target_os = "linux")] {
         mod linux;
-        use linux::SnapshotIter;
+        use linux::{
+            arcstats, dbufstats, is_permission_denied, mount_table, pools,
+            txgs, zfetchstats, SnapshotIter, ACCESS_HINT,
+        };
+        pub use linux::{set_container_mode, set_kstat_path};
         const CLOCK_UPTIME: ClockId = ClockId::CLOCK_BOOTTIME;
+    } else if #[cfg(target_os = "illumos")] {
+        use illumos::{
+            arcstats, dbufstats, is_permission_denied, mount_table, pools,
+            txgs, zfetchstats, SnapshotIter, ACCESS_HINT,
+        };
+        // illumos's monotonic clock counts from boot
+        const CLOCK_UPTIME: ClockId = ClockId::CLOCK_MONOTONIC;
+    }
+}
+// Also built for tests elsewhere, to test its parser
+#[cfg(any(target_os = "illumos", test))]
+mod illumos;
+
+/// How long to show which column was selected for sorting
+const SORT_HINT: Duration = Duration::from_secs(2);
+
+/// Mark newly created datasets for this many refreshes
+const NEW_REFRESHES: u32 = 3;
+
+/// Indexes of the table's columns, into `COLUMNS`
+pub mod col {
+    pub const OPS_R: usize = 0;
+    pub const R_S: usize = 1;
+    pub const OPS_W: usize = 2;
+    pub const W_S: usize = 3;
+    pub const OPS_D: usize = 4;
+    pub const D_S: usize = 5;
+    pub const PENDING_D: usize = 6;
+    pub const NREAD: usize = 7;
+    pub const NWRITTEN: usize = 8;
+    pub const USED: usize = 9;
+    pub const AVAIL: usize = 10;
+    pub const REFER: usize = 11;
+    pub const TYPE: usize = 12;
+    pub const ENC: usize = 13;
+    pub const DATASET: usize = 14;
+}
+
+/// Sort index for hotness, a weighted sum of the bandwidth columns.  It comes
+/// after the last real column.
+pub const HOTNESS: usize = col::DATASET + 1;
+
+/// The canonical name of each column, and the width of its header
+pub const COLUMNS: [(&str, usize); 15] = [
+    ("r/s", 6),
+    ("kB/s r", 7),
+    ("w/s", 6),
+    ("kB/s w", 7),
+    ("d/s", 6),
+    ("kB/s d", 6),
+    ("pend d", 6),
+    ("read", 6),
+    ("written", 7),
+    ("used", 6),
+    ("avail", 6),
+    ("refer", 6),
+    ("Type", 4),
+    ("Enc", 4),
+    ("Dataset", 0),
+];
+
+/// Columns that are hidden on narrow terminals
+pub const OPTIONAL_COLUMNS: [usize; 8] = [
+    col::PENDING_D,
+    col::NREAD,
+    col::NWRITTEN,
+    col::USED,
+    col::AVAIL,
+    col::REFER,
+    col::TYPE,
+    col::ENC,
+];
+
+/// Parse an objset ID from a kstat name like `objset-0x58c`
+fn parse_objset_id(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.strip_prefix("objset-0x")?, 16).ok()
+}
+
+/// Parse a mount table in fstab format, returning the names of all mounted
+/// ZFS file systems.  Spaces and other special characters are escaped as
+/// octal, like `\040`.
+fn parse_mounts(table: &str) -> HashSet<String> {
+    table
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_ascii_whitespace();
+            let (name, _, fstype) =
+                (fields.next()?, fields.next()?, fields.next()?);
+            (fstype == "zfs").then(|| unescape_octal(name))
+        })
+        .collect()
+}
+
+/// Decode the octal escapes used in mount tables
+fn unescape_octal(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = bytes
+            .get(i + 1..i + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u8::from_str_radix(d, 8).ok());
+        match (bytes[i], code) {
+            (b'\\', Some(c)) => {
+                out.push(c);
+                i += 4;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Clean up a dataset name as reported by the kernel.
+///
+/// Spaces are legal in dataset names, so only line terminators and NULs are
+/// trimmed.  Return `false` if the result can't be a real dataset name.
+fn normalize_name(name: &mut String) -> bool {
+    let len = name.trim_end_matches(['\0', '\r', '\n']).len();
+    name.truncate(len);
+    !name.contains(char::is_control)
+}
+
+/// Is this one of ZFS's internal objsets, like `$MOS` or `tank/$ORIGIN`,
+/// rather than a user-visible dataset?  Some OpenZFS versions report
+/// statistics for them.
+fn is_internal(name: &str) -> bool {
+    name.is_empty() || name.split('/').any(|c| c.starts_with('$'))
+}
+
+/// Is this a snapshot, like `tank/home@monday`?  Mounted snapshots have their
+/// own objsets.
+fn is_snapshot(name: &str) -> bool {
+    name.contains('@')
+}
+
+/// What kind of dataset a row shows
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum DatasetType {
+    #[default]
+    Filesystem,
+    Volume,
+    Snapshot,
+}
+
+impl DatasetType {
+    pub const ALL: [DatasetType; 3] = [
+        DatasetType::Filesystem,
+        DatasetType::Volume,
+        DatasetType::Snapshot,
+    ];
+
+    /// Classify a dataset by its name.  Volumes are recognized by their
+    /// device nodes in `/dev/zvol`, which exist unless `volmode` is `none`.
+    fn of(name: &str) -> Self {
+        if is_snapshot(name) {
+            DatasetType::Snapshot
+        } else if Path::new("/dev/zvol").join(name).exists() {
+            DatasetType::Volume
+        } else {
+            DatasetType::Filesystem
+        }
+    }
+
+    /// The abbreviation shown in the Type column
+    pub fn abbrev(self) -> &'static str {
+        match self {
+            DatasetType::Filesystem => "fs",
+            DatasetType::Volume => "zvol",
+            DatasetType::Snapshot => "snap",
+        }
+    }
+}
+
+impl fmt::Display for DatasetType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            DatasetType::Filesystem => "filesystem",
+            DatasetType::Volume => "volume",
+            DatasetType::Snapshot => "snapshot",
+        };
+        f.write_str(s)
+    }
+}
+
+impl FromStr for DatasetType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DatasetType::ALL
+            .into_iter()
+            .find(|t| t.to_string() == s)
+            .ok_or_else(|| format!("unknown dataset type {s:?}"))
+    }
+}
+
+/// A set of the per-dataset kstat counters.
+///
+/// Not every kernel provides all of them.  For example, FreeBSD 12.2 has no
+/// `nunlinks` or `nunlinked`, and new OpenZFS releases may rename others.
+/// Statistics derived from a missing counter are displayed as `-`.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+pub struct Counters(u8);
+
+impl Counters {
+    pub const ALL: Self = Counters(63);
+    const NAMES: [(Self, &'static str); 6] = [
+        (Self::READS, "reads"),
+        (Self::NREAD, "nread"),
+        (Self::WRITES, "writes"),
+        (Self::NWRITTEN, "nwritten"),
+        (Self::NUNLINKS, "nunlinks"),
+        (Self::NUNLINKED, "nunlinked"),
+    ];
+    pub const NREAD: Self = Counters(2);
+    pub const NUNLINKED: Self = Counters(32);
+    pub const NUNLINKS: Self = Counters(16);
+    pub const NWRITTEN: Self = Counters(8);
+    pub const READS: Self = Counters(1);
+    pub const WRITES: Self = Counters(4);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    /// The kstat names of the counters in this set
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(c, _)| self.contains(*c))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// The kstat names of the counters in `needed` that this set lacks
+    pub fn lacking(self, needed: Self) -> Vec<&'static str> {
+        Counters(needed.0 & !self.0).names()
+    }
+}
+
+impl BitOr for Counters {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Counters(self.0 | other.0)
+    }
+}
+
+/// The kstat counters that a column of the table is derived from
+pub fn column_counters(i: usize) -> Counters {
+    match i {
+        col::OPS_R..=col::PENDING_D => Metric::ALL[i].counters(),
+        col::NREAD => Counters::NREAD,
+        col::NWRITTEN => Counters::NWRITTEN,
+        _ => Counters::default(),
     }
 }
 
 /// A snapshot in time of a dataset's statistics.
 ///
 /// The various fields are not saved atomically, but ought to be close.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 struct Snapshot {
     name:      String,
+    /// The objset ID, which unlike the name is stable across renames
+    objset:    u64,
+    /// The counters that the kernel provided.  The others are zero.
+    counters:  Counters,
     nunlinked: u64,
     nunlinks:  u64,
     nread:     u64,
@@ -41,30 +356,77 @@ struct Snapshot {
 }
 
 impl Snapshot {
-    fn compute(&self, prev: Option<&Self>, etime: f64) -> Element {
-        if let Some(prev) = prev {
-            Element {
-                name:  self.name.clone(),
-                ops_r: (self.reads - prev.reads) as f64 / etime,
-                r_s:   (self.nread - prev.nread) as f64 / etime,
-                ops_w: (self.writes - prev.writes) as f64 / etime,
-                w_s:   (self.nwritten - prev.nwritten) as f64 / etime,
-                ops_d: (self.nunlinks - prev.nunlinks) as f64 / etime,
-                d_s:   (self.nunlinked - prev.nunlinked) as f64 / etime,
-            }
-        } else {
-            Element {
-                name:  self.name.clone(),
-                ops_r: self.reads as f64 / etime,
-                r_s:   self.nread as f64 / etime,
-                ops_w: self.writes as f64 / etime,
-                w_s:   self.nwritten as f64 / etime,
-                ops_d: self.nunlinks as f64 / etime,
-                d_s:   self.nunlinked as f64 / etime,
-            }
+    /// The values of the counters that rates are computed from: reads,
+    /// nread, writes, nwritten, nunlinks, and nunlinked
+    fn counts(&self) -> [u64; 6] {
+        [
+            self.reads,
+            self.nread,
+            self.writes,
+            self.nwritten,
+            self.nunlinks,
+            self.nunlinked,
+        ]
+    }
+
+    /// How much each of the counts grew since `prev`.  `None` if any went
+    /// backwards, as when a pool is exported and reimported.
+    fn growth(&self, prev: &Self) -> Option<[u64; 6]> {
+        let (cur, prev) = (self.counts(), prev.counts());
+        let mut growth = [0; 6];
+        for i in 0..6 {
+            growth[i] = cur[i].checked_sub(prev[i])?;
+        }
+        Some(growth)
+    }
+
+    /// Compute the rates from the counts' growth over `etime` seconds.  With
+    /// no usable growth, the rates are zero.
+    fn compute(&self, growth: Option<[u64; 6]>, etime: f64) -> Element {
+        let [reads, nread, writes, nwritten, nunlinks, nunlinked] =
+            growth.unwrap_or_default();
+        Element {
+            name:        self.name.clone(),
+            objset:      self.objset,
+            ops_r:       reads as f64 / etime,
+            r_s:         nread as f64 / etime,
+            ops_w:       writes as f64 / etime,
+            w_s:         nwritten as f64 / etime,
+            ops_d:       nunlinks as f64 / etime,
+            d_s:         nunlinked as f64 / etime,
+            pending_d:   self.pending_d(),
+            nread:       self.nread,
+            nwritten:    self.nwritten,
+            total:       self.total(),
+            nops:        self.reads + self.writes + self.nunlinks,
+            self_only:   false,
+            stale:       false,
+            new:         false,
+            implausible: false,
+            space:       None,
+            kind:        DatasetType::Filesystem,
+            encryption:  None,
         }
     }
 
+    /// The key used to match up snapshots of the same dataset.
+    ///
+    /// Objset IDs are only unique within a pool.
+    fn key(&self) -> (String, u64) {
+        let pool = self.name.split('/').next().unwrap_or_default();
+        (pool.to_owned(), self.objset)
+    }
+
+    /// Files that have been unlinked but not yet deleted
+    fn pending_d(&self) -> u64 {
+        self.nunlinks.saturating_sub(self.nunlinked)
+    }
+
+    /// Total bytes read, written, and unlinked since the pool was imported
+    fn total(&self) -> u64 {
+        self.nread + self.nwritten + self.nunlinked
+    }
+
     /// Iterate through ZFS datasets, returning stats for each.
     ///
     /// Iterates through every dataset beneath each of the given pools, or
@@ -89,298 +451,5442 @@ impl AddAssign<&Self> for Snapshot {
     }
 }
 
-#[derive(Default)]
-struct DataSource {
-    children: bool,
-    prev:     BTreeMap<String, Snapshot>,
-    prev_ts:  Option<TimeSpec>,
-    cur:      BTreeMap<String, Snapshot>,
-    cur_ts:   Option<TimeSpec>,
-    pools:    Vec<String>,
+/// The L2ARC's counters, from the global arcstats kstat.  Counters that this
+/// version of ZFS doesn't have are `None`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ArcStats {
+    /// Bytes of data cached in the L2ARC
+    l2_size:        Option<u64>,
+    l2_hits:        Option<u64>,
+    l2_misses:      Option<u64>,
+    l2_read_bytes:  Option<u64>,
+    l2_write_bytes: Option<u64>,
 }
 
-impl DataSource {
-    fn new(children: bool, pools: Vec<String>) -> Self {
-        DataSource {
-            children,
-            pools,
-            ..Default::default()
-        }
+/// The L2ARC's current size and activity
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct L2arcRates {
+    /// Bytes of data cached
+    pub size:    Option<u64>,
+    /// Hits per second
+    pub hits:    Option<f64>,
+    /// Misses per second
+    pub misses:  Option<f64>,
+    /// B/s read from the cache devices
+    pub read:    Option<f64>,
+    /// B/s written to the cache devices
+    pub written: Option<f64>,
+}
+
+/// The dbuf cache's counters, from the global dbufstats kstat
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DbufStats {
+    /// Bytes currently in the dbuf cache
+    size:   Option<u64>,
+    hits:   Option<u64>,
+    misses: Option<u64>,
+    evicts: Option<u64>,
+}
+
+/// The dbuf cache's activity, per second
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DbufRates {
+    /// Current size in bytes
+    pub size:   Option<u64>,
+    pub hits:   Option<f64>,
+    pub misses: Option<f64>,
+    pub evicts: Option<f64>,
+}
+
+/// The prefetcher's counters, from the global zfetchstats kstat
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ZfetchStats {
+    hits:        Option<u64>,
+    misses:      Option<u64>,
+    /// Times a new stream couldn't be created because all were in use
+    max_streams: Option<u64>,
+}
+
+/// The prefetcher's activity, per second
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ZfetchRates {
+    pub hits:        Option<f64>,
+    pub misses:      Option<f64>,
+    pub max_streams: Option<f64>,
+}
+
+/// Successive samples of some global counters, for computing their rates
+#[derive(Debug, Default)]
+pub struct Sampled<T> {
+    prev: Option<(Instant, T)>,
+    cur:  Option<(Instant, T)>,
+}
+
+impl<T: Copy> Sampled<T> {
+    fn push(&mut self, now: Instant, stats: T) {
+        self.prev = self.cur.replace((now, stats));
     }
 
-    /// Iterate through all the datasets, returning current stats
-    fn iter(&mut self) -> impl Iterator<Item = Element> + '_ {
-        let etime = if let Some(prev_ts) = self.prev_ts.as_ref() {
-            let delta = *self.cur_ts.as_ref().unwrap() - *prev_ts;
-            delta.tv_sec() as f64 + delta.tv_nsec() as f64 * 1e-9
-        } else {
-            let boottime = clock_gettime(CLOCK_UPTIME).unwrap();
-            boottime.tv_sec() as f64 + boottime.tv_nsec() as f64 * 1e-9
-        };
-        DataSourceIter {
-            inner_iter: self.cur.iter(),
-            ds: self,
-            etime,
-        }
+    /// The previous and current samples, and the seconds between them, if
+    /// there have been two
+    fn latest(&self) -> Option<(T, T, f64)> {
+        let ((t0, prev), (t1, cur)) = (self.prev?, self.cur?);
+        Some((prev, cur, (t1 - t0).as_secs_f64()))
     }
+}
 
-    /// Iterate over all of the names of parent datasets of the argument
-    fn with_parents(s: &str) -> impl Iterator<Item = &str> {
-        s.char_indices().filter_map(move |(idx, c)| {
-            if c == '/' {
-                Some(s.split_at(idx).0)
-            } else if idx == s.len() - 1 {
-                Some(s)
-            } else {
-                None
-            }
+/// Tracks the L2ARC's activity, for the L2ARC panel
+pub type L2arc = Sampled<ArcStats>;
+
+impl L2arc {
+    fn refresh(&mut self) -> Result<(), Box<dyn Error>> {
+        let stats = arcstats()?;
+        self.push(Instant::now(), stats);
+        Ok(())
+    }
+
+    /// Rates since the previous refresh
+    pub fn rates(&self) -> Option<L2arcRates> {
+        let (prev, cur, etime) = self.latest()?;
+        let rate = |c: Option<u64>, p: Option<u64>| {
+            Some(c?.saturating_sub(p?) as f64 / etime)
+        };
+        Some(L2arcRates {
+            size:    cur.l2_size,
+            hits:    rate(cur.l2_hits, prev.l2_hits),
+            misses:  rate(cur.l2_misses, prev.l2_misses),
+            read:    rate(cur.l2_read_bytes, prev.l2_read_bytes),
+            written: rate(cur.l2_write_bytes, prev.l2_write_bytes),
         })
     }
+}
+
+/// Tracks the dbuf cache's activity, for the dbuf panel
+pub type Dbuf = Sampled<DbufStats>;
 
+impl Dbuf {
     fn refresh(&mut self) -> Result<(), Box<dyn Error>> {
-        let now = clock_gettime(ClockId::CLOCK_MONOTONIC)?;
-        self.prev = mem::take(&mut self.cur);
-        self.prev_ts = self.cur_ts.replace(now);
-        if self.pools.is_empty() {
-            for rss in Snapshot::iter(None).unwrap() {
-                let ss = rss?;
-                Self::upsert(&mut self.cur, ss, self.children);
-            }
-        } else {
-            for pool in self.pools.iter() {
-                for rss in Snapshot::iter(Some(pool)).unwrap() {
-                    let ss = rss?;
-                    Self::upsert(&mut self.cur, ss, self.children);
-                }
-            }
-        }
+        let stats = dbufstats()?;
+        self.push(Instant::now(), stats);
         Ok(())
     }
 
-    fn toggle_children(&mut self) -> Result<(), Box<dyn Error>> {
-        self.children ^= true;
-        // Wipe out previous statistics.  The next refresh will report stats
-        // since boot.
-        self.refresh()?;
-        mem::take(&mut self.prev);
-        self.prev_ts = None;
+    /// Rates since the previous refresh
+    pub fn rates(&self) -> Option<DbufRates> {
+        let (prev, cur, etime) = self.latest()?;
+        let rate = |c: Option<u64>, p: Option<u64>| {
+            Some(c?.saturating_sub(p?) as f64 / etime)
+        };
+        Some(DbufRates {
+            size:   cur.size,
+            hits:   rate(cur.hits, prev.hits),
+            misses: rate(cur.misses, prev.misses),
+            evicts: rate(cur.evicts, prev.evicts),
+        })
+    }
+}
+
+/// Tracks the prefetcher's activity, for the prefetch panel
+pub type Zfetch = Sampled<ZfetchStats>;
+
+impl Zfetch {
+    fn refresh(&mut self) -> Result<(), Box<dyn Error>> {
+        let stats = zfetchstats()?;
+        self.push(Instant::now(), stats);
         Ok(())
     }
 
-    /// Insert a snapshot into `cur`, and/or update it and its parents
-    fn upsert(
-        cur: &mut BTreeMap<String, Snapshot>,
-        ss: Snapshot,
-        children: bool,
-    ) {
-        if children {
-            for dsname in Self::with_parents(&ss.name) {
-                match cur.entry(dsname.to_string()) {
-                    btree_map::Entry::Vacant(ve) => {
-                        if ss.name == dsname {
-                            ve.insert(ss.clone());
-                        } else {
-                            let mut parent_ss = ss.clone();
-                            parent_ss.name = dsname.to_string();
-                            ve.insert(parent_ss);
-                        }
-                    }
-                    btree_map::Entry::Occupied(mut oe) => {
-                        *oe.get_mut() += &ss;
-                    }
-                }
-            }
-        } else {
-            match cur.entry(ss.name.clone()) {
-                btree_map::Entry::Vacant(ve) => {
-                    ve.insert(ss);
-                }
-                btree_map::Entry::Occupied(mut oe) => {
-                    *oe.get_mut() += &ss;
-                }
-            }
+    /// Rates since the previous refresh
+    pub fn rates(&self) -> Option<ZfetchRates> {
+        let (prev, cur, etime) = self.latest()?;
+        let rate = |c: Option<u64>, p: Option<u64>| {
+            Some(c?.saturating_sub(p?) as f64 / etime)
         };
+        Some(ZfetchRates {
+            hits:        rate(cur.hits, prev.hits),
+            misses:      rate(cur.misses, prev.misses),
+            max_streams: rate(cur.max_streams, prev.max_streams),
+        })
     }
 }
 
-struct DataSourceIter<'a> {
-    inner_iter: btree_map::Iter<'a, String, Snapshot>,
-    ds:         &'a DataSource,
-    etime:      f64,
+/// Timings of a pool's most recently committed transaction group
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TxgStats {
+    pub txg:      u64,
+    /// Bytes of dirty data
+    pub ndirty:   u64,
+    pub nwritten: u64,
+    /// How long the txg was open
+    pub otime:    Duration,
+    /// How long it took to quiesce
+    pub qtime:    Duration,
+    /// How long it waited to start syncing
+    pub wtime:    Duration,
+    /// How long it took to sync
+    pub stime:    Duration,
 }
 
-impl Iterator for DataSourceIter<'_> {
-    type Item = Element;
+/// Find the most recently committed txg in a pool's `txgs` kstat.  Rows are
+/// oldest first, and the newest few are still open or syncing.
+fn parse_txgs(text: &str) -> Option<TxgStats> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let start = lines.iter().position(|l| l.starts_with("txg "))?;
+    let header = lines[start].split_whitespace().collect::<Vec<_>>();
+    let col = |name: &str| header.iter().position(|h| *h == name);
+    let (txg, state, ndirty, nwritten) =
+        (col("txg")?, col("state")?, col("ndirty")?, col("nwritten")?);
+    let (otime, qtime, wtime, stime) =
+        (col("otime")?, col("qtime")?, col("wtime")?, col("stime")?);
+    lines[start + 1..].iter().rev().find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.get(state) != Some(&"C") {
+            return None;
+        }
+        let num = |i: usize| fields.get(i)?.parse::<u64>().ok();
+        Some(TxgStats {
+            txg:      num(txg)?,
+            ndirty:   num(ndirty)?,
+            nwritten: num(nwritten)?,
+            otime:    Duration::from_nanos(num(otime)?),
+            qtime:    Duration::from_nanos(num(qtime)?),
+            wtime:    Duration::from_nanos(num(wtime)?),
+            stime:    Duration::from_nanos(num(stime)?),
+        })
+    })
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner_iter
-            .next()
-            .map(|(_, ss)| ss.compute(self.ds.prev.get(&ss.name), self.etime))
+/// One pool's space usage, from `zpool list`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PoolCapacity {
+    pub name:  String,
+    /// Total, allocated, and free bytes
+    pub size:  u64,
+    pub alloc: u64,
+    pub free:  u64,
+    /// Percentage of the pool that is allocated
+    pub cap:   u64,
+    /// Percentage of free space that is fragmented, if known
+    pub frag:  Option<u64>,
+}
+
+/// Parse the output of `zpool list -Hp -o name,size,alloc,free,cap,frag`
+fn parse_capacity(text: &str) -> Result<Vec<PoolCapacity>, String> {
+    text.lines()
+        .map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let num = |i: usize| fields.get(i)?.parse::<u64>().ok();
+            let bad = || format!("Unexpected output from zpool list: {line:?}");
+            Ok(PoolCapacity {
+                name:  fields[0].to_owned(),
+                size:  num(1).ok_or_else(bad)?,
+                alloc: num(2).ok_or_else(bad)?,
+                free:  num(3).ok_or_else(bad)?,
+                cap:   num(4).ok_or_else(bad)?,
+                frag:  num(5),
+            })
+        })
+        .collect()
+}
+
+/// Get the space usage of `pools`, or of every pool if empty
+fn pool_capacity(
+    pools: &[String],
+) -> Result<Vec<PoolCapacity>, Box<dyn Error>> {
+    let output = exec::command("zpool")?
+        .args(["list", "-Hp", "-o", "name,size,alloc,free,cap,frag"])
+        .args(pools)
+        .output()
+        .map_err(|e| format!("Cannot run zpool list: {e}"))?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("zpool list: {}", msg.trim()).into());
     }
+    Ok(parse_capacity(&String::from_utf8_lossy(&output.stdout))?)
 }
 
-/// One thing to display in the table
-#[derive(Clone, Debug)]
-pub struct Element {
-    pub name:  String,
-    /// Read IOPs
-    pub ops_r: f64,
-    /// Read B/s
-    pub r_s:   f64,
-    /// Delete IOPs
-    pub ops_d: f64,
-    /// Delete B/s
-    pub d_s:   f64,
-    /// Write IOPs
-    pub ops_w: f64,
-    /// Write B/s
-    pub w_s:   f64,
+/// One pool's dedup table, from `zpool status -D` and `zpool list`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolDedup {
+    pub name:    String,
+    /// Number of entries in the dedup table
+    pub entries: u64,
+    /// Total size of the table on disk and in memory
+    pub disk:    u64,
+    pub core:    u64,
+    /// How much the pool's data is reduced by dedup, if known
+    pub ratio:   Option<f64>,
 }
 
-#[derive(Default)]
-pub struct App {
-    auto:        bool,
-    data:        DataSource,
-    depth:       Option<NonZeroUsize>,
-    filter:      Option<Regex>,
-    reverse:     bool,
-    should_quit: bool,
-    /// 0-based index of the column to sort by, if any
-    sort_idx:    Option<usize>,
+/// Parse the output of `zpool status -Dp` and of
+/// `zpool list -Hp -o name,dedupratio`.  Pools without a dedup table have no
+/// entries.
+fn parse_dedup(status: &str, list: &str) -> Result<Vec<PoolDedup>, String> {
+    let mut pools = list
+        .lines()
+        .map(|line| {
+            let (name, ratio) = line.split_once('\t').ok_or_else(|| {
+                format!("Unexpected output from zpool list: {line:?}")
+            })?;
+            Ok(PoolDedup {
+                name: name.to_owned(),
+                ratio: ratio.trim_end_matches('x').parse().ok(),
+                ..Default::default()
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut pool = None;
+    for line in status.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix("pool: ") {
+            pool = pools.iter_mut().find(|p| p.name == name);
+        } else if let Some(ddt) = line.strip_prefix("dedup: DDT entries ") {
+            let (entries, disk, core) = parse_ddt(ddt).ok_or_else(|| {
+                format!("Unexpected output from zpool status: {line:?}")
+            })?;
+            if let Some(p) = pool.as_deref_mut() {
+                p.entries = entries;
+                p.disk = entries.saturating_mul(disk);
+                p.core = entries.saturating_mul(core);
+            }
+        }
+    }
+    Ok(pools)
 }
 
-impl App {
-    pub fn new(
-        auto: bool,
-        children: bool,
-        pools: Vec<String>,
-        depth: Option<NonZeroUsize>,
-        filter: Option<Regex>,
-        reverse: bool,
-        sort_idx: Option<usize>,
-    ) -> Self {
-        let mut data = DataSource::new(children, pools);
-        data.refresh().unwrap();
-        App {
-            auto,
-            data,
-            depth,
-            filter,
-            reverse,
-            sort_idx,
-            ..Default::default()
+/// Parse the rest of a `zpool status -D` line like "5, size 320 on disk, 192
+/// in core", where the sizes are those of an average entry
+fn parse_ddt(ddt: &str) -> Option<(u64, u64, u64)> {
+    let [entries, disk, core] = ddt.split(", ").collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let disk = disk.strip_prefix("size ")?.strip_suffix(" on disk")?;
+    let core = core.strip_suffix(" in core")?;
+    Some((
+        entries.parse().ok()?,
+        disk.parse().ok()?,
+        core.parse().ok()?,
+    ))
+}
+
+/// Get the dedup tables of `pools`, or of every pool if empty
+fn pool_dedup(pools: &[String]) -> Result<Vec<PoolDedup>, Box<dyn Error>> {
+    let run = |args: &[&str]| -> Result<String, Box<dyn Error>> {
+        let output = exec::command("zpool")?
+            .args(args)
+            .args(pools)
+            .output()
+            .map_err(|e| format!("Cannot run zpool {}: {e}", args[0]))?;
+        if !output.status.success() {
+            let msg = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("zpool {}: {}", args[0], msg.trim()).into());
         }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let list = run(&["list", "-Hp", "-o", "name,dedupratio"])?;
+    let status = run(&["status", "-Dp"])?;
+    Ok(parse_dedup(&status, &list)?)
+}
+
+/// One dataset's space usage, from `zfs list`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Space {
+    /// Bytes used by the dataset and its descendants
+    pub used:  u64,
+    /// Bytes available to the dataset
+    pub avail: u64,
+    /// Bytes referenced by the dataset itself
+    pub refer: u64,
+}
+
+/// Parse the output of `zfs list -Hp -o name,used,avail,refer`
+fn parse_space(text: &str) -> Result<HashMap<String, Space>, String> {
+    text.lines()
+        .map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let num = |i: usize| fields.get(i)?.parse::<u64>().ok();
+            let bad = || format!("Unexpected output from zfs list: {line:?}");
+            let space = Space {
+                used:  num(1).ok_or_else(bad)?,
+                avail: num(2).ok_or_else(bad)?,
+                refer: num(3).ok_or_else(bad)?,
+            };
+            Ok((fields[0].to_owned(), space))
+        })
+        .collect()
+}
+
+/// Get the space usage of every filesystem and volume in `pools`, or in
+/// every pool if empty
+fn dataset_space(
+    pools: &[String],
+) -> Result<HashMap<String, Space>, Box<dyn Error>> {
+    let mut cmd = exec::command("zfs")?;
+    cmd.args(["list", "-Hp", "-t", "filesystem,volume"])
+        .args(["-o", "name,used,avail,refer"]);
+    if !pools.is_empty() {
+        cmd.arg("-r").args(pools);
     }
+    let output = cmd
+        .output()
+        .map_err(|e| format!("Cannot run zfs list: {e}"))?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("zfs list: {}", msg.trim()).into());
+    }
+    Ok(parse_space(&String::from_utf8_lossy(&output.stdout))?)
+}
 
-    pub fn clear_filter(&mut self) {
-        self.filter = None;
+/// Get the name of every filesystem and volume in `pools`, or in every pool
+/// if empty, that `zfs list` shows.  In a jail, a zone, or a user namespace,
+/// those are the delegated datasets and their ancestors.
+fn visible_datasets(
+    pools: &[String],
+) -> Result<HashSet<String>, Box<dyn Error>> {
+    let mut cmd = exec::command("zfs")?;
+    cmd.args(["list", "-H", "-t", "filesystem,volume", "-o", "name"]);
+    if !pools.is_empty() {
+        cmd.arg("-r").args(pools);
     }
+    let output = cmd
+        .output()
+        .map_err(|e| format!("Cannot run zfs list: {e}"))?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("zfs list: {}", msg.trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_owned)
+        .collect())
+}
 
-    /// Return the elements that should be displayed, in order
-    #[rustfmt::skip]
-    pub fn elements(&mut self) -> Vec<Element> {
-        let auto = self.auto;
-        let depth = self.depth;
-        let filter = &self.filter;
-        let mut v = self.data.iter()
-            .filter(move |elem| {
-                if let Some(limit) = depth {
-                    let edepth = elem.name.split('/').count();
-                    edepth <= limit.get()
-                } else {
-                    true
-                }
-            }).filter(|elem|
-                 filter.as_ref()
-                 .map(|f| f.is_match(&elem.name))
-                 .unwrap_or(true)
-            ).filter(|elem| !auto || (elem.r_s + elem.w_s + elem.d_s > 1.0))
-            .collect::<Vec<_>>();
-        match (self.reverse, self.sort_idx) {
-            (false, Some(0)) => v.sort_by(|x, y| x.ops_r.total_cmp(&y.ops_r)),
-            (true,  Some(0)) => v.sort_by(|x, y| y.ops_r.total_cmp(&x.ops_r)),
-            (false, Some(1)) => v.sort_by(|x, y| x.r_s.total_cmp(&y.r_s)),
-            (true,  Some(1)) => v.sort_by(|x, y| y.r_s.total_cmp(&x.r_s)),
-            (false, Some(2)) => v.sort_by(|x, y| x.ops_w.total_cmp(&y.ops_w)),
-            (true,  Some(2)) => v.sort_by(|x, y| y.ops_w.total_cmp(&x.ops_w)),
-            (false, Some(3)) => v.sort_by(|x, y| x.w_s.total_cmp(&y.w_s)),
-            (true,  Some(3)) => v.sort_by(|x, y| y.w_s.total_cmp(&x.w_s)),
-            (false, Some(4)) => v.sort_by(|x, y| x.ops_d.total_cmp(&y.ops_d)),
-            (true,  Some(4)) => v.sort_by(|x, y| y.ops_d.total_cmp(&x.ops_d)),
-            (false, Some(5)) => v.sort_by(|x, y| x.d_s.total_cmp(&y.d_s)),
-            (true,  Some(5)) => v.sort_by(|x, y| y.d_s.total_cmp(&x.d_s)),
-            (false, Some(6)) => v.sort_by(|x, y| x.name.cmp(&y.name)),
-            (true,  Some(6)) => v.sort_by(|x, y| y.name.cmp(&x.name)),
-            _ => ()
+/// A command that runs on its own thread, so that a slow pool can't stall
+/// the display
+#[derive(Debug)]
+pub(crate) struct Job<T>(Option<JoinHandle<Result<T, String>>>);
+
+impl<T: Send + 'static> Job<T> {
+    /// Start running `f`, unless the previous run is still going
+    pub(crate) fn start<F>(&mut self, f: F)
+    where
+        F: FnOnce() -> Result<T, String> + Send + 'static,
+    {
+        if self.0.is_none() {
+            self.0 = Some(thread::spawn(f));
         }
-        v
     }
 
-    pub fn on_a(&mut self) {
-        self.auto ^= true;
+    pub(crate) fn is_running(&self) -> bool {
+        self.0.is_some()
     }
 
-    pub fn on_c(&mut self) -> Result<(), Box<dyn Error>> {
-        self.data.toggle_children()
+    /// Take the command's result, if it has finished
+    pub(crate) fn poll(&mut self) -> Option<Result<T, String>> {
+        if !self.0.as_ref().is_some_and(JoinHandle::is_finished) {
+            return None;
+        }
+        Some(self.0.take().unwrap().join().unwrap())
     }
+}
 
-    pub fn on_d(&mut self, more_depth: bool) {
-        self.depth = if more_depth {
-            match self.depth {
-                None => NonZeroUsize::new(1),
-                Some(x) => NonZeroUsize::new(x.get() + 1),
-            }
+impl<T> Default for Job<T> {
+    fn default() -> Self {
+        Job(None)
+    }
+}
+
+/// A dataset's encryption status, from `zfs get`
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Encryption {
+    Off,
+    /// Encrypted, but its key isn't loaded
+    Locked,
+    /// A file system whose key is loaded, but that isn't mounted
+    Unmounted,
+    /// Its key is loaded, and it's mounted if it's a file system
+    Unlocked,
+}
+
+impl Encryption {
+    /// The abbreviation shown in the Enc column
+    pub fn abbrev(self) -> &'static str {
+        match self {
+            Encryption::Off => "no",
+            Encryption::Locked => "lock",
+            Encryption::Unmounted => "key",
+            Encryption::Unlocked => "yes",
+        }
+    }
+}
+
+/// Which datasets to show, by their encryption status
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EncryptionFilter {
+    /// Every encrypted dataset
+    Encrypted,
+    /// Encrypted datasets whose keys are loaded, and that are mounted if
+    /// they're file systems
+    Unlocked,
+}
+
+impl EncryptionFilter {
+    fn matches(self, enc: Encryption) -> bool {
+        match self {
+            EncryptionFilter::Encrypted => enc != Encryption::Off,
+            EncryptionFilter::Unlocked => enc == Encryption::Unlocked,
+        }
+    }
+}
+
+impl fmt::Display for EncryptionFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            EncryptionFilter::Encrypted => "encrypted",
+            EncryptionFilter::Unlocked => "unlocked",
+        })
+    }
+}
+
+impl FromStr for EncryptionFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "encrypted" => Ok(EncryptionFilter::Encrypted),
+            "unlocked" => Ok(EncryptionFilter::Unlocked),
+            _ => Err(format!("unknown encryption filter {s:?}")),
+        }
+    }
+}
+
+/// Parse the output of
+/// `zfs get -Hp -o name,property,value encryption,keystatus,mounted`
+fn parse_encryption(text: &str) -> Result<HashMap<String, Encryption>, String> {
+    let mut props = BTreeMap::<&str, HashMap<&str, &str>>::new();
+    for line in text.lines() {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let [name, prop, value] = fields[..] else {
+            return Err(format!("Unexpected output from zfs get: {line:?}"));
+        };
+        props.entry(name).or_default().insert(prop, value);
+    }
+    Ok(props
+        .into_iter()
+        .map(|(name, props)| {
+            let prop = |p: &str| props.get(p).copied().unwrap_or("-");
+            let enc = if prop("encryption") == "off" {
+                Encryption::Off
+            } else if prop("keystatus") != "available" {
+                Encryption::Locked
+            } else if prop("mounted") == "no" {
+                // Volumes are never mounted, and their value is "-"
+                Encryption::Unmounted
+            } else {
+                Encryption::Unlocked
+            };
+            (name.to_owned(), enc)
+        })
+        .collect())
+}
+
+/// Get the encryption status of every filesystem and volume in `pools`, or
+/// in every pool if empty
+fn dataset_encryption(
+    pools: &[String],
+) -> Result<HashMap<String, Encryption>, Box<dyn Error>> {
+    let mut cmd = exec::command("zfs")?;
+    cmd.args(["get", "-Hp", "-t", "filesystem,volume"])
+        .args(["-o", "name,property,value"])
+        .arg("encryption,keystatus,mounted");
+    if !pools.is_empty() {
+        cmd.arg("-r").args(pools);
+    }
+    let output = cmd
+        .output()
+        .map_err(|e| format!("Cannot run zfs get: {e}"))?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("zfs get: {}", msg.trim()).into());
+    }
+    Ok(parse_encryption(&String::from_utf8_lossy(&output.stdout))?)
+}
+
+/// One of a dataset's properties, from `zfs get`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Property {
+    pub name:   String,
+    pub value:  String,
+    /// Where the value came from, like "local" or "default"
+    pub source: String,
+}
+
+/// Parse the output of `zfs get -H -o property,value,source`
+fn parse_properties(text: &str) -> Result<Vec<Property>, String> {
+    text.lines()
+        .map(|line| {
+            let mut fields = line.split('\t');
+            let mut field = || {
+                fields.next().map(str::to_owned).ok_or_else(|| {
+                    format!("Unexpected output from zfs get: {line:?}")
+                })
+            };
+            Ok(Property {
+                name:   field()?,
+                value:  field()?,
+                source: field()?,
+            })
+        })
+        .collect()
+}
+
+/// Get all of a dataset's properties
+pub fn dataset_properties(
+    dataset: &str,
+) -> Result<Vec<Property>, Box<dyn Error>> {
+    let output = exec::command("zfs")?
+        .args(["get", "-H", "-o", "property,value,source", "all"])
+        .arg(dataset)
+        .output()
+        .map_err(|e| format!("Cannot run zfs get: {e}"))?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("zfs get: {}", msg.trim()).into());
+    }
+    Ok(parse_properties(&String::from_utf8_lossy(&output.stdout))?)
+}
+
+/// The raw counters from one refresh, saved so that a restarted
+/// `ztop-agent` can compute its first rates over the time it was down
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct SavedCounters {
+    /// When the counters were read, in seconds on the monotonic clock
+    time:     f64,
+    /// When the system booted, in seconds since the epoch, to tell whether
+    /// it rebooted since.  It's estimated from the difference between the
+    /// clocks, so it may wander a little.
+    boot:     f64,
+    datasets: Vec<Snapshot>,
+}
+
+impl SavedCounters {
+    /// Load counters saved by [`SavedCounters::save`].  `None` if there are
+    /// none.
+    pub fn load(path: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(s) => serde_json::from_str(&s).map(Some).map_err(|e| {
+                format!("Invalid state file {}: {e}", path.display()).into()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                Err(format!("Cannot read {}: {e}", path.display()).into())
+            }
+        }
+    }
+
+    /// Save the counters, replacing any saved before
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)
+            .and_then(|()| fs::rename(&tmp, path))
+            .map_err(|e| format!("Cannot save {}: {e}", path.display()).into())
+    }
+}
+
+/// Seconds since an arbitrary point
+fn secs(ts: TimeSpec) -> f64 {
+    ts.tv_sec() as f64 + ts.tv_nsec() as f64 * 1e-9
+}
+
+/// When the system booted, in seconds since the epoch
+fn boot_time() -> Result<f64, Box<dyn Error>> {
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC)?;
+    let wall = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    Ok(wall.as_secs_f64() - secs(now))
+}
+
+/// How far apart two estimates of the boot time may be and still be taken
+/// for the same boot
+const BOOT_SLOP: f64 = 60.0;
+
+/// Where to collect statistics from
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Source {
+    /// Each dataset's kstats
+    #[default]
+    Kstat,
+    /// `zpool iostat`, for where the kstats can't be read.  It only reports
+    /// whole pools.
+    Exec,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Source::Kstat => "kstat",
+            Source::Exec => "exec",
+        })
+    }
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kstat" => Ok(Source::Kstat),
+            "exec" => Ok(Source::Exec),
+            _ => Err(format!("unknown source {s:?}")),
+        }
+    }
+}
+
+/// List every imported pool with `zpool list`, for when their kstats can't
+/// be
+fn exec_pools() -> Result<Vec<String>, Box<dyn Error>> {
+    let output = exec::command("zpool")?
+        .args(["list", "-H", "-o", "name"])
+        .output()
+        .map_err(|e| format!("Cannot run zpool list: {e}"))?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("zpool list: {}", msg.trim()).into());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().map(str::to_owned).collect())
+}
+
+/// Collects statistics for every dataset of interest
+#[derive(Default)]
+pub struct DataSource {
+    children:      bool,
+    /// The previous snapshots, keyed by pool and objset ID
+    prev:          HashMap<(String, u64), Snapshot>,
+    prev_ts:       Option<TimeSpec>,
+    cur:           BTreeMap<String, Snapshot>,
+    cur_ts:        Option<TimeSpec>,
+    pools:         Vec<String>,
+    /// Datasets matching any of these patterns are never collected
+    ignore:        Vec<Pattern>,
+    /// If not empty, collect only these objsets, by pool and ID, regardless
+    /// of their names.  IDs are only unique within a pool.
+    objsets:       Vec<(String, u64)>,
+    /// Pools whose statistics could not be collected by the last refresh
+    stale:         HashSet<String>,
+    /// The pools that the last refresh tried to collect
+    collected:     Vec<String>,
+    /// Why those pools could not be collected
+    failures:      Vec<String>,
+    /// Largest plausible read, write, or delete bandwidth for each pool, in
+    /// B/s
+    max_bandwidth: HashMap<String, f64>,
+    /// Datasets whose latest rates exceeded their pool's maximum bandwidth
+    implausible:   HashSet<(String, u64)>,
+    /// Datasets that have been warned about for implausible rates, so each
+    /// is only warned about once
+    warned:        HashSet<(String, u64)>,
+    /// Datasets that appeared recently, and for how many more refreshes they
+    /// should be marked as new
+    created:       HashMap<(String, u64), u32>,
+    /// Include mounted snapshots
+    snapshots:     bool,
+    /// Where to collect statistics from
+    source:        Source,
+    /// Each pool's counters, with `--source exec`
+    pool_iostat:   Option<Pools>,
+}
+
+impl DataSource {
+    pub fn new(
+        children: bool,
+        pools: Vec<String>,
+        ignore: Vec<Pattern>,
+    ) -> Self {
+        DataSource {
+            children,
+            pools,
+            ignore,
+            ..Default::default()
+        }
+    }
+
+    /// Collect from different pools, starting over as if newly created
+    fn set_pools(&mut self, pools: Vec<String>) {
+        *self = DataSource {
+            pools,
+            ..self.settings()
+        };
+    }
+
+    /// A new source with the same settings, but no statistics yet
+    fn settings(&self) -> Self {
+        DataSource {
+            children: self.children,
+            pools: self.pools.clone(),
+            ignore: self.ignore.clone(),
+            max_bandwidth: self.max_bandwidth.clone(),
+            snapshots: self.snapshots,
+            source: self.source,
+            ..Default::default()
+        }
+    }
+
+    /// Collect statistics from `source` from the next refresh on
+    pub fn set_source(&mut self, source: Source) {
+        self.source = source;
+        self.pool_iostat = None;
+    }
+
+    /// Collect only the given objsets, by pool and ID, for debugging
+    pub fn with_objsets(objsets: Vec<(String, u64)>) -> Self {
+        let mut pools = objsets
+            .iter()
+            .map(|(pool, _)| pool.clone())
+            .collect::<Vec<_>>();
+        pools.sort();
+        pools.dedup();
+        DataSource {
+            pools,
+            objsets,
+            ..Default::default()
+        }
+    }
+
+    /// The pools whose statistics are visible
+    pub fn available_pools() -> Result<Vec<String>, Box<dyn Error>> {
+        pools()
+    }
+
+    /// Check that every requested pool exists.
+    ///
+    /// If any don't, return an error that suggests the closest match and
+    /// lists the pools that do exist.
+    pub fn check_pools(requested: &[String]) -> Result<(), Box<dyn Error>> {
+        if requested.is_empty() {
+            return Ok(());
+        }
+        let available = pools()?;
+        let mut msgs = Vec::new();
+        for pool in requested.iter().filter(|p| !available.contains(p)) {
+            msgs.push(Self::missing_pool_message(pool, &available));
+        }
+        if msgs.is_empty() {
+            Ok(())
         } else {
-            match self.depth {
-                None => None,
-                Some(x) => NonZeroUsize::new(x.get() - 1),
+            if available.is_empty() {
+                msgs.push("No pools found; ZFS module not loaded?".to_owned());
+            } else {
+                msgs.push(format!("Available pools: {}", available.join(", ")));
             }
+            Err(msgs.join("\n").into())
         }
     }
 
-    pub fn on_minus(&mut self) {
-        self.sort_idx = match self.sort_idx {
-            Some(0) => None,
-            Some(old) => Some(old - 1),
-            None => Some(6),
+    /// Explain why a requested pool wasn't found
+    fn missing_pool_message(pool: &str, available: &[String]) -> String {
+        if let Some((parent, _)) = pool.split_once('/') {
+            return format!(
+                "{pool:?} is a dataset, not a pool.  Use the pool name \
+                 {parent:?}, and -f to display only some of its datasets."
+            );
+        }
+        let closest = available
+            .iter()
+            .map(|a| (edit_distance(pool, a), a))
+            .min()
+            .filter(|(dist, _)| *dist <= pool.chars().count() / 3 + 1);
+        match closest {
+            Some((_, a)) => format!(
+                "Statistics not found for pool {pool:?}.  Did you mean {a:?}?"
+            ),
+            None => format!("Statistics not found for pool {pool:?}."),
         }
     }
 
-    pub fn on_plus(&mut self) {
-        self.sort_idx = match self.sort_idx {
-            Some(old) if old >= 6 => None,
-            Some(old) => Some(old + 1),
-            None => Some(0),
+    /// Iterate through all the datasets, returning current stats
+    pub fn iter(&mut self) -> Box<dyn Iterator<Item = Element> + '_> {
+        let etime = if let Some(prev_ts) = self.prev_ts.as_ref() {
+            let delta = *self.cur_ts.as_ref().unwrap() - *prev_ts;
+            delta.tv_sec() as f64 + delta.tv_nsec() as f64 * 1e-9
+        } else {
+            let boottime = clock_gettime(CLOCK_UPTIME).unwrap();
+            boottime.tv_sec() as f64 + boottime.tv_nsec() as f64 * 1e-9
+        };
+        let snapshots = self.snapshots;
+        let raw = DataSourceIter {
+            inner_iter: self.cur.iter(),
+            ds: self,
+            etime,
+        }
+        .filter(move |elem| snapshots || !is_snapshot(&elem.name));
+        if self.children {
+            // Snapshots are always stored unaggregated, so that toggling
+            // children mode doesn't lose the previous sample.  Likewise ZFS
+            // snapshots are always collected, so that including them shows
+            // their rates right away.
+            Box::new(Self::aggregate(raw).into_values())
+        } else {
+            Box::new(raw)
         }
     }
 
-    pub fn on_q(&mut self) {
-        self.should_quit = true;
+    /// In children mode, return the unaggregated statistics of every dataset
+    /// that has children.
+    fn self_rows(&mut self) -> Vec<Element> {
+        let children = mem::replace(&mut self.children, false);
+        let raw = self.iter().collect::<Vec<_>>();
+        self.children = children;
+        let parents = raw
+            .iter()
+            .flat_map(|elem| {
+                Self::with_parents(&elem.name)
+                    .filter(|p| p.len() < elem.name.len())
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<HashSet<_>>();
+        raw.into_iter()
+            .filter(|elem| parents.contains(&elem.name))
+            .map(|elem| Element {
+                self_only: true,
+                ..elem
+            })
+            .collect()
     }
 
-    pub fn on_r(&mut self) {
-        self.reverse ^= true;
+    /// Add each dataset's statistics to all of its parents'
+    fn aggregate<I>(elems: I) -> BTreeMap<String, Element>
+    where
+        I: Iterator<Item = Element>,
+    {
+        let mut agg = BTreeMap::<String, Element>::new();
+        for elem in elems {
+            for dsname in Self::with_parents(&elem.name) {
+                match agg.entry(dsname.to_string()) {
+                    btree_map::Entry::Vacant(ve) => {
+                        let mut parent = elem.clone();
+                        if dsname != elem.name {
+                            parent.name = dsname.to_string();
+                            parent.objset = 0;
+                            parent.new = false;
+                        }
+                        ve.insert(parent);
+                    }
+                    btree_map::Entry::Occupied(mut oe) => {
+                        *oe.get_mut() += &elem;
+                    }
+                }
+            }
+        }
+        agg
     }
 
-    pub fn on_tick(&mut self) {
-        self.data.refresh().unwrap();
+    /// Iterate over all of the names of parent datasets of the argument
+    fn with_parents(s: &str) -> impl Iterator<Item = &str> {
+        s.char_indices().filter_map(move |(idx, c)| {
+            if c == '/' {
+                Some(s.split_at(idx).0)
+            } else if idx == s.len() - 1 {
+                Some(s)
+            } else {
+                None
+            }
+        })
     }
 
-    pub fn set_filter(&mut self, filter: Regex) {
-        self.filter = Some(filter);
+    /// If more than `max` datasets were found, return the greatest depth at
+    /// which no more than `max` would be displayed.  But never less than 1.
+    fn depth_limit(&self, max: usize) -> Option<NonZeroUsize> {
+        if self.cur.len() <= max {
+            return None;
+        }
+        let mut counts = BTreeMap::<usize, usize>::new();
+        for name in self.cur.keys() {
+            *counts.entry(name.split('/').count()).or_default() += 1;
+        }
+        let mut total = 0;
+        let mut limit = 1;
+        for (depth, count) in counts {
+            total += count;
+            if total > max {
+                break;
+            }
+            limit = depth;
+        }
+        NonZeroUsize::new(limit)
     }
 
-    pub fn should_quit(&self) -> bool {
-        self.should_quit
+    /// How many levels deep the deepest dataset is
+    fn max_depth(&self) -> usize {
+        self.cur
+            .keys()
+            .map(|name| name.split('/').count())
+            .max()
+            .unwrap_or(0)
     }
 
-    pub fn sort_idx(&self) -> Option<usize> {
-        self.sort_idx
+    /// The number of datasets found by the most recent refresh
+    pub fn ndatasets(&self) -> usize {
+        self.cur.len()
+    }
+
+    /// The counters that this kernel provides, or all of them if nothing has
+    /// been collected yet
+    pub fn counters(&self) -> Counters {
+        if self.cur.is_empty() {
+            return Counters::ALL;
+        }
+        self.cur
+            .values()
+            .fold(Counters::default(), |acc, ss| acc | ss.counters)
+    }
+
+    fn is_wanted(&self, ss: &Snapshot) -> bool {
+        if self.objsets.is_empty() {
+            !is_internal(&ss.name)
+                && !self.ignore.iter().any(|pat| pat.matches(&ss.name))
+        } else {
+            self.objsets.contains(&ss.key())
+        }
+    }
+
+    /// Describe the raw counters of every collected objset, and how much
+    /// each changed since the previous refresh.
+    pub fn raw_report(&self) -> Vec<String> {
+        self.cur
+            .values()
+            .map(|ss| {
+                let prev = self.prev.get(&ss.key());
+                let counters = [
+                    ("reads", ss.reads, prev.map(|p| p.reads)),
+                    ("nread", ss.nread, prev.map(|p| p.nread)),
+                    ("writes", ss.writes, prev.map(|p| p.writes)),
+                    ("nwritten", ss.nwritten, prev.map(|p| p.nwritten)),
+                    ("nunlinks", ss.nunlinks, prev.map(|p| p.nunlinks)),
+                    ("nunlinked", ss.nunlinked, prev.map(|p| p.nunlinked)),
+                ];
+                let mut line = format!("{:#x} {}", ss.objset, ss.name);
+                for (name, value, prev) in counters {
+                    let delta = match prev {
+                        Some(p) => format!("{:+}", value as i128 - p as i128),
+                        None => "-".to_owned(),
+                    };
+                    line.push_str(&format!("  {name} {value} ({delta})"));
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// Requested objsets that were not found by the most recent refresh
+    pub fn missing_objsets(&self) -> Vec<(String, u64)> {
+        self.objsets
+            .iter()
+            .filter(|key| !self.cur.values().any(|ss| ss.key() == **key))
+            .cloned()
+            .collect()
+    }
+
+    /// The pools that the last refresh tried to collect
+    pub fn collected_pools(&self) -> &[String] {
+        &self.collected
+    }
+
+    /// Collect new statistics.
+    ///
+    /// Each pool is collected separately.  If one fails, the others are
+    /// still collected, and the failed pool's last good statistics are kept
+    /// but marked as stale.  Those are never used to compute rates, since
+    /// that would attribute the whole outage's activity to one interval.
+    pub fn refresh(&mut self) -> Result<(), Box<dyn Error>> {
+        let pools = match (self.pools.is_empty(), self.source) {
+            (false, _) => self.pools.clone(),
+            (true, Source::Kstat) => pools()?,
+            (true, Source::Exec) => exec_pools()?,
+        };
+        if pools.is_empty() {
+            return Err("No pools found; ZFS module not loaded?".into());
+        }
+        if self.source == Source::Exec {
+            self.refresh_pool_iostat(&pools)?;
+        }
+        // After waiting for zpool iostat's first report, which the counters
+        // begin with
+        let now = clock_gettime(ClockId::CLOCK_MONOTONIC)?;
+        // Collect everything before touching the old data, so a failure
+        // leaves the last good sample intact.
+        let mut cur = BTreeMap::new();
+        self.failures.clear();
+        let was_stale = mem::take(&mut self.stale);
+        for pool in pools.iter().cloned() {
+            if let Err(e) = self.collect_pool(&pool, &mut cur) {
+                let old = self
+                    .cur
+                    .iter()
+                    .filter(|(name, _)| name.split('/').next() == Some(&pool));
+                for (name, ss) in old {
+                    cur.insert(name.clone(), ss.clone());
+                }
+                self.failures.push(format!("pool {pool}: {e}"));
+                self.stale.insert(pool);
+            }
+        }
+        self.advance(cur, &was_stale);
+        self.prev_ts = self.cur_ts.replace(now);
+        self.collected = pools;
+        self.find_implausible();
+        Ok(())
+    }
+
+    /// The latest raw counters, to resume from after a restart.  `None`
+    /// before the first refresh.
+    pub fn saved_counters(
+        &self,
+    ) -> Result<Option<SavedCounters>, Box<dyn Error>> {
+        let Some(cur_ts) = self.cur_ts else {
+            return Ok(None);
+        };
+        Ok(Some(SavedCounters {
+            time:     secs(cur_ts),
+            boot:     boot_time()?,
+            datasets: self.cur.values().cloned().collect(),
+        }))
+    }
+
+    /// Use counters saved by an earlier process as the previous sample, so
+    /// the current rates cover the time since then.  Only after one refresh,
+    /// and only if the system hasn't rebooted since they were saved.  Return
+    /// whether they were used.
+    pub fn resume(
+        &mut self,
+        saved: SavedCounters,
+    ) -> Result<bool, Box<dyn Error>> {
+        let Some(cur_ts) = self.cur_ts.filter(|_| self.prev_ts.is_none())
+        else {
+            return Ok(false);
+        };
+        if (saved.boot - boot_time()?).abs() > BOOT_SLOP
+            || saved.time >= secs(cur_ts)
+        {
+            return Ok(false);
+        }
+        self.prev = saved
+            .datasets
+            .into_iter()
+            .map(|ss| (ss.key(), ss))
+            .collect();
+        let d = Duration::from_secs_f64(saved.time);
+        self.prev_ts = Some(TimeSpec::from_duration(d));
+        self.find_implausible();
+        Ok(true)
+    }
+
+    /// Find datasets that seem to have read, written, or deleted faster than
+    /// their pool's hardware could go.  That's probably a counter anomaly.
+    fn find_implausible(&mut self) {
+        self.implausible.clear();
+        let (Some(prev_ts), Some(cur_ts)) = (self.prev_ts, self.cur_ts) else {
+            return;
+        };
+        let delta = cur_ts - prev_ts;
+        let etime = delta.tv_sec() as f64 + delta.tv_nsec() as f64 * 1e-9;
+        for ss in self.cur.values() {
+            let pool = ss.name.split('/').next().unwrap_or_default();
+            let Some(&max) = self.max_bandwidth.get(pool) else {
+                continue;
+            };
+            let Some(growth) =
+                self.prev.get(&ss.key()).and_then(|p| ss.growth(p))
+            else {
+                continue;
+            };
+            // nread, nwritten, and nunlinked
+            if [growth[1], growth[3], growth[5]]
+                .iter()
+                .any(|&bytes| bytes as f64 / etime > max)
+            {
+                if self.warned.insert(ss.key()) {
+                    warn(format!("Implausible rates for {}", ss.name));
+                }
+                self.implausible.insert(ss.key());
+            }
+        }
+    }
+
+    /// Replace the current sample with a new one, keeping the old one to
+    /// compute rates from, except for pools that were stale.
+    fn advance(
+        &mut self,
+        cur: BTreeMap<String, Snapshot>,
+        was_stale: &HashSet<String>,
+    ) {
+        self.track_created(&cur);
+        self.prev = mem::replace(&mut self.cur, cur)
+            .into_values()
+            .filter(|ss| !was_stale.contains(&ss.key().0))
+            .map(|ss| (ss.key(), ss))
+            .collect();
+    }
+
+    /// Note which datasets in a new sample weren't in the current one.
+    ///
+    /// Everything is new on the first refresh, so nothing is marked then.
+    fn track_created(&mut self, cur: &BTreeMap<String, Snapshot>) {
+        self.created.retain(|_, n| {
+            *n -= 1;
+            *n > 0
+        });
+        if self.cur_ts.is_none() {
+            return;
+        }
+        let old = self.cur.values().map(Snapshot::key).collect::<HashSet<_>>();
+        for key in cur.values().map(Snapshot::key) {
+            if !old.contains(&key) {
+                self.created.insert(key, NEW_REFRESHES);
+            }
+        }
+    }
+
+    /// Collect `zpool iostat`'s latest reports, starting it if need be.  The
+    /// first time, wait for its first report on every pool.
+    fn refresh_pool_iostat(
+        &mut self,
+        pools: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        let r = match self.pool_iostat.as_mut() {
+            Some(iostat) => iostat.refresh(),
+            None => {
+                let iostat = Pools::start(POOL_INTERVAL, self.pools.clone())?;
+                let iostat = self.pool_iostat.insert(iostat);
+                iostat.wait(POOL_INTERVAL * 20, |latest| {
+                    pools.iter().all(|p| latest.contains_key(p))
+                })
+            }
+        };
+        if r.is_err() {
+            // Start it again on the next refresh
+            self.pool_iostat = None;
+        }
+        Ok(r?)
+    }
+
+    /// Collect one pool's counters from `zpool iostat` into `cur`
+    fn collect_pool_iostat(
+        &self,
+        pool: &str,
+        cur: &mut BTreeMap<String, Snapshot>,
+    ) -> Result<(), Box<dyn Error>> {
+        let counts = self
+            .pool_iostat
+            .as_ref()
+            .and_then(|iostat| iostat.latest().get(pool))
+            .ok_or("not reported by zpool iostat")?;
+        let ss = Snapshot {
+            name: pool.to_owned(),
+            counters: Counters::READS
+                | Counters::NREAD
+                | Counters::WRITES
+                | Counters::NWRITTEN,
+            reads: counts.reads,
+            nread: counts.nread,
+            writes: counts.writes,
+            nwritten: counts.nwritten,
+            ..Default::default()
+        };
+        if self.is_wanted(&ss) {
+            cur.insert(ss.name.clone(), ss);
+        }
+        Ok(())
+    }
+
+    /// Collect one pool's statistics into `cur`.  On failure, add nothing.
+    ///
+    /// Datasets whose statistics can't be read because of permissions are
+    /// skipped, unless none of the pool's can be read.
+    fn collect_pool(
+        &self,
+        pool: &str,
+        cur: &mut BTreeMap<String, Snapshot>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.source == Source::Exec {
+            return self.collect_pool_iostat(pool, cur);
+        }
+        let mut pool_cur = BTreeMap::new();
+        let mut denied = None;
+        let mut readable = false;
+        for rss in Snapshot::iter(Some(pool))? {
+            let mut ss = match rss.map_err(Box::<dyn Error>::from) {
+                Ok(ss) => ss,
+                Err(e) if is_permission_denied(&*e) => {
+                    // Don't repeat it on every update
+                    warn_once(&e.to_string(), || {
+                        format!(
+                            "Cannot read some statistics for pool {pool}: {e}"
+                        )
+                    });
+                    denied = Some(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            readable = true;
+            if !normalize_name(&mut ss.name) {
+                warn(format!("Invalid dataset name {:?}", ss.name));
+            } else if self.is_wanted(&ss) {
+                Self::upsert(&mut pool_cur, ss);
+            }
+        }
+        if let (Some(e), false) = (denied, readable) {
+            return Err(format!("{e}.  {ACCESS_HINT}").into());
+        }
+        cur.append(&mut pool_cur);
+        Ok(())
+    }
+
+    /// Names of the datasets that first appeared during the most recent
+    /// refresh
+    pub fn created(&self) -> impl Iterator<Item = &str> {
+        self.cur
+            .values()
+            .filter(|ss| self.created.get(&ss.key()) == Some(&NEW_REFRESHES))
+            .map(|ss| ss.name.as_str())
+    }
+
+    /// Errors from the most recent refresh, for pools that could not be
+    /// collected
+    pub fn failures(&self) -> &[String] {
+        &self.failures
+    }
+
+    fn toggle_children(&mut self) {
+        self.children ^= true;
+    }
+
+    fn toggle_snapshots(&mut self) {
+        self.snapshots ^= true;
+    }
+
+    /// Insert a snapshot into `cur`, or combine it with an existing one
+    fn upsert(cur: &mut BTreeMap<String, Snapshot>, ss: Snapshot) {
+        match cur.entry(ss.name.clone()) {
+            btree_map::Entry::Vacant(ve) => {
+                ve.insert(ss);
+            }
+            btree_map::Entry::Occupied(mut oe) => {
+                *oe.get_mut() += &ss;
+            }
+        }
     }
 }
 
-#[cfg(test)]
-mod t {
+struct DataSourceIter<'a> {
+    inner_iter: btree_map::Iter<'a, String, Snapshot>,
+    ds:         &'a DataSource,
+    etime:      f64,
+}
+
+impl Iterator for DataSourceIter<'_> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner_iter.next().map(|(_, ss)| {
+            let new = self.ds.created.contains_key(&ss.key());
+            let growth = match self.ds.prev.get(&ss.key()) {
+                Some(prev) => ss.growth(prev),
+                // Everything since import, on the first refresh or for a
+                // dataset that was just created
+                None if self.ds.prev_ts.is_none() || new => Some(ss.counts()),
+                // The previous sample was unusable
+                None => None,
+            };
+            let mut elem = ss.compute(growth, self.etime);
+            let pool = ss.name.split('/').next().unwrap_or_default();
+            elem.stale = self.ds.stale.contains(pool);
+            elem.new = new;
+            elem.implausible = self.ds.implausible.contains(&ss.key());
+            elem
+        })
+    }
+}
+
+/// Which of each row's rates to display, when taking several samples per
+/// interval
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Statistic {
+    /// The rate over the whole interval, which is also the average of the
+    /// samples' rates
+    #[default]
+    Mean,
+    /// The lowest of the samples' rates
+    Min,
+    /// The highest of the samples' rates
+    Max,
+}
+
+impl fmt::Display for Statistic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Statistic::Mean => "mean",
+            Statistic::Min => "min",
+            Statistic::Max => "max",
+        })
+    }
+}
+
+impl FromStr for Statistic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Statistic::Mean),
+            "min" => Ok(Statistic::Min),
+            "max" => Ok(Statistic::Max),
+            _ => Err(format!("unknown statistic {s:?}")),
+        }
+    }
+}
+
+/// The lowest and highest of one row's rates over an interval's samples, in
+/// the order ops_r, r_s, ops_w, w_s, ops_d, d_s
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Extremes {
+    min: [f64; 6],
+    max: [f64; 6],
+}
+
+/// Reads the counters several times between refreshes, to find the extremes
+/// of each row's rates within the update interval
+#[derive(Default)]
+struct Sampler {
+    /// Samples per interval, counting the refresh that ends it
+    samples:  usize,
+    data:     DataSource,
+    /// The extremes so far this interval, by row name and whether it's a
+    /// self row
+    extremes: HashMap<(String, bool), Extremes>,
+    /// The extremes of the last complete interval
+    finished: HashMap<(String, bool), Extremes>,
+}
+
+impl Sampler {
+    fn new(samples: usize) -> Self {
+        Sampler {
+            samples,
+            ..Default::default()
+        }
+    }
+
+    /// Include the rates since the previous sample in the extremes
+    fn fold(&mut self) {
+        let mut rows = self.data.iter().collect::<Vec<_>>();
+        if self.data.children {
+            rows.extend(self.data.self_rows());
+        }
+        for elem in rows {
+            let rates = elem.rates();
+            self.extremes
+                .entry((elem.name, elem.self_only))
+                .and_modify(|x| {
+                    for (i, &rate) in rates.iter().enumerate() {
+                        x.min[i] = x.min[i].min(rate);
+                        x.max[i] = x.max[i].max(rate);
+                    }
+                })
+                .or_insert(Extremes {
+                    min: rates,
+                    max: rates,
+                });
+        }
+    }
+
+    /// Take a sample between refreshes of `data`
+    fn sample(&mut self, data: &DataSource) -> Result<(), Box<dyn Error>> {
+        if data.cur_ts.is_none() {
+            return Ok(());
+        }
+        if self.data.cur_ts.is_none() {
+            // Start from data's latest refresh
+            self.restart(data);
+        }
+        self.data.refresh()?;
+        self.fold();
+        Ok(())
+    }
+
+    /// Finish the interval that ended with `data`'s latest refresh, and
+    /// start the next one
+    fn finish(&mut self, data: &DataSource) {
+        if let (Some(_), Some(cur_ts)) = (self.data.cur_ts, data.cur_ts) {
+            self.data.advance(data.cur.clone(), &HashSet::new());
+            self.data.prev_ts = self.data.cur_ts.replace(cur_ts);
+            self.fold();
+            self.finished = mem::take(&mut self.extremes);
+        }
+        self.restart(data);
+    }
+
+    /// Take the next samples relative to `data`'s latest refresh, with its
+    /// settings
+    fn restart(&mut self, data: &DataSource) {
+        self.extremes.clear();
+        self.data = DataSource {
+            cur: data.cur.clone(),
+            cur_ts: data.cur_ts,
+            ..data.settings()
+        };
+    }
+}
+
+/// One thing to display in the table
+#[derive(Clone, Debug, Default)]
+pub struct Element {
+    pub name:        String,
+    /// Objset ID, or 0 for rows that combine several datasets' statistics
+    pub objset:      u64,
+    /// Read IOPs
+    pub ops_r:       f64,
+    /// Read B/s
+    pub r_s:         f64,
+    /// Delete IOPs
+    pub ops_d:       f64,
+    /// Delete B/s
+    pub d_s:         f64,
+    /// Write IOPs
+    pub ops_w:       f64,
+    /// Write B/s
+    pub w_s:         f64,
+    /// Files unlinked but not yet deleted
+    pub pending_d:   u64,
+    /// Bytes read since the pool was imported
+    pub nread:       u64,
+    /// Bytes written since the pool was imported
+    pub nwritten:    u64,
+    /// Bytes read, written, and unlinked since the pool was imported
+    pub total:       u64,
+    /// Read, write, and unlink operations since the pool was imported
+    pub nops:        u64,
+    /// In children mode, this row shows only the dataset's own statistics,
+    /// excluding its children's.
+    pub self_only:   bool,
+    /// This row's pool could not be collected, so its statistics are old
+    pub stale:       bool,
+    /// This dataset first appeared within the last few refreshes
+    pub new:         bool,
+    /// This row's rates exceed its pool's maximum bandwidth, so they're
+    /// probably wrong
+    pub implausible: bool,
+    /// Space usage, if the space columns are shown
+    pub space:       Option<Space>,
+    /// Filesystem, volume, or snapshot.  Only classified if the Type column
+    /// is shown or filtered.
+    pub kind:        DatasetType,
+    /// Encryption status, if the Enc column is shown or filtered and it's
+    /// known
+    pub encryption:  Option<Encryption>,
+}
+
+impl AddAssign<&Self> for Element {
+    fn add_assign(&mut self, other: &Self) {
+        self.ops_r += other.ops_r;
+        self.r_s += other.r_s;
+        self.ops_w += other.ops_w;
+        self.w_s += other.w_s;
+        self.ops_d += other.ops_d;
+        self.d_s += other.d_s;
+        self.pending_d += other.pending_d;
+        self.nread += other.nread;
+        self.nwritten += other.nwritten;
+        self.total += other.total;
+        self.nops += other.nops;
+        self.stale |= other.stale;
+        self.implausible |= other.implausible;
+    }
+}
+
+impl Element {
+    /// The rates, in the order ops_r, r_s, ops_w, w_s, ops_d, d_s
+    fn rates(&self) -> [f64; 6] {
+        [
+            self.ops_r, self.r_s, self.ops_w, self.w_s, self.ops_d, self.d_s,
+        ]
+    }
+
+    fn set_rates(&mut self, rates: [f64; 6]) {
+        [
+            self.ops_r, self.r_s, self.ops_w, self.w_s, self.ops_d, self.d_s,
+        ] = rates;
+    }
+
+    /// Is this the hidden clone that an incremental `zfs receive` writes to?
+    pub fn is_receiving(&self) -> bool {
+        self.name.rsplit('/').next() == Some("%recv")
+    }
+
+    /// If this element is a boot environment, or a child of one, return the
+    /// name of the dataset containing the boot environments.
+    fn be_root(&self) -> Option<&str> {
+        let mut fields = self.name.splitn(3, '/');
+        let pool = fields.next()?;
+        let root = fields.next()?;
+        fields.next()?;
+        if root == "ROOT" {
+            Some(&self.name[0..pool.len() + 5])
+        } else {
+            None
+        }
+    }
+
+    /// Merge the statistics of all boot environments into the dataset that
+    /// contains them, like `zroot/ROOT`.
+    ///
+    /// If `children` is true, the statistics have already been aggregated, so
+    /// the boot environments are simply removed.
+    fn collapse_boot_environments(
+        elems: Vec<Element>,
+        children: bool,
+    ) -> Vec<Element> {
+        let mut out: Vec<Element> = Vec::with_capacity(elems.len());
+        let mut roots: HashMap<String, usize> = HashMap::new();
+        for mut elem in elems {
+            let Some(root) = elem.be_root() else {
+                let mut fields = elem.name.split('/');
+                if fields.nth(1) == Some("ROOT") && fields.next().is_none() {
+                    roots.insert(elem.name.clone(), out.len());
+                }
+                out.push(elem);
+                continue;
+            };
+            if let Some(&i) = roots.get(root) {
+                if !children {
+                    out[i] += &elem;
+                }
+            } else {
+                elem.name = root.to_owned();
+                elem.objset = 0;
+                roots.insert(elem.name.clone(), out.len());
+                out.push(elem);
+            }
+        }
+        out
+    }
+
+    pub fn get(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::OpsR => self.ops_r,
+            Metric::RS => self.r_s,
+            Metric::OpsW => self.ops_w,
+            Metric::WS => self.w_s,
+            Metric::OpsD => self.ops_d,
+            Metric::DS => self.d_s,
+            Metric::PendingD => self.pending_d as f64,
+        }
+    }
+
+    /// An idle dataset named `name`, for tests to fill in
+    #[cfg(test)]
+    pub(crate) fn named(name: &str) -> Self {
+        Element {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// A dataset that's writing `w_s` B/s and doing nothing else
+    #[cfg(test)]
+    pub(crate) fn writing(name: &str, w_s: f64) -> Self {
+        Element {
+            w_s,
+            ..Self::named(name)
+        }
+    }
+}
+
+/// One of the numeric statistics reported for each `Element`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize)]
+pub enum Metric {
+    #[serde(rename = "ops_r")]
+    OpsR,
+    #[serde(rename = "r_s")]
+    RS,
+    #[serde(rename = "ops_w")]
+    OpsW,
+    #[serde(rename = "w_s")]
+    WS,
+    #[serde(rename = "ops_d")]
+    OpsD,
+    #[serde(rename = "d_s")]
+    DS,
+    #[serde(rename = "pending_d")]
+    PendingD,
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Metric::ALL
+            .into_iter()
+            .find(|m| m.to_string() == s)
+            .ok_or_else(|| format!("unknown metric {s:?}"))
+    }
+}
+
+impl Metric {
+    pub const ALL: [Metric; 7] = [
+        Metric::OpsR,
+        Metric::RS,
+        Metric::OpsW,
+        Metric::WS,
+        Metric::OpsD,
+        Metric::DS,
+        Metric::PendingD,
+    ];
+
+    /// The kstat counters that this metric is derived from
+    pub fn counters(self) -> Counters {
+        match self {
+            Metric::OpsR => Counters::READS,
+            Metric::RS => Counters::NREAD,
+            Metric::OpsW => Counters::WRITES,
+            Metric::WS => Counters::NWRITTEN,
+            Metric::OpsD => Counters::NUNLINKS,
+            Metric::DS => Counters::NUNLINKED,
+            Metric::PendingD => Counters::NUNLINKS | Counters::NUNLINKED,
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Metric::OpsR => "ops_r",
+            Metric::RS => "r_s",
+            Metric::OpsW => "ops_w",
+            Metric::WS => "w_s",
+            Metric::OpsD => "ops_d",
+            Metric::DS => "d_s",
+            Metric::PendingD => "pending_d",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The config file's alert rules, plus the built-in ones for delete pressure
+fn alert_rules(
+    mut rules: Vec<Rule>,
+    pressure: Option<PressureConfig>,
+) -> Vec<Rule> {
+    rules.extend(pressure.iter().flat_map(PressureConfig::rules));
+    rules
+}
+
+/// The English name of the dataset view's column `i`, or of hotness
+fn sort_name(i: usize) -> &'static str {
+    if i == HOTNESS {
+        "hotness"
+    } else {
+        COLUMNS[i].0
+    }
+}
+
+/// Compare two strings so that embedded numbers sort by their numeric value,
+/// like "vm9" < "vm10".
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    /// Split off the longest prefix consisting entirely of digits, or entirely
+    /// of non-digits.
+    fn chunk(s: &str) -> (&str, &str) {
+        let digits = s.starts_with(|c: char| c.is_ascii_digit());
+        let end = s
+            .find(|c: char| c.is_ascii_digit() != digits)
+            .unwrap_or(s.len());
+        s.split_at(end)
+    }
+
+    let (mut x, mut y) = (a, b);
+    while !x.is_empty() && !y.is_empty() {
+        let (xc, xr) = chunk(x);
+        let (yc, yr) = chunk(y);
+        let both_numeric = xc.starts_with(|c: char| c.is_ascii_digit())
+            && yc.starts_with(|c: char| c.is_ascii_digit());
+        let ord = if both_numeric {
+            let xn = xc.trim_start_matches('0');
+            let yn = yc.trim_start_matches('0');
+            xn.len().cmp(&yn.len()).then_with(|| xn.cmp(yn))
+        } else {
+            xc.cmp(yc)
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+        (x, y) = (xr, yr);
+    }
+    // Break ties, like leading zeros, with plain lexicographic order
+    x.len().cmp(&y.len()).then_with(|| a.cmp(b))
+}
+
+/// Quote a word for a POSIX shell, if it needs quoting
+fn shell_quote(word: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        Cow::Borrowed(word)
+    } else {
+        Cow::Owned(format!("'{}'", word.replace('\'', r"'\''")))
+    }
+}
+
+/// The Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let sub = diag + usize::from(ca != *cb);
+            diag = row[j + 1];
+            row[j + 1] = sub.min(row[j] + 1).min(diag + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// How many samples the history graph keeps: four hours' worth, at the
+/// default interval
+const HISTORY_LEN: usize = 14_400;
+
+/// The recent read and write bandwidth of the selected dataset, or of all the
+/// displayed ones, for the history graph
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    /// The graphed dataset, or `None` for the total of the displayed rows
+    subject: Option<String>,
+    /// When each sample was taken, and its read and write B/s
+    samples: VecDeque<(Instant, f64, f64)>,
+}
+
+impl History {
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    pub fn samples(&self) -> &VecDeque<(Instant, f64, f64)> {
+        &self.samples
+    }
+
+    fn push(&mut self, now: Instant, r_s: f64, w_s: f64) {
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, r_s, w_s));
+    }
+}
+
+/// How many of the busiest datasets auto mode shows when every one is idle,
+/// unless told otherwise
+pub const DEFAULT_AUTO_MIN: usize = 3;
+
+/// How the user asked to see the datasets, from the command line
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Only display datasets that have some activity
+    pub auto:           bool,
+    /// In auto mode, the minimum combined bytes per second for a dataset to
+    /// be considered active
+    pub auto_threshold: f64,
+    /// In auto mode, if every dataset is idle, show this many of the busiest
+    /// ones anyway
+    pub auto_min:       usize,
+    /// Include child datasets' stats with their parents'
+    pub children:       bool,
+    /// Show all boot environments as a single ROOT dataset
+    pub collapse_be:    bool,
+    /// Collect these pools and their children, or all if empty
+    pub pools:          Vec<String>,
+    /// Show datasets at most this many levels deep
+    pub depth:          Option<NonZeroUsize>,
+    /// Show only datasets whose names match
+    pub filter:         Option<Regex>,
+    /// Show each dataset's objset ID
+    pub objset_ids:     bool,
+    /// Reverse the sort order
+    pub reverse:        bool,
+    /// In children mode, also show each parent's own statistics
+    pub self_rows:      bool,
+    /// Show the bytes read and written by each dataset since its pool was
+    /// imported
+    pub totals:         bool,
+    /// Mark file systems that aren't mounted
+    pub mount_status:   bool,
+    /// Only display mounted file systems and volumes
+    pub mounted_only:   bool,
+    /// 0-based index of the column to sort by, if any
+    pub sort_idx:       Option<usize>,
+    /// Where to collect statistics from
+    pub source:         Source,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            auto:           false,
+            auto_threshold: 1.0,
+            auto_min:       DEFAULT_AUTO_MIN,
+            children:       false,
+            collapse_be:    false,
+            pools:          Vec::new(),
+            depth:          None,
+            filter:         None,
+            objset_ids:     false,
+            reverse:        false,
+            self_rows:      false,
+            totals:         false,
+            mount_status:   false,
+            mounted_only:   false,
+            sort_idx:       None,
+            source:         Source::Kstat,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    alerts:         Alerts,
+    auto:           bool,
+    /// Minimum combined bandwidth, in B/s, for auto mode to show a dataset
+    auto_threshold: f64,
+    /// In auto mode, show at least this many datasets even if they're idle
+    auto_min:       usize,
+    /// Records the statistics around `capture` alerts
+    capture:        Option<Capture>,
+    /// Show all boot environments as a single row
+    collapse_be:    bool,
+    data:           DataSource,
+    depth:          Option<NonZeroUsize>,
+    /// The most recent error, until dismissed
+    error:          Option<String>,
+    /// The error from the latest update, whether or not it was dismissed
+    tick_error:     Option<String>,
+    /// Activity of the L2ARC, if its panel is shown
+    l2arc:          Option<L2arc>,
+    /// Each dataset's space usage, if the space columns are shown
+    space:          Option<HashMap<String, Space>>,
+    /// Lists the datasets' space usage in the background
+    space_job:      Job<HashMap<String, Space>>,
+    /// Each dataset's encryption status, if the Enc column is shown or
+    /// filtered
+    encryption:     Option<HashMap<String, Encryption>>,
+    /// Lists the datasets' encryption status in the background
+    encryption_job: Job<HashMap<String, Encryption>>,
+    /// Show each dataset's encryption status
+    show_enc:       bool,
+    /// Show only datasets with this encryption status
+    enc_filter:     Option<EncryptionFilter>,
+    /// The datasets that `zfs list` shows, if only those are displayed
+    visible:        Option<HashSet<String>>,
+    /// Lists the visible datasets in the background
+    visible_job:    Job<HashSet<String>>,
+    /// The containers that use each file system, if they're shown
+    containers:     Option<Containers>,
+    /// Scans the containers' state in the background
+    containers_job: Job<Containers>,
+    /// Each pool's space usage, if the capacity panel is shown
+    capacity:       Option<Vec<PoolCapacity>>,
+    /// Lists the pools' space usage in the background
+    capacity_job:   Job<Vec<PoolCapacity>>,
+    /// Each pool's dedup table, if the dedup panel is shown
+    dedup:          Option<Vec<PoolDedup>>,
+    /// Lists the pools' dedup tables in the background
+    dedup_job:      Job<Vec<PoolDedup>>,
+    /// The graphed rates, if the history graph is shown
+    history:        Option<History>,
+    /// Per-vdev statistics, if the vdev view is shown
+    vdevs:          Option<Vdevs>,
+    /// 0-based index of the vdev view's column to sort by, if any
+    vdev_sort:      Option<usize>,
+    /// Reverse the vdev view's sort
+    vdev_reverse:   bool,
+    /// Each pool's latency histogram, if the latency view is shown
+    latency:        Option<Latency>,
+    /// Each vdev's queue depths, if the queue view is shown
+    queues:         Option<Queues>,
+    /// Activity of the dbuf cache, if its panel is shown
+    dbuf:           Option<Dbuf>,
+    /// Activity of the prefetcher, if its panel is shown
+    zfetch:         Option<Zfetch>,
+    /// Each pool's latest txg, or why it couldn't be read, if the TXG panel
+    /// is shown
+    txgs:           Option<Vec<(String, Result<TxgStats, String>)>>,
+    /// The first refresh, which runs in the background so the display can
+    /// appear immediately
+    first_refresh:  Option<JoinHandle<Result<DataSource, String>>>,
+    /// Counters saved by an earlier process, to compute the first rates from
+    resume:         Option<SavedCounters>,
+    /// Takes extra samples between refreshes, if more than one per interval
+    /// is requested
+    sampler:        Option<Sampler>,
+    /// Which of the samples' rates to display
+    statistic:      Statistic,
+    filter:         Option<Regex>,
+    /// Weights for sorting by hotness
+    hotness:        HotnessWeights,
+    #[cfg(target_os = "linux")]
+    journal:        Option<Summarizer>,
+    /// Log datasets that appear while running
+    log_new:        bool,
+    /// After the first refresh, limit the depth if there are more than this
+    /// many datasets
+    max_datasets:   Option<usize>,
+    /// Show whether each dataset is mounted
+    mount_status:   bool,
+    /// File systems that weren't mounted when the mount table was last read,
+    /// if known
+    unmounted:      Option<HashSet<String>>,
+    /// Reads the mount table in the background
+    mounts_job:     Job<HashSet<String>>,
+    /// Hide datasets that aren't mounted
+    mounted_only:   bool,
+    /// The terminal is too narrow to display the optional columns
+    narrow:         bool,
+    /// A message to display above the table
+    notice:         Option<String>,
+    /// How many columns are scrolled off the left of the table
+    pan:            usize,
+    /// Show each dataset's objset ID
+    objset_ids:     bool,
+    /// The name of the dataset whose row is highlighted, if any
+    selected:       Option<String>,
+    /// Rules for changing how dataset names are displayed
+    relabel:        Vec<Relabel>,
+    reverse:        bool,
+    /// In children mode, also show each parent's own statistics
+    self_rows:      bool,
+    /// Named sets of datasets from the config file
+    sets:           BTreeMap<String, DatasetSet>,
+    /// Show the bytes read and written since each pool was imported
+    totals:         bool,
+    /// Show each dataset's type
+    types:          bool,
+    /// Each dataset's type, since classifying a volume takes a system call
+    type_cache:     HashMap<String, DatasetType>,
+    /// Show only datasets of this type
+    type_filter:    Option<DatasetType>,
+    /// Where to look for VM configurations
+    vm_configs:     Vec<PathBuf>,
+    /// The VM that uses each volume, once they've been scanned
+    vms:            HashMap<String, String>,
+    /// Scans the VM configurations in the background
+    vms_job:        Job<HashMap<String, String>>,
+    should_quit:    bool,
+    /// Exporters that receive every dataset's statistics on each update
+    sinks:          Vec<Box<dyn Sink>>,
+    /// 0-based index of the column to sort by, if any
+    sort_idx:       Option<usize>,
+    /// The session's activity, to print on exit
+    summary:        Option<Summary>,
+    /// When the sort column was last changed interactively
+    sort_changed:   Option<Instant>,
+}
+
+impl App {
+    pub fn new(
+        options: Options,
+        config: Config,
+        sinks: Vec<Box<dyn Sink>>,
+    ) -> Self {
+        let Options {
+            auto,
+            auto_threshold,
+            auto_min,
+            children,
+            collapse_be,
+            pools,
+            depth,
+            filter,
+            objset_ids,
+            reverse,
+            self_rows,
+            totals,
+            mount_status,
+            mounted_only,
+            sort_idx,
+            source,
+        } = options;
+        let mut data = DataSource::new(children, pools, config.ignore);
+        data.set_source(source);
+        data.max_bandwidth = config.max_bandwidth;
+        let mut app = App {
+            alerts: Alerts::new(alert_rules(
+                config.alerts,
+                config.delete_pressure,
+            )),
+            auto,
+            auto_threshold,
+            auto_min,
+            capture: config.capture.map(Capture::new),
+            collapse_be,
+            data,
+            depth,
+            filter,
+            hotness: config.hotness,
+            objset_ids,
+            #[cfg(target_os = "linux")]
+            journal: config.journal.summary_interval.map(Summarizer::new),
+            log_new: config.log_new_datasets,
+            relabel: config.relabel,
+            sets: config.sets,
+            vm_configs: config.vm_configs.unwrap_or_else(vm::default_dirs),
+            reverse,
+            self_rows,
+            totals,
+            mount_status,
+            mounted_only,
+            sort_idx,
+            sinks,
+            ..Default::default()
+        };
+        app.start_first_refresh();
+        app
+    }
+
+    /// Collect the first statistics in the background, so the display can
+    /// appear without waiting for them
+    fn start_first_refresh(&mut self) {
+        let mut first = self.data.settings();
+        self.first_refresh = Some(thread::spawn(move || {
+            first.refresh().map_err(|e| e.to_string())?;
+            Ok(first)
+        }));
+    }
+
+    /// After the first refresh, limit the depth if there are too many
+    /// datasets to display quickly
+    pub fn limit_depth(&mut self, max_datasets: usize) {
+        self.max_datasets = Some(max_datasets);
+    }
+
+    /// Start accumulating a summary of the session's activity
+    pub fn record_summary(&mut self) {
+        self.summary = Some(Summary::new(Instant::now()));
+    }
+
+    /// The summary of the session so far, if one was being recorded
+    pub fn take_summary(&mut self) -> Option<Summary> {
+        self.summary.take()
+    }
+
+    /// Describe any pools that the last refresh could not collect
+    fn failure_message(&self) -> Option<String> {
+        if self.data.failures().is_empty() {
+            None
+        } else {
+            let pools = self.data.failures().join(", ");
+            Some(trf("Cannot collect statistics for {}", &[&pools]))
+        }
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn dismiss_error(&mut self) {
+        self.error = None;
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    /// Record the outcome of an update.  The banner only changes when the
+    /// outcome does, so an error stays dismissed for as long as it persists,
+    /// and a successful update doesn't hide an unrelated error.
+    fn set_tick_error(&mut self, error: Option<String>) {
+        if error == self.tick_error {
+            return;
+        }
+        if error.is_some() || self.error == self.tick_error {
+            self.error = error.clone();
+        }
+        self.tick_error = error;
+    }
+
+    /// Apply a reloaded configuration file.  Settings from the command line,
+    /// like the pools and filter, are kept.
+    pub fn reload_config(&mut self, config: Config, sinks: Vec<Box<dyn Sink>>) {
+        self.alerts
+            .reload(alert_rules(config.alerts, config.delete_pressure));
+        self.capture = match (self.capture.take(), config.capture) {
+            (Some(mut capture), Some(c)) => {
+                capture.reload(c);
+                Some(capture)
+            }
+            (_, c) => c.map(Capture::new),
+        };
+        self.data.ignore = config.ignore;
+        self.data.max_bandwidth = config.max_bandwidth;
+        self.hotness = config.hotness;
+        #[cfg(target_os = "linux")]
+        {
+            self.journal = config.journal.summary_interval.map(Summarizer::new);
+        }
+        self.log_new = config.log_new_datasets;
+        self.relabel = config.relabel;
+        self.sets = config.sets;
+        self.vm_configs = config.vm_configs.unwrap_or_else(vm::default_dirs);
+        self.sinks = sinks;
+        self.notice = Some(tr("Reloaded the configuration file"));
+    }
+
+    /// A command line that would start ztop in its current state, with the
+    /// same update interval.
+    pub fn command_line(&self, interval: Duration) -> String {
+        let mut words = vec![String::from("ztop")];
+        let mut flag = |f: &str, on: bool| {
+            if on {
+                words.push(f.to_owned());
+            }
+        };
+        flag("-a", self.auto);
+        flag("-b", self.collapse_be);
+        flag("-c", self.data.children);
+        flag("-r", self.reverse);
+        flag("--self-rows", self.self_rows);
+        flag("--snapshots", self.data.snapshots);
+        flag("--totals", self.totals);
+        flag("--types", self.types);
+        flag("--encryption", self.show_enc);
+        flag("--objset-ids", self.objset_ids);
+        flag("--mount-status", self.mount_status);
+        flag("--mounted-only", self.mounted_only);
+        flag("--delegated", self.visible.is_some());
+        flag("--container-names", self.containers.is_some());
+        flag("--l2arc", self.l2arc.is_some());
+        flag("--capacity", self.capacity.is_some());
+        flag("--graph", self.history.is_some());
+        flag("--space", self.space.is_some());
+        flag("--dedup", self.dedup.is_some());
+        flag("--dbuf", self.dbuf.is_some());
+        flag("--latency", self.latency.is_some());
+        flag("--queues", self.queues.is_some());
+        flag("--vdevs", self.vdevs.is_some());
+        flag("--txgs", self.txgs.is_some());
+        flag("--zfetch", self.zfetch.is_some());
+        if self.auto_threshold != 1.0 {
+            words.push("--auto-threshold".to_owned());
+            words.push(self.auto_threshold.to_string());
+        }
+        if self.auto_min != DEFAULT_AUTO_MIN {
+            words.push("--auto-min".to_owned());
+            words.push(self.auto_min.to_string());
+        }
+        if let Some(depth) = self.depth {
+            words.push("-d".to_owned());
+            words.push(depth.to_string());
+        }
+        if let Some(t) = self.type_filter {
+            words.push("--type-filter".to_owned());
+            words.push(t.to_string());
+        }
+        if let Some(e) = self.enc_filter {
+            words.push("--encryption-filter".to_owned());
+            words.push(e.to_string());
+        }
+        if self.data.source != Source::Kstat {
+            words.push("--source".to_owned());
+            words.push(self.data.source.to_string());
+        }
+        if self.sampler.is_some() {
+            words.push("--samples".to_owned());
+            words.push(self.samples().to_string());
+        }
+        if self.statistic != Statistic::Mean {
+            words.push("--statistic".to_owned());
+            words.push(self.statistic.to_string());
+        }
+        if let Some(filter) = self.filter.as_ref() {
+            words.push("-f".to_owned());
+            words.push(shell_quote(filter.as_str()).into_owned());
+        }
+        if let Some(i) = self.sort_idx {
+            words.push("-s".to_owned());
+            words.push(shell_quote(sort_name(i)).into_owned());
+        }
+        if interval != Duration::from_secs(1) {
+            words.push("-t".to_owned());
+            words.push(format_interval(interval));
+        }
+        words.extend(self.data.pools.iter().map(|p| shell_quote(p).into()));
+        words.join(" ")
+    }
+
+    /// Are any background commands still running?
+    pub fn has_jobs(&self) -> bool {
+        self.space_job.is_running()
+            || self.capacity_job.is_running()
+            || self.dedup_job.is_running()
+            || self.encryption_job.is_running()
+            || self.visible_job.is_running()
+            || self.containers_job.is_running()
+            || self.vms_job.is_running()
+            || self.mounts_job.is_running()
+    }
+
+    /// Start the background commands for the shown columns and panels, unless
+    /// they're already running
+    fn start_jobs(&mut self) {
+        if self.space.is_some() {
+            let pools = self.data.pools.clone();
+            self.space_job.start(move || {
+                dataset_space(&pools).map_err(|e| e.to_string())
+            });
+        }
+        // Volumes are only recognized when their type is shown or filtered
+        if self.types || self.type_filter.is_some() {
+            let dirs = self.vm_configs.clone();
+            self.vms_job.start(move || Ok(vm::scan(&dirs)));
+        }
+        if self.containers.is_some() {
+            self.containers_job.start(|| Ok(container::scan_default()));
+        }
+        if self.encryption.is_some() {
+            let pools = self.data.pools.clone();
+            self.encryption_job.start(move || {
+                dataset_encryption(&pools).map_err(|e| e.to_string())
+            });
+        }
+        if self.visible.is_some() {
+            let pools = self.data.pools.clone();
+            self.visible_job.start(move || {
+                visible_datasets(&pools).map_err(|e| e.to_string())
+            });
+        }
+        if self.capacity.is_some() {
+            let pools = self.data.pools.clone();
+            self.capacity_job.start(move || {
+                pool_capacity(&pools).map_err(|e| e.to_string())
+            });
+        }
+        if self.dedup.is_some() {
+            let pools = self.data.pools.clone();
+            self.dedup_job
+                .start(move || pool_dedup(&pools).map_err(|e| e.to_string()));
+        }
+    }
+
+    /// Display the results of any background commands that have finished.
+    /// Return true if any did.
+    pub fn poll_jobs(&mut self) -> bool {
+        let mut finished = false;
+        if let Some(r) = self.space_job.poll() {
+            match r {
+                // Unless the columns were hidden in the meantime
+                Ok(space) if self.space.is_some() => self.space = Some(space),
+                Ok(_) => (),
+                Err(e) => self.error = Some(e),
+            }
+            finished = true;
+        }
+        if let Some(Ok(vms)) = self.vms_job.poll() {
+            self.vms = vms;
+            finished = true;
+        }
+        if let Some(Ok(containers)) = self.containers_job.poll() {
+            if self.containers.is_some() {
+                self.containers = Some(containers);
+            }
+            finished = true;
+        }
+        if let Some(r) = self.mounts_job.poll() {
+            // If the mount table can't be read, don't guess
+            let wanted = self.mount_status || self.mounted_only;
+            self.unmounted = r.ok().filter(|_| wanted);
+            finished = true;
+        }
+        if let Some(r) = self.encryption_job.poll() {
+            match r {
+                // Unless neither the column nor the filter needs it anymore
+                Ok(enc) if self.encryption.is_some() => {
+                    self.encryption = Some(enc)
+                }
+                Ok(_) => (),
+                Err(e) => self.error = Some(e),
+            }
+            finished = true;
+        }
+        if let Some(r) = self.visible_job.poll() {
+            match r {
+                Ok(names) => self.visible = Some(names),
+                Err(e) => self.error = Some(e),
+            }
+            finished = true;
+        }
+        if let Some(r) = self.capacity_job.poll() {
+            match r {
+                // Unless the panel was hidden in the meantime
+                Ok(cap) if self.capacity.is_some() => self.capacity = Some(cap),
+                Ok(_) => (),
+                Err(e) => self.error = Some(e),
+            }
+            finished = true;
+        }
+        if let Some(r) = self.dedup_job.poll() {
+            match r {
+                // Unless the panel was hidden in the meantime
+                Ok(ddt) if self.dedup.is_some() => self.dedup = Some(ddt),
+                Ok(_) => (),
+                Err(e) => self.error = Some(e),
+            }
+            finished = true;
+        }
+        finished
+    }
+
+    /// Is the first refresh still running?
+    pub fn is_collecting(&self) -> bool {
+        self.first_refresh.is_some()
+    }
+
+    /// If the first refresh has finished, start displaying its results.
+    /// Return true if it did.
+    pub fn poll_first_refresh(&mut self) -> bool {
+        if !self
+            .first_refresh
+            .as_ref()
+            .is_some_and(JoinHandle::is_finished)
+        {
+            return false;
+        }
+        let handle = self.first_refresh.take().unwrap();
+        match handle.join().unwrap() {
+            Ok(mut data) => {
+                // The user may have toggled children mode in the meantime
+                data.children = self.data.children;
+                self.data = data;
+                self.set_tick_error(self.failure_message());
+                self.refresh_mounts();
+                if let Some(saved) = self.resume.take() {
+                    match self.data.resume(saved) {
+                        Ok(true) => self.export(),
+                        Ok(false) => (),
+                        Err(e) => self.error = Some(e.to_string()),
+                    }
+                }
+            }
+            Err(e) => {
+                // Keep the empty data.  on_tick will try again.
+                let msg = trf("Cannot collect statistics: {}", &[&e]);
+                self.set_tick_error(Some(msg));
+                return true;
+            }
+        }
+        if let Some(max_datasets) = self.max_datasets.take() {
+            if let Some(depth) = self.data.depth_limit(max_datasets) {
+                self.depth = Some(depth);
+                self.notice = Some(trf(
+                    "More than {} datasets; limiting depth to {}.  Press d to \
+                     show deeper ones.",
+                    &[&max_datasets, &depth],
+                ));
+            }
+        }
+        true
+    }
+
+    pub fn objset_ids(&self) -> bool {
+        self.objset_ids
+    }
+
+    pub fn notice(&self) -> Option<Cow<'_, str>> {
+        if self.is_collecting() {
+            return Some(Cow::Owned(tr("Collecting statistics...")));
+        }
+        self.notice.as_deref().map(Cow::Borrowed)
+    }
+
+    pub fn clear_filter(&mut self) {
+        self.filter = None;
+    }
+
+    /// Select the next dataset down, or the first if none is selected
+    pub fn on_down(&mut self) {
+        self.move_selection(1);
+    }
+
+    /// Select the next dataset up, or the last if none is selected
+    pub fn on_up(&mut self) {
+        self.move_selection(-1);
+    }
+
+    /// Scroll the table's columns right, back toward the first
+    pub fn on_left(&mut self) {
+        self.pan = self.pan.saturating_sub(1);
+    }
+
+    /// Scroll the table's columns left, if they don't all fit.  The Dataset
+    /// column stays put.
+    pub fn on_right(&mut self) {
+        self.pan += 1;
+    }
+
+    /// How many columns to scroll off the left of the table, given that at
+    /// most `max` need to be for the rest to fit
+    pub fn pan(&mut self, max: usize) -> usize {
+        self.pan = self.pan.min(max);
+        self.pan
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let mut names = self
+            .elements()
+            .into_iter()
+            .map(|elem| elem.name)
+            .collect::<Vec<_>>();
+        // A parent's self row has the same name as the parent
+        let mut seen = HashSet::new();
+        names.retain(|name| seen.insert(name.clone()));
+        let current = self
+            .selected
+            .as_ref()
+            .and_then(|s| names.iter().position(|name| name == s));
+        let i = match current {
+            Some(i) => i.saturating_add_signed(delta),
+            None if delta > 0 => 0,
+            None => names.len().saturating_sub(1),
+        };
+        self.selected =
+            names.get(i.min(names.len().saturating_sub(1))).cloned();
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selected = None;
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.selected.as_deref()
+    }
+
+    /// In children mode, how `dataset`'s total divides among its immediate
+    /// children, busiest first.  Its own I/O is a self row.  Empty outside of
+    /// children mode, where there is no total to divide.
+    pub fn breakdown(&mut self, dataset: &str) -> Vec<Element> {
+        if !self.data.children {
+            return Vec::new();
+        }
+        let is_child = |name: &str| {
+            name.strip_prefix(dataset)
+                .and_then(|rest| rest.strip_prefix('/'))
+                .is_some_and(|rest| !rest.contains('/'))
+        };
+        let mut v = self
+            .data
+            .iter()
+            .filter(|elem| is_child(&elem.name))
+            .collect::<Vec<_>>();
+        v.extend(
+            self.data
+                .self_rows()
+                .into_iter()
+                .filter(|elem| elem.name == dataset),
+        );
+        let bandwidth = |e: &Element| e.r_s + e.w_s + e.d_s;
+        v.sort_by(|x, y| bandwidth(y).total_cmp(&bandwidth(x)));
+        v
+    }
+
+    /// Return the elements that should be displayed, in order
+    #[rustfmt::skip]
+    pub fn elements(&mut self) -> Vec<Element> {
+        let auto = self.auto;
+        let auto_threshold = self.auto_threshold;
+        let depth = self.depth;
+        let filter = &self.filter;
+        let mut v = self.data.iter().collect::<Vec<_>>();
+        if self.self_rows && self.data.children {
+            v.extend(self.data.self_rows());
+            v.sort_by(|x, y|
+                x.name.cmp(&y.name).then(x.self_only.cmp(&y.self_only))
+            );
+        }
+        if let Some(sampler) = self.sampler.as_ref() {
+            for elem in v.iter_mut() {
+                let key = (elem.name.clone(), elem.self_only);
+                // Until an interval has finished, or for a new dataset, the
+                // mean is all there is
+                let Some(x) = sampler.finished.get(&key) else {
+                    continue;
+                };
+                match self.statistic {
+                    Statistic::Mean => (),
+                    Statistic::Min => elem.set_rates(x.min),
+                    Statistic::Max => elem.set_rates(x.max),
+                }
+            }
+        }
+        if let Some(space) = self.space.as_ref() {
+            for elem in v.iter_mut().filter(|e| !e.self_only) {
+                elem.space = space.get(&elem.name).copied();
+            }
+        }
+        if self.collapse_be {
+            v = Element::collapse_boot_environments(v, self.data.children);
+        }
+        if let Some(enc) = self.encryption.as_ref() {
+            for elem in v.iter_mut().filter(|e| !e.self_only) {
+                elem.encryption = enc.get(&elem.name).copied();
+            }
+        }
+        if self.types || self.type_filter.is_some() {
+            for elem in v.iter_mut() {
+                elem.kind = *self
+                    .type_cache
+                    .entry(elem.name.clone())
+                    .or_insert_with(|| DatasetType::of(&elem.name));
+            }
+        }
+        let mut v = v.into_iter()
+            .filter(move |elem| {
+                if let Some(limit) = depth {
+                    let edepth = elem.name.split('/').count();
+                    edepth <= limit.get()
+                } else {
+                    true
+                }
+            }).filter(|elem|
+                 filter.as_ref()
+                 .map(|f| f.is_match(&elem.name))
+                 .unwrap_or(true)
+            ).filter(|elem|
+                !self.mounted_only || !self.is_unmounted(&elem.name)
+            ).filter(|elem|
+                self.type_filter.map_or(true, |t| elem.kind == t)
+            ).filter(|elem|
+                // Until the status is known, show nothing rather than guess
+                self.enc_filter.map_or(true, |f|
+                    elem.encryption.is_some_and(|e| f.matches(e))
+                )
+            ).filter(|elem|
+                // Likewise, until the visible datasets are known
+                self.visible.as_ref().map_or(true, |names| {
+                    let fs = elem.name.split('@').next().unwrap();
+                    names.contains(fs)
+                })
+            ).collect::<Vec<_>>();
+        if auto {
+            let (active, mut idle): (Vec<_>, Vec<_>) = v.into_iter()
+                .partition(|elem|
+                    elem.r_s + elem.w_s + elem.d_s > auto_threshold
+                );
+            v = if active.is_empty() {
+                // Show the historically busiest datasets, so the user can
+                // tell that ztop is still alive.
+                idle.sort_by_key(|elem| Reverse(elem.total));
+                idle.truncate(self.auto_min);
+                idle
+            } else {
+                active
+            };
+        }
+        match (self.reverse, self.sort_idx) {
+            (false, Some(col::OPS_R)) => v.sort_by(|x, y| x.ops_r.total_cmp(&y.ops_r)),
+            (true,  Some(col::OPS_R)) => v.sort_by(|x, y| y.ops_r.total_cmp(&x.ops_r)),
+            (false, Some(col::R_S)) => v.sort_by(|x, y| x.r_s.total_cmp(&y.r_s)),
+            (true,  Some(col::R_S)) => v.sort_by(|x, y| y.r_s.total_cmp(&x.r_s)),
+            (false, Some(col::OPS_W)) => v.sort_by(|x, y| x.ops_w.total_cmp(&y.ops_w)),
+            (true,  Some(col::OPS_W)) => v.sort_by(|x, y| y.ops_w.total_cmp(&x.ops_w)),
+            (false, Some(col::W_S)) => v.sort_by(|x, y| x.w_s.total_cmp(&y.w_s)),
+            (true,  Some(col::W_S)) => v.sort_by(|x, y| y.w_s.total_cmp(&x.w_s)),
+            (false, Some(col::OPS_D)) => v.sort_by(|x, y| x.ops_d.total_cmp(&y.ops_d)),
+            (true,  Some(col::OPS_D)) => v.sort_by(|x, y| y.ops_d.total_cmp(&x.ops_d)),
+            (false, Some(col::D_S)) => v.sort_by(|x, y| x.d_s.total_cmp(&y.d_s)),
+            (true,  Some(col::D_S)) => v.sort_by(|x, y| y.d_s.total_cmp(&x.d_s)),
+            (false, Some(col::PENDING_D)) => v.sort_by_key(|x| x.pending_d),
+            (true,  Some(col::PENDING_D)) => v.sort_by_key(|x| Reverse(x.pending_d)),
+            (false, Some(col::NREAD)) => v.sort_by_key(|x| x.nread),
+            (true,  Some(col::NREAD)) => v.sort_by_key(|x| Reverse(x.nread)),
+            (false, Some(col::NWRITTEN)) => v.sort_by_key(|x| x.nwritten),
+            (true,  Some(col::NWRITTEN)) => v.sort_by_key(|x| Reverse(x.nwritten)),
+            (false, Some(col::USED)) => v.sort_by_key(|x| x.space.map(|s| s.used)),
+            (true,  Some(col::USED)) => v.sort_by_key(|x| Reverse(x.space.map(|s| s.used))),
+            (false, Some(col::AVAIL)) => v.sort_by_key(|x| x.space.map(|s| s.avail)),
+            (true,  Some(col::AVAIL)) => v.sort_by_key(|x| Reverse(x.space.map(|s| s.avail))),
+            (false, Some(col::REFER)) => v.sort_by_key(|x| x.space.map(|s| s.refer)),
+            (true,  Some(col::REFER)) => v.sort_by_key(|x| Reverse(x.space.map(|s| s.refer))),
+            (false, Some(col::TYPE)) => v.sort_by_key(|x| x.kind),
+            (true,  Some(col::TYPE)) => v.sort_by_key(|x| Reverse(x.kind)),
+            (false, Some(col::ENC)) => v.sort_by_key(|x| x.encryption),
+            (true,  Some(col::ENC)) => v.sort_by_key(|x| Reverse(x.encryption)),
+            (false, Some(col::DATASET)) => v.sort_by(|x, y| natural_cmp(&x.name, &y.name)),
+            (true,  Some(col::DATASET)) => v.sort_by(|x, y| natural_cmp(&y.name, &x.name)),
+            (false, Some(HOTNESS)) => v.sort_by(|x, y|
+                self.hotness(x).total_cmp(&self.hotness(y))
+            ),
+            (true,  Some(HOTNESS)) => v.sort_by(|x, y|
+                self.hotness(y).total_cmp(&self.hotness(x))
+            ),
+            _ => ()
+        }
+        v
+    }
+
+    /// Add up the displayed rows, without counting any dataset twice.  In
+    /// children mode, each row already includes its descendants, so only the
+    /// rows whose parents aren't displayed count.
+    pub fn total(&self, elems: &[Element]) -> Element {
+        let names = elems
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect::<HashSet<_>>();
+        let mut total = Element::default();
+        for elem in elems.iter().filter(|e| !e.self_only) {
+            let has_parent = DataSource::with_parents(&elem.name)
+                .any(|p| p.len() < elem.name.len() && names.contains(p));
+            if !(self.data.children && has_parent) {
+                total += elem;
+            }
+        }
+        total
+    }
+
+    /// A weighted sum of the dataset's read, write, and delete bandwidth
+    fn hotness(&self, elem: &Element) -> f64 {
+        let w = &self.hotness;
+        w.read * elem.r_s + w.write * elem.w_s + w.delete * elem.d_s
+    }
+
+    pub fn on_a(&mut self) {
+        self.auto ^= true;
+    }
+
+    pub fn on_b(&mut self) {
+        self.collapse_be ^= true;
+    }
+
+    pub fn on_c(&mut self) {
+        self.data.toggle_children()
+    }
+
+    /// Include or exclude mounted snapshots
+    pub fn toggle_snapshots(&mut self) {
+        self.data.toggle_snapshots();
+        if !self.data.snapshots
+            && self.type_filter == Some(DatasetType::Snapshot)
+        {
+            self.type_filter = None;
+        }
+    }
+
+    /// Show or hide the Type column
+    pub fn toggle_types(&mut self) {
+        self.types ^= true;
+        if !self.types && self.sort_idx == Some(col::TYPE) {
+            self.sort_idx = None;
+        }
+        self.start_jobs();
+    }
+
+    /// Show or hide the Enc column
+    pub fn toggle_encryption(&mut self) {
+        self.show_enc ^= true;
+        if !self.show_enc && self.sort_idx == Some(col::ENC) {
+            self.sort_idx = None;
+        }
+        self.update_encryption();
+    }
+
+    /// Show only datasets with this encryption status
+    pub fn set_encryption_filter(&mut self, f: EncryptionFilter) {
+        self.enc_filter = Some(f);
+        self.update_encryption();
+    }
+
+    /// Show only encrypted datasets, then only unlocked ones, and then every
+    /// dataset again
+    pub fn cycle_encryption_filter(&mut self) {
+        self.enc_filter = match self.enc_filter {
+            None => Some(EncryptionFilter::Encrypted),
+            Some(EncryptionFilter::Encrypted) => {
+                Some(EncryptionFilter::Unlocked)
+            }
+            Some(EncryptionFilter::Unlocked) => None,
+        };
+        self.notice = Some(match self.enc_filter {
+            None => tr("Showing every dataset"),
+            Some(EncryptionFilter::Encrypted) => {
+                tr("Showing only encrypted datasets")
+            }
+            Some(EncryptionFilter::Unlocked) => {
+                tr("Showing only unlocked datasets")
+            }
+        });
+        self.update_encryption();
+    }
+
+    /// Start or stop listing the encryption status, depending on whether
+    /// the column or the filter needs it
+    fn update_encryption(&mut self) {
+        if !self.show_enc && self.enc_filter.is_none() {
+            self.encryption = None;
+        } else if self.encryption.is_none() {
+            self.encryption = Some(HashMap::new());
+            self.start_jobs();
+        }
+    }
+
+    /// Show only the datasets that `zfs list` shows, such as those delegated
+    /// to a jail, zone, or user namespace
+    pub fn set_delegated(&mut self) {
+        if self.visible.is_none() {
+            self.visible = Some(HashSet::new());
+            self.start_jobs();
+        }
+    }
+
+    /// Show only datasets of this type
+    pub fn set_type_filter(&mut self, t: DatasetType) {
+        self.type_filter = Some(t);
+        self.start_jobs();
+    }
+
+    /// Name the Docker or LXD container that uses each file system
+    pub fn set_containers(&mut self) {
+        if self.containers.is_none() {
+            self.containers = Some(Containers::default());
+            self.start_jobs();
+        }
+    }
+
+    /// The name of the container that uses this dataset, if containers are
+    /// shown and one is known
+    pub fn container(&self, name: &str) -> Option<&str> {
+        self.containers.as_ref()?.get(name)
+    }
+
+    /// The name of the VM that uses this volume, if any is known
+    pub fn vm(&self, name: &str) -> Option<&str> {
+        self.vms.get(name).map(String::as_str)
+    }
+
+    /// Read the counters this many times per interval, counting the
+    /// refresh, so the display can show the lowest or highest rates in
+    /// between
+    pub fn set_samples(&mut self, samples: usize) {
+        self.sampler = (samples > 1).then(|| Sampler::new(samples));
+        if self.sampler.is_none() {
+            self.statistic = Statistic::Mean;
+        }
+    }
+
+    /// How many times to read the counters per interval, counting the
+    /// refresh
+    pub fn samples(&self) -> usize {
+        self.sampler.as_ref().map_or(1, |s| s.samples)
+    }
+
+    /// Which of the samples' rates to display
+    pub fn statistic(&self) -> Statistic {
+        self.statistic
+    }
+
+    /// Display the samples' mean, lowest, or highest rates
+    pub fn set_statistic(&mut self, statistic: Statistic) {
+        if self.sampler.is_some() {
+            self.statistic = statistic;
+        }
+    }
+
+    /// Display the samples' highest rates, then the lowest, and then the
+    /// mean again
+    pub fn cycle_statistic(&mut self) {
+        if self.sampler.is_none() {
+            self.notice =
+                Some(tr("Only one sample per interval; see --samples"));
+            return;
+        }
+        self.statistic = match self.statistic {
+            Statistic::Mean => Statistic::Max,
+            Statistic::Max => Statistic::Min,
+            Statistic::Min => Statistic::Mean,
+        };
+        self.notice = Some(match self.statistic {
+            Statistic::Mean => tr("Showing the mean rates"),
+            Statistic::Min => tr("Showing the lowest rates"),
+            Statistic::Max => tr("Showing the highest rates"),
+        });
+    }
+
+    /// Show only file systems, then only volumes, then only snapshots if
+    /// they're included, and then every type again
+    pub fn cycle_type_filter(&mut self) {
+        self.type_filter = match self.type_filter {
+            None => Some(DatasetType::Filesystem),
+            Some(DatasetType::Filesystem) => Some(DatasetType::Volume),
+            Some(DatasetType::Volume) if self.data.snapshots => {
+                Some(DatasetType::Snapshot)
+            }
+            Some(_) => None,
+        };
+        self.start_jobs();
+        self.notice = Some(match self.type_filter {
+            None => tr("Showing every type"),
+            Some(t) => trf("Showing only {}s", &[&t]),
+        });
+    }
+
+    /// Show one more level of datasets, or one fewer.  Once every level is
+    /// shown, the limit is removed.
+    pub fn on_d(&mut self, more_depth: bool) {
+        self.notice = None;
+        self.depth = if more_depth {
+            match self.depth {
+                None => NonZeroUsize::new(1),
+                Some(x) => NonZeroUsize::new(x.get() + 1)
+                    .filter(|d| d.get() < self.data.max_depth()),
+            }
+        } else {
+            match self.depth {
+                None => None,
+                Some(x) => NonZeroUsize::new(x.get() - 1),
+            }
+        }
+    }
+
+    /// Halve the auto mode threshold
+    pub fn on_lbracket(&mut self) {
+        self.auto_threshold /= 2.0;
+    }
+
+    /// Names of the dataset sets defined in the config file
+    pub fn set_names(&self) -> impl Iterator<Item = &str> {
+        self.sets.keys().map(String::as_str)
+    }
+
+    /// Display a named dataset set.  Return `false` if there's no such set.
+    pub fn select_set(&mut self, name: &str) -> bool {
+        let Some(set) = self.sets.get(name) else {
+            return false;
+        };
+        self.filter = set.filter.clone();
+        self.data.set_pools(set.pools.clone());
+        // Rather than display nothing until the next tick, collect at once.
+        // Any first refresh still running was for the old pools, so its
+        // results are abandoned.
+        self.start_first_refresh();
+        true
+    }
+
+    /// Reread the mount table in the background, if anything needs it.
+    /// Running `mount` on every update, or checking each row for a volume
+    /// on every draw, would be too slow.
+    fn refresh_mounts(&mut self) {
+        if !self.mount_status && !self.mounted_only {
+            self.unmounted = None;
+            return;
+        }
+        let names = self.data.cur.keys().cloned().collect::<Vec<_>>();
+        self.mounts_job.start(move || {
+            let table = mount_table().map_err(|e| e.to_string())?;
+            let mounted = parse_mounts(&table);
+            // Volumes are never mounted, so don't count them
+            let unmounted = names
+                .into_iter()
+                .filter(|name| {
+                    !mounted.contains(name)
+                        && !Path::new("/dev/zvol").join(name).exists()
+                })
+                .collect();
+            Ok(unmounted)
+        });
+    }
+
+    /// Forget the types of datasets that have gone away, whose names may be
+    /// reused.  File systems are checked again on every update, too, since a
+    /// volume's device node may not have been created yet when it was first
+    /// seen.
+    fn prune_type_cache(&mut self) {
+        let cur = &self.data.cur;
+        self.type_cache.retain(|name, kind| {
+            *kind != DatasetType::Filesystem && cur.contains_key(name)
+        });
+    }
+
+    /// Is this dataset a file system that isn't mounted?
+    pub fn is_unmounted(&self, name: &str) -> bool {
+        self.unmounted
+            .as_ref()
+            .is_some_and(|unmounted| unmounted.contains(name))
+    }
+
+    /// Should the display mark datasets that aren't mounted?
+    pub fn mount_status(&self) -> bool {
+        self.mount_status
+    }
+
+    pub fn on_m(&mut self) {
+        self.mount_status ^= true;
+        self.refresh_mounts();
+    }
+
+    /// Does this kernel provide the counters for this column?
+    pub fn is_available(&self, i: usize) -> bool {
+        self.data.counters().contains(column_counters(i))
+    }
+
+    /// Can the table be sorted by this column right now?
+    fn is_sortable(&self, i: usize) -> bool {
+        !self.is_hidden(i) && self.is_available(i)
+    }
+
+    /// Is this column currently hidden?
+    pub fn is_hidden(&self, i: usize) -> bool {
+        (!self.totals && (i == col::NREAD || i == col::NWRITTEN))
+            || (self.space.is_none() && (col::USED..=col::REFER).contains(&i))
+            || (!self.types && i == col::TYPE)
+            || (!self.show_enc && i == col::ENC)
+            || (self.narrow && OPTIONAL_COLUMNS.contains(&i))
+    }
+
+    /// Tell the App whether the terminal is too narrow for the optional
+    /// columns
+    pub fn set_narrow(&mut self, narrow: bool) {
+        self.narrow = narrow;
+    }
+
+    /// Should the display say which column was just selected for sorting?
+    pub fn show_sort_hint(&self) -> bool {
+        self.sort_changed.is_some_and(|t| t.elapsed() < SORT_HINT)
+    }
+
+    pub fn on_minus(&mut self) {
+        if self.vdevs.is_some() {
+            self.vdev_sort = match self.vdev_sort {
+                Some(0) => None,
+                Some(old) => Some(old - 1),
+                None => Some(VDEV_COLUMNS.len() - 1),
+            };
+            return;
+        }
+        loop {
+            self.sort_idx = match self.sort_idx {
+                Some(0) => None,
+                Some(old) => Some(old - 1),
+                None => Some(HOTNESS),
+            };
+            if self.sort_idx.map_or(true, |i| self.is_sortable(i)) {
+                break;
+            }
+        }
+        self.sort_changed = Some(Instant::now());
+    }
+
+    pub fn on_plus(&mut self) {
+        if self.vdevs.is_some() {
+            self.vdev_sort = match self.vdev_sort {
+                Some(old) if old + 1 >= VDEV_COLUMNS.len() => None,
+                Some(old) => Some(old + 1),
+                None => Some(0),
+            };
+            return;
+        }
+        loop {
+            self.sort_idx = match self.sort_idx {
+                Some(old) if old >= HOTNESS => None,
+                Some(old) => Some(old + 1),
+                None => Some(0),
+            };
+            if self.sort_idx.map_or(true, |i| self.is_sortable(i)) {
+                break;
+            }
+        }
+        self.sort_changed = Some(Instant::now());
+    }
+
+    pub fn on_t(&mut self) {
+        self.totals ^= true;
+    }
+
+    /// Show or hide the L2ARC panel
+    pub fn on_l(&mut self) {
+        if self.l2arc.take().is_none() {
+            let mut l2arc = L2arc::default();
+            match l2arc.refresh() {
+                Ok(()) => self.l2arc = Some(l2arc),
+                Err(e) => {
+                    self.error =
+                        Some(trf("Cannot read ARC statistics: {}", &[&e]));
+                }
+            }
+        }
+    }
+
+    pub fn l2arc(&self) -> Option<&L2arc> {
+        self.l2arc.as_ref()
+    }
+
+    /// Show or hide the dbuf cache panel
+    pub fn toggle_dbuf(&mut self) {
+        if self.dbuf.take().is_none() {
+            let mut dbuf = Dbuf::default();
+            match dbuf.refresh() {
+                Ok(()) => self.dbuf = Some(dbuf),
+                Err(e) => {
+                    self.error =
+                        Some(trf("Cannot read dbuf statistics: {}", &[&e]));
+                }
+            }
+        }
+    }
+
+    pub fn dbuf(&self) -> Option<&Dbuf> {
+        self.dbuf.as_ref()
+    }
+
+    /// Close whichever view is backed by `zpool iostat`, returning to the
+    /// dataset view
+    fn close_iostat_views(&mut self) {
+        self.vdevs = None;
+        self.latency = None;
+        self.queues = None;
+    }
+
+    /// Switch between the dataset view and the vdev view
+    pub fn toggle_vdevs(&mut self, interval: Duration) {
+        let was_shown = self.vdevs.is_some();
+        self.close_iostat_views();
+        if !was_shown {
+            match Vdevs::start(interval, self.data.pools.clone()) {
+                Ok(vdevs) => self.vdevs = Some(vdevs),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+    }
+
+    pub fn vdevs(&self) -> Option<&Vdevs> {
+        self.vdevs.as_ref()
+    }
+
+    /// The vdev view's latest rows, in display order
+    pub fn vdev_rows(&self) -> Vec<VdevRow> {
+        let Some(vdevs) = self.vdevs.as_ref() else {
+            return Vec::new();
+        };
+        match self.vdev_sort {
+            Some(i) => sort_vdevs(vdevs.latest(), i, self.vdev_reverse),
+            None => vdevs.latest().clone(),
+        }
+    }
+
+    pub fn vdev_sort(&self) -> Option<usize> {
+        self.vdev_sort
+    }
+
+    /// Each view's sort, to save for the next session
+    pub fn sorts(&self) -> BTreeMap<String, SavedSort> {
+        let mut sorts = BTreeMap::new();
+        if let Some(i) = self.sort_idx {
+            let sort = SavedSort {
+                column:  sort_name(i).to_owned(),
+                reverse: self.reverse,
+            };
+            sorts.insert("datasets".to_owned(), sort);
+        }
+        if let Some(i) = self.vdev_sort {
+            let sort = SavedSort {
+                column:  VDEV_COLUMNS[i].to_owned(),
+                reverse: self.vdev_reverse,
+            };
+            sorts.insert("vdevs".to_owned(), sort);
+        }
+        sorts
+    }
+
+    /// Restore the sorts saved by a previous session.  Unless `datasets`,
+    /// the dataset view's sort is left alone, as when it was given on the
+    /// command line.
+    pub fn restore_sorts(
+        &mut self,
+        saved: &BTreeMap<String, SavedSort>,
+        datasets: bool,
+    ) {
+        if let Some(sort) = saved.get("datasets").filter(|_| datasets) {
+            let i = if sort.column == "hotness" {
+                Some(HOTNESS)
+            } else {
+                COLUMNS.iter().position(|(name, _)| *name == sort.column)
+            };
+            if let Some(i) = i.filter(|&i| self.is_sortable(i)) {
+                self.sort_idx = Some(i);
+                self.reverse = sort.reverse;
+            }
+        }
+        if let Some(sort) = saved.get("vdevs") {
+            let i = VDEV_COLUMNS.iter().position(|name| *name == sort.column);
+            if let Some(i) = i {
+                self.vdev_sort = Some(i);
+                self.vdev_reverse = sort.reverse;
+            }
+        }
+    }
+
+    /// Switch between the dataset view and the latency view
+    pub fn toggle_latency(&mut self, interval: Duration) {
+        let was_shown = self.latency.is_some();
+        self.close_iostat_views();
+        if !was_shown {
+            match Latency::start(interval, self.data.pools.clone()) {
+                Ok(latency) => self.latency = Some(latency),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+    }
+
+    pub fn latency(&self) -> Option<&Latency> {
+        self.latency.as_ref()
+    }
+
+    /// Switch between the dataset view and the queue view
+    pub fn toggle_queues(&mut self, interval: Duration) {
+        let was_shown = self.queues.is_some();
+        self.close_iostat_views();
+        if !was_shown {
+            match Queues::start(interval, self.data.pools.clone()) {
+                Ok(queues) => self.queues = Some(queues),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+    }
+
+    pub fn queues(&self) -> Option<&Queues> {
+        self.queues.as_ref()
+    }
+
+    /// Collect the latest reports for whichever view is backed by `zpool
+    /// iostat`
+    pub fn refresh_iostat(&mut self, interval: Duration) {
+        let pools = &self.data.pools;
+        let r = refresh_view(&mut self.vdevs, interval, pools)
+            .and_then(|()| refresh_view(&mut self.latency, interval, pools))
+            .and_then(|()| refresh_view(&mut self.queues, interval, pools));
+        if let Err(e) = r {
+            self.error = Some(e);
+        }
+    }
+
+    /// Show or hide the prefetch panel
+    pub fn on_p(&mut self) {
+        if self.zfetch.take().is_none() {
+            let mut zfetch = Zfetch::default();
+            match zfetch.refresh() {
+                Ok(()) => self.zfetch = Some(zfetch),
+                Err(e) => {
+                    self.error =
+                        Some(trf("Cannot read prefetch statistics: {}", &[&e]));
+                }
+            }
+        }
+    }
+
+    pub fn zfetch(&self) -> Option<&Zfetch> {
+        self.zfetch.as_ref()
+    }
+
+    /// Show or hide the TXG panel
+    pub fn on_g(&mut self) {
+        if self.txgs.take().is_none() {
+            self.refresh_txgs();
+        }
+    }
+
+    /// Read the latest txg of every displayed pool
+    fn refresh_txgs(&mut self) {
+        // Don't enumerate the pools again if the last refresh just did
+        let pools = if !self.data.collected_pools().is_empty() {
+            self.data.collected_pools().to_vec()
+        } else if self.data.pools.is_empty() {
+            match pools() {
+                Ok(pools) => pools,
+                Err(e) => {
+                    self.error = Some(trf("Cannot list pools: {}", &[&e]));
+                    return;
+                }
+            }
+        } else {
+            self.data.pools.clone()
+        };
+        let stats = pools
+            .into_iter()
+            .map(|pool| {
+                let r = txgs(&pool).map_err(|e| e.to_string()).and_then(|s| {
+                    parse_txgs(&s).ok_or_else(|| "no committed txgs".to_owned())
+                });
+                (pool, r)
+            })
+            .collect();
+        self.txgs = Some(stats);
+    }
+
+    pub fn txgs(&self) -> Option<&[(String, Result<TxgStats, String>)]> {
+        self.txgs.as_deref()
+    }
+
+    /// Show or hide the used, avail, and refer columns
+    pub fn toggle_space(&mut self) {
+        if self.space.take().is_none() {
+            // The columns stay blank until the first listing arrives
+            self.space = Some(HashMap::new());
+            self.start_jobs();
+        } else if self
+            .sort_idx
+            .is_some_and(|i| (col::USED..=col::REFER).contains(&i))
+        {
+            self.sort_idx = None;
+        }
+    }
+
+    /// Show or hide the pool capacity panel
+    pub fn toggle_capacity(&mut self) {
+        if self.capacity.take().is_none() {
+            // The panel stays empty until the first listing arrives
+            self.capacity = Some(Vec::new());
+            self.start_jobs();
+        }
+    }
+
+    pub fn capacity(&self) -> Option<&[PoolCapacity]> {
+        self.capacity.as_deref()
+    }
+
+    /// Show or hide the history graph.  It starts out empty.
+    pub fn toggle_graph(&mut self) {
+        if self.history.take().is_none() {
+            self.history = Some(History::default());
+        }
+    }
+
+    pub fn graph(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Add the latest rates to the history graph, starting it over if
+    /// another dataset has been selected
+    fn record_history(&mut self, now: Instant) {
+        if self.history.is_none() {
+            return;
+        }
+        let elems = self.elements();
+        let subject = self.selected.clone();
+        let elem = match subject.as_deref() {
+            Some(name) => elems
+                .into_iter()
+                .find(|e| e.name == name)
+                .unwrap_or_default(),
+            None => self.total(&elems),
+        };
+        let history = self.history.as_mut().unwrap();
+        if history.subject != subject {
+            *history = History {
+                subject,
+                ..Default::default()
+            };
+        }
+        history.push(now, elem.r_s, elem.w_s);
+    }
+
+    /// Show or hide the dedup table panel
+    pub fn toggle_dedup(&mut self) {
+        if self.dedup.take().is_none() {
+            // The panel stays empty until the first listing arrives
+            self.dedup = Some(Vec::new());
+            self.start_jobs();
+        }
+    }
+
+    pub fn dedup(&self) -> Option<&[PoolDedup]> {
+        self.dedup.as_deref()
+    }
+
+    pub fn on_o(&mut self) {
+        self.objset_ids ^= true;
+    }
+
+    pub fn on_q(&mut self) {
+        self.should_quit = true;
+    }
+
+    pub fn on_r(&mut self) {
+        if self.vdevs.is_some() {
+            self.vdev_reverse ^= true;
+        } else {
+            self.reverse ^= true;
+        }
+    }
+
+    pub fn on_s(&mut self) {
+        self.self_rows ^= true;
+    }
+
+    /// Double the auto mode threshold
+    pub fn on_rbracket(&mut self) {
+        if self.auto_threshold > 0.0 {
+            self.auto_threshold *= 2.0;
+        } else {
+            self.auto_threshold = 1.0;
+        }
+    }
+
+    /// Does anything besides the display need statistics for every dataset?
+    pub fn has_consumers(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if self.journal.is_some() {
+            return true;
+        }
+        !self.alerts.is_empty()
+            || !self.sinks.is_empty()
+            || self.capture.is_some()
+            || self.summary.is_some()
+    }
+
+    /// How a dataset's name should be displayed
+    pub fn label<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        Relabel::apply(&self.relabel, name)
+    }
+
+    /// Should this dataset be highlighted because of an alert?
+    pub fn is_alerting(&self, dataset: &str) -> bool {
+        self.alerts.is_highlighted(dataset)
+    }
+
+    /// Stop highlighting the alerts that have fired for the selected
+    /// dataset, or for every dataset if none is selected
+    pub fn ack_alerts(&mut self) {
+        let n = self.alerts.ack(self.selected.as_deref());
+        self.notice = Some(match (n, self.selected.as_deref()) {
+            (0, _) => tr("No alerts to acknowledge"),
+            (_, Some(dataset)) => trf("Acknowledged alerts on {}", &[&dataset]),
+            (_, None) => trf("Acknowledged {} alerts", &[&n]),
+        });
+    }
+
+    /// Silence the rules that have fired for the selected dataset, or for
+    /// any dataset if none is selected, for `duration`
+    pub fn snooze_alerts(&mut self, duration: Duration) {
+        let shown = format!("{duration:?}");
+        let Some(until) = Instant::now().checked_add(duration) else {
+            self.error = Some(trf("Cannot snooze for {}", &[&shown]));
+            return;
+        };
+        let rules = self.alerts.snooze(self.selected.as_deref(), until);
+        self.notice = Some(if rules.is_empty() {
+            tr("No alerts to snooze")
+        } else {
+            trf("Snoozed {} for {}", &[&rules.join(", "), &shown])
+        });
+    }
+
+    /// Is a `capture` alert's recording being written?
+    pub fn is_capturing(&self) -> bool {
+        self.capture.as_ref().is_some_and(Capture::is_recording)
+    }
+
+    /// Has this dataset been deleting heavily for long enough to be marked?
+    pub fn is_delete_pressured(&self, dataset: &str) -> bool {
+        self.alerts.has_fired(pressure::RULE_NAME, dataset)
+    }
+
+    pub fn on_tick(&mut self) {
+        if self.is_collecting() {
+            return;
+        }
+        if let Err(e) = self.data.refresh() {
+            // Keep displaying the last good data
+            let msg = trf("Cannot collect statistics: {}", &[&e]);
+            self.set_tick_error(Some(msg));
+            return;
+        }
+        if let Some(sampler) = self.sampler.as_mut() {
+            sampler.finish(&self.data);
+        }
+        let mut error = self.failure_message();
+        self.refresh_mounts();
+        self.prune_type_cache();
+        if let Some(l2arc) = self.l2arc.as_mut() {
+            if let Err(e) = l2arc.refresh() {
+                error = Some(trf("Cannot read ARC statistics: {}", &[&e]));
+            }
+        }
+        self.poll_jobs();
+        self.start_jobs();
+        if let Some(dbuf) = self.dbuf.as_mut() {
+            if let Err(e) = dbuf.refresh() {
+                error = Some(trf("Cannot read dbuf statistics: {}", &[&e]));
+            }
+        }
+        if let Some(zfetch) = self.zfetch.as_mut() {
+            if let Err(e) = zfetch.refresh() {
+                error = Some(trf("Cannot read prefetch statistics: {}", &[&e]));
+            }
+        }
+        self.set_tick_error(error);
+        if self.txgs.is_some() {
+            self.refresh_txgs();
+        }
+        self.record_history(Instant::now());
+        if self.log_new {
+            for name in self.data.created() {
+                let _ = alert::syslog(&format!("New dataset {name}"));
+            }
+        }
+        self.export();
+    }
+
+    /// Take one of the extra samples between refreshes
+    pub fn on_sample(&mut self) {
+        if self.is_collecting() {
+            return;
+        }
+        if let Some(sampler) = self.sampler.as_mut() {
+            // Any error will be reported by the next refresh
+            let _ = sampler.sample(&self.data);
+        }
+    }
+
+    /// Feed the latest statistics to the alerts and exporters
+    fn export(&mut self) {
+        if !self.has_consumers() {
+            return;
+        }
+        // Implausible rates aren't worth alerting on or exporting
+        let elems = self
+            .data
+            .iter()
+            .filter(|e| !e.implausible)
+            .collect::<Vec<_>>();
+        let now = Instant::now();
+        let firings = self.alerts.evaluate(&elems, now, self.data.counters());
+        self.alerts.notify(&firings);
+        if let Some(capture) = self.capture.as_mut() {
+            capture.tick(&elems, self.alerts.capturing(&firings));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(summarizer) = self.journal.as_mut() {
+            summarizer.tick(&elems, now);
+        }
+        if let Some(summary) = self.summary.as_mut() {
+            summary.tick(&elems, now);
+        }
+        for sink in self.sinks.iter_mut() {
+            // Like with syslog, there's nowhere to report a failure.
+            let _ = sink.send(&elems);
+        }
+    }
+
+    /// Compute the first rates since `saved` was saved, instead of over the
+    /// first interval, if the system hasn't rebooted since.  They're exported
+    /// as soon as the first refresh finishes.
+    pub fn resume_from(&mut self, saved: SavedCounters) {
+        self.resume = Some(saved);
+    }
+
+    /// The latest raw counters, to save before exiting
+    pub fn saved_counters(
+        &self,
+    ) -> Result<Option<SavedCounters>, Box<dyn Error>> {
+        self.data.saved_counters()
+    }
+
+    pub fn set_filter(&mut self, filter: Regex) {
+        self.filter = Some(filter);
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.should_quit
+    }
+
+    pub fn sort_idx(&self) -> Option<usize> {
+        self.sort_idx
+    }
+
+    /// An App that has collected one sample, one second long, in which each
+    /// dataset did `[reads, nread, writes, nwritten, nunlinks, nunlinked]`.
+    #[cfg(all(test, feature = "tui"))]
+    pub(crate) fn with_rates(rates: &[(&str, [u64; 6])]) -> Self {
+        let mut app = App {
+            auto_threshold: 1.0,
+            auto_min: DEFAULT_AUTO_MIN,
+            ..Default::default()
+        };
+        for (i, (name, r)) in rates.iter().enumerate() {
+            let ss = Snapshot {
+                name:      (*name).to_owned(),
+                objset:    i as u64 + 1,
+                counters:  Counters::ALL,
+                reads:     r[0],
+                nread:     r[1],
+                writes:    r[2],
+                nwritten:  r[3],
+                nunlinks:  r[4],
+                nunlinked: r[5],
+            };
+            let prev = Snapshot {
+                name: ss.name.clone(),
+                objset: ss.objset,
+                ..Default::default()
+            };
+            app.data.prev.insert(prev.key(), prev);
+            app.data.cur.insert(ss.name.clone(), ss);
+        }
+        app.data.prev_ts = Some(TimeSpec::new(0, 0));
+        app.data.cur_ts = Some(TimeSpec::new(1, 0));
+        app
+    }
+}
+
+#[cfg(test)]
+mod t {
+    #[test]
+    fn parse_objset_id() {
+        assert_eq!(super::parse_objset_id("objset-0x58c"), Some(0x58c));
+        assert_eq!(super::parse_objset_id("objset-58c"), None);
+        assert_eq!(super::parse_objset_id("txgs"), None);
+    }
+
+    mod missing_pool_message {
+        use super::super::*;
+
+        fn available() -> Vec<String> {
+            vec!["tank".to_owned(), "my.pool".to_owned(), "zroot".to_owned()]
+        }
+
+        #[test]
+        fn dataset() {
+            let msg =
+                DataSource::missing_pool_message("tank/foo", &available());
+            assert!(msg.starts_with("\"tank/foo\" is a dataset"), "{msg}");
+            assert!(msg.contains("\"tank\""), "{msg}");
+        }
+
+        #[test]
+        fn suggestion() {
+            let msg = DataSource::missing_pool_message("my.poo", &available());
+            assert!(msg.ends_with("Did you mean \"my.pool\"?"), "{msg}");
+        }
+
+        /// Unusual characters are quoted, so they can be told apart
+        #[test]
+        fn quoted() {
+            let msg =
+                DataSource::missing_pool_message("pool one", &available());
+            assert_eq!(msg, "Statistics not found for pool \"pool one\".");
+        }
+    }
+
+    mod parse_mounts {
+        use super::super::*;
+
+        #[test]
+        fn zfs_only() {
+            let table = "\
+tank/a /tank/a zfs rw,noatime,xattr,noacl 0 0
+/dev/sda1 /boot ext4 rw 0 0
+tank/my\\040data /tank/my\\040data zfs rw 0 0
+";
+            let mounted = parse_mounts(table);
+            assert_eq!(mounted.len(), 2);
+            assert!(mounted.contains("tank/a"));
+            assert!(mounted.contains("tank/my data"));
+        }
+
+        #[test]
+        fn unescape() {
+            assert_eq!(unescape_octal("a\\040b\\134c"), "a b\\c");
+            assert_eq!(unescape_octal("trailing\\04"), "trailing\\04");
+            assert_eq!(unescape_octal("ünï\\011"), "ünï\t");
+        }
+
+        /// The mount table is read in the background
+        #[test]
+        fn job() {
+            let mut app = App {
+                mount_status: true,
+                ..Default::default()
+            };
+            let name = "ztop-test/not mounted".to_owned();
+            app.data.cur.insert(name.clone(), Snapshot::default());
+            app.refresh_mounts();
+            while !app.poll_jobs() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(app.is_unmounted(&name));
+            app.on_m();
+            assert!(!app.is_unmounted(&name));
+        }
+    }
+
+    mod normalize_name {
+        use super::super::*;
+
+        fn normalize(s: &str) -> Option<String> {
+            let mut name = s.to_owned();
+            normalize_name(&mut name).then_some(name)
+        }
+
+        #[test]
+        fn plain() {
+            assert_eq!(normalize("tank/foo").as_deref(), Some("tank/foo"));
+        }
+
+        #[test]
+        fn spaces() {
+            assert_eq!(
+                normalize("tank/my data").as_deref(),
+                Some("tank/my data")
+            );
+        }
+
+        #[test]
+        fn terminators() {
+            assert_eq!(normalize("tank/foo\0").as_deref(), Some("tank/foo"));
+            assert_eq!(normalize("tank/foo\n").as_deref(), Some("tank/foo"));
+        }
+
+        #[test]
+        fn control() {
+            assert_eq!(normalize("tank/f\x1bo"), None);
+        }
+    }
+
+    mod edit_distance {
+        use super::super::*;
+
+        #[test]
+        fn equal() {
+            assert_eq!(edit_distance("zroot", "zroot"), 0);
+        }
+
+        #[test]
+        fn insertion() {
+            assert_eq!(edit_distance("zrot", "zroot"), 1);
+        }
+
+        #[test]
+        fn substitution() {
+            assert_eq!(edit_distance("tenk", "tank"), 1);
+        }
+
+        #[test]
+        fn unrelated() {
+            assert_eq!(edit_distance("", "tank"), 4);
+            assert_eq!(edit_distance("boot", "tank"), 4);
+        }
+    }
+
+    mod natural_cmp {
+        use super::super::*;
+
+        #[test]
+        fn numbers() {
+            assert_eq!(natural_cmp("tank/vm9", "tank/vm10"), Ordering::Less);
+            assert_eq!(natural_cmp("tank/vm10", "tank/vm9"), Ordering::Greater);
+            assert_eq!(
+                natural_cmp("vm2/disk10", "vm2/disk9"),
+                Ordering::Greater
+            );
+        }
+
+        #[test]
+        fn equal() {
+            assert_eq!(natural_cmp("tank/vm10", "tank/vm10"), Ordering::Equal);
+        }
+
+        #[test]
+        fn leading_zeros() {
+            assert_eq!(natural_cmp("vm007", "vm7"), Ordering::Less);
+            assert_eq!(natural_cmp("vm007", "vm8"), Ordering::Less);
+        }
+
+        #[test]
+        fn prefix() {
+            assert_eq!(natural_cmp("tank", "tank/vm1"), Ordering::Less);
+            assert_eq!(natural_cmp("tank/vm1", "tank/vm1a"), Ordering::Less);
+        }
+
+        #[test]
+        fn sort() {
+            let mut v = vec!["vm10", "vm9", "vm1", "vm100", "vm", "vm1/a"];
+            v.sort_by(|x, y| natural_cmp(x, y));
+            assert_eq!(v, ["vm", "vm1", "vm1/a", "vm9", "vm10", "vm100"]);
+        }
+    }
+
+    mod is_receiving {
+        use super::super::*;
+
+        #[test]
+        fn recv() {
+            assert!(Element::named("tank/backup/%recv").is_receiving());
+        }
+
+        #[test]
+        fn normal() {
+            assert!(!Element::named("tank/backup").is_receiving());
+            assert!(!Element::named("tank/%recvd").is_receiving());
+        }
+    }
+
+    mod collapse_boot_environments {
+        use super::super::*;
+
+        fn collapse(elems: Vec<Element>, children: bool) -> Vec<(String, f64)> {
+            Element::collapse_boot_environments(elems, children)
+                .into_iter()
+                .map(|e| (e.name, e.w_s))
+                .collect()
+        }
+
+        #[test]
+        fn no_children() {
+            let elems = vec![
+                Element::writing("zroot", 1.0),
+                Element::writing("zroot/ROOT", 2.0),
+                Element::writing("zroot/ROOT/13.0", 4.0),
+                Element::writing("zroot/ROOT/14.0", 8.0),
+                Element::writing("zroot/ROOT/14.0/var", 16.0),
+                Element::writing("zroot/ROOTS", 32.0),
+                Element::writing("zroot/usr", 64.0),
+            ];
+            let expected = [
+                ("zroot".to_owned(), 1.0),
+                ("zroot/ROOT".to_owned(), 30.0),
+                ("zroot/ROOTS".to_owned(), 32.0),
+                ("zroot/usr".to_owned(), 64.0),
+            ];
+            assert_eq!(collapse(elems, false), expected);
+        }
+
+        /// With children mode, the ROOT dataset already includes its boot
+        /// environments' statistics.
+        #[test]
+        fn children() {
+            let elems = vec![
+                Element::writing("zroot", 127.0),
+                Element::writing("zroot/ROOT", 14.0),
+                Element::writing("zroot/ROOT/13.0", 4.0),
+                Element::writing("zroot/ROOT/14.0", 8.0),
+            ];
+            let expected =
+                [("zroot".to_owned(), 127.0), ("zroot/ROOT".to_owned(), 14.0)];
+            assert_eq!(collapse(elems, true), expected);
+        }
+
+        /// Two pools may each have boot environments
+        #[test]
+        fn two_pools() {
+            let elems = vec![
+                Element::writing("rpool/ROOT", 1.0),
+                Element::writing("rpool/ROOT/default", 2.0),
+                Element::writing("zroot/ROOT", 4.0),
+                Element::writing("zroot/ROOT/default", 8.0),
+            ];
+            let expected = [
+                ("rpool/ROOT".to_owned(), 3.0),
+                ("zroot/ROOT".to_owned(), 12.0),
+            ];
+            assert_eq!(collapse(elems, false), expected);
+        }
+    }
+
+    mod data_source {
+        use super::super::*;
+
+        fn snapshot(name: &str, objset: u64, nwritten: u64) -> Snapshot {
+            Snapshot {
+                name: name.to_owned(),
+                objset,
+                nwritten,
+                ..Default::default()
+            }
+        }
+
+        /// A DataSource that has written 10 bytes to tank and 20 to tank/a
+        /// during a one second interval.
+        fn data_source() -> DataSource {
+            let mut ds = DataSource::default();
+            for ss in [snapshot("tank", 54, 100), snapshot("tank/a", 55, 200)] {
+                ds.prev.insert(ss.key(), ss);
+            }
+            for ss in [snapshot("tank", 54, 110), snapshot("tank/a", 55, 220)] {
+                ds.cur.insert(ss.name.clone(), ss);
+            }
+            ds.prev_ts = Some(TimeSpec::new(0, 0));
+            ds.cur_ts = Some(TimeSpec::new(1, 0));
+            ds
+        }
+
+        fn rates(ds: &mut DataSource) -> Vec<(String, f64)> {
+            ds.iter().map(|e| (e.name, e.w_s)).collect()
+        }
+
+        #[test]
+        fn no_children() {
+            let mut ds = data_source();
+            let expected =
+                [("tank".to_owned(), 10.0), ("tank/a".to_owned(), 20.0)];
+            assert_eq!(rates(&mut ds), expected);
+        }
+
+        #[test]
+        fn children() {
+            let mut ds = data_source();
+            ds.children = true;
+            let expected =
+                [("tank".to_owned(), 30.0), ("tank/a".to_owned(), 20.0)];
+            assert_eq!(rates(&mut ds), expected);
+        }
+
+        /// A DataSource that has refreshed once, at 10 seconds, when tank had
+        /// written 110 bytes and tank/a 220
+        fn first_refresh() -> DataSource {
+            let mut ds = DataSource::default();
+            for ss in [snapshot("tank", 54, 110), snapshot("tank/a", 55, 220)] {
+                ds.cur.insert(ss.name.clone(), ss);
+            }
+            ds.cur_ts = Some(TimeSpec::new(10, 0));
+            ds
+        }
+
+        /// Saved counters become the previous sample
+        #[test]
+        fn resume() {
+            let saved = SavedCounters {
+                time:     5.0,
+                boot:     boot_time().unwrap(),
+                datasets: vec![
+                    snapshot("tank", 54, 100),
+                    snapshot("tank/a", 55, 200),
+                ],
+            };
+            let mut ds = first_refresh();
+            assert!(ds.resume(saved).unwrap());
+            let expected =
+                [("tank".to_owned(), 2.0), ("tank/a".to_owned(), 4.0)];
+            assert_eq!(rates(&mut ds), expected);
+        }
+
+        /// Counters from before a reboot, or from the future, are ignored
+        #[test]
+        fn resume_stale() {
+            let saved = |time, boot| SavedCounters {
+                time,
+                boot,
+                datasets: vec![snapshot("tank", 54, 100)],
+            };
+            let mut ds = first_refresh();
+            let boot = boot_time().unwrap();
+            assert!(!ds.resume(saved(5.0, boot - 3600.0)).unwrap());
+            assert!(!ds.resume(saved(15.0, boot)).unwrap());
+            assert!(ds.prev.is_empty());
+            assert!(ds.prev_ts.is_none());
+        }
+
+        #[test]
+        fn save_and_load() {
+            let path = std::env::temp_dir()
+                .join(format!("ztop-{}-counters.json", std::process::id()));
+            assert!(SavedCounters::load(&path).unwrap().is_none());
+            let saved = first_refresh().saved_counters().unwrap().unwrap();
+            assert_eq!(saved.time, 10.0);
+            saved.save(&path).unwrap();
+            let loaded = SavedCounters::load(&path).unwrap().unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded, saved);
+        }
+
+        #[test]
+        fn with_objsets() {
+            let tank = |id| ("tank".to_owned(), id);
+            let mut ds = DataSource::with_objsets(vec![tank(55), tank(57)]);
+            assert_eq!(ds.pools, ["tank"]);
+            assert!(ds.is_wanted(&snapshot("tank/a", 55, 0)));
+            assert!(!ds.is_wanted(&snapshot("tank", 54, 0)));
+            // The same ID in another pool is a different objset
+            assert!(!ds.is_wanted(&snapshot("zroot/a", 55, 0)));
+            ds.cur
+                .insert("tank/a".to_owned(), snapshot("tank/a", 55, 0));
+            assert_eq!(ds.missing_objsets(), [tank(57)]);
+        }
+
+        /// Internal objsets are skipped, unless requested by ID
+        #[test]
+        fn internal() {
+            let ds = DataSource::default();
+            assert!(ds.is_wanted(&snapshot("tank/a", 55, 0)));
+            assert!(!ds.is_wanted(&snapshot("$MOS", 0, 0)));
+            assert!(!ds.is_wanted(&snapshot("tank/$ORIGIN", 1, 0)));
+            assert!(!ds.is_wanted(&snapshot("", 2, 0)));
+            let ds = DataSource::with_objsets(vec![("tank".to_owned(), 1)]);
+            assert!(ds.is_wanted(&snapshot("tank/$ORIGIN", 1, 0)));
+        }
+
+        #[test]
+        fn raw_report() {
+            let mut ds = data_source();
+            ds.cur.remove("tank");
+            assert_eq!(
+                ds.raw_report(),
+                ["0x37 tank/a  reads 0 (+0)  nread 0 (+0)  writes 0 (+0)  \
+                  nwritten 220 (+20)  nunlinks 0 (+0)  nunlinked 0 (+0)"]
+            );
+        }
+
+        /// Rows of pools that could not be collected are marked stale
+        #[test]
+        fn stale() {
+            let mut ds = data_source();
+            ds.cur.insert("zroot".to_owned(), snapshot("zroot", 54, 0));
+            ds.stale.insert("tank".to_owned());
+            let stale =
+                ds.iter().map(|e| (e.name, e.stale)).collect::<Vec<_>>();
+            assert_eq!(
+                stale,
+                [
+                    ("tank".to_owned(), true),
+                    ("tank/a".to_owned(), true),
+                    ("zroot".to_owned(), false)
+                ]
+            );
+        }
+
+        /// Counters that went backwards, as after a pool is reimported, give
+        /// no rates for one interval rather than a nonsensical one
+        #[test]
+        fn reset() {
+            let mut ds = data_source();
+            ds.cur
+                .insert("tank/a".to_owned(), snapshot("tank/a", 55, 50));
+            let expected =
+                [("tank".to_owned(), 10.0), ("tank/a".to_owned(), 0.0)];
+            assert_eq!(rates(&mut ds), expected);
+        }
+
+        /// When a stale pool recovers, its rates aren't computed from the
+        /// snapshots kept during the outage
+        #[test]
+        fn recovered() {
+            let mut ds = data_source();
+            let mut cur = ds.cur.clone();
+            cur.insert("tank/a".to_owned(), snapshot("tank/a", 55, 1000));
+            cur.insert("zroot".to_owned(), snapshot("zroot", 54, 0));
+            let was_stale = HashSet::from(["tank".to_owned()]);
+            ds.advance(cur, &was_stale);
+            assert!(ds.prev.keys().all(|(pool, _)| pool == "zroot"));
+            let expected = [
+                ("tank".to_owned(), 0.0),
+                ("tank/a".to_owned(), 0.0),
+                ("zroot".to_owned(), 0.0),
+            ];
+            assert_eq!(rates(&mut ds), expected);
+        }
+
+        /// Rates faster than the pool's maximum bandwidth are marked, and
+        /// warned about only once
+        #[test]
+        fn max_bandwidth() {
+            let mut ds = data_source();
+            ds.max_bandwidth.insert("tank".to_owned(), 15.0);
+            ds.find_implausible();
+            ds.find_implausible();
+            assert_eq!(ds.warned.len(), 1);
+            let marked = ds
+                .iter()
+                .map(|e| (e.name, e.w_s, e.implausible))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                marked,
+                [
+                    ("tank".to_owned(), 10.0, false),
+                    ("tank/a".to_owned(), 20.0, true)
+                ]
+            );
+        }
+
+        /// Deletions count against the maximum bandwidth, too
+        #[test]
+        fn max_bandwidth_delete() {
+            let mut ds = data_source();
+            ds.max_bandwidth.insert("tank".to_owned(), 25.0);
+            ds.cur.get_mut("tank").unwrap().nunlinked = 100;
+            ds.find_implausible();
+            assert!(ds.implausible.contains(&("tank".to_owned(), 54)));
+            assert!(!ds.implausible.contains(&("tank".to_owned(), 55)));
+        }
+
+        /// A renamed dataset should still be matched with its previous
+        /// snapshot.
+        #[test]
+        fn renamed() {
+            let mut ds = data_source();
+            let ss = ds.cur.remove("tank/a").unwrap();
+            let ss = Snapshot {
+                name: "tank/b".to_owned(),
+                ..ss
+            };
+            ds.cur.insert(ss.name.clone(), ss);
+            let expected =
+                [("tank".to_owned(), 10.0), ("tank/b".to_owned(), 20.0)];
+            assert_eq!(rates(&mut ds), expected);
+        }
+
+        /// A dataset that was destroyed and recreated with the same name
+        /// should not be compared to the old one.
+        #[test]
+        fn recreated() {
+            let mut ds = data_source();
+            ds.cur
+                .insert("tank/a".to_owned(), snapshot("tank/a", 56, 5));
+            ds.created.insert(("tank".to_owned(), 56), NEW_REFRESHES);
+            let expected =
+                [("tank".to_owned(), 10.0), ("tank/a".to_owned(), 5.0)];
+            assert_eq!(rates(&mut ds), expected);
+        }
+
+        /// Datasets that appear after the first refresh are marked new for a
+        /// few refreshes
+        #[test]
+        fn created() {
+            let mut ds = data_source();
+            let mut cur = ds.cur.clone();
+            cur.insert("tank/b".to_owned(), snapshot("tank/b", 56, 0));
+            ds.track_created(&cur);
+            ds.prev = mem::replace(&mut ds.cur, cur)
+                .into_values()
+                .map(|ss| (ss.key(), ss))
+                .collect();
+            assert_eq!(ds.created().collect::<Vec<_>>(), ["tank/b"]);
+            let new = ds.iter().map(|e| (e.name, e.new)).collect::<Vec<_>>();
+            assert_eq!(
+                new,
+                [
+                    ("tank".to_owned(), false),
+                    ("tank/a".to_owned(), false),
+                    ("tank/b".to_owned(), true)
+                ]
+            );
+            for _ in 1..NEW_REFRESHES {
+                let cur = ds.cur.clone();
+                ds.track_created(&cur);
+                assert!(ds.created().next().is_none());
+                assert!(ds.iter().any(|e| e.new));
+            }
+            let cur = ds.cur.clone();
+            ds.track_created(&cur);
+            assert!(!ds.iter().any(|e| e.new));
+        }
+
+        #[test]
+        fn depth_limit() {
+            let mut ds = DataSource::default();
+            for name in ["tank", "tank/a", "tank/b", "tank/a/x", "tank/a/y"] {
+                ds.cur.insert(name.to_owned(), snapshot(name, 0, 0));
+            }
+            assert_eq!(ds.depth_limit(5), None);
+            assert_eq!(ds.depth_limit(4), NonZeroUsize::new(2));
+            assert_eq!(ds.depth_limit(3), NonZeroUsize::new(2));
+            assert_eq!(ds.depth_limit(2), NonZeroUsize::new(1));
+            assert_eq!(ds.depth_limit(0), NonZeroUsize::new(1));
+        }
+
+        /// Pressing d enough removes the limit
+        #[test]
+        fn more_depth() {
+            let mut app = App::default();
+            for name in ["tank", "tank/a", "tank/a/x"] {
+                app.data.cur.insert(name.to_owned(), snapshot(name, 0, 0));
+            }
+            app.depth = NonZeroUsize::new(1);
+            app.on_d(true);
+            assert_eq!(app.depth, NonZeroUsize::new(2));
+            app.on_d(true);
+            assert_eq!(app.depth, None);
+        }
+
+        /// Only datasets with children get a separate row for themselves
+        #[test]
+        fn self_rows() {
+            let mut ds = data_source();
+            ds.children = true;
+            let rows = ds.self_rows();
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0].name, "tank");
+            assert_eq!(rows[0].w_s, 10.0);
+            assert!(rows[0].self_only);
+            assert!(ds.children);
+        }
+
+        /// Snapshots are excluded by default, even from their parents'
+        /// statistics in children mode
+        #[test]
+        fn snapshots() {
+            let mut ds = data_source();
+            let ss = snapshot("tank/a@monday", 56, 300);
+            ds.prev.insert(ss.key(), ss);
+            let ss = snapshot("tank/a@monday", 56, 305);
+            ds.cur.insert(ss.name.clone(), ss);
+            ds.children = true;
+            let expected =
+                [("tank".to_owned(), 30.0), ("tank/a".to_owned(), 20.0)];
+            assert_eq!(rates(&mut ds), expected);
+            ds.toggle_snapshots();
+            let expected = [
+                ("tank".to_owned(), 35.0),
+                ("tank/a".to_owned(), 20.0),
+                ("tank/a@monday".to_owned(), 5.0),
+            ];
+            assert_eq!(rates(&mut ds), expected);
+        }
+
+        /// Toggling children mode should not discard the previous sample
+        #[test]
+        fn toggle_children() {
+            let mut ds = data_source();
+            ds.toggle_children();
+            assert!(ds.prev_ts.is_some());
+            let expected =
+                [("tank".to_owned(), 30.0), ("tank/a".to_owned(), 20.0)];
+            assert_eq!(rates(&mut ds), expected);
+            ds.toggle_children();
+            let expected =
+                [("tank".to_owned(), 10.0), ("tank/a".to_owned(), 20.0)];
+            assert_eq!(rates(&mut ds), expected);
+        }
+    }
+
+    mod elements {
+        use super::super::*;
+
+        /// Build an App whose datasets have the given lifetime bytes read,
+        /// but have been idle for the last interval.
+        fn idle_app(datasets: &[(&str, u64)]) -> App {
+            let mut app = App::default();
+            for (i, (name, nread)) in datasets.iter().enumerate() {
+                let ss = Snapshot {
+                    name: name.to_string(),
+                    objset: i as u64,
+                    nread: *nread,
+                    ..Default::default()
+                };
+                app.data.cur.insert(name.to_string(), ss);
+            }
+            app.data.prev = app
+                .data
+                .cur
+                .values()
+                .map(|ss| (ss.key(), ss.clone()))
+                .collect();
+            app.data.prev_ts = Some(TimeSpec::new(0, 0));
+            app.data.cur_ts = Some(TimeSpec::new(1, 0));
+            app
+        }
+
+        #[test]
+        fn auto_min() {
+            let mut app =
+                idle_app(&[("tank", 100), ("tank/a", 300), ("tank/b", 200)]);
+            app.auto = true;
+            app.auto_threshold = 1.0;
+            app.auto_min = 2;
+            let names = app
+                .elements()
+                .into_iter()
+                .map(|elem| elem.name)
+                .collect::<Vec<_>>();
+            assert_eq!(names, ["tank/a", "tank/b"]);
+        }
+
+        #[test]
+        fn auto_min_zero() {
+            let mut app = idle_app(&[("tank", 100)]);
+            app.auto = true;
+            app.auto_threshold = 1.0;
+            assert!(app.elements().is_empty());
+        }
+
+        /// Cycling the type filter skips snapshots unless they're included
+        #[test]
+        fn type_filter() {
+            let mut app = idle_app(&[("tank", 100), ("tank@monday", 200)]);
+            app.toggle_snapshots();
+            let kinds = |app: &mut App| {
+                app.elements()
+                    .into_iter()
+                    .map(|elem| (elem.name, elem.kind))
+                    .collect::<Vec<_>>()
+            };
+            app.cycle_type_filter();
+            let fs = ("tank".to_owned(), DatasetType::Filesystem);
+            assert_eq!(kinds(&mut app), [fs]);
+            app.cycle_type_filter();
+            assert!(kinds(&mut app).is_empty());
+            app.cycle_type_filter();
+            let snap = ("tank@monday".to_owned(), DatasetType::Snapshot);
+            assert_eq!(kinds(&mut app), [snap]);
+            app.toggle_snapshots();
+            assert_eq!(app.type_filter, None);
+            app.cycle_type_filter();
+            app.cycle_type_filter();
+            app.cycle_type_filter();
+            assert_eq!(app.type_filter, None);
+        }
+
+        /// Vanished datasets and file systems are classified again
+        #[test]
+        fn type_cache() {
+            let mut app = idle_app(&[("tank/fs", 100), ("tank/vol", 200)]);
+            for (name, kind) in [
+                ("tank/fs", DatasetType::Filesystem),
+                ("tank/vol", DatasetType::Volume),
+                ("tank/gone", DatasetType::Volume),
+            ] {
+                app.type_cache.insert(name.to_owned(), kind);
+            }
+            app.prune_type_cache();
+            let cached = app.type_cache.keys().collect::<Vec<_>>();
+            assert_eq!(cached, ["tank/vol"]);
+        }
+    }
+
+    mod sort_cycle {
+        use super::super::*;
+
+        /// Press a key 9 times, and record the sort column after each
+        fn cycle(app: &mut App, f: fn(&mut App)) -> Vec<Option<usize>> {
+            (0..9)
+                .map(|_| {
+                    f(app);
+                    app.sort_idx()
+                })
+                .collect()
+        }
+
+        /// Hidden totals columns are skipped, and it wraps through hotness
+        /// and then unsorted
+        #[test]
+        fn plus() {
+            let mut app = App::default();
+            let expected =
+                [0, 1, 2, 3, 4, 5, 6, col::DATASET, HOTNESS].map(Some);
+            assert_eq!(cycle(&mut app, App::on_plus), expected);
+            app.on_plus();
+            assert_eq!(app.sort_idx(), None);
+            assert!(app.show_sort_hint());
+        }
+
+        /// Columns whose counters this kernel lacks are skipped
+        #[test]
+        fn plus_missing_counters() {
+            let mut app = App::default();
+            let ss = Snapshot {
+                name: "tank".to_owned(),
+                counters: Counters::READS
+                    | Counters::NREAD
+                    | Counters::WRITES
+                    | Counters::NWRITTEN,
+                ..Default::default()
+            };
+            app.data.cur.insert(ss.name.clone(), ss);
+            let expected = [0, 1, 2, 3, col::DATASET, HOTNESS].map(Some);
+            assert_eq!(cycle(&mut app, App::on_plus)[..6], expected);
+        }
+
+        /// The Type column can be sorted once it's shown
+        #[test]
+        fn minus_types() {
+            let mut app = App::default();
+            app.toggle_types();
+            let expected =
+                [HOTNESS, col::DATASET, col::TYPE, col::PENDING_D].map(Some);
+            assert_eq!(cycle(&mut app, App::on_minus)[..4], expected);
+            app.sort_idx = Some(col::TYPE);
+            app.toggle_types();
+            assert_eq!(app.sort_idx(), None);
+        }
+
+        /// Columns hidden by a narrow terminal are skipped
+        #[test]
+        fn minus_narrow() {
+            let mut app = App::default();
+            app.set_narrow(true);
+            app.totals = true;
+            let expected = [HOTNESS, col::DATASET, 5, 4, 3, 2, 1, 0].map(Some);
+            assert_eq!(cycle(&mut app, App::on_minus)[..8], expected);
+            assert_eq!(app.sort_idx(), None);
+        }
+
+        /// Hotness weighs each kind of bandwidth
+        #[test]
+        fn hotness() {
+            let app = App {
+                hotness: HotnessWeights {
+                    read:   1.0,
+                    write:  4.0,
+                    delete: 0.0,
+                },
+                ..Default::default()
+            };
+            let reader = Element {
+                r_s: 300.0,
+                d_s: 1000.0,
+                ..Default::default()
+            };
+            let writer = Element {
+                w_s: 100.0,
+                ..Default::default()
+            };
+            assert!(app.hotness(&writer) > app.hotness(&reader));
+        }
+    }
+
+    mod saved_sort {
+        use super::super::*;
+
+        /// Each view's sort is saved and restored separately
+        #[test]
+        fn round_trip() {
+            let app = App {
+                sort_idx: Some(HOTNESS),
+                vdev_sort: Some(3),
+                vdev_reverse: true,
+                ..Default::default()
+            };
+            let saved = app.sorts();
+            assert_eq!(saved["datasets"].column, "hotness");
+            assert!(!saved["datasets"].reverse);
+            assert_eq!(saved["vdevs"].column, "kB/s r");
+            let mut app = App::default();
+            app.restore_sorts(&saved, true);
+            assert_eq!(app.sort_idx(), Some(HOTNESS));
+            assert!(!app.reverse);
+            assert_eq!(app.vdev_sort(), Some(3));
+            assert!(app.vdev_reverse);
+        }
+
+        /// A sort given on the command line wins over the saved one
+        #[test]
+        fn command_line() {
+            let mut saved = BTreeMap::new();
+            let sort = SavedSort {
+                column:  "w/s".to_owned(),
+                reverse: true,
+            };
+            saved.insert("datasets".to_owned(), sort.clone());
+            saved.insert("vdevs".to_owned(), sort);
+            let mut app = App {
+                sort_idx: Some(col::R_S),
+                ..Default::default()
+            };
+            app.restore_sorts(&saved, false);
+            assert_eq!(app.sort_idx(), Some(col::R_S));
+            assert!(!app.reverse);
+            assert_eq!(app.vdev_sort(), Some(4));
+        }
+
+        /// A column that can't be sorted now, like a hidden one, isn't
+        /// restored
+        #[test]
+        fn hidden() {
+            let mut saved = BTreeMap::new();
+            let sort = SavedSort {
+                column:  "Type".to_owned(),
+                reverse: false,
+            };
+            saved.insert("datasets".to_owned(), sort);
+            let mut app = App::default();
+            app.restore_sorts(&saved, true);
+            assert_eq!(app.sort_idx(), None);
+        }
+    }
+
+    mod l2arc {
+        use super::super::*;
+
+        #[test]
+        fn rates() {
+            let mut l2arc = L2arc::default();
+            let t0 = Instant::now();
+            let stats = ArcStats {
+                l2_size:        Some(1 << 30),
+                l2_hits:        Some(100),
+                l2_misses:      Some(50),
+                l2_read_bytes:  Some(1 << 20),
+                l2_write_bytes: Some(0),
+            };
+            l2arc.push(t0, stats);
+            assert_eq!(l2arc.rates(), None);
+            let stats = ArcStats {
+                l2_size: Some(2 << 30),
+                l2_hits: Some(300),
+                l2_misses: Some(60),
+                l2_read_bytes: Some(3 << 20),
+                ..stats
+            };
+            l2arc.push(t0 + Duration::from_secs(2), stats);
+            let rates = l2arc.rates().unwrap();
+            assert_eq!(rates.size, Some(2 << 30));
+            assert_eq!(rates.hits, Some(100.0));
+            assert_eq!(rates.misses, Some(5.0));
+            assert_eq!(rates.read, Some(f64::from(1 << 20)));
+            assert_eq!(rates.written, Some(0.0));
+        }
+    }
+
+    mod capacity {
+        use super::super::*;
+
+        #[test]
+        fn parse() {
+            let s =
+                "tank\t1000\t600\t400\t60\t12\nzroot\t2000\t100\t1900\t5\t-\n";
+            let pools = parse_capacity(s).unwrap();
+            assert_eq!(pools.len(), 2);
+            assert_eq!(
+                pools[0],
+                PoolCapacity {
+                    name:  "tank".to_owned(),
+                    size:  1000,
+                    alloc: 600,
+                    free:  400,
+                    cap:   60,
+                    frag:  Some(12),
+                }
+            );
+            assert_eq!(pools[1].frag, None);
+        }
+
+        #[test]
+        fn garbage() {
+            assert!(parse_capacity("tank\tbig\n").is_err());
+        }
+
+        /// The listing arrives in the background
+        #[test]
+        fn job() {
+            let mut app = App {
+                capacity: Some(Vec::new()),
+                ..Default::default()
+            };
+            app.capacity_job
+                .start(|| parse_capacity("tank\t1000\t600\t400\t60\t12\n"));
+            while !app.poll_jobs() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(!app.has_jobs());
+            assert_eq!(app.capacity().unwrap()[0].name, "tank");
+        }
+    }
+
+    mod dedup {
+        use super::super::*;
+
+        const STATUS: &str = "  pool: tank
+ state: ONLINE
+config:
+
+	NAME        STATE     READ WRITE CKSUM
+	tank        ONLINE       0     0     0
+	  da0       ONLINE       0     0     0
+
+errors: No known data errors
+
+ dedup: DDT entries 1000, size 320 on disk, 192 in core
+
+bucket              allocated                       referenced
+______   ______________________________   ______________________________
+refcnt   blocks   LSIZE   PSIZE   DSIZE   blocks   LSIZE   PSIZE   DSIZE
+------   ------   -----   -----   -----   ------   -----   -----   -----
+     1      900    112M    112M    112M      900    112M    112M    112M
+     2      100   12.5M   12.5M   12.5M      200     25M     25M     25M
+ Total     1000    125M    125M    125M     1100    137M    137M    137M
+
+  pool: zroot
+ state: ONLINE
+config:
+
+	NAME        STATE     READ WRITE CKSUM
+	zroot       ONLINE       0     0     0
+	  ada0p3    ONLINE       0     0     0
+
+errors: No known data errors
+
+ dedup: no DDT entries
+";
+
+        #[test]
+        fn parse() {
+            let list = "tank\t1.10x\nzroot\t1.00\n";
+            let pools = parse_dedup(STATUS, list).unwrap();
+            assert_eq!(
+                pools,
+                [
+                    PoolDedup {
+                        name:    "tank".to_owned(),
+                        entries: 1000,
+                        disk:    320_000,
+                        core:    192_000,
+                        ratio:   Some(1.1),
+                    },
+                    PoolDedup {
+                        name:    "zroot".to_owned(),
+                        entries: 0,
+                        disk:    0,
+                        core:    0,
+                        ratio:   Some(1.0),
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn garbage() {
+            let status = "  pool: tank\n dedup: DDT entries many\n";
+            assert!(parse_dedup(status, "tank\t1.00x\n").is_err());
+            assert!(parse_dedup("", "tank\n").is_err());
+        }
+
+        /// The listing arrives in the background
+        #[test]
+        fn job() {
+            let mut app = App {
+                dedup: Some(Vec::new()),
+                ..Default::default()
+            };
+            app.dedup_job.start(|| parse_dedup(STATUS, "tank\t1.10x\n"));
+            while !app.poll_jobs() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(!app.has_jobs());
+            assert_eq!(app.dedup().unwrap()[0].entries, 1000);
+        }
+    }
+
+    mod space {
+        use super::super::*;
+
+        #[test]
+        fn parse() {
+            let s = "tank\t3000\t500\t100\ntank/my fs\t2000\t500\t2000\n";
+            let space = parse_space(s).unwrap();
+            assert_eq!(space.len(), 2);
+            assert_eq!(
+                space["tank/my fs"],
+                Space {
+                    used:  2000,
+                    avail: 500,
+                    refer: 2000,
+                }
+            );
+        }
+
+        #[test]
+        fn garbage() {
+            assert!(parse_space("tank\t-\t500\t100\n").is_err());
+        }
+
+        /// The columns are hidden unless shown, and each dataset's space is
+        /// merged into its element
+        #[test]
+        fn merge() {
+            let mut app = App::default();
+            assert!(app.is_hidden(9));
+            let mut space = HashMap::new();
+            let tank = Space {
+                used:  3000,
+                avail: 500,
+                refer: 100,
+            };
+            space.insert("tank".to_owned(), tank);
+            app.space = Some(space);
+            assert!(!app.is_hidden(9));
+            for name in ["tank", "tank/a"] {
+                let ss = Snapshot {
+                    name: name.to_owned(),
+                    ..Default::default()
+                };
+                app.data.cur.insert(ss.name.clone(), ss);
+            }
+            app.data.prev_ts = Some(TimeSpec::new(0, 0));
+            app.data.cur_ts = Some(TimeSpec::new(1, 0));
+            let elems = app.elements();
+            assert_eq!(elems[0].space, Some(tank));
+            assert_eq!(elems[1].space, None);
+        }
+
+        fn wait_for_jobs(app: &mut App) {
+            while !app.poll_jobs() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(!app.has_jobs());
+        }
+
+        /// The listing arrives in the background
+        #[test]
+        fn job() {
+            let mut app = App {
+                space: Some(HashMap::new()),
+                ..Default::default()
+            };
+            app.space_job
+                .start(|| parse_space("tank\t3000\t500\t100\n"));
+            assert!(app.has_jobs());
+            wait_for_jobs(&mut app);
+            assert_eq!(app.space.as_ref().unwrap()["tank"].used, 3000);
+        }
+
+        /// A listing that finishes after the columns were hidden is discarded
+        #[test]
+        fn job_hidden() {
+            let mut app = App {
+                space: Some(HashMap::new()),
+                ..Default::default()
+            };
+            app.space_job
+                .start(|| parse_space("tank\t3000\t500\t100\n"));
+            app.toggle_space();
+            wait_for_jobs(&mut app);
+            assert!(app.space.is_none());
+        }
+
+        #[test]
+        fn job_error() {
+            let mut app = App {
+                space: Some(HashMap::new()),
+                ..Default::default()
+            };
+            app.space_job.start(|| Err("zfs list: oops".to_owned()));
+            wait_for_jobs(&mut app);
+            assert_eq!(app.error.as_deref(), Some("zfs list: oops"));
+            assert!(app.space.is_some());
+        }
+    }
+
+    mod encryption {
+        use super::super::*;
+
+        const GET: &str = "\
+tank\tencryption\toff
+tank\tkeystatus\t-
+tank\tmounted\tyes
+tank/locked\tencryption\taes-256-gcm
+tank/locked\tkeystatus\tunavailable
+tank/locked\tmounted\tno
+tank/home\tencryption\taes-256-gcm
+tank/home\tkeystatus\tavailable
+tank/home\tmounted\tyes
+tank/spare\tencryption\taes-256-gcm
+tank/spare\tkeystatus\tavailable
+tank/spare\tmounted\tno
+tank/vol\tencryption\taes-256-gcm
+tank/vol\tkeystatus\tavailable
+tank/vol\tmounted\t-
+";
+
+        #[test]
+        fn parse() {
+            let enc = parse_encryption(GET).unwrap();
+            assert_eq!(enc.len(), 5);
+            assert_eq!(enc["tank"], Encryption::Off);
+            assert_eq!(enc["tank/locked"], Encryption::Locked);
+            assert_eq!(enc["tank/home"], Encryption::Unlocked);
+            assert_eq!(enc["tank/spare"], Encryption::Unmounted);
+            assert_eq!(enc["tank/vol"], Encryption::Unlocked);
+        }
+
+        #[test]
+        fn garbage() {
+            assert!(parse_encryption("tank\tencryption\n").is_err());
+        }
+
+        fn app() -> App {
+            let mut app = App::default();
+            for name in ["tank", "tank/home", "tank/locked", "tank/new"] {
+                let ss = Snapshot {
+                    name: name.to_owned(),
+                    ..Default::default()
+                };
+                app.data.cur.insert(ss.name.clone(), ss);
+            }
+            app.data.prev_ts = Some(TimeSpec::new(0, 0));
+            app.data.cur_ts = Some(TimeSpec::new(1, 0));
+            app
+        }
+
+        fn names(app: &mut App) -> Vec<String> {
+            app.elements().into_iter().map(|e| e.name).collect()
+        }
+
+        /// The column is hidden unless shown, and each dataset's status is
+        /// merged into its element
+        #[test]
+        fn merge() {
+            let mut app = app();
+            assert!(app.is_hidden(col::ENC));
+            app.show_enc = true;
+            app.encryption = Some(parse_encryption(GET).unwrap());
+            assert!(!app.is_hidden(col::ENC));
+            let elems = app.elements();
+            assert_eq!(elems[0].encryption, Some(Encryption::Off));
+            assert_eq!(elems[1].encryption, Some(Encryption::Unlocked));
+            assert_eq!(elems[2].encryption, Some(Encryption::Locked));
+            // Created since the last listing
+            assert_eq!(elems[3].encryption, None);
+        }
+
+        #[test]
+        fn filter() {
+            let mut app = app();
+            app.encryption = Some(parse_encryption(GET).unwrap());
+            app.enc_filter = Some(EncryptionFilter::Encrypted);
+            assert_eq!(names(&mut app), ["tank/home", "tank/locked"]);
+            app.enc_filter = Some(EncryptionFilter::Unlocked);
+            assert_eq!(names(&mut app), ["tank/home"]);
+        }
+
+        /// The status is only listed while the column or the filter needs it
+        #[test]
+        fn cycle() {
+            let mut app = app();
+            app.cycle_encryption_filter();
+            assert_eq!(app.enc_filter, Some(EncryptionFilter::Encrypted));
+            assert!(app.encryption.is_some());
+            app.cycle_encryption_filter();
+            assert_eq!(app.enc_filter, Some(EncryptionFilter::Unlocked));
+            app.toggle_encryption();
+            app.cycle_encryption_filter();
+            assert_eq!(app.enc_filter, None);
+            assert!(app.encryption.is_some());
+            app.toggle_encryption();
+            assert!(app.encryption.is_none());
+        }
+    }
+
+    mod delegated {
+        use super::super::*;
+
+        fn app() -> App {
+            let mut app = App::default();
+            for name in ["tank", "tank/jail", "tank/jail/www", "tank/other"] {
+                let ss = Snapshot {
+                    name: name.to_owned(),
+                    ..Default::default()
+                };
+                app.data.cur.insert(ss.name.clone(), ss);
+            }
+            app.data.prev_ts = Some(TimeSpec::new(0, 0));
+            app.data.cur_ts = Some(TimeSpec::new(1, 0));
+            app
+        }
+
+        fn names(app: &mut App) -> Vec<String> {
+            app.elements().into_iter().map(|e| e.name).collect()
+        }
+
+        #[test]
+        fn filter() {
+            let mut app = app();
+            assert_eq!(names(&mut app).len(), 4);
+            app.visible = Some(HashSet::new());
+            assert!(names(&mut app).is_empty());
+            app.visible = Some(
+                ["tank", "tank/jail", "tank/jail/www"]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect(),
+            );
+            assert_eq!(names(&mut app), ["tank", "tank/jail", "tank/jail/www"]);
+        }
+
+        #[test]
+        fn snapshots() {
+            let mut app = app();
+            app.data.snapshots = true;
+            let ss = Snapshot {
+                name: "tank/jail@daily".to_owned(),
+                ..Default::default()
+            };
+            app.data.cur.insert(ss.name.clone(), ss);
+            app.visible = Some(["tank/jail".to_owned()].into_iter().collect());
+            assert_eq!(names(&mut app), ["tank/jail", "tank/jail@daily"]);
+        }
+    }
+
+    mod samples {
+        use super::super::*;
+
+        /// Read tank's counters again, `secs` seconds after the start, when
+        /// it has read `nread` bytes in all
+        fn step(data: &mut DataSource, secs: i64, nread: u64) {
+            let ss = Snapshot {
+                name: "tank".to_owned(),
+                nread,
+                ..Default::default()
+            };
+            data.advance([(ss.name.clone(), ss)].into(), &HashSet::new());
+            data.prev_ts = data.cur_ts.replace(TimeSpec::new(secs, 0));
+        }
+
+        fn r_s(app: &mut App) -> f64 {
+            app.elements()[0].r_s
+        }
+
+        /// Over a 4 second interval, read 100 B/s for a second, nothing for
+        /// two, and then 500 B/s
+        fn app() -> App {
+            let mut app = App::default();
+            app.set_samples(4);
+            step(&mut app.data, 0, 0);
+            let sampler = app.sampler.as_mut().unwrap();
+            sampler.restart(&app.data);
+            for (secs, nread) in [(1, 100), (2, 100), (3, 100)] {
+                step(&mut sampler.data, secs, nread);
+                sampler.fold();
+            }
+            step(&mut app.data, 4, 600);
+            app.sampler.as_mut().unwrap().finish(&app.data);
+            app
+        }
+
+        #[test]
+        fn extremes() {
+            let mut app = app();
+            assert_eq!(r_s(&mut app), 150.0);
+            app.cycle_statistic();
+            assert_eq!(app.statistic(), Statistic::Max);
+            assert_eq!(r_s(&mut app), 500.0);
+            app.cycle_statistic();
+            assert_eq!(r_s(&mut app), 0.0);
+            app.cycle_statistic();
+            assert_eq!(r_s(&mut app), 150.0);
+        }
+
+        /// The next interval starts from the refresh that ended this one
+        #[test]
+        fn restart() {
+            let mut app = app();
+            let sampler = app.sampler.as_ref().unwrap();
+            assert!(sampler.extremes.is_empty());
+            assert_eq!(sampler.data.cur_ts, Some(TimeSpec::new(4, 0)));
+            assert_eq!(sampler.data.cur["tank"].nread, 600);
+            // Before any sample, the interval's extremes are its mean
+            step(&mut app.data, 8, 1000);
+            app.sampler.as_mut().unwrap().finish(&app.data);
+            app.set_statistic(Statistic::Max);
+            assert_eq!(r_s(&mut app), 100.0);
+        }
+
+        #[test]
+        fn one_sample() {
+            let mut app = App::default();
+            app.set_samples(1);
+            assert_eq!(app.samples(), 1);
+            app.set_statistic(Statistic::Max);
+            assert_eq!(app.statistic(), Statistic::Mean);
+            app.cycle_statistic();
+            assert_eq!(app.statistic(), Statistic::Mean);
+        }
+    }
+
+    mod dbuf {
+        use super::super::*;
+
+        /// The size is reported as-is, not as a rate
+        #[test]
+        fn rates() {
+            let mut dbuf = Dbuf::default();
+            let t0 = Instant::now();
+            let stats = DbufStats {
+                size:   Some(1 << 20),
+                hits:   Some(100),
+                misses: Some(10),
+                evicts: Some(0),
+            };
+            dbuf.push(t0, stats);
+            assert_eq!(dbuf.rates(), None);
+            let stats = DbufStats {
+                size:   Some(1 << 21),
+                hits:   Some(300),
+                misses: Some(30),
+                evicts: Some(4),
+            };
+            dbuf.push(t0 + Duration::from_secs(2), stats);
+            let rates = dbuf.rates().unwrap();
+            assert_eq!(rates.size, Some(1 << 21));
+            assert_eq!(rates.hits, Some(100.0));
+            assert_eq!(rates.misses, Some(10.0));
+            assert_eq!(rates.evicts, Some(2.0));
+        }
+    }
+
+    mod zfetch {
+        use super::super::*;
+
+        #[test]
+        fn rates() {
+            let mut zfetch = Zfetch::default();
+            let t0 = Instant::now();
+            let stats = ZfetchStats {
+                hits:        Some(1000),
+                misses:      Some(500),
+                max_streams: Some(7),
+            };
+            zfetch.push(t0, stats);
+            assert_eq!(zfetch.rates(), None);
+            let stats = ZfetchStats {
+                hits:        Some(1400),
+                misses:      Some(520),
+                max_streams: Some(7),
+            };
+            zfetch.push(t0 + Duration::from_secs(4), stats);
+            let rates = zfetch.rates().unwrap();
+            assert_eq!(rates.hits, Some(100.0));
+            assert_eq!(rates.misses, Some(5.0));
+            assert_eq!(rates.max_streams, Some(0.0));
+        }
+
+        /// A counter that this version of ZFS lacks has no rate, rather than
+        /// failing the whole panel
+        #[test]
+        fn missing() {
+            let mut zfetch = Zfetch::default();
+            let t0 = Instant::now();
+            let stats = ZfetchStats {
+                hits:        Some(1000),
+                misses:      Some(500),
+                max_streams: None,
+            };
+            zfetch.push(t0, stats);
+            zfetch.push(t0 + Duration::from_secs(1), stats);
+            let rates = zfetch.rates().unwrap();
+            assert_eq!(rates.hits, Some(0.0));
+            assert_eq!(rates.max_streams, None);
+        }
+    }
+
+    mod txgs {
+        use super::super::*;
+
+        const SAMPLE: &str = "18 0 0x01 100 11200 6195457399 29917338318432
+txg      birth            state ndirty       nread        nwritten     reads    writes   otime        qtime        wtime        stime
+7854924  29905237818003   C     4194304      0            8388608      0        120      5000107597   6079         28547        21591000
+7854925  29910237925600   C     0            0            0            0        0        4999979062   5838         23911        19372
+7854926  29915237904662   S     0            0            0            0        0        5000062478   5447         27130        0
+7854927  29920237967140   O     0            0            0            0        0        0            0            0            0
+";
+
+        /// The newest committed txg is reported, not the open or syncing ones
+        #[test]
+        fn newest_committed() {
+            let stats = parse_txgs(SAMPLE).unwrap();
+            assert_eq!(stats.txg, 7854925);
+            assert_eq!(stats.otime, Duration::from_nanos(4999979062));
+            assert_eq!(stats.stime, Duration::from_nanos(19372));
+        }
+
+        #[test]
+        fn fields() {
+            let text = SAMPLE.replace(" C ", " X ").replacen(" X ", " C ", 1);
+            let stats = parse_txgs(&text).unwrap();
+            assert_eq!(stats.txg, 7854924);
+            assert_eq!(stats.ndirty, 4194304);
+            assert_eq!(stats.nwritten, 8388608);
+            assert_eq!(stats.qtime, Duration::from_nanos(6079));
+            assert_eq!(stats.wtime, Duration::from_nanos(28547));
+            assert_eq!(stats.stime, Duration::from_micros(21591));
+        }
+
+        /// With zfs_txg_history=0, the kstat has no rows
+        #[test]
+        fn empty() {
+            assert_eq!(parse_txgs(""), None);
+            let header = SAMPLE.lines().take(2).collect::<Vec<_>>().join("\n");
+            assert_eq!(parse_txgs(&header), None);
+        }
+    }
+
+    mod command_line {
+        use super::super::*;
+
+        fn app() -> App {
+            App {
+                auto_threshold: 1.0,
+                auto_min: DEFAULT_AUTO_MIN,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn defaults() {
+            let cmd = app().command_line(Duration::from_secs(1));
+            assert_eq!(cmd, "ztop");
+        }
+
+        #[test]
+        fn everything() {
+            let mut app = App {
+                auto: true,
+                depth: NonZeroUsize::new(2),
+                filter: Some(Regex::new("vm.*").unwrap()),
+                reverse: true,
+                sort_idx: Some(3),
+                ..app()
+            };
+            app.data.pools = vec!["tank".to_owned()];
+            let cmd = app.command_line(Duration::from_millis(500));
+            assert_eq!(
+                cmd,
+                "ztop -a -r -d 2 -f 'vm.*' -s 'kB/s w' -t 500ms tank"
+            );
+        }
+
+        #[test]
+        fn hotness() {
+            let app = App {
+                sort_idx: Some(HOTNESS),
+                ..app()
+            };
+            let cmd = app.command_line(Duration::from_secs(5));
+            assert_eq!(cmd, "ztop -s hotness -t 5s");
+        }
+
+        #[test]
+        fn quote() {
+            assert_eq!(shell_quote("tank/vm-1"), "tank/vm-1");
+            assert_eq!(shell_quote("it's"), r"'it'\''s'");
+            assert_eq!(shell_quote(""), "''");
+        }
+    }
+
+    mod sets {
+        use super::super::*;
+
+        #[test]
+        fn unknown() {
+            let mut app = App::default();
+            app.sets.insert("db".to_owned(), DatasetSet::default());
+            assert_eq!(app.set_names().collect::<Vec<_>>(), ["db"]);
+            assert!(!app.select_set("web"));
+        }
+
+        /// The new set's statistics are collected in the background
+        #[test]
+        fn background() {
+            let mut app = App::default();
+            let set = DatasetSet {
+                pools: vec!["tank".to_owned()],
+                ..Default::default()
+            };
+            app.sets.insert("db".to_owned(), set);
+            assert!(app.select_set("db"));
+            assert!(app.is_collecting());
+            assert_eq!(app.data.pools, ["tank"]);
+        }
+    }
+
+    mod properties {
+        use super::super::*;
+
+        #[test]
+        fn parse() {
+            let s = "type\tfilesystem\t-\nmountpoint\t/mnt/my fs\tlocal\n";
+            let props = parse_properties(s).unwrap();
+            assert_eq!(props.len(), 2);
+            assert_eq!(
+                props[1],
+                Property {
+                    name:   "mountpoint".to_owned(),
+                    value:  "/mnt/my fs".to_owned(),
+                    source: "local".to_owned(),
+                }
+            );
+        }
+
+        #[test]
+        fn garbage() {
+            assert!(parse_properties("type\tfilesystem\n").is_err());
+        }
+    }
+
+    mod total {
+        use super::super::*;
+
+        #[test]
+        fn no_children() {
+            let app = App::default();
+            let elems = [
+                Element::writing("tank", 1.0),
+                Element::writing("tank/a", 2.0),
+            ];
+            assert_eq!(app.total(&elems).w_s, 3.0);
+        }
+
+        /// Parents' rows already include their children's statistics
+        #[test]
+        fn children() {
+            let mut app = App::default();
+            app.data.children = true;
+            let elems = [
+                Element::writing("tank", 3.0),
+                Element::writing("tank/a", 2.0),
+                Element::writing("tank/a/b", 1.0),
+                Element::writing("zroot/x", 5.0),
+            ];
+            assert_eq!(app.total(&elems).w_s, 8.0);
+        }
+
+        #[test]
+        fn self_rows() {
+            let mut app = App::default();
+            app.data.children = true;
+            let own = Element {
+                self_only: true,
+                ..Element::writing("tank", 1.0)
+            };
+            let elems = [
+                Element::writing("tank", 3.0),
+                own,
+                Element::writing("tank/a", 2.0),
+            ];
+            assert_eq!(app.total(&elems).w_s, 3.0);
+        }
+    }
+
+    mod graph {
+        use super::super::*;
+
+        fn app() -> App {
+            let mut app = App::default();
+            let written = [("tank", 1000), ("zroot", 500)];
+            for (i, (name, nwritten)) in written.into_iter().enumerate() {
+                let ss = Snapshot {
+                    name: name.to_owned(),
+                    objset: i as u64,
+                    ..Default::default()
+                };
+                app.data.prev.insert(ss.key(), ss.clone());
+                let ss = Snapshot { nwritten, ..ss };
+                app.data.cur.insert(ss.name.clone(), ss);
+            }
+            app.data.prev_ts = Some(TimeSpec::new(0, 0));
+            app.data.cur_ts = Some(TimeSpec::new(1, 0));
+            app.toggle_graph();
+            app
+        }
+
+        #[test]
+        fn toggle() {
+            let mut app = App::default();
+            assert!(app.graph().is_none());
+            app.toggle_graph();
+            assert!(app.graph().unwrap().samples().is_empty());
+            app.toggle_graph();
+            assert!(app.graph().is_none());
+        }
+
+        /// With nothing selected, the graph shows the total
+        #[test]
+        fn total() {
+            let mut app = app();
+            app.record_history(Instant::now());
+            let history = app.graph().unwrap();
+            assert_eq!(history.subject(), None);
+            assert_eq!(history.samples()[0].2, 1500.0);
+        }
+
+        /// Selecting another dataset starts the graph over
+        #[test]
+        fn selected() {
+            let mut app = app();
+            app.record_history(Instant::now());
+            app.on_down();
+            app.record_history(Instant::now());
+            app.record_history(Instant::now());
+            let history = app.graph().unwrap();
+            assert_eq!(history.subject(), Some("tank"));
+            assert_eq!(history.samples().len(), 2);
+            assert_eq!(history.samples()[1].2, 1000.0);
+        }
+    }
+
+    mod selection {
+        use super::super::*;
+
+        fn app() -> App {
+            let mut app = App::default();
+            for name in ["tank", "tank/a", "tank/b"] {
+                let ss = Snapshot {
+                    name: name.to_owned(),
+                    ..Default::default()
+                };
+                app.data.cur.insert(ss.name.clone(), ss);
+            }
+            app.data.prev_ts = Some(TimeSpec::new(0, 0));
+            app.data.cur_ts = Some(TimeSpec::new(1, 0));
+            app
+        }
+
+        #[test]
+        fn down() {
+            let mut app = app();
+            app.on_down();
+            assert_eq!(app.selected(), Some("tank"));
+            app.on_down();
+            app.on_down();
+            app.on_down();
+            assert_eq!(app.selected(), Some("tank/b"));
+        }
+
+        #[test]
+        fn up() {
+            let mut app = app();
+            app.on_up();
+            assert_eq!(app.selected(), Some("tank/b"));
+            app.on_up();
+            app.on_up();
+            app.on_up();
+            assert_eq!(app.selected(), Some("tank"));
+        }
+
+        /// If the selected dataset is filtered out, start over
+        #[test]
+        fn vanished() {
+            let mut app = app();
+            app.on_up();
+            app.set_filter(Regex::new("a$").unwrap());
+            app.on_down();
+            assert_eq!(app.selected(), Some("tank/a"));
+        }
+    }
+
+    mod breakdown {
+        use super::super::*;
+
+        fn app(children: bool) -> App {
+            let mut app = App::default();
+            let written = [
+                ("tank", 10),
+                ("tank/a", 20),
+                ("tank/a/x", 5),
+                ("tank/b", 40),
+            ];
+            for (i, (name, nwritten)) in written.into_iter().enumerate() {
+                let ss = Snapshot {
+                    name: name.to_owned(),
+                    objset: i as u64,
+                    ..Default::default()
+                };
+                app.data.prev.insert(ss.key(), ss.clone());
+                let ss = Snapshot { nwritten, ..ss };
+                app.data.cur.insert(ss.name.clone(), ss);
+            }
+            app.data.children = children;
+            app.data.prev_ts = Some(TimeSpec::new(0, 0));
+            app.data.cur_ts = Some(TimeSpec::new(1, 0));
+            app
+        }
+
+        /// Immediate children include their own children, and the parent's
+        /// own I/O is a self row
+        #[test]
+        fn children() {
+            let mut app = app(true);
+            let v = app
+                .breakdown("tank")
+                .into_iter()
+                .map(|e| (e.name, e.w_s, e.self_only))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                v,
+                [
+                    ("tank/b".to_owned(), 40.0, false),
+                    ("tank/a".to_owned(), 25.0, false),
+                    ("tank".to_owned(), 10.0, true),
+                ]
+            );
+        }
+
+        #[test]
+        fn leaf() {
+            let mut app = app(true);
+            assert!(app.breakdown("tank/b").is_empty());
+        }
+
+        #[test]
+        fn no_children() {
+            let mut app = app(false);
+            assert!(app.breakdown("tank").is_empty());
+        }
+    }
+
+    mod reload {
+        use super::super::*;
+
+        /// Settings from the file are replaced, but not those from the
+        /// command line
+        #[test]
+        fn keeps_command_line() {
+            let mut app = App::default();
+            app.set_filter(Regex::new("db").unwrap());
+            let s = r#"
+                [sets.web]
+                pools = ["tank"]
+
+                [[alert]]
+                metric = "w_s"
+                threshold = 100
+            "#;
+            let config: Config = toml::from_str(s).unwrap();
+            app.reload_config(config, Vec::new());
+            assert_eq!(app.set_names().collect::<Vec<_>>(), ["web"]);
+            assert!(!app.alerts.is_empty());
+            assert_eq!(app.filter.as_ref().unwrap().as_str(), "db");
+            assert_eq!(
+                app.notice().as_deref(),
+                Some("Reloaded the configuration file")
+            );
+        }
+    }
+
+    mod tick_error {
+        use super::super::*;
+
+        /// A dismissed error stays dismissed while it persists
+        #[test]
+        fn dismissed() {
+            let mut app = App::default();
+            app.set_tick_error(Some("tank failed".to_owned()));
+            app.dismiss_error();
+            app.set_tick_error(Some("tank failed".to_owned()));
+            assert_eq!(app.error(), None);
+            app.set_tick_error(Some("zroot failed".to_owned()));
+            assert_eq!(app.error(), Some("zroot failed"));
+        }
+
+        #[test]
+        fn recovered() {
+            let mut app = App::default();
+            app.set_tick_error(Some("tank failed".to_owned()));
+            app.set_tick_error(None);
+            assert_eq!(app.error(), None);
+        }
+
+        /// Successful updates don't hide other errors
+        #[test]
+        fn other_error() {
+            let mut app = App::default();
+            app.set_tick_error(Some("tank failed".to_owned()));
+            app.set_error("Cannot snooze".to_owned());
+            app.set_tick_error(None);
+            assert_eq!(app.error(), Some("Cannot snooze"));
+            app.set_tick_error(None);
+            assert_eq!(app.error(), Some("Cannot snooze"));
+        }
+    }
+
     mod with_parents {
         use super::super::*;
 
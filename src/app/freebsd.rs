@@ -1,28 +1,30 @@
 // vim: tw=80
 use std::{error::Error, mem};
 
-use cfg_if::cfg_if;
 use sysctl::{Ctl, CtlIter, CtlValue, Sysctl, SysctlError};
 
-use super::Snapshot;
+use super::{
+    parse_objset_id,
+    ArcStats,
+    Counters,
+    DbufStats,
+    Snapshot,
+    ZfetchStats,
+};
+use crate::warning::warn_once;
 
-cfg_if! {
-    if #[cfg(debug_assertions)] {
-        macro_rules! debug_println {
-            ($($tokens:tt),*) => {
-                eprintln!($($tokens),*)
-            }
-        }
-    } else {
-        macro_rules! debug_println {
-            ($($tokens:tt),*) => {()}
-        }
-    }
+/// Warn about an unknown sysctl, but only once for each field, since every
+/// dataset has the same ones
+fn unknown(field: &str, name: &str) {
+    warn_once(&format!("sysctl {field}"), || {
+        format!("Unknown sysctl {name:?}")
+    });
 }
 
 #[derive(Default)]
 struct Builder {
     dataset_name: Option<String>,
+    objset:       Option<u64>,
     nunlinked:    Option<u64>,
     nunlinks:     Option<u64>,
     nread:        Option<u64>,
@@ -34,11 +36,15 @@ struct Builder {
 impl Builder {
     fn build(&mut self, name: &str, value: CtlValue) {
         let mut fields = name.split('.');
-        let field = fields.nth(5).unwrap();
+        let objset = fields.nth(4).and_then(parse_objset_id);
+        let field = fields.next().unwrap();
+        if self.objset.is_none() {
+            self.objset = objset;
+        }
         match value {
             CtlValue::String(s) => {
                 if field != "dataset_name" {
-                    debug_println!("Unknown sysctl {:?}", name);
+                    unknown(field, name);
                 }
                 assert_eq!(self.dataset_name.replace(s), None);
             }
@@ -64,26 +70,37 @@ impl Builder {
                 _ => {
                     /* The zil_ stats aren't interesting to ztop */
                     if !name.contains(".zil_") {
-                        debug_println!("Unknown sysctl {:?}", name);
+                        unknown(field, name);
                     }
                 }
             },
-            _ => debug_println!("Unknown sysctl {:?}", name),
+            _ => unknown(field, name),
         };
     }
 
     fn finish(mut self) -> Option<Snapshot> {
         let name = self.dataset_name.take()?;
-        // On FreeBSD 12.2 and earlier, unlinked and nunlinks will not be
-        // present.  Set them to zero.
-        let nunlinked = self.nunlinked.take().unwrap_or(0);
-        let nunlinks = self.nunlinks.take().unwrap_or(0);
-        let nread = self.nread.take()?;
-        let reads = self.reads.take()?;
-        let nwritten = self.nwritten.take()?;
-        let writes = self.writes.take()?;
+        // Some counters may be missing, like nunlinked and nunlinks on
+        // FreeBSD 12.2 and earlier.  Set them to zero, and record which ones
+        // were present.
+        let mut counters = Counters::default();
+        let mut take = |field: &mut Option<u64>, counter: Counters| {
+            field.take().map_or(0, |x| {
+                counters.insert(counter);
+                x
+            })
+        };
+        let nunlinked = take(&mut self.nunlinked, Counters::NUNLINKED);
+        let nunlinks = take(&mut self.nunlinks, Counters::NUNLINKS);
+        let nread = take(&mut self.nread, Counters::NREAD);
+        let reads = take(&mut self.reads, Counters::READS);
+        let nwritten = take(&mut self.nwritten, Counters::NWRITTEN);
+        let writes = take(&mut self.writes, Counters::WRITES);
+        let objset = self.objset.take().unwrap_or(0);
         Some(Snapshot {
             name,
+            objset,
+            counters,
             nunlinked,
             nunlinks,
             nread,
@@ -103,7 +120,7 @@ pub(super) struct SnapshotIter {
 
 impl SnapshotIter {
     pub(crate) fn new(pool: Option<&str>) -> Result<Self, Box<dyn Error>> {
-        Ok(Self::with_inner(SysctlIter::new(pool)))
+        Ok(Self::with_inner(SysctlIter::new(pool)?))
     }
 
     fn with_inner<T>(inner: T) -> Self
@@ -179,24 +196,129 @@ impl Iterator for SnapshotIter {
     }
 }
 
+/// Read a pool's `txgs` kstat, the history of its recent transaction groups
+pub(super) fn txgs(pool: &str) -> Result<String, Box<dyn Error>> {
+    let name = format!("kstat.zfs.{}.txgs", encode_pool(pool));
+    Ok(Ctl::new(&name)?.value_string()?)
+}
+
+/// Read the ARC's global statistics
+pub(super) fn arcstats() -> Result<ArcStats, Box<dyn Error>> {
+    let get = |field: &str| misc_counter("arcstats", field);
+    Ok(ArcStats {
+        l2_size:        get("l2_size")?,
+        l2_hits:        get("l2_hits")?,
+        l2_misses:      get("l2_misses")?,
+        l2_read_bytes:  get("l2_read_bytes")?,
+        l2_write_bytes: get("l2_write_bytes")?,
+    })
+}
+
+/// Read the dbuf cache's global statistics
+pub(super) fn dbufstats() -> Result<DbufStats, Box<dyn Error>> {
+    let get = |field: &str| misc_counter("dbufstats", field);
+    Ok(DbufStats {
+        size:   get("cache_size_bytes")?,
+        hits:   get("hash_hits")?,
+        misses: get("hash_misses")?,
+        evicts: get("cache_total_evicts")?,
+    })
+}
+
+/// Read the prefetcher's global statistics
+pub(super) fn zfetchstats() -> Result<ZfetchStats, Box<dyn Error>> {
+    let get = |field: &str| misc_counter("zfetchstats", field);
+    Ok(ZfetchStats {
+        hits:        get("hits")?,
+        misses:      get("misses")?,
+        max_streams: get("max_streams")?,
+    })
+}
+
+/// Read one counter from a global kstat, like `kstat.zfs.misc.arcstats`.  A
+/// missing counter reads as `None`, since the set of counters varies between
+/// ZFS versions.
+fn misc_counter(
+    kstat: &str,
+    field: &str,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    let name = format!("kstat.zfs.misc.{kstat}.{field}");
+    let ctl = match Ctl::new(&name) {
+        Ok(ctl) => ctl,
+        Err(SysctlError::NotFound(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    match ctl.value()? {
+        CtlValue::U64(x) => Ok(Some(x)),
+        _ => Err(format!("Unexpected type for sysctl {name}").into()),
+    }
+}
+
+/// What access ztop needs, to explain permission errors
+pub(super) const ACCESS_HINT: &str =
+    "ztop needs read access to the kstat.zfs sysctls.  Try running it as root.";
+
+/// Was this error caused by insufficient permissions?
+pub(super) fn is_permission_denied(e: &(dyn Error + 'static)) -> bool {
+    let kind = match e.downcast_ref::<SysctlError>() {
+        Some(SysctlError::IoError(e)) => Some(e.kind()),
+        _ => e.downcast_ref::<std::io::Error>().map(std::io::Error::kind),
+    };
+    kind == Some(std::io::ErrorKind::PermissionDenied)
+}
+
+/// Escape a pool name for use as a sysctl name component.  Dots would
+/// otherwise be taken as separators.
+fn encode_pool(pool: &str) -> String {
+    pool.replace('.', "%25")
+}
+
+/// Undo `encode_pool`
+fn decode_pool(s: &str) -> String {
+    s.replace("%25", ".")
+}
+
+/// List the names of all imported pools that have dataset statistics
+pub(super) fn pools() -> Result<Vec<String>, Box<dyn Error>> {
+    let root = Ctl::new("kstat.zfs")?;
+    let mut pools = Vec::new();
+    for ctl in CtlIter::below(root) {
+        let name = ctl?.name()?;
+        let mut fields = name.split('.');
+        if let (Some(pool), Some("dataset")) = (fields.nth(2), fields.next()) {
+            let pool = decode_pool(pool);
+            if pools.last() != Some(&pool) {
+                pools.push(pool);
+            }
+        }
+    }
+    Ok(pools)
+}
+
+/// Read the table of mounted ZFS file systems, in fstab format
+pub(super) fn mount_table() -> std::io::Result<String> {
+    let output = crate::exec::command("/sbin/mount")?
+        .args(["-p", "-t", "zfs"])
+        .output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other("mount -p failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
 /// Iterate through all of the sysctls, but only return the ones we care about.
 struct SysctlIter(CtlIter);
 
 impl SysctlIter {
-    fn new(pool: Option<&str>) -> Self {
+    fn new(pool: Option<&str>) -> Result<Self, Box<dyn Error>> {
         let root = if let Some(s) = pool {
-            Ctl::new(&format!("kstat.zfs.{}.dataset", s.replace('.', "%25")))
-                .unwrap_or_else(|_e| {
-                    eprintln!("Statistics not found for pool {s}");
-                    std::process::exit(1);
-                })
+            Ctl::new(&format!("kstat.zfs.{}.dataset", encode_pool(s)))
+                .map_err(|_| format!("Statistics not found for pool {s}"))?
         } else {
-            Ctl::new("kstat.zfs").unwrap_or_else(|_e| {
-                eprintln!("ZFS kernel module not loaded?");
-                std::process::exit(1);
-            })
+            Ctl::new("kstat.zfs")
+                .map_err(|_| "ZFS kernel module not loaded?")?
         };
-        Self(CtlIter::below(root))
+        Ok(Self(CtlIter::below(root)))
     }
 }
 
@@ -237,6 +359,22 @@ impl Iterator for SysctlIter {
 
 #[cfg(test)]
 mod t {
+    mod pool_names {
+        use super::super::*;
+
+        #[test]
+        fn round_trip() {
+            for pool in ["tank", "my.pool", "a.b.c", "tank-2_x:y"] {
+                assert_eq!(decode_pool(&encode_pool(pool)), pool);
+            }
+        }
+
+        #[test]
+        fn encode() {
+            assert_eq!(encode_pool("my.pool"), "my%25pool");
+        }
+    }
+
     mod builder {
         use super::super::*;
 
@@ -264,8 +402,13 @@ mod t {
             }
             let r = builder.finish().unwrap();
             assert_eq!(r.name, "tank/foo");
+            assert_eq!(r.objset, 0x58c);
             assert_eq!(r.nunlinked, 0);
             assert_eq!(r.nunlinks, 0);
+            assert_eq!(
+                r.counters.lacking(Counters::ALL),
+                ["nunlinks", "nunlinked"]
+            );
             assert_eq!(r.nread, 3);
             assert_eq!(r.reads, 4);
             assert_eq!(r.nwritten, 5);
@@ -300,6 +443,7 @@ mod t {
             }
             let r = builder.finish().unwrap();
             assert_eq!(r.name, "tank/foo");
+            assert_eq!(r.objset, 0x58c);
             assert_eq!(r.nunlinked, 1);
             assert_eq!(r.nunlinks, 2);
             assert_eq!(r.nread, 3);
@@ -0,0 +1,287 @@
+// vim: tw=80
+//! Statistics on illumos, from the parsable output of kstat(8).
+//!
+//! Each objset's statistics are a named kstat of class `dataset`, with the
+//! same fields as ZFS on Linux's `objset-0x36` files.  The kstats' module
+//! and name layout varies between illumos distributions, so they're found by
+//! class, and each one's pool is taken from its `dataset_name`.
+//!
+//! This module is also compiled for tests on other platforms, so that the
+//! parser is tested everywhere.
+
+#![warn(clippy::all, clippy::pedantic)]
+#![cfg_attr(not(target_os = "illumos"), allow(dead_code))]
+
+use std::{collections::BTreeMap, error::Error, io, vec};
+
+use super::{
+    parse_objset_id,
+    ArcStats,
+    Counters,
+    DbufStats,
+    Snapshot,
+    ZfetchStats,
+};
+use crate::exec;
+
+/// One kstat's statistics, by name
+type Stats = BTreeMap<String, String>;
+
+/// Split the output of `kstat -p` into each kstat's statistics, keyed by
+/// `module:instance:name`.  Each line is `module:instance:name:statistic`, a
+/// tab, and the value.
+fn parse(text: &str) -> BTreeMap<String, Stats> {
+    let mut kstats = BTreeMap::<String, Stats>::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('\t') else {
+            continue;
+        };
+        let Some((kstat, stat)) = key.rsplit_once(':') else {
+            continue;
+        };
+        kstats
+            .entry(kstat.to_owned())
+            .or_default()
+            .insert(stat.to_owned(), value.to_owned());
+    }
+    kstats
+}
+
+/// Build a snapshot from one objset's kstat, like `zfs:0:objset-0x36`
+fn snapshot(kstat: &str, stats: &Stats) -> Option<Snapshot> {
+    let name = stats.get("dataset_name")?;
+    let objset = kstat
+        .rsplit([':', '/'])
+        .next()
+        .and_then(parse_objset_id)
+        .unwrap_or(0);
+    let mut snap = Snapshot {
+        name: name.clone(),
+        objset,
+        ..Snapshot::default()
+    };
+    let fields = [
+        ("nread", &mut snap.nread, Counters::NREAD),
+        ("nunlinked", &mut snap.nunlinked, Counters::NUNLINKED),
+        ("nunlinks", &mut snap.nunlinks, Counters::NUNLINKS),
+        ("nwritten", &mut snap.nwritten, Counters::NWRITTEN),
+        ("reads", &mut snap.reads, Counters::READS),
+        ("writes", &mut snap.writes, Counters::WRITES),
+    ];
+    let mut counters = Counters::default();
+    for (stat, field, counter) in fields {
+        if let Some(n) = stats.get(stat).and_then(|v| v.parse().ok()) {
+            *field = n;
+            counters.insert(counter);
+        }
+    }
+    snap.counters = counters;
+    Some(snap)
+}
+
+/// The pool that a dataset or snapshot belongs to
+fn pool_of(name: &str) -> &str {
+    name.split(['/', '@']).next().unwrap_or_default()
+}
+
+/// Every objset's snapshot in the output of `kstat -p -c dataset`, or only
+/// those of `pool`
+fn snapshots(text: &str, pool: Option<&str>) -> Vec<Snapshot> {
+    parse(text)
+        .iter()
+        .filter_map(|(kstat, stats)| snapshot(kstat, stats))
+        .filter(|snap| pool.map_or(true, |p| pool_of(&snap.name) == p))
+        .collect()
+}
+
+/// The names of the pools in the output of `kstat -p -c dataset`
+fn pool_names(text: &str) -> Vec<String> {
+    let mut pools = snapshots(text, None)
+        .iter()
+        .map(|snap| pool_of(&snap.name).to_owned())
+        .collect::<Vec<_>>();
+    pools.sort();
+    pools.dedup();
+    pools
+}
+
+/// Run `kstat -p` with these selectors
+fn kstat(args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = exec::command("kstat")?
+        .arg("-p")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Cannot run kstat: {e}"))?;
+    // kstat fails without a message if nothing matched
+    if !output.status.success() && !output.stderr.is_empty() {
+        let msg = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("kstat: {}", msg.trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Read one of the global kstats, like `arcstats`.  A missing counter reads
+/// as `None`, since the set of counters varies between ZFS versions.
+fn read_counters(
+    kstat_name: &str,
+) -> Result<impl Fn(&str) -> Option<u64>, Box<dyn Error>> {
+    let text = kstat(&[&format!("zfs:0:{kstat_name}")])?;
+    let Some(stats) = parse(&text).into_values().next() else {
+        return Err(format!("Cannot read zfs:0:{kstat_name}").into());
+    };
+    Ok(move |name: &str| stats.get(name)?.parse().ok())
+}
+
+/// illumos doesn't keep a history of each pool's transaction groups
+pub(super) fn txgs(_pool: &str) -> Result<String, Box<dyn Error>> {
+    Err("Transaction group history isn't available on illumos".into())
+}
+
+/// Read the ARC's global statistics
+pub(super) fn arcstats() -> Result<ArcStats, Box<dyn Error>> {
+    let get = read_counters("arcstats")?;
+    Ok(ArcStats {
+        l2_size:        get("l2_size"),
+        l2_hits:        get("l2_hits"),
+        l2_misses:      get("l2_misses"),
+        l2_read_bytes:  get("l2_read_bytes"),
+        l2_write_bytes: get("l2_write_bytes"),
+    })
+}
+
+/// Read the dbuf cache's global statistics
+pub(super) fn dbufstats() -> Result<DbufStats, Box<dyn Error>> {
+    let get = read_counters("dbufstats")?;
+    Ok(DbufStats {
+        size:   get("cache_size_bytes"),
+        hits:   get("hash_hits"),
+        misses: get("hash_misses"),
+        evicts: get("cache_total_evicts"),
+    })
+}
+
+/// Read the prefetcher's global statistics
+pub(super) fn zfetchstats() -> Result<ZfetchStats, Box<dyn Error>> {
+    let get = read_counters("zfetchstats")?;
+    Ok(ZfetchStats {
+        hits:        get("hits"),
+        misses:      get("misses"),
+        max_streams: get("max_streams"),
+    })
+}
+
+/// What access ztop needs, to explain permission errors
+pub(super) const ACCESS_HINT: &str =
+    "ztop needs to read the ZFS kstats with kstat(8).  Try running it as root.";
+
+/// Was this error caused by insufficient permissions?
+pub(super) fn is_permission_denied(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+}
+
+/// List the names of all imported pools that have dataset statistics
+pub(super) fn pools() -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(pool_names(&kstat(&["-c", "dataset"])?))
+}
+
+/// Read the mount table.  Its fields are in the same order as fstab's.
+pub(super) fn mount_table() -> io::Result<String> {
+    std::fs::read_to_string("/etc/mnttab")
+}
+
+pub(super) struct SnapshotIter(vec::IntoIter<Snapshot>);
+
+impl SnapshotIter {
+    pub(crate) fn new(pool: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let snapshots = snapshots(&kstat(&["-c", "dataset"])?, pool);
+        if snapshots.is_empty() {
+            return Err(match pool {
+                Some(pool) => format!("Statistics not found for pool {pool}"),
+                None => "No pools found; ZFS module not loaded?".to_owned(),
+            }
+            .into());
+        }
+        Ok(SnapshotIter(snapshots.into_iter()))
+    }
+}
+
+impl Iterator for SnapshotIter {
+    type Item = io::Result<Snapshot>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod t {
+    // While I normally agree that wildcard imports are bad, "use super::*" is
+    // an exception.
+    #[allow(clippy::wildcard_imports)]
+    use super::*;
+
+    const SAMPLE: &str = "zfs:0:objset-0x36:class\tdataset
+zfs:0:objset-0x36:crtime\t38.216742561
+zfs:0:objset-0x36:dataset_name\trpool/ROOT/omnios
+zfs:0:objset-0x36:nread\t160
+zfs:0:objset-0x36:nunlinked\t7
+zfs:0:objset-0x36:nunlinks\t7
+zfs:0:objset-0x36:nwritten\t100
+zfs:0:objset-0x36:reads\t8
+zfs:0:objset-0x36:snaptime\t5156.962179
+zfs:0:objset-0x36:writes\t5
+zfs:1:objset-0x36:class\tdataset
+zfs:1:objset-0x36:dataset_name\tdata/my files
+zfs:1:objset-0x36:nread\t4096
+zfs:1:objset-0x36:nwritten\t0
+zfs:1:objset-0x36:reads\t1
+zfs:1:objset-0x36:writes\t0
+zfs:1:objset-0x81:dataset_name\tdata@monday
+zfs:1:objset-0x81:reads\t2
+";
+
+    #[test]
+    fn objset_parsing() {
+        let snaps = snapshots(SAMPLE, Some("rpool"));
+        assert_eq!(snaps.len(), 1);
+        let snap = &snaps[0];
+        assert_eq!("rpool/ROOT/omnios", snap.name.as_str());
+        assert_eq!(0x36, snap.objset);
+        assert_eq!(8, snap.reads);
+        assert_eq!(5, snap.writes);
+        assert_eq!(160, snap.nread);
+        assert_eq!(7, snap.nunlinks);
+        assert_eq!(7, snap.nunlinked);
+        assert_eq!(100, snap.nwritten);
+        assert_eq!(snap.counters, Counters::ALL);
+    }
+
+    /// Counters that the kernel doesn't provide are zero, and recorded as
+    /// missing
+    #[test]
+    fn objset_missing_counters() {
+        let snaps = snapshots(SAMPLE, Some("data"));
+        let names = snaps.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["data/my files", "data@monday"]);
+        assert_eq!(snaps[0].nread, 4096);
+        assert_eq!(
+            snaps[0].counters.lacking(Counters::ALL),
+            ["nunlinks", "nunlinked"]
+        );
+    }
+
+    #[test]
+    fn pools() {
+        assert_eq!(pool_names(SAMPLE), ["data", "rpool"]);
+        assert!(pool_names("").is_empty());
+    }
+
+    /// Lines that aren't statistics, like kstat's warnings, are skipped
+    #[test]
+    fn garbage() {
+        let kstats = parse("kstat: warning\nzfs:0:arcstats:hits\t12\n");
+        assert_eq!(kstats.len(), 1);
+        assert_eq!(kstats["zfs:0:arcstats"]["hits"], "12");
+    }
+}
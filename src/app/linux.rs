@@ -3,16 +3,31 @@
 #![warn(clippy::all, clippy::pedantic)]
 
 use std::{
+    collections::HashMap,
+    env,
     error::Error,
     fs::File,
     io,
     io::BufRead,
     iter::{Flatten, Peekable},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
 };
 
 use glob::{glob, Paths, Pattern};
 
-use super::Snapshot;
+use super::{
+    parse_objset_id,
+    ArcStats,
+    Counters,
+    DbufStats,
+    Snapshot,
+    ZfetchStats,
+};
+use crate::warning::warn;
 
 // Similar to sysctl::CtlValue, but only as many types as necessary.
 #[derive(Debug)]
@@ -21,25 +36,31 @@ enum ObjsetValue {
     U64(u64),
 }
 
+/// Split off the first whitespace-delimited field of a row
+fn next_field(row: &str) -> Option<(&str, &str)> {
+    let row = row.trim_start();
+    let end = row.find(|c: char| c.is_ascii_whitespace())?;
+    Some((&row[..end], row[end..].trim_start()))
+}
+
 fn parse_objset_row(row: &str) -> Option<(String, ObjsetValue)> {
-    let mut fields = row.split_ascii_whitespace();
-
-    match (fields.next(), fields.next(), fields.next()) {
-        (Some(name), Some(_), Some(value)) => {
-            let field_name = (*name).to_string();
-            if field_name == "dataset_name" {
-                Some((field_name, ObjsetValue::String((*value).to_string())))
-            } else {
-                match value.parse::<u64>().ok() {
-                    Some(n) => Some((field_name, ObjsetValue::U64(n))),
-                    None => Some((
-                        field_name,
-                        ObjsetValue::String((*value).to_string()),
-                    )),
+    let (name, rest) = next_field(row)?;
+    let (_, value) = next_field(rest)?;
+    let field_name = name.to_string();
+    if field_name == "dataset_name" {
+        // Dataset names may contain spaces, so the value is the rest of the
+        // row.
+        Some((field_name, ObjsetValue::String(value.to_string())))
+    } else {
+        match value.trim_end() {
+            "" => None,
+            value => match value.parse::<u64>().ok() {
+                Some(n) => Some((field_name, ObjsetValue::U64(n))),
+                None => {
+                    Some((field_name, ObjsetValue::String(value.to_string())))
                 }
-            }
+            },
         }
-        _ => None,
     }
 }
 
@@ -51,23 +72,98 @@ fn parse_objset<R: BufRead>(reader: R) -> io::Result<Snapshot> {
     let mut snap = Snapshot::default();
 
     for line in lines {
-        let fields = parse_objset_row(&line?).expect("malformed objset row");
+        let line = line?;
+        let Some(fields) = parse_objset_row(&line) else {
+            warn(format!("Malformed objset row {line:?}"));
+            continue;
+        };
         match fields.1 {
             ObjsetValue::String(name) => snap.name = name,
-            ObjsetValue::U64(n) => match fields.0.as_str() {
-                "nread" => snap.nread = n,
-                "nunlinked" => snap.nunlinked = n,
-                "nunlinks" => snap.nunlinks = n,
-                "nwritten" => snap.nwritten = n,
-                "reads" => snap.reads = n,
-                "writes" => snap.writes = n,
-                _ => (),
-            },
+            ObjsetValue::U64(n) => {
+                let (field, counter) = match fields.0.as_str() {
+                    "nread" => (&mut snap.nread, Counters::NREAD),
+                    "nunlinked" => (&mut snap.nunlinked, Counters::NUNLINKED),
+                    "nunlinks" => (&mut snap.nunlinks, Counters::NUNLINKS),
+                    "nwritten" => (&mut snap.nwritten, Counters::NWRITTEN),
+                    "reads" => (&mut snap.reads, Counters::READS),
+                    "writes" => (&mut snap.writes, Counters::WRITES),
+                    _ => continue,
+                };
+                *field = n;
+                snap.counters.insert(counter);
+            }
         }
     }
     Ok(snap)
 }
 
+/// Parse the numeric rows of a global kstat like `arcstats`
+fn parse_counters<R: BufRead>(reader: R) -> io::Result<HashMap<String, u64>> {
+    // The same two header lines as an objset's kstat
+    let lines = reader.lines().skip(2);
+
+    let mut counters = HashMap::new();
+    for line in lines {
+        let line = line?;
+        if let Some((name, ObjsetValue::U64(n))) = parse_objset_row(&line) {
+            counters.insert(name, n);
+        }
+    }
+    Ok(counters)
+}
+
+/// Read one of the global kstats, like `arcstats`.  A missing counter reads
+/// as `None`, since the set of counters varies between ZFS versions.
+fn read_counters(
+    kstat: &str,
+) -> Result<impl Fn(&str) -> Option<u64>, Box<dyn Error>> {
+    let path = kstat_path().join(kstat);
+    let file = File::open(&path)
+        .map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+    let counters = parse_counters(io::BufReader::new(file))?;
+    Ok(move |name: &str| counters.get(name).copied())
+}
+
+/// Read a pool's `txgs` kstat, the history of its recent transaction groups
+pub(super) fn txgs(pool: &str) -> Result<String, Box<dyn Error>> {
+    let path = kstat_path().join(pool).join("txgs");
+    std::fs::read_to_string(&path)
+        .map_err(|e| format!("Cannot read {}: {e}", path.display()).into())
+}
+
+/// Read the ARC's global statistics
+pub(super) fn arcstats() -> Result<ArcStats, Box<dyn Error>> {
+    let get = read_counters("arcstats")?;
+    Ok(ArcStats {
+        l2_size:        get("l2_size"),
+        l2_hits:        get("l2_hits"),
+        l2_misses:      get("l2_misses"),
+        l2_read_bytes:  get("l2_read_bytes"),
+        l2_write_bytes: get("l2_write_bytes"),
+    })
+}
+
+/// Read the dbuf cache's global statistics
+pub(super) fn dbufstats() -> Result<DbufStats, Box<dyn Error>> {
+    let get = read_counters("dbufstats")?;
+    Ok(DbufStats {
+        size:   get("cache_size_bytes"),
+        hits:   get("hash_hits"),
+        misses: get("hash_misses"),
+        evicts: get("cache_total_evicts"),
+    })
+}
+
+/// Read the prefetcher's global statistics
+pub(super) fn zfetchstats() -> Result<ZfetchStats, Box<dyn Error>> {
+    let get = read_counters("zfetchstats")?;
+    Ok(ZfetchStats {
+        hits:        get("hits"),
+        misses:      get("misses"),
+        max_streams: get("max_streams"),
+    })
+}
+
 /// Convenience implementation for use with glob's `PathBuf`'s
 impl TryFrom<File> for Snapshot {
     type Error = io::Error;
@@ -87,35 +183,152 @@ impl TryFrom<&str> for Snapshot {
     }
 }
 
+/// Where ZFS publishes its kstats, unless overridden
+const DEFAULT_KSTAT_PATH: &str = "/proc/spl/kstat/zfs";
+
+static KSTAT_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+static CONTAINER: AtomicBool = AtomicBool::new(false);
+
+/// Read kstats from somewhere other than the default, such as a procfs
+/// bind-mounted into a container.  Must be called before any statistics are
+/// collected.
+pub fn set_kstat_path(path: PathBuf) {
+    // Ignore the error; a second call can only come from a programming error.
+    let _ = KSTAT_PATH.set(path);
+}
+
+/// Expect that only some pools' kstats may be visible, as in a container that
+/// bind-mounts only some of the host's pool directories.  Every pool
+/// directory is then listed, even if it can't be read, so that its errors are
+/// reported with the other pools' statistics.
+pub fn set_container_mode() {
+    CONTAINER.store(true, Ordering::Relaxed);
+}
+
+/// The directory containing each pool's kstats.  `$ZTOP_KSTAT_PATH` is the
+/// fallback for when `set_kstat_path` was never called.
+fn kstat_path() -> &'static Path {
+    KSTAT_PATH.get_or_init(|| {
+        env::var_os("ZTOP_KSTAT_PATH")
+            .map_or_else(|| PathBuf::from(DEFAULT_KSTAT_PATH), PathBuf::from)
+    })
+}
+
+/// What access ztop needs, to explain permission errors
+pub(super) const ACCESS_HINT: &str =
+    "ztop needs read access to the ZFS kstats.  Try running it as root.";
+
+/// Was this error caused by insufficient permissions?
+pub(super) fn is_permission_denied(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+}
+
+/// Globbing silently skips directories that can't be read.  Check whether
+/// that's why nothing was found.
+fn check_access(dir: &Path) -> Result<(), Box<dyn Error>> {
+    match std::fs::read_dir(dir) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Err(format!(
+            "Cannot read {}: {e}.  {ACCESS_HINT}",
+            dir.display()
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// List the names of all imported pools that have dataset statistics
+pub(super) fn pools() -> Result<Vec<String>, Box<dyn Error>> {
+    if CONTAINER.load(Ordering::Relaxed) {
+        let pools = pool_dirs(kstat_path())?;
+        if pools.is_empty() {
+            return Err(format!(
+                "No pools found in {}.  Are the host's pool kstats \
+                 bind-mounted there?",
+                kstat_path().display()
+            )
+            .into());
+        }
+        return Ok(pools);
+    }
+    let mut pools = glob(&objset_pattern(kstat_path(), "*"))?
+        .flatten()
+        .filter_map(|path| {
+            let dir = path.parent()?.file_name()?;
+            Some(dir.to_string_lossy().into_owned())
+        })
+        .collect::<Vec<_>>();
+    pools.dedup();
+    if pools.is_empty() {
+        check_access(kstat_path())?;
+    }
+    Ok(pools)
+}
+
+/// List every pool directory, whether or not its contents can be read
+fn pool_dirs(base: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let entries = std::fs::read_dir(base)
+        .map_err(|e| format!("Cannot read {}: {e}", base.display()))?;
+    let mut pools = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            pools.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    pools.sort();
+    Ok(pools)
+}
+
+/// The glob pattern matching one pool's objset kstats, or every pool's if
+/// `pool` is `*`.  Pool names and the base path may contain characters that
+/// are special to glob, so escape them.
+fn objset_pattern(base: &Path, pool: &str) -> String {
+    let pool = match pool {
+        "*" => String::from("*"),
+        p => Pattern::escape(p),
+    };
+    format!(
+        "{}/{pool}/objset-*",
+        Pattern::escape(&base.to_string_lossy())
+    )
+}
+
+/// Read the mount table, in fstab format
+pub(super) fn mount_table() -> io::Result<String> {
+    std::fs::read_to_string("/proc/self/mounts")
+}
+
 pub(super) struct SnapshotIter {
     inner: Peekable<Flatten<Paths>>,
 }
 
 impl SnapshotIter {
-    // Clippy complains about unnecessary wraps, but the type signature is
-    // retained to be consistent with FreeBSD implementation.
-    #[allow(clippy::unnecessary_wraps, clippy::single_match_else)]
+    #[allow(clippy::single_match_else)]
     pub(crate) fn new(pool: Option<&str>) -> Result<Self, Box<dyn Error>> {
         let paths = match pool {
             Some(poolname) => {
-                let poolpat = Pattern::escape(poolname);
-                let mut paths =
-                    glob(&format!("/proc/spl/kstat/zfs/{poolpat}/objset-*"))?
-                        .flatten()
-                        .peekable();
+                let mut paths = glob(&objset_pattern(kstat_path(), poolname))?
+                    .flatten()
+                    .peekable();
                 if paths.peek().is_none() {
-                    eprintln!("Statistics not found for pool {poolname}");
-                    std::process::exit(1);
+                    check_access(kstat_path())?;
+                    check_access(&kstat_path().join(poolname))?;
+                    return Err(format!(
+                        "Statistics not found for pool {poolname}"
+                    )
+                    .into());
                 }
                 paths
             }
             None => {
-                let mut paths = glob("/proc/spl/kstat/zfs/*/objset-*")?
+                let mut paths = glob(&objset_pattern(kstat_path(), "*"))?
                     .flatten()
                     .peekable();
                 if paths.peek().is_none() {
-                    eprintln!("No pools found; ZFS module not loaded?");
-                    std::process::exit(1);
+                    check_access(kstat_path())?;
+                    return Err("No pools found; ZFS module not loaded?".into());
                 }
                 paths
             }
@@ -129,9 +342,19 @@ impl Iterator for SnapshotIter {
     type Item = io::Result<Snapshot>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|glob_result| {
-            let file = File::open(glob_result)?;
-            Snapshot::try_from(file)
+        self.inner.next().map(|path| {
+            let objset = path
+                .file_name()
+                .and_then(|f| parse_objset_id(&f.to_string_lossy()))
+                .unwrap_or(0);
+            // Name the file, so each unreadable one is only reported once
+            let file = File::open(&path).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+            })?;
+            Ok(Snapshot {
+                objset,
+                ..Snapshot::try_from(file)?
+            })
         })
     }
 }
@@ -165,6 +388,104 @@ nunlinked                       4    7
         assert_eq!(7, snap.nunlinks);
         assert_eq!(7, snap.nunlinked);
         assert_eq!(100, snap.nwritten);
+        assert_eq!(snap.counters, Counters::ALL);
+    }
+
+    /// Counters that the kernel doesn't provide are zero, and recorded as
+    /// missing
+    #[test]
+    fn objset_missing_counters() {
+        let sample = SAMPLE_OBJSET
+            .lines()
+            .filter(|l| !l.starts_with("nunlink"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let snap = parse_objset(io::BufReader::new(sample.as_bytes())).unwrap();
+        assert_eq!(0, snap.nunlinks);
+        assert!(!snap.counters.contains(Counters::NUNLINKS));
+        assert_eq!(
+            snap.counters.lacking(Counters::ALL),
+            ["nunlinks", "nunlinked"]
+        );
+    }
+
+    /// Pool names with glob metacharacters or other unusual characters
+    /// should match only their own kstats
+    #[test]
+    fn objset_pattern_round_trip() {
+        let pools =
+            ["tank", "my.pool", "a[b]c", "star*", "q?", "p:1-x_y", "ünï"];
+        let base = Path::new(DEFAULT_KSTAT_PATH);
+        for pool in pools {
+            let pat = Pattern::new(&objset_pattern(base, pool)).unwrap();
+            for other in pools {
+                let path = format!("/proc/spl/kstat/zfs/{other}/objset-0x36");
+                assert_eq!(pat.matches(&path), pool == other, "{pool} {other}");
+            }
+        }
+    }
+
+    /// Glob metacharacters in the base path should be matched literally
+    #[test]
+    fn objset_pattern_base() {
+        let base = Path::new("/srv/[host]/proc/spl/kstat/zfs");
+        let pat = Pattern::new(&objset_pattern(base, "*")).unwrap();
+        assert!(pat.matches("/srv/[host]/proc/spl/kstat/zfs/tank/objset-0x36"));
+        assert!(!pat.matches("/srv/h/proc/spl/kstat/zfs/tank/objset-0x36"));
+    }
+
+    /// Only directories are pools.  The other kstats are files.
+    #[test]
+    fn pool_dirs() {
+        let base = env::temp_dir().join(format!("ztop-{}", std::process::id()));
+        for pool in ["zroot", "tank"] {
+            std::fs::create_dir_all(base.join(pool)).unwrap();
+        }
+        std::fs::write(base.join("arcstats"), "").unwrap();
+        let r = super::pool_dirs(&base);
+        std::fs::remove_dir_all(&base).unwrap();
+        assert_eq!(r.unwrap(), ["tank", "zroot"]);
+    }
+
+    #[test]
+    fn permission_denied() {
+        let e = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(is_permission_denied(&e));
+        let e = io::Error::from(io::ErrorKind::NotFound);
+        assert!(!is_permission_denied(&e));
+    }
+
+    /// Dataset names may contain spaces
+    #[test]
+    fn objset_name_with_spaces() {
+        let s = SAMPLE_OBJSET.replace("rpool/ROOT/default", "rpool/my data");
+        let snap = Snapshot::try_from(s.as_str()).unwrap();
+        assert_eq!("rpool/my data", snap.name.as_str());
+        assert_eq!(8, snap.reads);
+    }
+
+    #[test]
+    fn counters_parsing() {
+        let s = "13 1 0x01 147 39984 5156962179 648086076730177
+name                            type data
+hits                            4    51234
+l2_hits                         4    3456
+l2_misses                       4    789
+l2_read_bytes                   4    1048576
+l2_write_bytes                  4    2097152
+l2_size                         4    1073741824
+";
+        let c = parse_counters(io::BufReader::new(s.as_bytes())).unwrap();
+        assert_eq!(c.len(), 6);
+        assert_eq!(c["hits"], 51234);
+        assert_eq!(c["l2_hits"], 3456);
+        assert_eq!(c["l2_size"], 1_073_741_824);
+    }
+
+    #[test]
+    fn objset_row_missing_value() {
+        assert!(parse_objset_row("nread                           4").is_none());
+        assert!(parse_objset_row("nread").is_none());
     }
 
     #[test]
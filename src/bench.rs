@@ -0,0 +1,120 @@
+// vim: tw=80
+//! Measure how long it takes to collect statistics, without the TUI
+use std::{error::Error, fmt, num::NonZeroUsize, time::Instant};
+
+use crate::app::DataSource;
+
+/// Time repeated collection cycles and report statistics about them
+#[derive(Debug, clap::Args)]
+pub struct BenchArgs {
+    /// number of collection cycles to run
+    #[clap(short = 'n', long = "count", default_value = "10")]
+    count: NonZeroUsize,
+    /// Collect from these pools and their children
+    pools: Vec<String>,
+}
+
+/// Minimum, average, and maximum of a series of measurements
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Summary {
+    min: f64,
+    max: f64,
+    sum: f64,
+    n:   usize,
+}
+
+impl Summary {
+    fn add(&mut self, x: f64) {
+        if self.n == 0 || x < self.min {
+            self.min = x;
+        }
+        if self.n == 0 || x > self.max {
+            self.max = x;
+        }
+        self.sum += x;
+        self.n += 1;
+    }
+
+    fn avg(&self) -> f64 {
+        self.sum / self.n as f64
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prec = f.precision().unwrap_or(0);
+        write!(
+            f,
+            "min {:.prec$}  avg {:.prec$}  max {:.prec$}",
+            self.min,
+            self.avg(),
+            self.max
+        )
+    }
+}
+
+/// The number of read-like system calls this process has made so far, if the
+/// OS reports it.
+#[cfg(target_os = "linux")]
+fn syscalls() -> Option<u64> {
+    let io = std::fs::read_to_string("/proc/self/io").ok()?;
+    io.lines()
+        .find_map(|line| line.strip_prefix("syscr:"))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn syscalls() -> Option<u64> {
+    None
+}
+
+/// Run the benchmark and print its results
+pub fn run(args: &BenchArgs) -> Result<(), Box<dyn Error>> {
+    DataSource::check_pools(&args.pools)?;
+    let mut data = DataSource::new(false, args.pools.clone(), Vec::new());
+    let mut times = Summary::default();
+    let mut datasets = Summary::default();
+    let mut calls = Summary::default();
+    for _ in 0..args.count.get() {
+        let before = syscalls();
+        let start = Instant::now();
+        data.refresh()?;
+        let elapsed = start.elapsed();
+        if let (Some(before), Some(after)) = (before, syscalls()) {
+            calls.add((after - before) as f64);
+        }
+        times.add(elapsed.as_secs_f64() * 1000.0);
+        datasets.add(data.ndatasets() as f64);
+    }
+    println!("cycles:          {}", args.count);
+    println!("datasets:        {datasets}");
+    println!("time (ms):       {times:.3}");
+    if calls.n > 0 {
+        println!("read syscalls:   {calls}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let mut s = Summary::default();
+        for x in [3.0, 1.0, 2.0, 6.0] {
+            s.add(x);
+        }
+        assert_eq!(s.min, 1.0);
+        assert_eq!(s.max, 6.0);
+        assert_eq!(s.avg(), 3.0);
+        assert_eq!(format!("{s:.1}"), "min 1.0  avg 3.0  max 6.0");
+    }
+
+    #[test]
+    fn summary_negative() {
+        let mut s = Summary::default();
+        s.add(-1.0);
+        assert_eq!(format!("{s}"), "min -1  avg -1  max -1");
+    }
+}
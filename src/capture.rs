@@ -0,0 +1,1137 @@
+// vim: tw=80
+//! Records the statistics around an alert, for incidents nobody was watching
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{Child, Stdio},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bincode::Options;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    app::{Element, Metric},
+    config::{de_opt_duration, de_opt_size},
+    exec,
+    warning::warn,
+};
+
+/// The `[capture]` section of the config file
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Write captures to this directory
+    pub dir:      PathBuf,
+    /// Include this many updates from before the alert fired
+    #[serde(default = "CaptureConfig::default_len")]
+    pub before:   usize,
+    /// Keep recording for this many updates after the alert fired
+    #[serde(default = "CaptureConfig::default_len")]
+    pub after:    usize,
+    /// Continue a long recording in a new file once it's this many bytes
+    #[serde(default, deserialize_with = "de_opt_size")]
+    pub max_size: Option<u64>,
+    /// Continue a long recording in a new file once it's this old
+    #[serde(default, deserialize_with = "de_opt_duration")]
+    pub max_age:  Option<Duration>,
+    /// Delete the oldest files beyond this many
+    #[serde(default)]
+    pub keep:     Option<NonZeroUsize>,
+    /// Compress each file once it's finished
+    #[serde(default)]
+    pub compress: Compression,
+    /// How to encode each update
+    #[serde(default)]
+    pub format:   Format,
+}
+
+impl CaptureConfig {
+    fn default_len() -> usize {
+        60
+    }
+}
+
+/// How to compress finished capture files
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The program that compresses a file in place, and its arguments
+    fn command(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some(("gzip", &["-f"])),
+            Compression::Zstd => Some(("zstd", &["-q", "-f", "--rm"])),
+        }
+    }
+}
+
+/// `true` means gzip, for compatibility with older config files
+impl<'de> Deserialize<'de> for Compression {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bool(bool),
+            Name(String),
+        }
+        match Raw::deserialize(d)? {
+            Raw::Bool(false) => Ok(Compression::None),
+            Raw::Bool(true) => Ok(Compression::Gzip),
+            Raw::Name(s) => match s.as_str() {
+                "none" => Ok(Compression::None),
+                "gzip" => Ok(Compression::Gzip),
+                "zstd" => Ok(Compression::Zstd),
+                _ => Err(serde::de::Error::custom(format!(
+                    "unknown compression {s:?}"
+                ))),
+            },
+        }
+    }
+}
+
+/// How capture files encode each update
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// One line of JSON per update
+    #[default]
+    Json,
+    /// Length-prefixed bincode frames after a versioned header, with each
+    /// dataset's name written only once per file
+    Binary,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!("unknown capture format {s:?}")),
+        }
+    }
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "jsonl",
+            Format::Binary => "zcap",
+        }
+    }
+}
+
+/// The start of every binary capture file, followed by the version
+const MAGIC: &[u8; 8] = b"ZTOPCAP\0";
+
+/// The version of the binary format that this ztop writes
+const VERSION: u32 = 1;
+
+/// Refuse to read a binary frame longer than this, in case of corruption
+const MAX_FRAME: u32 = 1 << 30;
+
+/// Bincode's settings for binary captures: little-endian, with variable
+/// length integers, so that small IDs and counts take only a byte.
+fn bincode() -> impl Options {
+    bincode::DefaultOptions::new().with_limit(u64::from(MAX_FRAME))
+}
+
+/// One dataset's statistics in a binary capture, with its name replaced by
+/// an ID
+#[derive(Debug, Deserialize, Serialize)]
+struct BinaryRow {
+    id:        u32,
+    ops_r:     f64,
+    r_s:       f64,
+    ops_w:     f64,
+    w_s:       f64,
+    ops_d:     f64,
+    d_s:       f64,
+    pending_d: u64,
+}
+
+/// One update in a binary capture
+#[derive(Debug, Deserialize, Serialize)]
+struct BinaryFrame {
+    time:     f64,
+    alerts:   Vec<String>,
+    /// The names of the datasets first seen in this frame, which get the
+    /// next IDs in order
+    names:    Vec<String>,
+    datasets: Vec<BinaryRow>,
+}
+
+/// Writes updates to a capture file in one of the formats
+#[derive(Debug)]
+enum Encoder {
+    Json,
+    /// The ID of every dataset name written so far
+    Binary(HashMap<String, u32>),
+}
+
+impl Encoder {
+    fn new(format: Format) -> Self {
+        match format {
+            Format::Json => Encoder::Json,
+            Format::Binary => Encoder::Binary(HashMap::new()),
+        }
+    }
+
+    /// Write the start of a new file.  Return its length.
+    fn header(&self, w: &mut impl Write) -> io::Result<u64> {
+        match self {
+            Encoder::Json => Ok(0),
+            Encoder::Binary(_) => {
+                w.write_all(MAGIC)?;
+                w.write_all(&VERSION.to_le_bytes())?;
+                Ok(12)
+            }
+        }
+    }
+
+    /// Write one frame.  Return its length.
+    fn write(&mut self, w: &mut impl Write, frame: &Frame) -> io::Result<u64> {
+        let buf = match self {
+            Encoder::Json => {
+                let mut line = serde_json::to_vec(frame)?;
+                line.push(b'\n');
+                line
+            }
+            Encoder::Binary(ids) => {
+                let mut names = Vec::new();
+                let datasets = frame
+                    .datasets
+                    .iter()
+                    .map(|row| {
+                        let next = ids.len() as u32;
+                        let id =
+                            *ids.entry(row.name.clone()).or_insert_with(|| {
+                                names.push(row.name.clone());
+                                next
+                            });
+                        BinaryRow {
+                            id,
+                            ops_r: row.ops_r,
+                            r_s: row.r_s,
+                            ops_w: row.ops_w,
+                            w_s: row.w_s,
+                            ops_d: row.ops_d,
+                            d_s: row.d_s,
+                            pending_d: row.pending_d,
+                        }
+                    })
+                    .collect();
+                let bf = BinaryFrame {
+                    time: frame.time,
+                    alerts: frame.alerts.clone(),
+                    names,
+                    datasets,
+                };
+                let body =
+                    bincode().serialize(&bf).map_err(io::Error::other)?;
+                let mut buf = (body.len() as u32).to_le_bytes().to_vec();
+                buf.extend(body);
+                buf
+            }
+        };
+        w.write_all(&buf)?;
+        Ok(buf.len() as u64)
+    }
+}
+
+/// One dataset's statistics from one update.  `ztop-agent --serve` uses the
+/// same format.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Row {
+    pub(crate) name:      String,
+    pub(crate) ops_r:     f64,
+    pub(crate) r_s:       f64,
+    pub(crate) ops_w:     f64,
+    pub(crate) w_s:       f64,
+    pub(crate) ops_d:     f64,
+    pub(crate) d_s:       f64,
+    pub(crate) pending_d: u64,
+}
+
+impl From<&Element> for Row {
+    fn from(elem: &Element) -> Self {
+        Row {
+            name:      elem.name.clone(),
+            ops_r:     elem.ops_r,
+            r_s:       elem.r_s,
+            ops_w:     elem.ops_w,
+            w_s:       elem.w_s,
+            ops_d:     elem.ops_d,
+            d_s:       elem.d_s,
+            pending_d: elem.pending_d,
+        }
+    }
+}
+
+impl Row {
+    pub(crate) fn get(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::OpsR => self.ops_r,
+            Metric::RS => self.r_s,
+            Metric::OpsW => self.ops_w,
+            Metric::WS => self.w_s,
+            Metric::OpsD => self.ops_d,
+            Metric::DS => self.d_s,
+            Metric::PendingD => self.pending_d as f64,
+        }
+    }
+}
+
+/// One update, written as a single line of JSON.  `ztop-agent --serve` uses
+/// the same format for its history.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Frame {
+    /// Seconds since the epoch
+    pub(crate) time:     f64,
+    /// The capturing alerts that fired during this update
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) alerts:   Vec<String>,
+    pub(crate) datasets: Vec<Row>,
+}
+
+impl Frame {
+    /// An update happening now
+    pub(crate) fn now(alerts: Vec<String>, datasets: Vec<Row>) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        Frame {
+            time,
+            alerts,
+            datasets,
+        }
+    }
+}
+
+/// A capture file that is still being written
+struct Recording {
+    path:      PathBuf,
+    file:      BufWriter<File>,
+    encoder:   Encoder,
+    /// Bytes written so far
+    size:      u64,
+    opened:    Instant,
+    /// How many more updates to write
+    remaining: usize,
+}
+
+/// Is this the name of a capture file, compressed or not?
+fn is_capture(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    let name = name
+        .strip_suffix(".gz")
+        .or_else(|| name.strip_suffix(".zst"))
+        .unwrap_or(&name);
+    name.starts_with("ztop-")
+        && (name.ends_with(".jsonl") || name.ends_with(".zcap"))
+}
+
+/// Keeps the most recent updates in memory, and writes them to a file when
+/// an alert with the `capture` action fires
+pub struct Capture {
+    config:      CaptureConfig,
+    history:     VecDeque<Frame>,
+    recording:   Option<Recording>,
+    /// gzip processes that haven't yet been reaped
+    compressing: Vec<Child>,
+}
+
+impl Capture {
+    pub fn new(config: CaptureConfig) -> Self {
+        Capture {
+            config,
+            history: VecDeque::new(),
+            recording: None,
+            compressing: Vec::new(),
+        }
+    }
+
+    /// Record one update.  `alerts` names the capturing alerts that just
+    /// fired, if any.  Another alert during a capture extends it, rather than
+    /// starting a new file.
+    pub fn tick(&mut self, elems: &[Element], alerts: Vec<String>) {
+        let datasets =
+            elems.iter().filter(|e| !e.stale).map(Row::from).collect();
+        let frame = Frame::now(alerts, datasets);
+        if !frame.alerts.is_empty() {
+            if let Some(recording) = self.recording.as_mut() {
+                recording.remaining = self.config.after;
+            } else {
+                self.start(frame.time);
+            }
+        }
+        self.compressing
+            .retain_mut(|c| !matches!(c.try_wait(), Ok(Some(_))));
+        if let Some(recording) = self.recording.as_mut() {
+            let r = recording
+                .encoder
+                .write(&mut recording.file, &frame)
+                .and_then(|n| {
+                    recording.size += n;
+                    recording.file.flush()
+                });
+            if let Err(e) = r {
+                warn(format!("Cannot write capture: {e}"));
+                self.recording = None;
+            } else if recording.remaining == 0 {
+                self.finish();
+            } else {
+                recording.remaining -= 1;
+                let full = self
+                    .config
+                    .max_size
+                    .is_some_and(|max| recording.size >= max);
+                let old = self
+                    .config
+                    .max_age
+                    .is_some_and(|max| recording.opened.elapsed() >= max);
+                if full || old {
+                    let remaining = recording.remaining;
+                    self.finish();
+                    self.open(frame.time, remaining, false);
+                }
+            }
+        }
+        self.history.push_back(frame);
+        while self.history.len() > self.config.before {
+            self.history.pop_front();
+        }
+    }
+
+    /// Apply a reloaded config file's settings.  The saved history and any
+    /// recording in progress are kept.
+    pub fn reload(&mut self, config: CaptureConfig) {
+        self.config = config;
+        while self.history.len() > self.config.before {
+            self.history.pop_front();
+        }
+    }
+
+    /// Create a capture file named after `time`.  If another capture started
+    /// in the same second, add a counter rather than overwrite it.
+    fn create(&self, time: f64) -> (PathBuf, io::Result<File>) {
+        let secs = time as u64;
+        let ext = self.config.format.extension();
+        let mut n = 0;
+        loop {
+            let name = match n {
+                0 => format!("ztop-{secs}.{ext}"),
+                n => format!("ztop-{secs}-{n}.{ext}"),
+            };
+            let path = self.config.dir.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                r => return (path, r),
+            }
+        }
+    }
+
+    /// Open a new capture file and write the saved history to it
+    fn start(&mut self, time: f64) {
+        self.open(time, self.config.after, true);
+    }
+
+    /// Open a new capture file to record `remaining` more updates after the
+    /// next one, starting with the saved history if `history`
+    fn open(&mut self, time: f64, remaining: usize, history: bool) {
+        let (path, r) = self.create(time);
+        let mut encoder = Encoder::new(self.config.format);
+        let mut size = 0;
+        let r = r.and_then(|f| {
+            let mut file = BufWriter::new(f);
+            size += encoder.header(&mut file)?;
+            for frame in self.history.iter().filter(|_| history) {
+                size += encoder.write(&mut file, frame)?;
+            }
+            Ok(file)
+        });
+        match r {
+            Ok(file) => {
+                self.prune(&path);
+                self.recording = Some(Recording {
+                    path,
+                    file,
+                    encoder,
+                    size,
+                    opened: Instant::now(),
+                    remaining,
+                });
+            }
+            Err(e) => {
+                warn(format!("Cannot create {}: {e}", path.display()));
+            }
+        }
+    }
+
+    /// Close the current capture file, compressing it if configured
+    fn finish(&mut self) {
+        let Some(recording) = self.recording.take() else {
+            return;
+        };
+        drop(recording.file);
+        let Some((program, args)) = self.config.compress.command() else {
+            return;
+        };
+        let child = exec::command(program).and_then(|mut cmd| {
+            cmd.args(args)
+                .arg(&recording.path)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+        });
+        match child {
+            Ok(child) => self.compressing.push(child),
+            Err(e) => warn(format!(
+                "Cannot compress {}: {e}",
+                recording.path.display()
+            )),
+        }
+    }
+
+    /// Delete the oldest capture files beyond the configured number, never
+    /// including `current`
+    fn prune(&self, current: &Path) {
+        let Some(keep) = self.config.keep else {
+            return;
+        };
+        let Ok(entries) = fs::read_dir(&self.config.dir) else {
+            return;
+        };
+        let mut files = entries
+            .flatten()
+            .filter(|e| is_capture(&e.file_name()))
+            .filter_map(|e| {
+                Some((e.metadata().ok()?.modified().ok()?, e.path()))
+            })
+            .filter(|(_, path)| path != current)
+            .collect::<Vec<_>>();
+        files.sort();
+        // The current file counts, too
+        let excess = (files.len() + 1).saturating_sub(keep.get());
+        for (_, path) in files.into_iter().take(excess) {
+            if let Err(e) = fs::remove_file(&path) {
+                warn(format!("Cannot delete {}: {e}", path.display()));
+            }
+        }
+    }
+
+    /// Is a capture file being written right now?
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+}
+
+/// Reads the updates from a capture file in either format
+pub(crate) struct Reader<R> {
+    inner: R,
+    /// For a binary capture, the name of each dataset ID seen so far
+    names: Option<Vec<String>>,
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Start reading an uncompressed capture, telling the format by its
+    /// first bytes
+    pub(crate) fn new(mut inner: R) -> Result<Self, Box<dyn Error>> {
+        let binary = inner.fill_buf()?.starts_with(MAGIC);
+        let names = if binary {
+            let mut header = [0; 12];
+            inner.read_exact(&mut header)?;
+            let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+            if version != VERSION {
+                return Err(format!(
+                    "Unsupported capture format version {version}"
+                )
+                .into());
+            }
+            Some(Vec::new())
+        } else {
+            None
+        };
+        Ok(Reader { inner, names })
+    }
+
+    fn read_binary(&mut self) -> Result<Option<Frame>, Box<dyn Error>> {
+        let mut len = [0; 4];
+        if self.inner.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        self.inner.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len);
+        if len > MAX_FRAME {
+            return Err(format!("Capture frame too long: {len} bytes").into());
+        }
+        let mut body = vec![0; len as usize];
+        self.inner.read_exact(&mut body)?;
+        let bf: BinaryFrame = bincode().deserialize(&body)?;
+        let names = self.names.as_mut().unwrap();
+        names.extend(bf.names);
+        let datasets = bf
+            .datasets
+            .into_iter()
+            .map(|row| {
+                let name = names.get(row.id as usize).ok_or_else(|| {
+                    format!("Unknown dataset ID {} in capture", row.id)
+                })?;
+                Ok(Row {
+                    name:      name.clone(),
+                    ops_r:     row.ops_r,
+                    r_s:       row.r_s,
+                    ops_w:     row.ops_w,
+                    w_s:       row.w_s,
+                    ops_d:     row.ops_d,
+                    d_s:       row.d_s,
+                    pending_d: row.pending_d,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Some(Frame {
+            time: bf.time,
+            alerts: bf.alerts,
+            datasets,
+        }))
+    }
+
+    fn read_json(&mut self) -> Result<Option<Frame>, Box<dyn Error>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.inner.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                return Ok(Some(serde_json::from_str(&line)?));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Frame, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let r = if self.names.is_some() {
+            self.read_binary()
+        } else {
+            self.read_json()
+        };
+        r.transpose()
+    }
+}
+
+/// What `ztop convert` writes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Output {
+    /// One row per dataset per update, with a header
+    Csv,
+    /// One line of JSON per update, like a JSON capture
+    Json,
+}
+
+impl std::str::FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Output::Csv),
+            "json" => Ok(Output::Json),
+            _ => Err(format!("unknown output format {s:?}")),
+        }
+    }
+}
+
+/// Quote a CSV field, if it needs it
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Call `each` with every frame from `reader`, until it returns false
+fn read_frames<R: BufRead>(
+    reader: Reader<R>,
+    each: &mut dyn FnMut(Frame) -> Result<bool, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    for frame in reader {
+        if !each(frame?)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Call `each` with every frame from a capture file, compressed or not,
+/// until it returns false
+fn read_file(
+    path: &Path,
+    each: &mut dyn FnMut(Frame) -> Result<bool, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = BufReader::new(
+        File::open(path)
+            .map_err(|e| format!("Cannot open {}: {e}", path.display()))?,
+    );
+    let start = file.fill_buf()?;
+    let program = if start.starts_with(&[0x1f, 0x8b]) {
+        "gzip"
+    } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        "zstd"
+    } else {
+        return read_frames(Reader::new(file)?, each);
+    };
+    let mut child = exec::command(program)?
+        .arg("-dc")
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Cannot run {program}: {e}"))?;
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let r = Reader::new(stdout).and_then(|reader| read_frames(reader, each));
+    // If reading stopped early, the rest isn't needed
+    let _ = child.kill();
+    let status = child.wait()?;
+    r?;
+    // Killed by the signal above, if it hadn't finished
+    if !status.success() && status.code().is_some() {
+        return Err(
+            format!("{program} -dc {}: {status}", path.display()).into()
+        );
+    }
+    Ok(())
+}
+
+/// Write one frame as CSV or JSON
+fn write_output(
+    frame: &Frame,
+    output: Output,
+    w: &mut impl Write,
+) -> io::Result<()> {
+    match output {
+        Output::Csv => {
+            let alerts = csv_field(&frame.alerts.join(";"));
+            for row in frame.datasets.iter() {
+                writeln!(
+                    w,
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    frame.time,
+                    csv_field(&row.name),
+                    row.ops_r,
+                    row.r_s,
+                    row.ops_w,
+                    row.w_s,
+                    row.ops_d,
+                    row.d_s,
+                    row.pending_d,
+                    alerts
+                )?;
+            }
+        }
+        Output::Json => {
+            Encoder::Json.write(w, frame)?;
+        }
+    }
+    Ok(())
+}
+
+/// Convert a capture file, compressed or not, to CSV or JSON.  With CSV,
+/// start with a header if `header`.
+pub fn convert_file(
+    path: &Path,
+    output: Output,
+    header: bool,
+    w: &mut impl Write,
+) -> Result<(), Box<dyn Error>> {
+    if output == Output::Csv && header {
+        writeln!(
+            w,
+            "time,name,ops_r,r_s,ops_w,w_s,ops_d,d_s,pending_d,alerts"
+        )?;
+    }
+    read_file(path, &mut |frame| {
+        write_output(&frame, output, w)?;
+        Ok(true)
+    })
+}
+
+/// Stitch capture files together into one, written to `w` in `format`.
+///
+/// The files may be given in any order, but they're written in the order of
+/// their first updates, and every update must be later than the one before
+/// it, so overlapping files are refused.  Return the number of updates
+/// written.
+pub fn merge(
+    paths: &[PathBuf],
+    format: Format,
+    w: &mut impl Write,
+) -> Result<usize, Box<dyn Error>> {
+    let mut starts = Vec::with_capacity(paths.len());
+    for path in paths {
+        let mut first = None;
+        read_file(path, &mut |frame| {
+            first = Some(frame.time);
+            Ok(false)
+        })?;
+        // Empty files have nothing to merge
+        if let Some(time) = first {
+            starts.push((time, path));
+        }
+    }
+    starts.sort_by(|x, y| x.0.total_cmp(&y.0));
+    let mut encoder = Encoder::new(format);
+    encoder.header(w)?;
+    let mut prev = None::<(f64, &Path)>;
+    let mut count = 0;
+    for (_, path) in starts {
+        read_file(path, &mut |frame| {
+            if let Some((time, prev_path)) = prev.filter(|p| frame.time <= p.0)
+            {
+                return Err(format!(
+                    "{}: the update at {} is not after the one at {time} in {}",
+                    path.display(),
+                    frame.time,
+                    prev_path.display()
+                )
+                .into());
+            }
+            prev = Some((frame.time, path));
+            encoder.write(w, &frame)?;
+            count += 1;
+            Ok(true)
+        })?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod t {
+    use std::{env, fs};
+
+    use super::*;
+
+    /// Captures the history before the alert, the update that fired it, and
+    /// the configured number of updates after it
+    #[test]
+    fn before_and_after() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-capture", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = CaptureConfig {
+            dir:      dir.clone(),
+            before:   2,
+            after:    1,
+            max_size: None,
+            max_age:  None,
+            keep:     None,
+            compress: Compression::None,
+            format:   Format::Json,
+        };
+        let mut capture = Capture::new(config);
+        for w_s in [1.0, 2.0, 3.0] {
+            capture.tick(&[Element::writing("tank", w_s)], Vec::new());
+        }
+        assert!(!capture.is_recording());
+        capture.tick(&[Element::writing("tank", 4.0)], vec!["busy".to_owned()]);
+        assert!(capture.is_recording());
+        capture.tick(&[Element::writing("tank", 5.0)], Vec::new());
+        assert!(!capture.is_recording());
+        capture.tick(&[Element::writing("tank", 6.0)], Vec::new());
+
+        let files = fs::read_dir(&dir).unwrap().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        let path = files[0].as_ref().unwrap().path();
+        let s = fs::read_to_string(path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let frames = s
+            .lines()
+            .map(|l| serde_json::from_str::<Frame>(l).unwrap())
+            .collect::<Vec<_>>();
+        let rates =
+            frames.iter().map(|f| f.datasets[0].w_s).collect::<Vec<_>>();
+        assert_eq!(rates, [2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(frames[2].alerts, ["busy"]);
+        assert!(frames[3].alerts.is_empty());
+    }
+
+    /// Captures that start in the same second go to separate files
+    #[test]
+    fn same_second() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-capture-same", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = CaptureConfig {
+            dir:      dir.clone(),
+            before:   0,
+            after:    0,
+            max_size: None,
+            max_age:  None,
+            keep:     None,
+            compress: Compression::None,
+            format:   Format::Json,
+        };
+        let mut capture = Capture::new(config);
+        for _ in 0..3 {
+            capture.tick(
+                &[Element::writing("tank", 1.0)],
+                vec!["busy".to_owned()],
+            );
+            assert!(!capture.is_recording());
+        }
+        let (path, r) = capture.create(1.0);
+        r.unwrap();
+        let (path2, r) = capture.create(1.0);
+        r.unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, 5);
+        assert!(path.ends_with("ztop-1.jsonl"));
+        assert!(path2.ends_with("ztop-1-1.jsonl"));
+    }
+
+    fn rotating(dir: &Path) -> CaptureConfig {
+        CaptureConfig {
+            dir:      dir.to_owned(),
+            before:   0,
+            after:    5,
+            max_size: Some(1),
+            max_age:  None,
+            keep:     NonZeroUsize::new(2),
+            compress: Compression::None,
+            format:   Format::Json,
+        }
+    }
+
+    /// A long recording continues in new files, and only the newest are kept
+    #[test]
+    fn rotate() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-capture-rotate", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut capture = Capture::new(rotating(&dir));
+        capture.tick(&[Element::writing("tank", 1.0)], vec!["busy".to_owned()]);
+        for w_s in [2.0, 3.0, 4.0] {
+            capture.tick(&[Element::writing("tank", w_s)], Vec::new());
+        }
+        assert!(capture.is_recording());
+        let mut files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| fs::read_to_string(e.unwrap().path()).unwrap())
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(&dir).unwrap();
+        files.sort();
+        // Every file holds one update, and the current one is still empty
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0], "");
+        let frame = serde_json::from_str::<Frame>(&files[1]).unwrap();
+        assert_eq!(frame.datasets[0].w_s, 4.0);
+    }
+
+    /// Finished files are compressed
+    #[test]
+    fn compress() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-capture-gzip", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = CaptureConfig {
+            after: 0,
+            compress: Compression::Gzip,
+            ..rotating(&dir)
+        };
+        let mut capture = Capture::new(config);
+        capture.tick(&[Element::writing("tank", 1.0)], vec!["busy".to_owned()]);
+        assert!(!capture.is_recording());
+        for mut child in capture.compressing.drain(..) {
+            child.wait().unwrap();
+        }
+        let files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_string_lossy().ends_with(".jsonl.gz"));
+    }
+
+    /// A binary capture, with a dataset that appears partway through
+    fn binary(dir: &Path, compress: Compression) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let config = CaptureConfig {
+            after: 1,
+            format: Format::Binary,
+            compress,
+            max_size: None,
+            keep: None,
+            ..rotating(dir)
+        };
+        let mut capture = Capture::new(config);
+        let tank = || Element::writing("tank", 1.0);
+        capture.tick(&[tank()], vec!["busy".to_owned()]);
+        let home = Element::writing("tank/home, \"2\"", 2.5);
+        capture.tick(&[tank(), home], Vec::new());
+        for mut child in capture.compressing.drain(..) {
+            child.wait().unwrap();
+        }
+        let files = fs::read_dir(dir).unwrap().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        files[0].as_ref().unwrap().path()
+    }
+
+    fn convert(path: &Path, output: Output) -> String {
+        let mut out = Vec::new();
+        convert_file(path, output, true, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Binary captures read back as the same frames as JSON captures
+    #[test]
+    fn binary_json() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-capture-binary", std::process::id()));
+        let path = binary(&dir, Compression::None);
+        let json = convert(&path, Output::Json);
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(path.to_string_lossy().ends_with(".zcap"));
+        assert!(bytes.starts_with(MAGIC));
+        let frames = json
+            .lines()
+            .map(|l| serde_json::from_str::<Frame>(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].alerts, ["busy"]);
+        assert_eq!(
+            frames[0].datasets,
+            [Row::from(&Element::writing("tank", 1.0))]
+        );
+        assert_eq!(frames[1].datasets[1].name, "tank/home, \"2\"");
+        assert_eq!(frames[1].datasets[1].w_s, 2.5);
+        // JSON captures can be read, too
+        let reader = Reader::new(json.as_bytes()).unwrap();
+        let again = reader.map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(again.len(), 2);
+        assert_eq!(again[1].datasets, frames[1].datasets);
+    }
+
+    /// Compressed captures are decompressed, and CSV fields are quoted
+    #[test]
+    fn binary_csv() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-capture-zstd", std::process::id()));
+        let path = binary(&dir, Compression::Zstd);
+        let csv = convert(&path, Output::Csv);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(path.to_string_lossy().ends_with(".zcap.zst"));
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "time,name,ops_r,r_s,ops_w,w_s,ops_d,d_s,pending_d,alerts"
+        );
+        assert!(
+            lines[1].ends_with(",tank,0,0,0,1,0,0,0,busy"),
+            "{}",
+            lines[1]
+        );
+        assert!(
+            lines[3].ends_with(",\"tank/home, \"\"2\"\"\",0,0,0,2.5,0,0,0,"),
+            "{}",
+            lines[3]
+        );
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn version() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        let e = Reader::new(&bytes[..]).err().unwrap();
+        assert_eq!(e.to_string(), "Unsupported capture format version 2");
+    }
+
+    #[test]
+    fn compression() {
+        #[derive(Deserialize)]
+        struct T {
+            compress: Compression,
+        }
+        let parse = |s: &str| toml::from_str::<T>(s).map(|t| t.compress);
+        assert_eq!(parse("compress = true").unwrap(), Compression::Gzip);
+        assert_eq!(parse("compress = false").unwrap(), Compression::None);
+        assert_eq!(parse("compress = \"zstd\"").unwrap(), Compression::Zstd);
+        assert!(parse("compress = \"lz4\"").is_err());
+    }
+
+    /// Write a JSON capture with an update at each of `times`
+    fn json_capture(path: &Path, times: &[f64]) {
+        let mut file = File::create(path).unwrap();
+        for &time in times {
+            let row = Row::from(&Element::writing("tank", time));
+            let frame = Frame {
+                time,
+                alerts: Vec::new(),
+                datasets: vec![row],
+            };
+            Encoder::Json.write(&mut file, &frame).unwrap();
+        }
+    }
+
+    /// Files are merged in the order of their first updates
+    #[test]
+    fn merge_files() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-capture-merge", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = ["b", "empty", "a"].map(|name| dir.join(name));
+        json_capture(&paths[0], &[3.0, 4.0]);
+        json_capture(&paths[1], &[]);
+        json_capture(&paths[2], &[1.0, 2.0]);
+        let mut out = Vec::new();
+        let count = merge(&paths, Format::Binary, &mut out).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(count, 4);
+        let frames = Reader::new(&out[..])
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let times = frames.iter().map(|f| f.time).collect::<Vec<_>>();
+        assert_eq!(times, [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(frames[3].datasets[0].w_s, 4.0);
+    }
+
+    /// Overlapping files can't be stitched together
+    #[test]
+    fn merge_overlap() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-capture-overlap", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = ["a", "b"].map(|name| dir.join(name));
+        json_capture(&paths[0], &[1.0, 3.0]);
+        json_capture(&paths[1], &[2.0, 4.0]);
+        let e = merge(&paths, Format::Json, &mut Vec::new()).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        let msg = e.to_string();
+        assert!(
+            msg.contains("the update at 2 is not after the one at 3"),
+            "{msg}"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+// vim: tw=80
+//! A Nagios-compatible check of one dataset's activity
+use std::{error::Error, fmt, thread, time::Duration};
+
+use crate::{
+    app::{DataSource, Element, Metric},
+    config,
+};
+
+/// Sample one dataset and report its status like a Nagios plugin
+#[derive(Debug, clap::Args)]
+pub struct CheckArgs {
+    /// Include child datasets' stats with their parents'.
+    #[clap(short = 'c', long = "children")]
+    children: bool,
+    /// the dataset to check
+    #[clap(long = "dataset")]
+    dataset:  String,
+    /// the metric to check: ops_r, r_s, ops_w, w_s, ops_d, d_s, or pending_d
+    #[clap(long = "metric")]
+    metric:   Metric,
+    /// exit with WARNING status if the metric exceeds this
+    #[clap(long = "warn", value_parser = config::rate_from_str)]
+    warn:     Option<f64>,
+    /// exit with CRITICAL status if the metric exceeds this
+    #[clap(long = "crit", value_parser = config::rate_from_str)]
+    crit:     Option<f64>,
+    /// sampling interval, in seconds or with the specified unit
+    #[clap(short = 't', value_parser = config::interval_from_str, long = "time")]
+    time:     Option<Duration>,
+}
+
+/// Nagios plugin return codes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Status {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+            Status::Unknown => "UNKNOWN",
+        };
+        f.write_str(s)
+    }
+}
+
+impl CheckArgs {
+    fn sample(&self) -> Result<Option<Element>, Box<dyn Error>> {
+        let pool = self.dataset.split('/').next().unwrap_or_default();
+        let pools = vec![pool.to_owned()];
+        DataSource::check_pools(&pools)?;
+        let mut data = DataSource::new(self.children, pools, Vec::new());
+        data.refresh()?;
+        thread::sleep(self.time.unwrap_or(Duration::from_secs(1)));
+        data.refresh()?;
+        if let Some(failure) = data.failures().first() {
+            return Err(failure.clone().into());
+        }
+        let missing = data.counters().lacking(self.metric.counters());
+        if !missing.is_empty() {
+            let msg = format!(
+                "{} needs counters that this kernel lacks: {}",
+                self.metric,
+                missing.join(", ")
+            );
+            return Err(msg.into());
+        }
+        let elem = data.iter().find(|elem| elem.name == self.dataset);
+        Ok(elem)
+    }
+
+    fn status(&self, value: f64) -> Status {
+        if self.crit.map(|c| value > c).unwrap_or(false) {
+            Status::Critical
+        } else if self.warn.map(|w| value > w).unwrap_or(false) {
+            Status::Warning
+        } else {
+            Status::Ok
+        }
+    }
+
+    /// Format the status line, including performance data
+    fn report(&self, value: f64) -> (Status, String) {
+        let status = self.status(value);
+        let fmt_threshold =
+            |t: Option<f64>| t.map(|x| format!("{x:.0}")).unwrap_or_default();
+        let line = format!(
+            "ZTOP {status} - {} {} is {value:.0} | '{}'={value:.0};{};{};0;",
+            self.dataset,
+            self.metric,
+            self.metric,
+            fmt_threshold(self.warn),
+            fmt_threshold(self.crit)
+        );
+        (status, line)
+    }
+}
+
+/// Run the check, print its result, and return the process's exit code
+pub fn run(args: &CheckArgs) -> i32 {
+    let (status, line) = match args.sample() {
+        Ok(Some(elem)) => args.report(elem.get(args.metric)),
+        Ok(None) => (
+            Status::Unknown,
+            format!("ZTOP UNKNOWN - dataset {} not found", args.dataset),
+        ),
+        Err(e) => (Status::Unknown, format!("ZTOP UNKNOWN - {e}")),
+    };
+    println!("{line}");
+    status as i32
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn args(warn: Option<f64>, crit: Option<f64>) -> CheckArgs {
+        CheckArgs {
+            children: false,
+            dataset: "tank/db".to_owned(),
+            metric: Metric::WS,
+            warn,
+            crit,
+            time: None,
+        }
+    }
+
+    #[test]
+    fn ok() {
+        let (status, line) = args(Some(100.0), Some(500.0)).report(50.0);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(line, "ZTOP OK - tank/db w_s is 50 | 'w_s'=50;100;500;0;");
+    }
+
+    #[test]
+    fn warning() {
+        let (status, _) = args(Some(100.0), Some(500.0)).report(200.0);
+        assert_eq!(status, Status::Warning);
+    }
+
+    #[test]
+    fn critical() {
+        let (status, line) = args(Some(100.0), Some(500.0)).report(600.0);
+        assert_eq!(status, Status::Critical);
+        assert!(line.starts_with("ZTOP CRITICAL - "));
+    }
+
+    #[test]
+    fn no_thresholds() {
+        let (status, line) = args(None, None).report(600.0);
+        assert_eq!(status, Status::Ok);
+        assert!(line.ends_with("'w_s'=600;;;0;"));
+    }
+}
@@ -0,0 +1,689 @@
+// vim: tw=80
+//! ztop's configuration file
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    env,
+    error::Error,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use cfg_if::cfg_if;
+use glob::Pattern;
+use humanize_rs::bytes::Bytes;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+#[cfg(not(target_os = "linux"))]
+use crate::warning::warn;
+use crate::{
+    agentx::AgentxConfig,
+    alert::Rule,
+    app::COLUMNS,
+    capture::CaptureConfig,
+    pressure::PressureConfig,
+    serve::OpenMetricsConfig,
+    statsd::StatsdConfig,
+    zabbix::ZabbixConfig,
+};
+
+cfg_if! {
+    if #[cfg(target_os = "freebsd")] {
+        mod freebsd;
+        use freebsd::Notify;
+    } else if #[cfg(target_os = "linux")] {
+        mod linux;
+        use linux::Notify;
+    } else if #[cfg(target_os = "illumos")] {
+        mod illumos;
+        use illumos::Notify;
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "alert")]
+    pub alerts:           Vec<Rule>,
+    /// Where and how much to record when a `capture` alert fires
+    #[serde(default)]
+    pub capture:          Option<CaptureConfig>,
+    /// Mark datasets that are deleting heavily for a sustained period
+    #[serde(default)]
+    pub delete_pressure:  Option<PressureConfig>,
+    /// Quit when Esc is pressed with no popup open
+    #[serde(default)]
+    pub esc_quits:        bool,
+    /// Custom column headers, keyed by the canonical header
+    #[serde(default)]
+    pub headers:          HashMap<String, String>,
+    /// Weights for sorting by hotness
+    #[serde(default)]
+    pub hotness:          HotnessWeights,
+    /// Translate headers and messages into this language, instead of the
+    /// one from the environment
+    #[serde(default)]
+    pub lang:             Option<String>,
+    /// Never display or alert on datasets matching these glob patterns
+    #[serde(default, deserialize_with = "de_patterns")]
+    pub ignore:           Vec<Pattern>,
+    /// Log each dataset that appears while running to syslog
+    #[serde(default)]
+    pub log_new_datasets: bool,
+    /// Largest plausible read, write, or delete bandwidth for each pool, in B/s
+    #[serde(default, deserialize_with = "de_rates")]
+    pub max_bandwidth:    HashMap<String, f64>,
+    /// Rules for changing how dataset names are displayed
+    #[serde(default)]
+    pub relabel:          Vec<Relabel>,
+    /// Named sets of datasets, selectable with `--set` or at runtime
+    #[serde(default)]
+    pub sets:             BTreeMap<String, DatasetSet>,
+    /// Directories of libvirt or bhyve VM configurations, to name the VM
+    /// that uses each volume
+    #[serde(default)]
+    pub vm_configs:       Option<Vec<PathBuf>>,
+    #[cfg(target_os = "linux")]
+    #[serde(default)]
+    pub journal:          crate::journal::JournalConfig,
+    /// The systemd journal only exists on Linux, but the same config file
+    /// may be shared with other hosts
+    #[cfg(not(target_os = "linux"))]
+    #[serde(default)]
+    pub journal:          Option<serde::de::IgnoredAny>,
+    #[serde(default)]
+    pub agentx:           AgentxConfig,
+    #[serde(default)]
+    pub openmetrics:      OpenMetricsConfig,
+    #[serde(default)]
+    pub statsd:           StatsdConfig,
+    #[serde(default)]
+    pub zabbix:           ZabbixConfig,
+}
+
+impl Config {
+    /// Load the configuration file.
+    ///
+    /// If `path` is `None`, use the default location, and silently ignore it
+    /// if it does not exist.
+    pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let (path, required) = match path {
+            Some(p) => (p.to_owned(), true),
+            None => match Self::default_path() {
+                Some(p) => (p, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let config: Config = match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).map_err(|e| {
+                format!("Invalid config file {}: {e}", path.display())
+            })?,
+            Err(e) if !required && e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Config::default());
+            }
+            Err(e) => {
+                return Err(
+                    format!("Cannot read {}: {e}", path.display()).into()
+                )
+            }
+        };
+        for rule in config.alerts.iter() {
+            rule.validate(config.capture.is_some())?;
+        }
+        if let Some(pressure) = config.delete_pressure.as_ref() {
+            pressure.validate()?;
+        }
+        config.openmetrics.validate()?;
+        #[cfg(not(target_os = "linux"))]
+        if config.journal.is_some() {
+            warn("Ignoring the [journal] section, which requires Linux".into());
+        }
+        for col in config.headers.keys() {
+            if !COLUMNS.iter().any(|(name, _)| name == col) {
+                return Err(format!("Unknown column {col:?} in headers").into());
+            }
+        }
+        Ok(config)
+    }
+
+    /// `$XDG_CONFIG_HOME/ztop`, or `~/.config/ztop`
+    pub fn dir() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(
+            || env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")),
+        )?;
+        Some(dir.join("ztop"))
+    }
+
+    /// `config.toml` in [`Config::dir`]
+    pub fn default_path() -> Option<PathBuf> {
+        Some(Self::dir()?.join("config.toml"))
+    }
+}
+
+/// Notices when the configuration file changes, with inotify on Linux or
+/// kqueue on FreeBSD.  While the file's directory doesn't exist, there's
+/// nothing to watch, so its modification time is polled instead.
+#[derive(Debug)]
+pub struct Watcher {
+    path:   Option<PathBuf>,
+    notify: Option<Notify>,
+    mtime:  Option<SystemTime>,
+}
+
+impl Watcher {
+    /// Watch the file at `path`, or at the default location if `None`
+    pub fn new(path: Option<&Path>) -> Self {
+        let path = path.map(Path::to_owned).or_else(Config::default_path);
+        let notify = path.as_deref().and_then(|p| Notify::new(p).ok());
+        let mtime = path.as_deref().and_then(modified);
+        Watcher {
+            path,
+            notify,
+            mtime,
+        }
+    }
+
+    /// Has the file been modified, created, or removed since the last call?
+    pub fn changed(&mut self) -> bool {
+        let Some(path) = self.path.as_deref() else {
+            return false;
+        };
+        if let Some(notify) = self.notify.as_mut() {
+            match notify.changed() {
+                Ok(changed) => {
+                    if changed {
+                        self.mtime = modified(path);
+                    }
+                    return changed;
+                }
+                // The directory went away, and maybe the file with it
+                Err(_) => self.notify = None,
+            }
+        }
+        self.notify = Notify::new(path).ok();
+        let mtime = modified(path);
+        if mtime == self.mtime {
+            false
+        } else {
+            self.mtime = mtime;
+            true
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|md| md.modified()).ok()
+}
+
+/// A named set of datasets to display, from a `[sets.NAME]` table
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatasetSet {
+    /// Display only these pools, or all pools if empty
+    #[serde(default)]
+    pub pools:  Vec<String>,
+    /// Display only datasets with names matching this regex
+    #[serde(default, deserialize_with = "de_opt_regex")]
+    pub filter: Option<Regex>,
+}
+
+/// How much each kind of bandwidth contributes to a dataset's hotness
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotnessWeights {
+    pub read:   f64,
+    pub write:  f64,
+    pub delete: f64,
+}
+
+impl Default for HotnessWeights {
+    fn default() -> Self {
+        HotnessWeights {
+            read:   1.0,
+            write:  1.0,
+            delete: 1.0,
+        }
+    }
+}
+
+/// Rewrites a dataset's displayed name.  Its real name is unaffected.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Relabel {
+    #[serde(rename = "match", deserialize_with = "de_regex")]
+    pattern: Regex,
+    /// Replacement text, which may refer to capture groups like `$1`
+    replace: String,
+}
+
+impl Relabel {
+    /// Apply a list of rules, in order, to a dataset name
+    pub fn apply<'a>(rules: &[Relabel], name: &'a str) -> Cow<'a, str> {
+        let mut label = Cow::Borrowed(name);
+        for rule in rules {
+            if let Cow::Owned(s) =
+                rule.pattern.replace(&label, rule.replace.as_str())
+            {
+                label = Cow::Owned(s);
+            }
+        }
+        label
+    }
+}
+
+/// Parse a duration like "30s", or a plain number of seconds
+pub fn duration_from_str(s: &str) -> Result<Duration, String> {
+    if let Ok(fsecs) = s.parse::<f64>() {
+        secs_to_duration(fsecs)
+    } else {
+        // Must have units
+        humanize_rs::duration::parse(s).map_err(|e| e.to_string())
+    }
+}
+
+/// Convert a number of seconds to a Duration, if it isn't negative, NaN, or
+/// too large
+fn secs_to_duration(secs: f64) -> Result<Duration, String> {
+    if secs.is_nan() || secs < 0.0 {
+        return Err(format!("{secs} is not a number of seconds"));
+    }
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| format!("{secs} seconds is too long"))
+}
+
+/// Parse an update interval, which unlike other durations can't be zero
+pub fn interval_from_str(s: &str) -> Result<Duration, String> {
+    match duration_from_str(s)? {
+        d if d.is_zero() => Err("the interval must be positive".to_owned()),
+        d => Ok(d),
+    }
+}
+
+/// Format an update interval the way `interval_from_str` would accept it
+pub fn format_interval(d: Duration) -> String {
+    if d.subsec_nanos() == 0 {
+        format!("{}s", d.as_secs())
+    } else if d.subsec_nanos() % 1_000_000 == 0 {
+        format!("{}ms", d.as_millis())
+    } else {
+        format!("{}us", d.as_micros())
+    }
+}
+
+/// Parse a rate like "100M", or a plain positive number
+pub fn rate_from_str(s: &str) -> Result<f64, String> {
+    if let Ok(rate) = s.parse::<f64>() {
+        check_rate(rate)
+    } else {
+        // Must have units
+        s.parse::<Bytes<u64>>()
+            .map_err(|e| e.to_string())
+            .and_then(|b| check_rate(b.size() as f64))
+    }
+}
+
+/// Rates must be finite and positive
+fn check_rate(rate: f64) -> Result<f64, String> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(format!("{rate} is not a positive rate"))
+    }
+}
+
+/// Deserialize a duration like "30s", or a plain number of seconds
+pub fn de_duration<'de, D>(d: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match NumOrStr::deserialize(d)? {
+        NumOrStr::Num(secs) => {
+            secs_to_duration(secs).map_err(serde::de::Error::custom)
+        }
+        NumOrStr::Str(s) => {
+            duration_from_str(&s).map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+/// Like `de_duration`, for optional fields
+pub fn de_opt_duration<'de, D>(d: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    de_duration(d).map(Some)
+}
+
+/// Deserialize a rate like "100M", or a plain number
+pub fn de_rate<'de, D>(d: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match NumOrStr::deserialize(d)? {
+        NumOrStr::Num(x) => check_rate(x).map_err(serde::de::Error::custom),
+        NumOrStr::Str(s) => rate_from_str(&s).map_err(serde::de::Error::custom),
+    }
+}
+
+/// Deserialize an optional size like "10M", or a plain number of bytes
+pub fn de_opt_size<'de, D>(d: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let size = match NumOrStr::deserialize(d)? {
+        NumOrStr::Num(x) => check_rate(x).map(|x| x as u64),
+        NumOrStr::Str(s) => rate_from_str(&s).map(|x| x as u64),
+    };
+    match size {
+        Ok(0) => Err(serde::de::Error::custom("the size must be positive")),
+        Ok(size) => Ok(Some(size)),
+        Err(e) => Err(serde::de::Error::custom(e)),
+    }
+}
+
+/// Like `de_rate`, for optional fields
+pub fn de_opt_rate<'de, D>(d: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    de_rate(d).map(Some)
+}
+
+/// Deserialize a table of rates, each like `de_rate`
+pub fn de_rates<'de, D>(d: D) -> Result<HashMap<String, f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, NumOrStr>::deserialize(d)?
+        .into_iter()
+        .map(|(k, v)| match v {
+            NumOrStr::Num(x) => check_rate(x)
+                .map(|x| (k, x))
+                .map_err(serde::de::Error::custom),
+            NumOrStr::Str(s) => rate_from_str(&s)
+                .map(|x| (k, x))
+                .map_err(serde::de::Error::custom),
+        })
+        .collect()
+}
+
+/// Deserialize a list of glob patterns
+pub fn de_patterns<'de, D>(d: D) -> Result<Vec<Pattern>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|s| Pattern::new(s).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Deserialize a regular expression
+pub fn de_regex<'de, D>(d: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(d)?;
+    Regex::new(&s).map_err(serde::de::Error::custom)
+}
+
+/// Like `de_regex`, for optional fields
+pub fn de_opt_regex<'de, D>(d: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    de_regex(d).map(Some)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumOrStr {
+    Num(f64),
+    Str(String),
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn watcher() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-watcher", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, "").unwrap();
+        let mut watcher = Watcher::new(Some(&path));
+        assert!(!watcher.changed());
+        fs::write(&path, "[sets]").unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+        // Other files in the same directory don't matter
+        fs::write(dir.join("other.toml"), "").unwrap();
+        assert!(!watcher.changed());
+        // Editors often replace the file rather than rewrite it
+        fs::write(dir.join("config.toml.tmp"), "").unwrap();
+        fs::rename(dir.join("config.toml.tmp"), &path).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+        // Once the directory is back, so is the file
+        fs::create_dir_all(&dir).unwrap();
+        assert!(!watcher.changed());
+        fs::write(&path, "").unwrap();
+        assert!(watcher.changed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty() {
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.alerts.is_empty());
+    }
+
+    /// A config file shared with Linux hosts is valid everywhere
+    #[test]
+    fn journal() {
+        let s = "[journal]\nsummary_interval = \"1m\"\n";
+        toml::from_str::<Config>(s).unwrap();
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(duration_from_str("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(duration_from_str("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(duration_from_str("0"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn duration_invalid() {
+        for s in ["-1", "nan", "inf", "1e30", "soon", ""] {
+            assert!(duration_from_str(s).is_err(), "{s:?} was accepted");
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct WithDuration {
+        #[serde(deserialize_with = "de_duration")]
+        d: Duration,
+    }
+
+    #[test]
+    fn config_duration() {
+        let w: WithDuration = toml::from_str("d = 1.5").unwrap();
+        assert_eq!(w.d, Duration::from_millis(1500));
+        let w: WithDuration = toml::from_str("d = \"1m\"").unwrap();
+        assert_eq!(w.d, Duration::from_secs(60));
+    }
+
+    /// Bad durations in the config file are errors, not panics
+    #[test]
+    fn config_duration_invalid() {
+        for s in ["d = -1", "d = nan", "d = inf", "d = 1e30", "d = \"-1\""] {
+            assert!(toml::from_str::<WithDuration>(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn interval() {
+        assert_eq!(interval_from_str("5"), Ok(Duration::from_secs(5)));
+        assert!(interval_from_str("0").is_err());
+        assert!(interval_from_str("-0.5").is_err());
+    }
+
+    #[test]
+    fn rate() {
+        assert_eq!(rate_from_str("1.5"), Ok(1.5));
+        assert_eq!(rate_from_str("100M"), Ok(100e6));
+        assert_eq!(rate_from_str("1Ki"), Ok(1024.0));
+    }
+
+    #[test]
+    fn rate_invalid() {
+        for s in ["-1", "0", "nan", "inf", "-inf", "0M", "fast", ""] {
+            assert!(rate_from_str(s).is_err(), "{s:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn alerts() {
+        let s = r#"
+            [[alert]]
+            metric = "w_s"
+            threshold = "100M"
+
+            [[alert]]
+            metric = "d_s"
+            threshold = "1G"
+        "#;
+        let config: Config = toml::from_str(s).unwrap();
+        assert_eq!(config.alerts.len(), 2);
+    }
+
+    #[test]
+    fn delete_pressure() {
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.delete_pressure.is_none());
+        let s = r#"
+            [delete_pressure]
+            rate = "50M"
+        "#;
+        let config: Config = toml::from_str(s).unwrap();
+        let pressure = config.delete_pressure.unwrap();
+        assert_eq!(pressure.rate, Some(5e7));
+        assert_eq!(pressure.pending, None);
+    }
+
+    #[test]
+    fn esc_quits() {
+        let config: Config = toml::from_str("esc_quits = true").unwrap();
+        assert!(config.esc_quits);
+        let config: Config = toml::from_str("").unwrap();
+        assert!(!config.esc_quits);
+    }
+
+    #[test]
+    fn hotness() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.hotness, HotnessWeights::default());
+        let s = r#"
+            [hotness]
+            write = 2.0
+            delete = 0.5
+        "#;
+        let config: Config = toml::from_str(s).unwrap();
+        assert_eq!(config.hotness.read, 1.0);
+        assert_eq!(config.hotness.write, 2.0);
+        assert_eq!(config.hotness.delete, 0.5);
+    }
+
+    #[test]
+    fn ignore() {
+        let s = r#"ignore = ["*/swap", "rpool/ROOT/*@*"]"#;
+        let config: Config = toml::from_str(s).unwrap();
+        assert_eq!(config.ignore.len(), 2);
+        assert!(config.ignore[0].matches("rpool/swap"));
+        assert!(config.ignore[0].matches("tank/vm/swap"));
+        assert!(!config.ignore[0].matches("tank/swapfiles"));
+        assert!(config.ignore[1].matches("rpool/ROOT/default@snap"));
+        assert!(!config.ignore[1].matches("rpool/ROOT/default"));
+    }
+
+    #[test]
+    fn log_new_datasets() {
+        let s = "log_new_datasets = true";
+        let config: Config = toml::from_str(s).unwrap();
+        assert!(config.log_new_datasets);
+    }
+
+    #[test]
+    fn max_bandwidth() {
+        let s = r#"
+            [max_bandwidth]
+            tank = "2G"
+            zroot = 500000000
+        "#;
+        let config: Config = toml::from_str(s).unwrap();
+        assert_eq!(config.max_bandwidth["tank"], 2e9);
+        assert_eq!(config.max_bandwidth["zroot"], 5e8);
+    }
+
+    #[test]
+    fn max_bandwidth_negative() {
+        let s = "[max_bandwidth]\ntank = -1";
+        assert!(toml::from_str::<Config>(s).is_err());
+    }
+
+    #[test]
+    fn relabel() {
+        let s = r#"
+            [[relabel]]
+            match = "^tank/srv/containers/"
+            replace = ""
+
+            [[relabel]]
+            match = "^([0-9a-f]{8})[0-9a-f-]{28}$"
+            replace = "vm-$1"
+        "#;
+        let config: Config = toml::from_str(s).unwrap();
+        let rules = &config.relabel;
+        assert_eq!(Relabel::apply(rules, "tank/srv/containers/web"), "web");
+        assert_eq!(
+            Relabel::apply(
+                rules,
+                "tank/srv/containers/0123abcd-0000-0000-0000-000000000000"
+            ),
+            "vm-0123abcd"
+        );
+        assert_eq!(Relabel::apply(rules, "tank/home"), "tank/home");
+    }
+
+    #[test]
+    fn sets() {
+        let s = r#"
+            [sets.prod-dbs]
+            pools = ["tank", "fast"]
+            filter = "/db[0-9]+$"
+
+            [sets.all]
+        "#;
+        let config: Config = toml::from_str(s).unwrap();
+        let names = config.sets.keys().collect::<Vec<_>>();
+        assert_eq!(names, ["all", "prod-dbs"]);
+        let set = &config.sets["prod-dbs"];
+        assert_eq!(set.pools, ["tank", "fast"]);
+        assert!(set.filter.as_ref().unwrap().is_match("fast/db12"));
+        assert!(config.sets["all"].pools.is_empty());
+        assert!(config.sets["all"].filter.is_none());
+    }
+
+    #[test]
+    fn unknown_field() {
+        assert!(toml::from_str::<Config>("colour = true").is_err());
+    }
+}
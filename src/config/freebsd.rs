@@ -0,0 +1,129 @@
+// vim: tw=80
+//! Watching the configuration file with kqueue
+use std::{
+    error::Error,
+    fs::{self, File},
+    os::{fd::AsRawFd, unix::fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    libc::timespec,
+    sys::event::{EventFilter, EventFlag, FilterFlag, KEvent, Kqueue},
+};
+
+/// Reports changes to a file.  Its directory is watched too, to notice the
+/// file being created, removed, or replaced by an editor.
+#[derive(Debug)]
+pub(super) struct Notify {
+    kq:   Kqueue,
+    path: PathBuf,
+    dir:  File,
+    /// The file itself, if it exists
+    file: Option<File>,
+}
+
+impl Notify {
+    /// Start watching `path`.  Its directory must already exist.
+    pub(super) fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut notify = Notify {
+            kq:   Kqueue::new()?,
+            path: path.to_owned(),
+            dir:  File::open(dir)?,
+            file: None,
+        };
+        notify.register(
+            &notify.dir,
+            FilterFlag::NOTE_WRITE
+                | FilterFlag::NOTE_DELETE
+                | FilterFlag::NOTE_RENAME,
+        )?;
+        notify.reopen()?;
+        Ok(notify)
+    }
+
+    /// Has the file changed since the last call?  Fails once the directory is
+    /// removed or renamed, since the watch is then useless.
+    pub(super) fn changed(&mut self) -> Result<bool, Box<dyn Error>> {
+        let poll = timespec {
+            tv_sec:  0,
+            tv_nsec: 0,
+        };
+        let empty = KEvent::new(
+            0,
+            EventFilter::EVFILT_VNODE,
+            EventFlag::empty(),
+            FilterFlag::empty(),
+            0,
+            0,
+        );
+        let mut events = [empty; 2];
+        let dir = self.dir.as_raw_fd() as usize;
+        let mut changed = false;
+        loop {
+            let n = self.kq.kevent(&[], &mut events, Some(poll))?;
+            if n == 0 {
+                break;
+            }
+            for event in &events[..n] {
+                if event.ident() != dir {
+                    changed = true;
+                } else if event.fflags().intersects(
+                    FilterFlag::NOTE_DELETE | FilterFlag::NOTE_RENAME,
+                ) {
+                    return Err("The directory is gone".into());
+                } else {
+                    // Something in the directory changed.  It only matters if
+                    // the file appeared, disappeared, or was replaced.
+                    let ino = fs::metadata(&self.path).ok().map(|md| md.ino());
+                    changed |= ino != self.ino();
+                }
+            }
+        }
+        if changed {
+            self.reopen()?;
+        }
+        Ok(changed)
+    }
+
+    /// The inode number of the file being watched, if it exists
+    fn ino(&self) -> Option<u64> {
+        let md = self.file.as_ref()?.metadata().ok()?;
+        Some(md.ino())
+    }
+
+    /// Watch whatever is now at the file's path.  Closing the old file stops
+    /// watching it.
+    fn reopen(&mut self) -> Result<(), Box<dyn Error>> {
+        self.file = File::open(&self.path).ok();
+        if let Some(file) = &self.file {
+            self.register(
+                file,
+                FilterFlag::NOTE_WRITE
+                    | FilterFlag::NOTE_EXTEND
+                    | FilterFlag::NOTE_ATTRIB
+                    | FilterFlag::NOTE_DELETE
+                    | FilterFlag::NOTE_RENAME,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Ask for the `fflags` events on `file`
+    fn register(&self, file: &File, fflags: FilterFlag) -> nix::Result<()> {
+        let event = KEvent::new(
+            file.as_raw_fd() as usize,
+            EventFilter::EVFILT_VNODE,
+            EventFlag::EV_ADD | EventFlag::EV_CLEAR,
+            fflags,
+            0,
+            0,
+        );
+        self.kq.kevent(&[event], &mut [], None)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+// vim: tw=80
+//! Watching the configuration file by polling its metadata, since illumos
+//! has neither inotify nor kqueue
+use std::{
+    error::Error,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+/// The identity and modification time of the file, or `None` if it doesn't
+/// exist
+type Stamp = Option<(u64, u64, i64, i64, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let md = fs::metadata(path).ok()?;
+    Some((md.dev(), md.ino(), md.mtime(), md.mtime_nsec(), md.size()))
+}
+
+/// Reports changes to a file, including its replacement by an editor
+#[derive(Debug)]
+pub(super) struct Notify {
+    path:  PathBuf,
+    dir:   PathBuf,
+    stamp: Stamp,
+}
+
+impl Notify {
+    /// Start watching `path`.  Its directory must already exist.
+    pub(super) fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if !dir.is_dir() {
+            return Err(format!("{} is not a directory", dir.display()).into());
+        }
+        Ok(Notify {
+            path:  path.to_owned(),
+            dir:   dir.to_owned(),
+            stamp: stamp(path),
+        })
+    }
+
+    /// Has the file changed since the last call?  Fails once the directory is
+    /// removed, since the watch is then useless.
+    pub(super) fn changed(&mut self) -> Result<bool, Box<dyn Error>> {
+        if !self.dir.is_dir() {
+            return Err("The directory is gone".into());
+        }
+        let stamp = stamp(&self.path);
+        let changed = stamp != self.stamp;
+        self.stamp = stamp;
+        Ok(changed)
+    }
+}
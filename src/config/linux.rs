@@ -0,0 +1,68 @@
+// vim: tw=80
+//! Watching the configuration file with inotify
+use std::{error::Error, ffi::OsString, path::Path};
+
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+
+/// Reports changes to a file by watching its directory, so it notices editors
+/// that replace the file instead of rewriting it
+#[derive(Debug)]
+pub(super) struct Notify {
+    inotify: Inotify,
+    name:    OsString,
+}
+
+impl Notify {
+    /// Start watching `path`.  Its directory must already exist.
+    pub(super) fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| format!("{} is not a file", path.display()))?
+            .to_owned();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let inotify =
+            Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        inotify.add_watch(
+            dir,
+            AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_CREATE
+                | AddWatchFlags::IN_DELETE
+                | AddWatchFlags::IN_MOVED_FROM
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_DELETE_SELF
+                | AddWatchFlags::IN_MOVE_SELF,
+        )?;
+        Ok(Notify { inotify, name })
+    }
+
+    /// Has the file changed since the last call?  Fails once the directory is
+    /// removed or renamed, since the watch is then useless.
+    pub(super) fn changed(&mut self) -> Result<bool, Box<dyn Error>> {
+        let mut changed = false;
+        loop {
+            let events = match self.inotify.read_events() {
+                Ok(events) => events,
+                Err(Errno::EAGAIN) => return Ok(changed),
+                Err(e) => return Err(e.into()),
+            };
+            for event in events {
+                if event.mask.intersects(
+                    AddWatchFlags::IN_DELETE_SELF
+                        | AddWatchFlags::IN_MOVE_SELF
+                        | AddWatchFlags::IN_IGNORED,
+                ) {
+                    return Err("The directory is gone".into());
+                }
+                // After an overflow, there's no telling what was lost
+                changed |= event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW)
+                    || event.name.as_ref() == Some(&self.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// vim: tw=80
+//! Name the containers that use each file system, on Linux.
+//!
+//! Docker's zfs storage driver and LXD both keep enough state on disk to map
+//! datasets back to containers, without asking either daemon:
+//!
+//! * Docker names each container's writable layer `<parent>/<mount-id>`,
+//!   and records the mount id in
+//!   `image/zfs/layerdb/mounts/<container id>/mount-id` under its data root.
+//!   The container's name is in `containers/<container id>/config.v2.json`.
+//! * LXD names a container's dataset `<pool>/containers/<name>` and a VM's
+//!   `<pool>/virtual-machines/<name>`, and mounts each under
+//!   `storage-pools/<pool>/` in its own directory.  Incus does the same.
+//!
+//! containerd's zfs snapshotter keeps its mapping in a bbolt database, which
+//! ztop can't read, so its containers aren't named.
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+/// Where Docker keeps its state, unless `/etc/docker/daemon.json` says
+/// otherwise
+const DOCKER_ROOT: &str = "/var/lib/docker";
+const DOCKER_CONFIG: &str = "/etc/docker/daemon.json";
+/// Where the LXD snap, a packaged LXD, and Incus keep their state
+const LXD_DIRS: &[&str] =
+    &["/var/snap/lxd/common/lxd", "/var/lib/lxd", "/var/lib/incus"];
+
+/// The containers that use each dataset
+#[derive(Clone, Debug, Default)]
+pub struct Containers {
+    /// Docker containers' names, by their writable layers' mount ids
+    layers: HashMap<String, String>,
+    /// The names of LXD's containers and VMs
+    lxd:    HashSet<String>,
+}
+
+impl Containers {
+    /// The name of the container that uses this dataset, if any
+    pub fn get(&self, dataset: &str) -> Option<&str> {
+        let (parent, base) = dataset.rsplit_once('/')?;
+        if let Some(name) = self.layers.get(base) {
+            return Some(name);
+        }
+        let kind = parent.rsplit('/').next()?;
+        if !matches!(kind, "containers" | "virtual-machines") {
+            return None;
+        }
+        let base = base.strip_suffix(".block").unwrap_or(base);
+        self.lxd.get(base).map(String::as_str)
+    }
+
+    /// Were no containers found?
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty() && self.lxd.is_empty()
+    }
+}
+
+/// Docker's data root, from its daemon configuration if that sets one
+fn docker_root(config: Option<&str>) -> PathBuf {
+    config
+        .and_then(|text| serde_json::from_str::<Value>(text).ok())
+        .and_then(|v| v.get("data-root")?.as_str().map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(DOCKER_ROOT))
+}
+
+/// The container's name from its `config.v2.json`, without Docker's leading
+/// slash
+fn docker_name(text: &str) -> Option<String> {
+    let v = serde_json::from_str::<Value>(text).ok()?;
+    let name = v.get("Name")?.as_str()?;
+    Some(name.trim_start_matches('/').to_owned())
+}
+
+/// Map each Docker container's writable layer to its name.  A container
+/// whose configuration can't be read is named by its short id, as
+/// `docker ps` would.
+fn scan_docker(root: &Path, layers: &mut HashMap<String, String>) {
+    let mounts = root.join("image/zfs/layerdb/mounts");
+    let Ok(entries) = fs::read_dir(mounts) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(mount_id) = fs::read_to_string(entry.path().join("mount-id"))
+        else {
+            continue;
+        };
+        let id = entry.file_name().to_string_lossy().into_owned();
+        let config = root.join("containers").join(&id).join("config.v2.json");
+        let name = fs::read_to_string(config)
+            .ok()
+            .and_then(|text| docker_name(&text))
+            .unwrap_or_else(|| id.chars().take(12).collect());
+        layers.insert(mount_id.trim().to_owned(), name);
+    }
+}
+
+/// Collect the names of the containers and VMs in each of LXD's storage
+/// pools
+fn scan_lxd(dir: &Path, lxd: &mut HashSet<String>) {
+    let Ok(pools) = fs::read_dir(dir.join("storage-pools")) else {
+        return;
+    };
+    for pool in pools.flatten().map(|e| e.path()) {
+        for kind in ["containers", "virtual-machines"] {
+            if let Ok(entries) = fs::read_dir(pool.join(kind)) {
+                lxd.extend(
+                    entries
+                        .flatten()
+                        .map(|e| e.file_name().to_string_lossy().into_owned()),
+                );
+            }
+        }
+    }
+}
+
+/// Scan Docker's data root and LXD's state directories for containers.
+/// Directories and files that can't be read are skipped, since most hosts
+/// have no containers at all.
+pub fn scan(docker_roots: &[PathBuf], lxd_dirs: &[PathBuf]) -> Containers {
+    let mut containers = Containers::default();
+    for root in docker_roots {
+        scan_docker(root, &mut containers.layers);
+    }
+    for dir in lxd_dirs {
+        scan_lxd(dir, &mut containers.lxd);
+    }
+    containers
+}
+
+/// Scan the default locations
+pub fn scan_default() -> Containers {
+    let config = fs::read_to_string(DOCKER_CONFIG).ok();
+    let docker = [docker_root(config.as_deref())];
+    let lxd = LXD_DIRS.iter().map(PathBuf::from).collect::<Vec<_>>();
+    scan(&docker, &lxd)
+}
+
+#[cfg(test)]
+mod t {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn daemon_json() {
+        let root = docker_root(Some(r#"{"data-root": "/srv/docker"}"#));
+        assert_eq!(root, Path::new("/srv/docker"));
+        assert_eq!(docker_root(Some("{}")), Path::new(DOCKER_ROOT));
+        assert_eq!(docker_root(None), Path::new(DOCKER_ROOT));
+    }
+
+    #[test]
+    fn docker() {
+        let root = env::temp_dir()
+            .join(format!("ztop-{}-container-docker", std::process::id()));
+        let id = "4f1c2a9d8e7b6a5f4c3b2a1d0e9f8a7b";
+        let mounts = root.join("image/zfs/layerdb/mounts");
+        fs::create_dir_all(mounts.join(id)).unwrap();
+        fs::write(mounts.join(id).join("mount-id"), "a1b2c3\n").unwrap();
+        // A container without a configuration is named by its short id
+        fs::create_dir_all(mounts.join("0123456789abcdef")).unwrap();
+        fs::write(mounts.join("0123456789abcdef/mount-id"), "d4e5f6").unwrap();
+        let config = root.join("containers").join(id);
+        fs::create_dir_all(&config).unwrap();
+        fs::write(
+            config.join("config.v2.json"),
+            r#"{"ID": "4f1c", "Name": "/web", "State": {"Running": true}}"#,
+        )
+        .unwrap();
+        let containers = scan(std::slice::from_ref(&root), &[]);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(containers.get("tank/docker/a1b2c3"), Some("web"));
+        assert_eq!(containers.get("tank/docker/d4e5f6"), Some("0123456789ab"));
+        assert_eq!(containers.get("tank/docker/a1b2c3-init"), None);
+        assert_eq!(containers.get("a1b2c3"), None);
+    }
+
+    #[test]
+    fn lxd() {
+        let dir = env::temp_dir()
+            .join(format!("ztop-{}-container-lxd", std::process::id()));
+        let pool = dir.join("storage-pools/default");
+        fs::create_dir_all(pool.join("containers/db")).unwrap();
+        fs::create_dir_all(pool.join("virtual-machines/win")).unwrap();
+        let containers = scan(&[], std::slice::from_ref(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(containers.get("tank/lxd/containers/db"), Some("db"));
+        assert_eq!(
+            containers.get("tank/lxd/virtual-machines/win.block"),
+            Some("win")
+        );
+        assert_eq!(containers.get("tank/lxd/images/db"), None);
+        assert_eq!(containers.get("tank/lxd/containers/web"), None);
+    }
+
+    #[test]
+    fn missing_dirs() {
+        let missing = [PathBuf::from("/nonexistent/ztop/containers")];
+        assert!(scan(&missing, &missing).is_empty());
+    }
+}
@@ -0,0 +1,31 @@
+// vim: tw=80
+//! Convert capture files to CSV or JSON
+use std::{
+    error::Error,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use crate::capture::{self, Output};
+
+/// Convert capture files, in either format and compressed or not, to CSV or
+/// JSON on standard output
+#[derive(Debug, clap::Args)]
+pub struct ConvertArgs {
+    /// the format to write: csv, with one row per dataset per update, or
+    /// json, with one line per update
+    #[clap(long = "to", default_value = "csv")]
+    to:    Output,
+    /// the capture files to convert, in order
+    #[clap(required = true)]
+    files: Vec<PathBuf>,
+}
+
+pub fn run(args: &ConvertArgs) -> Result<(), Box<dyn Error>> {
+    let mut w = BufWriter::new(io::stdout().lock());
+    for (i, path) in args.files.iter().enumerate() {
+        capture::convert_file(path, args.to, i == 0, &mut w)?;
+    }
+    w.flush()?;
+    Ok(())
+}
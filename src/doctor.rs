@@ -0,0 +1,273 @@
+// vim: tw=80
+//! Diagnose the environment problems that keep ztop from working
+use std::{
+    env,
+    fmt,
+    io::{self, IsTerminal},
+};
+
+use crate::{
+    app::{column_counters, Counters, DataSource, COLUMNS},
+    config::Config,
+    exec,
+    i18n,
+    ui::{COMPACT_WIDTH, FULL_WIDTH},
+    Cli,
+};
+
+/// Check the platform, kstats, pools, terminal, and configuration
+#[derive(Debug, clap::Args)]
+pub struct DoctorArgs {
+    /// Also check that these pools are visible
+    pools: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Status::Ok => "ok",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        f.pad(s)
+    }
+}
+
+/// The result of one check
+#[derive(Debug)]
+struct Finding {
+    status: Status,
+    what:   &'static str,
+    msg:    String,
+}
+
+impl Finding {
+    fn new(status: Status, what: &'static str, msg: impl Into<String>) -> Self {
+        Finding {
+            status,
+            what,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    /// Continuation lines are indented under the message
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = self.msg.lines();
+        let first = lines.next().unwrap_or_default();
+        write!(f, "{:<4}  {:<9} {first}", self.status, self.what)?;
+        for line in lines {
+            write!(f, "\n{:16}{line}", "")?;
+        }
+        Ok(())
+    }
+}
+
+fn platform() -> Finding {
+    let msg = format!("{} on {}", env!("CARGO_PKG_VERSION"), env::consts::OS);
+    Finding::new(Status::Ok, "platform", msg)
+}
+
+/// Can the kstats be read at all, and which pools do they show?
+fn pools(requested: &[String]) -> Vec<Finding> {
+    let available = match DataSource::available_pools() {
+        Ok(available) => available,
+        Err(e) => {
+            return vec![Finding::new(Status::Fail, "kstats", e.to_string())]
+        }
+    };
+    if available.is_empty() {
+        let msg = "No pools found; ZFS module not loaded?";
+        return vec![Finding::new(Status::Fail, "pools", msg)];
+    }
+    let mut findings = Vec::new();
+    let mut counters = Counters::default();
+    for pool in available.iter() {
+        let mut data = DataSource::new(false, vec![pool.clone()], Vec::new());
+        let finding = match data.refresh() {
+            Ok(()) if data.failures().is_empty() => {
+                counters = counters | data.counters();
+                let msg = format!("{pool}: {} datasets", data.ndatasets());
+                Finding::new(Status::Ok, "kstats", msg)
+            }
+            Ok(()) => {
+                Finding::new(Status::Fail, "kstats", data.failures().join("\n"))
+            }
+            Err(e) => {
+                Finding::new(Status::Fail, "kstats", format!("{pool}: {e}"))
+            }
+        };
+        findings.push(finding);
+    }
+    if counters != Counters::default() {
+        findings.push(capabilities(counters));
+    }
+    if !requested.is_empty() {
+        findings.push(match DataSource::check_pools(requested) {
+            Ok(()) => Finding::new(Status::Ok, "pools", requested.join(", ")),
+            Err(e) => Finding::new(Status::Fail, "pools", e.to_string()),
+        });
+    }
+    findings
+}
+
+/// Which kstat counters does this kernel provide?
+fn capabilities(counters: Counters) -> Finding {
+    let lacking = counters.lacking(Counters::ALL);
+    if lacking.is_empty() {
+        return Finding::new(Status::Ok, "counters", "all present");
+    }
+    let columns = (0..COLUMNS.len())
+        .filter(|&i| !counters.contains(column_counters(i)))
+        .map(|i| COLUMNS[i].0)
+        .collect::<Vec<_>>();
+    let msg = format!(
+        "This kernel lacks {}\nThese columns will show -: {}",
+        lacking.join(", "),
+        columns.join(", ")
+    );
+    Finding::new(Status::Warn, "counters", msg)
+}
+
+/// The vdev, latency, queue, capacity, and dedup displays run `zpool`
+fn zpool() -> Finding {
+    let output = exec::command("zpool")
+        .and_then(|mut cmd| cmd.args(["list", "-H", "-o", "name"]).output());
+    match output {
+        Ok(output) if output.status.success() => {
+            Finding::new(Status::Ok, "zpool", "zpool list works")
+        }
+        Ok(output) => {
+            let msg = String::from_utf8_lossy(&output.stderr);
+            Finding::new(Status::Warn, "zpool", msg.trim().to_owned())
+        }
+        Err(e) => {
+            let msg = format!(
+                "Cannot run zpool: {e}\nThe vdev, latency, queue, capacity, \
+                 and dedup displays need it."
+            );
+            Finding::new(Status::Warn, "zpool", msg)
+        }
+    }
+}
+
+fn terminal() -> Finding {
+    if !io::stdout().is_terminal() {
+        let msg = "stdout is not a terminal.  The interactive display needs \
+                   one, but the bench, check, and top subcommands don't.";
+        return Finding::new(Status::Warn, "terminal", msg);
+    }
+    let term = env::var("TERM").unwrap_or_default();
+    if term.is_empty() || term == "dumb" {
+        let msg = format!(
+            "TERM is {term:?}, which may not support colors or cursor \
+             movement.\nTry --plain."
+        );
+        return Finding::new(Status::Warn, "terminal", msg);
+    }
+    match crossterm::terminal::size() {
+        Ok((width, height)) if width < COMPACT_WIDTH => {
+            let msg = format!(
+                "{width}x{height} is narrow, so each dataset will take two \
+                 lines"
+            );
+            Finding::new(Status::Warn, "terminal", msg)
+        }
+        Ok((width, height)) if width < FULL_WIDTH => {
+            let msg = format!(
+                "{width}x{height} is narrow, so some columns will be hidden"
+            );
+            Finding::new(Status::Warn, "terminal", msg)
+        }
+        Ok((width, height)) => Finding::new(
+            Status::Ok,
+            "terminal",
+            format!("{term}, {width}x{height}"),
+        ),
+        Err(e) => Finding::new(
+            Status::Warn,
+            "terminal",
+            format!("Cannot get the terminal's size: {e}"),
+        ),
+    }
+}
+
+/// Is the configuration file, and any translation it selects, valid?
+fn config(cli: &Cli) -> Vec<Finding> {
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Finding::new(Status::Fail, "config", e.to_string())]
+        }
+    };
+    let path = cli.config.clone().or_else(Config::default_path);
+    let msg = match path {
+        Some(p) if p.exists() => format!("{} is valid", p.display()),
+        _ => "No configuration file; using the defaults".to_owned(),
+    };
+    let mut findings = vec![Finding::new(Status::Ok, "config", msg)];
+    if let Some(lang) = i18n::language(config.lang.as_deref()) {
+        findings.push(match i18n::Catalog::load(&lang) {
+            Ok(_) => Finding::new(Status::Ok, "language", lang),
+            Err(e) => Finding::new(Status::Fail, "language", e.to_string()),
+        });
+    }
+    findings
+}
+
+/// Run every check and print the findings.  Return the exit status.
+pub fn run(args: &DoctorArgs, cli: &Cli) -> i32 {
+    let mut findings = vec![platform()];
+    findings.extend(pools(&args.pools));
+    findings.push(zpool());
+    findings.push(terminal());
+    findings.extend(config(cli));
+    for finding in findings.iter() {
+        println!("{finding}");
+    }
+    if findings.iter().any(|f| f.status == Status::Fail) {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn capabilities_lacking() {
+        let counters = Counters::READS
+            | Counters::NREAD
+            | Counters::WRITES
+            | Counters::NWRITTEN;
+        let finding = capabilities(counters);
+        assert_eq!(finding.status, Status::Warn);
+        assert_eq!(
+            finding.msg,
+            "This kernel lacks nunlinks, nunlinked\nThese columns will show \
+             -: d/s, kB/s d, pend d"
+        );
+        assert_eq!(capabilities(Counters::ALL).status, Status::Ok);
+    }
+
+    #[test]
+    fn display() {
+        let finding = Finding::new(Status::Fail, "kstats", "first\nsecond");
+        assert_eq!(
+            finding.to_string(),
+            "FAIL  kstats    first\n                second"
+        );
+        let finding = Finding::new(Status::Ok, "zpool", "fine");
+        assert_eq!(finding.to_string(), "ok    zpool     fine");
+    }
+}
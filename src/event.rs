@@ -10,9 +10,10 @@ pub enum Event {
     Other,
 }
 
-/// Poll stdin for events with a timeout
-pub fn poll(tick_rate: &Duration) -> Option<Event> {
-    if !event::poll(*tick_rate).unwrap() {
+/// Poll stdin for events with a timeout.  Returns `Tick` if the timeout
+/// expires first.
+pub fn poll(timeout: &Duration) -> Option<Event> {
+    if !event::poll(*timeout).unwrap() {
         Some(Event::Tick)
     } else {
         match event::read() {
@@ -0,0 +1,23 @@
+// vim: tw=80
+//! Run other programs, unless forbidden by `--no-exec` or `--sandbox`
+use std::{io, process::Command, sync::OnceLock};
+
+/// The option that forbade running other programs, if any did
+static FORBIDDEN: OnceLock<&'static str> = OnceLock::new();
+
+/// Never run any other programs from now on, because of `option`
+pub fn forbid(option: &'static str) {
+    let _ = FORBIDDEN.set(option);
+}
+
+/// Like `Command::new`, but fail if running other programs is forbidden
+pub fn command(program: &str) -> io::Result<Command> {
+    if let Some(option) = FORBIDDEN.get() {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("not running {program}, because of {option}"),
+        ))
+    } else {
+        Ok(Command::new(program))
+    }
+}
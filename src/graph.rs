@@ -0,0 +1,540 @@
+// vim: tw=80
+//! Draw the history graph as an image, on terminals that support the Kitty
+//! graphics protocol or Sixel.
+//!
+//! The display leaves the graph's cells blank, and after each redraw the
+//! image is written over them.  Kitty images are replaced by id, and erased
+//! by deleting them.  Sixel pixels are part of the cells, so erasing them
+//! takes a full redraw.
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Write},
+    os::fd::AsRawFd,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crossterm::{cursor::MoveTo, queue, terminal};
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    unistd,
+};
+use ratatui::layout::Rect;
+
+use crate::{app::History, i18n::trf, serve::base64, warning::warn};
+
+/// How long to wait for the terminal to answer the capability queries
+const DETECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The id of the Kitty image, so each new one replaces the last
+const KITTY_ID: u32 = 1;
+
+/// The most base64 that one Kitty escape sequence may carry
+const KITTY_CHUNK: usize = 4096;
+
+/// Palette indices.  Unset pixels show the terminal's background.
+const CLEAR: u8 = 0;
+const AXIS: u8 = 1;
+const READ: u8 = 2;
+const WRITE: u8 = 3;
+/// Each palette index's color, matching the braille graph's
+const PALETTE: [[u8; 3]; 4] =
+    [[0, 0, 0], [128, 128, 128], [92, 160, 255], [255, 96, 96]];
+
+/// How to draw the history graph
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Graphics {
+    /// Braille characters, which every terminal can display
+    #[default]
+    Braille,
+    /// Whichever of the others the terminal supports
+    Auto,
+    Kitty,
+    Sixel,
+}
+
+impl fmt::Display for Graphics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Graphics::Braille => "braille",
+            Graphics::Auto => "auto",
+            Graphics::Kitty => "kitty",
+            Graphics::Sixel => "sixel",
+        })
+    }
+}
+
+impl FromStr for Graphics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "braille" => Ok(Graphics::Braille),
+            "auto" => Ok(Graphics::Auto),
+            "kitty" => Ok(Graphics::Kitty),
+            "sixel" => Ok(Graphics::Sixel),
+            _ => Err(format!("unknown graphics {s:?}")),
+        }
+    }
+}
+
+/// Interpret the terminal's answers to the queries that `detect` sends.
+/// Returns `None` until the Primary Device Attributes arrive, which every
+/// terminal sends, and which come after the answer to the Kitty query, if
+/// any.
+fn parse_replies(buf: &[u8]) -> Option<Graphics> {
+    let start = buf.windows(3).position(|w| w == b"\x1b[?")?;
+    let da1 = &buf[start + 3..];
+    let end = da1.iter().position(|&b| b == b'c')?;
+    let kitty = b"\x1b_Gi=31;OK";
+    if buf[..start].windows(kitty.len()).any(|w| w == kitty) {
+        return Some(Graphics::Kitty);
+    }
+    let attrs = String::from_utf8_lossy(&da1[..end]);
+    if attrs.split(';').any(|a| a == "4") {
+        Some(Graphics::Sixel)
+    } else {
+        Some(Graphics::Braille)
+    }
+}
+
+/// Ask the terminal which image protocols it supports.  It must already be
+/// in raw mode, and nothing else may be reading its input yet.
+pub fn detect() -> io::Result<Graphics> {
+    let mut stdout = io::stdout();
+    // A one-pixel query image, then the Primary Device Attributes
+    stdout.write_all(b"\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c")?;
+    stdout.flush()?;
+    let stdin = io::stdin();
+    let deadline = Instant::now() + DETECT_TIMEOUT;
+    let mut buf = Vec::new();
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(&stdin, PollFlags::POLLIN)];
+        if poll(&mut fds, left.as_millis() as i32)? == 0 {
+            return Ok(Graphics::Braille);
+        }
+        let mut chunk = [0u8; 256];
+        let n = unistd::read(stdin.as_raw_fd(), &mut chunk)?;
+        if n == 0 {
+            return Ok(Graphics::Braille);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(graphics) = parse_replies(&buf) {
+            return Ok(graphics);
+        }
+    }
+}
+
+/// An image, as palette indices
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pixmap {
+    width:  usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Pixmap {
+    fn new(width: usize, height: usize) -> Self {
+        Pixmap {
+            width,
+            height,
+            pixels: vec![CLEAR; width * height],
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: u8) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    /// Fill one column between two rows, inclusive, and `thickness` pixels
+    /// beyond them
+    fn vline(
+        &mut self,
+        x: usize,
+        y0: usize,
+        y1: usize,
+        thickness: usize,
+        c: u8,
+    ) {
+        let lo = y0.min(y1).saturating_sub(thickness / 2);
+        let hi = y0.max(y1) + (thickness - 1) / 2;
+        for y in lo..=hi {
+            self.set(x, y, c);
+        }
+    }
+}
+
+/// Render a pixmap as plain ASCII, one character per pixel, for a display
+/// without braille or colors
+pub fn ascii(pixmap: &Pixmap) -> Vec<String> {
+    (0..pixmap.height)
+        .map(|y| {
+            (0..pixmap.width)
+                .map(|x| match pixmap.get(x, y) {
+                    CLEAR => ' ',
+                    AXIS => '.',
+                    READ => 'r',
+                    _ => 'w',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Plot the history's read and write rates, oldest on the left, against a
+/// vertical scale from zero to the highest rate.  When the history is
+/// longer than the image is wide, each column spans every rate in its
+/// stretch of time, so brief bursts still show.
+pub fn plot(
+    samples: &VecDeque<(Instant, f64, f64)>,
+    width: usize,
+    height: usize,
+) -> Pixmap {
+    let mut pixmap = Pixmap::new(width, height);
+    if width < 2 || height < 2 {
+        return pixmap;
+    }
+    let bottom = height - 1;
+    for x in 0..width {
+        pixmap.set(x, bottom, AXIS);
+    }
+    for y in 0..height {
+        pixmap.set(0, y, AXIS);
+    }
+    // A dotted line at half of the scale
+    for x in (0..width).step_by(4) {
+        pixmap.set(x, bottom / 2, AXIS);
+    }
+    let Some(&(first, ..)) = samples.front() else {
+        return pixmap;
+    };
+    let last = samples.back().unwrap().0;
+    let span = (last - first).as_secs_f64();
+    let peak = samples
+        .iter()
+        .map(|&(_, r, w)| r.max(w))
+        .fold(1.0, f64::max);
+    let x_of = |t: Instant| {
+        if span > 0.0 {
+            ((t - first).as_secs_f64() / span * (width - 1) as f64).round()
+                as usize
+        } else {
+            width - 1
+        }
+    };
+    let y_of = |v: f64| bottom - (v / peak * bottom as f64).round() as usize;
+    let thickness = 1 + height / 150;
+    for (color, rate) in [
+        (READ, (|&(_, r, _)| r) as fn(&(Instant, f64, f64)) -> f64),
+        (WRITE, |&(_, _, w)| w),
+    ] {
+        let mut prev = (x_of(first), y_of(rate(&samples[0])));
+        for sample in samples.iter().skip(1) {
+            let (x1, y1) = (x_of(sample.0), y_of(rate(sample)));
+            let (x0, y0) = prev;
+            if x1 == x0 {
+                pixmap.vline(x0, y0, y1, thickness, color);
+            }
+            for x in x0..x1 {
+                // Interpolate, and join each column to the next
+                let at = |x: usize| {
+                    let f = (x - x0) as f64 / (x1 - x0) as f64;
+                    (y0 as f64 + f * (y1 as f64 - y0 as f64)).round() as usize
+                };
+                pixmap.vline(x, at(x), at(x + 1), thickness, color);
+            }
+            prev = (x1, y1);
+        }
+        pixmap.vline(prev.0, prev.1, prev.1, thickness, color);
+    }
+    pixmap
+}
+
+/// Encode for the Kitty graphics protocol, scaled to fill `cols` by `rows`
+/// cells, without moving the cursor.  Replaces the previous image.
+pub fn kitty(pixmap: &Pixmap, cols: u16, rows: u16) -> String {
+    let mut rgba = Vec::with_capacity(pixmap.pixels.len() * 4);
+    for &p in &pixmap.pixels {
+        let alpha = if p == CLEAR { 0 } else { 255 };
+        rgba.extend(PALETTE[usize::from(p)]);
+        rgba.push(alpha);
+    }
+    let data = base64(&rgba);
+    let chunks = data.as_bytes().chunks(KITTY_CHUNK).collect::<Vec<_>>();
+    let mut out = String::with_capacity(data.len() + 32 * chunks.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            out.push_str(&format!(
+                "\x1b_Ga=T,f=32,s={},v={},c={cols},r={rows},i={KITTY_ID},q=2,\
+                 C=1,m={more};",
+                pixmap.width, pixmap.height
+            ));
+        } else {
+            out.push_str(&format!("\x1b_Gm={more};"));
+        }
+        // base64 is always ASCII
+        out.push_str(std::str::from_utf8(chunk).unwrap());
+        out.push_str("\x1b\\");
+    }
+    out
+}
+
+/// Delete the Kitty image, and free its data
+fn kitty_delete() -> String {
+    format!("\x1b_Ga=d,d=I,i={KITTY_ID},q=2\x1b\\")
+}
+
+/// Encode as Sixel, leaving unset pixels transparent.  Each band of six
+/// rows is drawn once per color that it uses, with runs of the same sixel
+/// compressed.
+pub fn sixel(pixmap: &Pixmap) -> String {
+    let mut out =
+        format!("\x1bP0;1;0q\"1;1;{};{}", pixmap.width, pixmap.height);
+    for (i, rgb) in PALETTE.iter().enumerate().skip(1) {
+        let [r, g, b] = rgb.map(|c| u32::from(c) * 100 / 255);
+        out.push_str(&format!("#{i};2;{r};{g};{b}"));
+    }
+    let mut line = Vec::with_capacity(pixmap.width);
+    for band in (0..pixmap.height).step_by(6) {
+        let rows = band..(band + 6).min(pixmap.height);
+        for color in [AXIS, READ, WRITE] {
+            line.clear();
+            for x in 0..pixmap.width {
+                let bits = rows
+                    .clone()
+                    .filter(|&y| pixmap.get(x, y) == color)
+                    .fold(0u8, |bits, y| bits | 1 << (y - band));
+                line.push(bits);
+            }
+            // Nothing needs drawing after the last set pixel
+            let Some(end) = line.iter().rposition(|&bits| bits != 0) else {
+                continue;
+            };
+            out.push_str(&format!("#{color}"));
+            let mut x = 0;
+            while x <= end {
+                let run = line[x..=end]
+                    .iter()
+                    .take_while(|&&bits| bits == line[x])
+                    .count();
+                let c = char::from(0x3f + line[x]);
+                if run > 3 {
+                    out.push_str(&format!("!{run}{c}"));
+                } else {
+                    out.extend(std::iter::repeat(c).take(run));
+                }
+                x += run;
+            }
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Writes the history graph's image over the cells that the display left
+/// blank for it
+#[derive(Debug)]
+pub struct Raster {
+    /// Kitty or Sixel
+    graphics: Graphics,
+    /// Is an image on the screen?
+    shown:    bool,
+}
+
+impl Raster {
+    /// A Raster for the detected or requested protocol, or `None` if the
+    /// graph should be drawn with braille.  An image needs the size of the
+    /// terminal's cells in pixels, which some terminals don't report.
+    pub fn new(graphics: Graphics) -> Option<Self> {
+        let graphics = match graphics {
+            Graphics::Auto => detect().unwrap_or_else(|e| {
+                warn(trf("Cannot detect terminal graphics: {}", &[&e]));
+                Graphics::Braille
+            }),
+            g => g,
+        };
+        if graphics == Graphics::Braille {
+            return None;
+        }
+        match cell_size() {
+            Ok(Some(_)) => Some(Raster {
+                graphics,
+                shown: false,
+            }),
+            Ok(None) => {
+                warn(trf(
+                    "The terminal doesn't report its size in pixels, so the \
+                     graph is drawn with {} instead of {}",
+                    &[&Graphics::Braille, &graphics],
+                ));
+                None
+            }
+            Err(e) => {
+                warn(trf("Cannot read the terminal's size: {}", &[&e]));
+                None
+            }
+        }
+    }
+
+    /// Draw the graph into `area`, or erase it if there's no longer an area
+    /// for it.  Returns `true` if the screen must be redrawn to erase it.
+    pub fn draw(
+        &mut self,
+        out: &mut impl Write,
+        area: Option<Rect>,
+        history: Option<&History>,
+    ) -> io::Result<bool> {
+        let size = cell_size()?;
+        match (area, history, size) {
+            (Some(area), Some(history), Some((cw, ch))) if !area.is_empty() => {
+                let width = usize::from(area.width) * cw;
+                let mut height = usize::from(area.height) * ch;
+                let image = if self.graphics == Graphics::Kitty {
+                    kitty(
+                        &plot(history.samples(), width, height),
+                        area.width,
+                        area.height,
+                    )
+                } else {
+                    // A partial band would spill into the next row of cells
+                    height -= height % 6;
+                    sixel(&plot(history.samples(), width, height))
+                };
+                queue!(out, MoveTo(area.x, area.y))?;
+                out.write_all(image.as_bytes())?;
+                out.flush()?;
+                self.shown = true;
+                Ok(false)
+            }
+            _ if self.shown => {
+                self.shown = false;
+                if self.graphics == Graphics::Kitty {
+                    out.write_all(kitty_delete().as_bytes())?;
+                    out.flush()?;
+                    Ok(false)
+                } else {
+                    Ok(true)
+                }
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// The size of the terminal's cells in pixels, if it says
+fn cell_size() -> io::Result<Option<(usize, usize)>> {
+    let size = terminal::window_size()?;
+    if size.width == 0
+        || size.height == 0
+        || size.columns == 0
+        || size.rows == 0
+    {
+        return Ok(None);
+    }
+    Ok(Some((
+        usize::from(size.width / size.columns),
+        usize::from(size.height / size.rows),
+    )))
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn samples(rates: &[(f64, f64)]) -> VecDeque<(Instant, f64, f64)> {
+        let start = Instant::now();
+        rates
+            .iter()
+            .enumerate()
+            .map(|(i, &(r, w))| (start + Duration::from_secs(i as u64), r, w))
+            .collect()
+    }
+
+    #[test]
+    fn detect_kitty() {
+        let replies = b"\x1b_Gi=31;OK\x1b\\\x1b[?62;c";
+        assert_eq!(parse_replies(replies), Some(Graphics::Kitty));
+    }
+
+    #[test]
+    fn detect_sixel() {
+        assert_eq!(parse_replies(b"\x1b[?62;4;22c"), Some(Graphics::Sixel));
+        assert_eq!(parse_replies(b"\x1b[?4c"), Some(Graphics::Sixel));
+    }
+
+    #[test]
+    fn detect_neither() {
+        assert_eq!(parse_replies(b"\x1b[?62;22;44c"), Some(Graphics::Braille));
+        let replies = b"\x1b_Gi=31;EINVAL\x1b\\\x1b[?1;2c";
+        assert_eq!(parse_replies(replies), Some(Graphics::Braille));
+    }
+
+    /// Keep reading until the device attributes are complete
+    #[test]
+    fn detect_partial() {
+        assert_eq!(parse_replies(b""), None);
+        assert_eq!(parse_replies(b"\x1b_Gi=31;OK\x1b\\"), None);
+        assert_eq!(parse_replies(b"\x1b[?62;4"), None);
+    }
+
+    #[test]
+    fn plot_lines() {
+        let samples = samples(&[(0.0, 4.0), (4.0, 2.0), (2.0, 0.0)]);
+        let pixmap = plot(&samples, 8, 5);
+        let expected =
+            ["w  rr   ", "wwwrrrr ", ".rwww rr", "rr  www ", "r.....ww"];
+        assert_eq!(ascii(&pixmap), expected);
+    }
+
+    /// A burst shorter than a column still shows
+    #[test]
+    fn plot_dense() {
+        let mut rates = vec![(1.0, 0.0); 100];
+        rates[50] = (8.0, 0.0);
+        let pixmap = plot(&samples(&rates), 10, 9);
+        assert!((0..10).any(|x| pixmap.get(x, 0) == READ));
+    }
+
+    #[test]
+    fn plot_empty() {
+        let pixmap = plot(&VecDeque::new(), 6, 5);
+        let expected = [".     ", ".     ", ".   . ", ".     ", "......"];
+        assert_eq!(ascii(&pixmap), expected);
+    }
+
+    #[test]
+    fn kitty_chunks() {
+        let pixmap = Pixmap::new(40, 40);
+        let out = kitty(&pixmap, 4, 2);
+        assert!(out.starts_with(
+            "\x1b_Ga=T,f=32,s=40,v=40,c=4,r=2,i=1,q=2,C=1,m=1;AAAA"
+        ));
+        // 6400 bytes of RGBA take 8536 of base64, in three chunks
+        assert_eq!(out.matches("\x1b\\").count(), 3);
+        assert!(out.contains("\x1b_Gm=1;"));
+        assert!(out.contains("\x1b_Gm=0;"));
+    }
+
+    #[test]
+    fn sixel_bands() {
+        let mut pixmap = Pixmap::new(6, 7);
+        for x in 0..6 {
+            pixmap.set(x, 0, AXIS);
+        }
+        pixmap.set(1, 6, READ);
+        let out = sixel(&pixmap);
+        let body = out.split_once("#3;2;100;37;37").unwrap().1;
+        assert_eq!(body, "#1!6@$-#2?@$-\x1b\\");
+    }
+}
@@ -0,0 +1,211 @@
+// vim: tw=80
+//! Translations of column headers and interactive messages.
+//!
+//! A translation is a TOML file named after its language, like `de.toml` or
+//! `pt_BR.toml`, with a `[headers]` table keyed by the canonical column
+//! headers and a `[messages]` table keyed by the English messages.  In a
+//! message, each `{}` stands for a value that ztop fills in, in order.
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use serde::Deserialize;
+
+use crate::app::COLUMNS;
+
+/// Where packagers should install translations
+#[cfg(target_os = "freebsd")]
+const SYSTEM_DIR: &str = "/usr/local/share/ztop/lang";
+#[cfg(not(target_os = "freebsd"))]
+const SYSTEM_DIR: &str = "/usr/share/ztop/lang";
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+/// One language's translations
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Catalog {
+    /// Column headers, keyed by the canonical header
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Messages, keyed by the English message
+    #[serde(default)]
+    messages:    HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Load the translation for `lang`, preferring the user's own over the
+    /// system's, and the exact territory over the bare language.  If there
+    /// is none, use English.
+    pub fn load(lang: &str) -> Result<Self, Box<dyn Error>> {
+        let user_dir = crate::config::Config::dir().map(|d| d.join("lang"));
+        let dirs = user_dir.into_iter().chain([PathBuf::from(SYSTEM_DIR)]);
+        for dir in dirs {
+            if let Some(catalog) = Self::load_from(&dir, lang)? {
+                return Ok(catalog);
+            }
+        }
+        Ok(Catalog::default())
+    }
+
+    fn load_from(
+        dir: &Path,
+        lang: &str,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        for name in candidates(lang) {
+            let path = dir.join(format!("{name}.toml"));
+            let s = match fs::read_to_string(&path) {
+                Ok(s) => s,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(
+                        format!("Cannot read {}: {e}", path.display()).into()
+                    )
+                }
+            };
+            let catalog: Catalog = toml::from_str(&s).map_err(|e| {
+                format!("Invalid translation {}: {e}", path.display())
+            })?;
+            for col in catalog.headers.keys() {
+                if !COLUMNS.iter().any(|(name, _)| name == col) {
+                    return Err(format!(
+                        "Unknown column {col:?} in {}",
+                        path.display()
+                    )
+                    .into());
+                }
+            }
+            return Ok(Some(catalog));
+        }
+        Ok(None)
+    }
+}
+
+/// The user's language, from the configuration file or the environment, or
+/// `None` for English
+pub fn language(configured: Option<&str>) -> Option<String> {
+    let lang = match configured {
+        Some(lang) => lang.to_owned(),
+        None => ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|v| !v.is_empty())?,
+    };
+    // Drop the encoding and modifier, like in "de_DE.UTF-8@euro"
+    let lang = lang.split(['.', '@']).next().unwrap_or_default();
+    match lang {
+        "" | "C" | "POSIX" | "en" => None,
+        _ if lang.starts_with("en_") => None,
+        _ => Some(lang.to_owned()),
+    }
+}
+
+/// The file names to try for a language, most specific first
+fn candidates(lang: &str) -> Vec<&str> {
+    match lang.split_once('_') {
+        Some((bare, _)) => vec![lang, bare],
+        None => vec![lang],
+    }
+}
+
+/// Use these translations for the rest of the process
+pub fn set_catalog(catalog: Catalog) {
+    CATALOG
+        .set(catalog)
+        .expect("set_catalog may only be called once");
+}
+
+/// Translate a message that has no values to fill in
+pub fn tr(msg: &str) -> String {
+    match CATALOG.get().and_then(|c| c.messages.get(msg)) {
+        Some(t) => t.clone(),
+        None => msg.to_owned(),
+    }
+}
+
+/// Translate a message, then fill in its values
+pub fn trf(msg: &str, args: &[&dyn fmt::Display]) -> String {
+    fill(&tr(msg), args)
+}
+
+/// Replace each `{}` in `msg` with the next of `args`.  Extra placeholders
+/// are left as-is, so a bad translation is visible but harmless.
+fn fill(msg: &str, args: &[&dyn fmt::Display]) -> String {
+    let mut out = String::with_capacity(msg.len());
+    let mut args = args.iter();
+    let mut pieces = msg.split("{}");
+    out.push_str(pieces.next().unwrap_or_default());
+    for piece in pieces {
+        match args.next() {
+            Some(arg) => out.push_str(&arg.to_string()),
+            None => out.push_str("{}"),
+        }
+        out.push_str(piece);
+    }
+    out
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn candidate_files() {
+        assert_eq!(candidates("pt_BR"), ["pt_BR", "pt"]);
+        assert_eq!(candidates("de"), ["de"]);
+    }
+
+    #[test]
+    fn configured_language() {
+        assert_eq!(language(Some("de_DE.UTF-8")).as_deref(), Some("de_DE"));
+        assert_eq!(language(Some("sr_RS@latin")).as_deref(), Some("sr_RS"));
+        assert_eq!(language(Some("C.UTF-8")), None);
+        assert_eq!(language(Some("en_US")), None);
+    }
+
+    #[test]
+    fn fill_values() {
+        assert_eq!(fill("Sortiert nach {}", &[&"w/s"]), "Sortiert nach w/s");
+        assert_eq!(fill("{} von {}", &[&1, &2]), "1 von 2");
+        assert_eq!(fill("{} {}", &[&1]), "1 {}");
+    }
+
+    /// Load `contents` as the German translation from a scratch directory
+    fn load_german(
+        test: &str,
+        lang: &str,
+        contents: &str,
+    ) -> Result<Option<Catalog>, Box<dyn Error>> {
+        let dir =
+            env::temp_dir().join(format!("ztop-{}-{test}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("de.toml"), contents).unwrap();
+        let r = Catalog::load_from(&dir, lang);
+        fs::remove_dir_all(&dir).unwrap();
+        r
+    }
+
+    #[test]
+    fn load_territory_then_language() {
+        let s = "[headers]\n\"w/s\" = \"S/s\"\n[messages]\n\"Not sorted\" = \
+                 \"Unsortiert\"\n";
+        let catalog = load_german("territory", "de_AT", s).unwrap().unwrap();
+        assert_eq!(catalog.headers["w/s"], "S/s");
+        assert_eq!(catalog.messages["Not sorted"], "Unsortiert");
+        assert!(load_german("other", "fr", s).unwrap().is_none());
+    }
+
+    #[test]
+    fn unknown_column() {
+        assert!(
+            load_german("unknown", "de", "[headers]\nfoo = \"x\"\n").is_err()
+        );
+    }
+}
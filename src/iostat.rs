@@ -0,0 +1,825 @@
+// vim: tw=80
+//! Per-vdev statistics, latency histograms, and queue depths, for the views
+//! that show them.
+//!
+//! ZFS doesn't publish these as kstats, so they come from a `zpool iostat`
+//! process that runs for as long as the view is shown.  With `--source exec`,
+//! so do each pool's I/O counters.
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    io::{BufRead, BufReader, Read},
+    process::{Child, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::exec;
+
+/// Sections of `zpool iostat -v` for allocation classes, whose totals are
+/// worth showing
+const CLASSES: [&str; 2] = ["special", "dedup"];
+
+/// The headings of the vdev view's sortable columns, in order
+pub const VDEV_COLUMNS: [&str; 6] =
+    ["alloc", "free", "r/s", "kB/s r", "w/s", "kB/s w"];
+
+/// One line of `zpool iostat -v`: a pool, a vdev, or a section like `logs`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VdevRow {
+    /// How deeply the row is nested below its pool
+    pub depth: usize,
+    pub name:  String,
+    /// This row is the special or dedup allocation class, totalled from its
+    /// vdevs
+    pub class: bool,
+    /// Bytes allocated and free.  Like the rates, they're `None` for rows
+    /// that have no statistics of their own, like leaf vdevs.
+    pub alloc: Option<u64>,
+    pub free:  Option<u64>,
+    pub ops_r: Option<f64>,
+    pub ops_w: Option<f64>,
+    pub r_s:   Option<f64>,
+    pub w_s:   Option<f64>,
+}
+
+impl VdevRow {
+    fn parse(line: &str) -> Option<Self> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        // The name, then alloc, free, and the four rates
+        let name_fields = fields.len().checked_sub(6).filter(|&n| n > 0)?;
+        let bytes = |i: usize| fields[name_fields + i].parse::<u64>().ok();
+        let rate = |i: usize| fields[name_fields + i].parse::<f64>().ok();
+        let indent = line.len() - line.trim_start().len();
+        Some(VdevRow {
+            depth: indent / 2,
+            name:  fields[..name_fields].join(" "),
+            class: false,
+            alloc: bytes(0),
+            free:  bytes(1),
+            ops_r: rate(2),
+            ops_w: rate(3),
+            r_s:   rate(4),
+            w_s:   rate(5),
+        })
+    }
+
+    /// The statistic in column `i` of [`VDEV_COLUMNS`]
+    fn get(&self, i: usize) -> Option<f64> {
+        match i {
+            0 => self.alloc.map(|x| x as f64),
+            1 => self.free.map(|x| x as f64),
+            2 => self.ops_r,
+            3 => self.r_s,
+            4 => self.ops_w,
+            5 => self.w_s,
+            _ => None,
+        }
+    }
+
+    /// Add another row's statistics to this one's
+    fn add(&mut self, other: &Self) {
+        fn sum<T: std::ops::Add<Output = T>>(
+            x: Option<T>,
+            y: Option<T>,
+        ) -> Option<T> {
+            match (x, y) {
+                (Some(x), Some(y)) => Some(x + y),
+                (x, y) => x.or(y),
+            }
+        }
+        self.alloc = sum(self.alloc, other.alloc);
+        self.free = sum(self.free, other.free);
+        self.ops_r = sum(self.ops_r, other.ops_r);
+        self.ops_w = sum(self.ops_w, other.ops_w);
+        self.r_s = sum(self.r_s, other.r_s);
+        self.w_s = sum(self.w_s, other.w_s);
+    }
+}
+
+/// Fill in each allocation class's section with the totals of its top-level
+/// vdevs.  `zpool iostat` leaves them blank.
+fn total_classes(rows: &mut [VdevRow]) {
+    let mut section = None;
+    for i in 0..rows.len() {
+        if rows[i].depth == 0 {
+            // A pool has statistics of its own, even if it's named "special"
+            let is_class = CLASSES.contains(&rows[i].name.as_str())
+                && rows[i].ops_r.is_none();
+            rows[i].class = is_class;
+            section = is_class.then_some(i);
+        } else if let Some(s) = section.filter(|_| rows[i].depth == 1) {
+            let vdev = rows[i].clone();
+            rows[s].add(&vdev);
+        }
+    }
+}
+
+/// Sort each vdev's children by column `col` of [`VDEV_COLUMNS`], keeping
+/// them below their parents.  Pools and sections like `logs` stay in
+/// `zpool iostat`'s order.  Rows without the statistic sort lowest.
+pub fn sort_vdevs(rows: &[VdevRow], col: usize, reverse: bool) -> Vec<VdevRow> {
+    let mut out = Vec::with_capacity(rows.len());
+    let mut i = 0;
+    while i < rows.len() {
+        let end = rows[i + 1..]
+            .iter()
+            .position(|r| r.depth == 0)
+            .map_or(rows.len(), |n| i + 1 + n);
+        out.push(rows[i].clone());
+        sort_siblings(&rows[i + 1..end], col, reverse, &mut out);
+        i = end;
+    }
+    out
+}
+
+/// Sort the first row's siblings, each followed by its own sorted children
+fn sort_siblings(
+    rows: &[VdevRow],
+    col: usize,
+    reverse: bool,
+    out: &mut Vec<VdevRow>,
+) {
+    let Some(first) = rows.first() else {
+        return;
+    };
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for (i, row) in rows.iter().enumerate().skip(1) {
+        if row.depth <= first.depth {
+            groups.push(&rows[start..i]);
+            start = i;
+        }
+    }
+    groups.push(&rows[start..]);
+    let key = |g: &[VdevRow]| g[0].get(col).unwrap_or(f64::NEG_INFINITY);
+    if reverse {
+        groups.sort_by(|x, y| key(y).total_cmp(&key(x)));
+    } else {
+        groups.sort_by(|x, y| key(x).total_cmp(&key(y)));
+    }
+    for group in groups {
+        out.push(group[0].clone());
+        sort_siblings(&group[1..], col, reverse, out);
+    }
+}
+
+/// Splits the output of `zpool iostat` into updates
+pub trait Parser: Default + Send + 'static {
+    type Update: Send + 'static;
+    /// Everything collected so far
+    type State: Default;
+
+    /// The options that select this kind of output
+    const FLAGS: &'static str;
+
+    /// Consume one line of output.  Return an update, if this line finished
+    /// one.
+    fn push(&mut self, line: &str) -> Option<Self::Update>;
+
+    fn apply(state: &mut Self::State, update: Self::Update);
+}
+
+/// Splits the output of `zpool iostat -v` into reports
+#[derive(Debug, Default)]
+pub struct VdevParser {
+    rows: Vec<VdevRow>,
+}
+
+impl Parser for VdevParser {
+    type State = Vec<VdevRow>;
+    type Update = Vec<VdevRow>;
+
+    // -p for exact numbers, -y to skip the report of averages since boot
+    const FLAGS: &'static str = "-pvy";
+
+    fn push(&mut self, line: &str) -> Option<Vec<VdevRow>> {
+        let mut words = line.split_whitespace();
+        let first = words.next();
+        let is_header = first == Some("capacity")
+            || (first == Some("pool") && words.next() == Some("alloc"));
+        if first.is_none() || is_header {
+            // Each report ends with a blank line, and begins with a header
+            if self.rows.is_empty() {
+                None
+            } else {
+                total_classes(&mut self.rows);
+                Some(std::mem::take(&mut self.rows))
+            }
+        } else {
+            if !line.trim().chars().all(|c| c == '-' || c == ' ') {
+                if let Some(row) = VdevRow::parse(line) {
+                    self.rows.push(row);
+                }
+            }
+            None
+        }
+    }
+
+    fn apply(state: &mut Vec<VdevRow>, update: Vec<VdevRow>) {
+        *state = update;
+    }
+}
+
+/// One line of `zpool iostat -qv`, with the queue depths of each I/O class
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QueueRow {
+    /// How deeply the row is nested below its pool
+    pub depth:  usize,
+    pub name:   String,
+    /// Pending and active operations of each class, in the order of
+    /// [`QueueReport::classes`].  `None` for rows with no queues of their own.
+    pub queues: Vec<Option<(u64, u64)>>,
+}
+
+/// One interval's queue depths
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QueueReport {
+    /// The I/O classes, like `syncq_read`.  They vary between ZFS versions.
+    pub classes: Vec<String>,
+    pub rows:    Vec<QueueRow>,
+}
+
+/// Splits the output of `zpool iostat -qv` into reports
+#[derive(Debug, Default)]
+pub struct QueueParser {
+    report: QueueReport,
+}
+
+impl Parser for QueueParser {
+    type State = QueueReport;
+    type Update = QueueReport;
+
+    const FLAGS: &'static str = "-pqvy";
+
+    fn push(&mut self, line: &str) -> Option<QueueReport> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let finished = |report: &mut QueueReport| {
+            if report.rows.is_empty() {
+                None
+            } else {
+                let classes = report.classes.clone();
+                Some(std::mem::replace(
+                    report,
+                    QueueReport {
+                        classes,
+                        rows: Vec::new(),
+                    },
+                ))
+            }
+        };
+        match fields.as_slice() {
+            [] | ["pool", "alloc", ..] => finished(&mut self.report),
+            // Each class's name heads its pair of columns
+            ["capacity", "operations", "bandwidth", classes @ ..] => {
+                let r = finished(&mut self.report);
+                self.report.classes =
+                    classes.iter().map(|c| (*c).to_owned()).collect();
+                r
+            }
+            _ if line.trim().chars().all(|c| c == '-' || c == ' ') => None,
+            _ => {
+                // The name, then alloc, free, the four rates, and the queues
+                let nqueues = self.report.classes.len();
+                let name_fields = fields.len().checked_sub(6 + 2 * nqueues)?;
+                if name_fields == 0 {
+                    return None;
+                }
+                let num = |i: usize| fields[i].parse::<u64>().ok();
+                let queues = (0..nqueues)
+                    .map(|i| {
+                        let pend = name_fields + 6 + 2 * i;
+                        Some((num(pend)?, num(pend + 1)?))
+                    })
+                    .collect();
+                let indent = line.len() - line.trim_start().len();
+                self.report.rows.push(QueueRow {
+                    depth: indent / 2,
+                    name: fields[..name_fields].join(" "),
+                    queues,
+                });
+                None
+            }
+        }
+    }
+
+    fn apply(state: &mut QueueReport, update: QueueReport) {
+        *state = update;
+    }
+}
+
+/// One bucket of a pool's latency histogram, like `zpool iostat -w`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LatencyBucket {
+    /// The bucket's upper bound, like `1ms`
+    pub label: String,
+    /// Operations whose total latency fell in this bucket
+    pub read:  u64,
+    pub write: u64,
+}
+
+/// Splits the output of `zpool iostat -w` into each pool's histogram
+#[derive(Debug, Default)]
+pub struct LatencyParser {
+    pool:    Option<String>,
+    buckets: Vec<LatencyBucket>,
+}
+
+impl Parser for LatencyParser {
+    type State = BTreeMap<String, Vec<LatencyBucket>>;
+    type Update = (String, Vec<LatencyBucket>);
+
+    const FLAGS: &'static str = "-pwy";
+
+    fn push(&mut self, line: &str) -> Option<Self::Update> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        match fields.as_slice() {
+            // Each pool's histogram begins with a header naming the pool
+            [pool, "total_wait", ..] => {
+                self.pool = Some((*pool).to_owned());
+                self.buckets.clear();
+                None
+            }
+            // And ends with a line of dashes
+            [dashes] if dashes.chars().all(|c| c == '-') => {
+                if self.buckets.is_empty() {
+                    None
+                } else {
+                    let pool = self.pool.take()?;
+                    Some((pool, std::mem::take(&mut self.buckets)))
+                }
+            }
+            // Total wait read and write are the first two columns
+            [label, read, write, ..] if self.pool.is_some() => {
+                if let (Ok(read), Ok(write)) = (read.parse(), write.parse()) {
+                    self.buckets.push(LatencyBucket {
+                        label: (*label).to_owned(),
+                        read,
+                        write,
+                    });
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn apply(state: &mut Self::State, (pool, buckets): Self::Update) {
+        state.insert(pool, buckets);
+    }
+}
+
+/// How often `zpool iostat` reports each pool's I/O for `--source exec`.
+/// Much shorter than any update interval, so that each update's rates come
+/// out close to right no matter when it falls between reports.
+pub const POOL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A pool's operations and bytes since `zpool iostat` started reporting on
+/// it
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PoolCounters {
+    pub reads:    u64,
+    pub nread:    u64,
+    pub writes:   u64,
+    pub nwritten: u64,
+}
+
+/// Turns the rates of `zpool iostat -H`, reported every [`POOL_INTERVAL`],
+/// back into counters
+#[derive(Debug, Default)]
+pub struct PoolParser {
+    /// When each pool's previous report arrived.  zpool iostat computes
+    /// rates over the time that actually passed, which can be a little
+    /// longer than the interval.
+    last: HashMap<String, Instant>,
+}
+
+impl PoolParser {
+    /// Like [`Parser::push`], for a line that arrived at `now`
+    fn push_at(
+        &mut self,
+        line: &str,
+        now: Instant,
+    ) -> Option<(String, PoolCounters)> {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let [pool, _alloc, _free, ops_r, ops_w, r_s, w_s] = fields[..] else {
+            return None;
+        };
+        let rates = [ops_r, r_s, ops_w, w_s]
+            .map(|rate| rate.parse::<f64>().ok().filter(|r| *r >= 0.0));
+        let [Some(ops_r), Some(r_s), Some(ops_w), Some(w_s)] = rates else {
+            return None;
+        };
+        let elapsed = self
+            .last
+            .insert(pool.to_owned(), now)
+            .map_or(POOL_INTERVAL, |last| now - last)
+            .as_secs_f64();
+        let count = |rate: f64| (rate * elapsed).round() as u64;
+        let counts = PoolCounters {
+            reads:    count(ops_r),
+            nread:    count(r_s),
+            writes:   count(ops_w),
+            nwritten: count(w_s),
+        };
+        Some((pool.to_owned(), counts))
+    }
+}
+
+impl Parser for PoolParser {
+    type State = BTreeMap<String, PoolCounters>;
+    /// A pool's operations and bytes during one report's interval
+    type Update = (String, PoolCounters);
+
+    // -H for one tab-separated line per pool
+    const FLAGS: &'static str = "-Hpy";
+
+    fn push(&mut self, line: &str) -> Option<Self::Update> {
+        self.push_at(line, Instant::now())
+    }
+
+    fn apply(state: &mut Self::State, (pool, counts): Self::Update) {
+        let total = state.entry(pool).or_default();
+        total.reads += counts.reads;
+        total.nread += counts.nread;
+        total.writes += counts.writes;
+        total.nwritten += counts.nwritten;
+    }
+}
+
+/// A running `zpool iostat`, and everything it has reported
+#[derive(Debug)]
+pub struct Iostat<P: Parser> {
+    interval: Duration,
+    pools:    Vec<String>,
+    child:    Child,
+    updates:  Receiver<Result<P::Update, String>>,
+    latest:   P::State,
+}
+
+/// Each vdev's statistics, for the vdev view
+pub type Vdevs = Iostat<VdevParser>;
+
+/// Each pool's latency histogram, for the latency view
+pub type Latency = Iostat<LatencyParser>;
+
+/// Each vdev's queue depths, for the queue view
+pub type Queues = Iostat<QueueParser>;
+
+/// Each pool's I/O counters, for `--source exec`
+pub type Pools = Iostat<PoolParser>;
+
+/// Collect a view's latest updates.  If the interval or the pools have
+/// changed, start reporting anew.  If that fails, close the view.
+pub fn refresh_view<P: Parser>(
+    view: &mut Option<Iostat<P>>,
+    interval: Duration,
+    pools: &[String],
+) -> Result<(), String> {
+    let r = match view.as_mut() {
+        None => return Ok(()),
+        Some(iostat) if iostat.is_for(interval, pools) => iostat.refresh(),
+        Some(_) => Iostat::start(interval, pools.to_vec())
+            .map(|iostat| *view = Some(iostat))
+            .map_err(|e| e.to_string()),
+    };
+    if r.is_err() {
+        *view = None;
+    }
+    r
+}
+
+impl<P: Parser> Iostat<P> {
+    /// Start reporting on `pools`, or all pools if empty, every `interval`
+    pub fn start(
+        interval: Duration,
+        pools: Vec<String>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut child = exec::command("zpool")?
+            .args(["iostat", P::FLAGS])
+            .args(&pools)
+            .arg(interval.as_secs_f64().to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Cannot run zpool iostat: {e}"))?;
+        let stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        let (tx, updates) = mpsc::channel();
+        thread::spawn(move || {
+            let mut parser = P::default();
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if let Some(update) = parser.push(&line) {
+                    if tx.send(Ok(update)).is_err() {
+                        return;
+                    }
+                }
+            }
+            let mut msg = String::new();
+            let _ = stderr.read_to_string(&mut msg);
+            let msg = match msg.trim() {
+                "" => "zpool iostat exited".to_owned(),
+                m => format!("zpool iostat: {m}"),
+            };
+            let _ = tx.send(Err(msg));
+        });
+        Ok(Iostat {
+            interval,
+            pools,
+            child,
+            updates,
+            latest: P::State::default(),
+        })
+    }
+
+    /// Is this reporting on these pools at this interval?
+    fn is_for(&self, interval: Duration, pools: &[String]) -> bool {
+        self.interval == interval && self.pools == pools
+    }
+
+    /// Collect any updates that have arrived since the last refresh
+    pub fn refresh(&mut self) -> Result<(), String> {
+        while let Ok(update) = self.updates.try_recv() {
+            P::apply(&mut self.latest, update?);
+        }
+        Ok(())
+    }
+
+    /// Collect updates until `done` is satisfied or `timeout` passes, for
+    /// when there's nothing to show without the first report
+    pub fn wait<F>(&mut self, timeout: Duration, done: F) -> Result<(), String>
+    where
+        F: Fn(&P::State) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        while !done(&self.latest) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.updates.recv_timeout(left) {
+                Ok(update) => P::apply(&mut self.latest, update?),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("zpool iostat exited".to_owned())
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Everything reported so far
+    pub fn latest(&self) -> &P::State {
+        &self.latest
+    }
+}
+
+impl<P: Parser> Drop for Iostat<P> {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    const REPORT: &str = "              capacity     operations     bandwidth
+pool        alloc   free   read  write   read  write
+----------  -----  -----  -----  -----  -----  -----
+tank         1024   4096     10     20   4096   8192
+  mirror-0   1024   4096     10     20   4096   8192
+    ada0p3      -      -      5     10   2048   4096
+    ada1p3      -      -      5     10   2048   4096
+logs            -      -      -      -      -      -
+  ada2p1        0   1024      0      3      0    512
+----------  -----  -----  -----  -----  -----  -----
+
+";
+
+    fn parse_all<P: Parser>(text: &str) -> Vec<P::Update> {
+        let mut parser = P::default();
+        text.lines().filter_map(|l| parser.push(l)).collect()
+    }
+
+    #[test]
+    fn report() {
+        let reports = parse_all::<VdevParser>(REPORT);
+        assert_eq!(reports.len(), 1);
+        let rows = &reports[0];
+        let names = rows.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["tank", "mirror-0", "ada0p3", "ada1p3", "logs", "ada2p1"]
+        );
+        let depths = rows.iter().map(|r| r.depth).collect::<Vec<_>>();
+        assert_eq!(depths, [0, 1, 2, 2, 0, 1]);
+        assert_eq!(rows[2].ops_r, Some(5.0));
+        assert_eq!(rows[2].w_s, Some(4096.0));
+        assert_eq!(rows[4].ops_w, None);
+        assert!(!rows[4].class);
+        assert_eq!(rows[0].alloc, Some(1024));
+        assert_eq!(rows[2].free, None);
+    }
+
+    const CLASS_REPORT: &str = "              capacity     operations     \
+                                bandwidth
+pool        alloc   free   read  write   read  write
+----------  -----  -----  -----  -----  -----  -----
+special      3072  12288     35     45  14336  18432
+  mirror-0   1024   4096     10     20   4096   8192
+    ada0p3      -      -      5     10   2048   4096
+    ada1p3      -      -      5     10   2048   4096
+special         -      -      -      -      -      -
+  mirror-1    512   2048     20     15   8192   6144
+    ada2p1      -      -     10      8   4096   3072
+    ada3p1      -      -     10      7   4096   3072
+  ada4p1      512   2048      5     10   2048   4096
+dedup           -      -      -      -      -      -
+  ada5p1     1024   4096      0      0      0      0
+----------  -----  -----  -----  -----  -----  -----
+
+";
+
+    /// Allocation class sections are totalled from their top-level vdevs
+    #[test]
+    fn classes() {
+        let rows = parse_all::<VdevParser>(CLASS_REPORT).remove(0);
+        // Just a pool with an unfortunate name
+        assert!(!rows[0].class);
+        assert_eq!(rows[0].ops_r, Some(35.0));
+
+        let special = &rows[4];
+        assert!(special.class);
+        assert_eq!(special.alloc, Some(1024));
+        assert_eq!(special.free, Some(4096));
+        assert_eq!(special.ops_r, Some(25.0));
+        assert_eq!(special.ops_w, Some(25.0));
+        assert_eq!(special.r_s, Some(10240.0));
+        assert_eq!(special.w_s, Some(10240.0));
+
+        let dedup = &rows[9];
+        assert!(dedup.class);
+        assert_eq!(dedup.alloc, Some(1024));
+        assert_eq!(dedup.ops_r, Some(0.0));
+    }
+
+    /// Siblings are sorted, but stay below their parents and within their
+    /// pools and sections
+    #[test]
+    fn sort() {
+        let rows = parse_all::<VdevParser>(CLASS_REPORT).remove(0);
+        let names = |rows: &[VdevRow]| {
+            rows.iter().map(|r| r.name.clone()).collect::<Vec<_>>()
+        };
+        // By w/s, busiest first
+        let sorted = sort_vdevs(&rows, 4, true);
+        assert_eq!(
+            names(&sorted),
+            [
+                "special", "mirror-0", "ada0p3", "ada1p3", "special",
+                "mirror-1", "ada2p1", "ada3p1", "ada4p1", "dedup", "ada5p1"
+            ]
+        );
+        // By r/s, least busy first
+        let sorted = sort_vdevs(&rows, 2, false);
+        assert_eq!(
+            names(&sorted),
+            [
+                "special", "mirror-0", "ada0p3", "ada1p3", "special", "ada4p1",
+                "mirror-1", "ada2p1", "ada3p1", "dedup", "ada5p1"
+            ]
+        );
+        // By w/s, least busy first, which reorders the leaves
+        let sorted = sort_vdevs(&rows, 4, false);
+        assert_eq!(names(&sorted)[5..8], ["ada4p1", "mirror-1", "ada3p1"]);
+    }
+
+    /// Each report is finished by its blank line, or else by the next header
+    #[test]
+    fn successive_reports() {
+        let stream = format!("{REPORT}{REPORT}");
+        assert_eq!(parse_all::<VdevParser>(&stream).len(), 2);
+        let stream = format!("{}\n{REPORT}", REPORT.trim_end());
+        assert_eq!(parse_all::<VdevParser>(&stream).len(), 2);
+    }
+
+    const HISTOGRAMS: &str = "
+tank         total_wait     disk_wait    syncq_wait    asyncq_wait
+latency      read  write   read  write   read  write   read  write  scrub   trim
+----------  -----  -----  -----  -----  -----  -----  -----  -----  -----  -----
+1ns             0      0      0      0      0      0      0      0      0      0
+127us          12     40     12     40      0      0      0      3      0      0
+1ms             3      7      3      5      0      0      0      2      0      0
+----------------------------------------------------------------------------------
+
+zroot        total_wait     disk_wait    syncq_wait    asyncq_wait
+latency      read  write   read  write   read  write   read  write  scrub   trim
+----------  -----  -----  -----  -----  -----  -----  -----  -----  -----  -----
+1ns             0      0      0      0      0      0      0      0      0      0
+----------------------------------------------------------------------------------
+";
+
+    #[test]
+    fn histograms() {
+        let updates = parse_all::<LatencyParser>(HISTOGRAMS);
+        assert_eq!(updates.len(), 2);
+        let (pool, buckets) = &updates[0];
+        assert_eq!(pool, "tank");
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(
+            buckets[1],
+            LatencyBucket {
+                label: "127us".to_owned(),
+                read:  12,
+                write: 40,
+            }
+        );
+        assert_eq!(updates[1].0, "zroot");
+    }
+
+    const QUEUES: &str =
+        "              capacity     operations     bandwidth    syncq_read    \
+         syncq_write
+pool        alloc   free   read  write   read  write   pend  activ   pend  \
+         activ
+----------  -----  -----  -----  -----  -----  -----  -----  -----  -----  \
+         -----
+tank         1024   4096     10     20   4096   8192      0      0      0      \
+         0
+  mirror-0   1024   4096     10     20   4096   8192      -      -      -      \
+         -
+    ada0p3      -      -      5     10   2048   4096      3      1      0      \
+         2
+----------  -----  -----  -----  -----  -----  -----  -----  -----  -----  \
+         -----
+
+";
+
+    #[test]
+    fn queues() {
+        let reports = parse_all::<QueueParser>(QUEUES);
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.classes, ["syncq_read", "syncq_write"]);
+        assert_eq!(report.rows.len(), 3);
+        assert_eq!(report.rows[1].queues, [None, None]);
+        assert_eq!(
+            report.rows[2],
+            QueueRow {
+                depth:  2,
+                name:   "ada0p3".to_owned(),
+                queues: vec![Some((3, 1)), Some((0, 2))],
+            }
+        );
+    }
+
+    /// Each report's rates are turned into counts over the time since the
+    /// pool's previous report, and added up
+    #[test]
+    fn pool_counters() {
+        let start = Instant::now();
+        let lines = [
+            (0, "tank\t1024\t4096\t10\t20\t40960\t81920"),
+            (0, "zroot\t2048\t8192\t0\t0\t0\t0"),
+            // Reported a little late
+            (200, "tank\t1024\t4096\t30\t0\t122880\t0"),
+        ];
+        let mut parser = PoolParser::default();
+        let mut state = BTreeMap::new();
+        for (ms, line) in lines {
+            let now = start + Duration::from_millis(ms);
+            let update = parser.push_at(line, now).unwrap();
+            PoolParser::apply(&mut state, update);
+        }
+        let tank = PoolCounters {
+            reads:    7,
+            nread:    28672,
+            writes:   2,
+            nwritten: 8192,
+        };
+        assert_eq!(state["tank"], tank);
+        assert_eq!(state["zroot"], PoolCounters::default());
+    }
+
+    /// Lines that aren't a pool's report, like errors, are ignored
+    #[test]
+    fn pool_garbage() {
+        let text = "cannot open 'nope': no such pool\ntank\t1\t2\t-\t0\t0\t0\n";
+        assert!(parse_all::<PoolParser>(text).is_empty());
+    }
+
+    /// Later histograms replace earlier ones for the same pool
+    #[test]
+    fn apply_histograms() {
+        let mut state = BTreeMap::new();
+        for update in parse_all::<LatencyParser>(HISTOGRAMS) {
+            LatencyParser::apply(&mut state, update);
+        }
+        let update = ("tank".to_owned(), Vec::new());
+        LatencyParser::apply(&mut state, update);
+        assert!(state["tank"].is_empty());
+        assert_eq!(state["zroot"].len(), 1);
+    }
+}
@@ -0,0 +1,121 @@
+// vim: tw=80
+//! Structured logging to the systemd journal, using its native protocol
+use std::{
+    io,
+    os::unix::net::UnixDatagram,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{app::Element, config::de_opt_duration};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The `[journal]` section of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JournalConfig {
+    /// Log a summary of every active dataset this often
+    #[serde(default, deserialize_with = "de_opt_duration")]
+    pub summary_interval: Option<Duration>,
+}
+
+/// Send one entry to the journal.
+///
+/// Field names must be uppercase, and values must not contain newlines.
+pub fn send<V: AsRef<str>>(fields: &[(&str, V)]) -> io::Result<()> {
+    let mut entry = String::from("SYSLOG_IDENTIFIER=ztop\n");
+    for (k, v) in fields {
+        let v = v.as_ref();
+        debug_assert!(!v.contains('\n'));
+        entry.push_str(k);
+        entry.push('=');
+        entry.push_str(v);
+        entry.push('\n');
+    }
+    let sock = UnixDatagram::unbound()?;
+    sock.send_to(entry.as_bytes(), JOURNAL_SOCKET)?;
+    Ok(())
+}
+
+/// Periodically logs the activity of every busy dataset
+pub struct Summarizer {
+    interval: Duration,
+    last:     Option<Instant>,
+}
+
+impl Summarizer {
+    pub fn new(interval: Duration) -> Self {
+        Summarizer {
+            interval,
+            last: None,
+        }
+    }
+
+    /// Log a summary, if one is due.
+    ///
+    /// Entries that journald doesn't accept are dropped.  Losing one summary
+    /// isn't worth a warning, since the next will be along shortly.
+    pub fn tick(&mut self, elems: &[Element], now: Instant) {
+        if let Some(last) = self.last {
+            if now - last < self.interval {
+                return;
+            }
+        }
+        self.last = Some(now);
+        for elem in elems.iter().filter(|e| e.r_s + e.w_s + e.d_s > 0.0) {
+            let _ = send(&Self::fields(elem));
+        }
+    }
+
+    fn fields(elem: &Element) -> [(&'static str, String); 9] {
+        [
+            ("PRIORITY", "6".to_owned()),
+            (
+                "MESSAGE",
+                format!(
+                    "{}: {:.0} B/s read, {:.0} B/s written, {:.0} B/s deleted",
+                    elem.name, elem.r_s, elem.w_s, elem.d_s
+                ),
+            ),
+            ("DATASET", elem.name.clone()),
+            ("OPS_R", format!("{:.0}", elem.ops_r)),
+            ("RATE_R", format!("{:.0}", elem.r_s)),
+            ("OPS_W", format!("{:.0}", elem.ops_w)),
+            ("RATE_W", format!("{:.0}", elem.w_s)),
+            ("OPS_D", format!("{:.0}", elem.ops_d)),
+            ("RATE_D", format!("{:.0}", elem.d_s)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let s = r#"summary_interval = "5m""#;
+        let config: JournalConfig = toml::from_str(s).unwrap();
+        assert_eq!(config.summary_interval, Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn fields() {
+        let elem = Element {
+            name: "tank/db".to_owned(),
+            ops_r: 1.0,
+            r_s: 2.0,
+            ops_w: 3.0,
+            w_s: 4.0,
+            ops_d: 5.0,
+            d_s: 6.0,
+            ..Default::default()
+        };
+        let fields = Summarizer::fields(&elem);
+        assert!(fields.contains(&("DATASET", "tank/db".to_owned())));
+        assert!(fields.contains(&("RATE_W", "4".to_owned())));
+        assert!(fields.contains(&("OPS_D", "5".to_owned())));
+    }
+}
@@ -0,0 +1,31 @@
+// vim: tw=80
+//! The parts of ztop shared by the interactive display and `ztop-agent`:
+//! statistics collection, alerts, the exporters, and the display itself.
+pub mod agentx;
+pub mod alert;
+pub mod app;
+pub mod capture;
+pub mod config;
+pub mod container;
+pub mod exec;
+#[cfg(feature = "tui")]
+pub mod graph;
+pub mod i18n;
+pub mod iostat;
+#[cfg(target_os = "linux")]
+pub mod journal;
+pub mod pressure;
+#[cfg(target_os = "linux")]
+pub mod sandbox;
+#[cfg(feature = "tui")]
+pub mod screen;
+pub mod serve;
+pub mod sink;
+pub mod state;
+pub mod statsd;
+pub mod summary;
+#[cfg(feature = "tui")]
+pub mod ui;
+pub mod vm;
+pub mod warning;
+pub mod zabbix;
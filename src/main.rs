@@ -1,194 +1,390 @@
 // vim: tw=80
-use std::{error::Error, io, num::NonZeroUsize, time::Duration};
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    io,
+    mem,
+    num::NonZeroUsize,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use clap::Parser;
-use crossterm::event::KeyCode;
-use ratatui::{
-    backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
-    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table},
-    Terminal,
-};
+use ratatui::{backend::CrosstermBackend, Terminal};
 use regex::Regex;
+use ztop::{
+    app::{
+        self,
+        App,
+        DataSource,
+        DatasetType,
+        EncryptionFilter,
+        Source,
+        Statistic,
+    },
+    capture,
+    config::{self, Config},
+    exec,
+    graph::{Graphics, Raster},
+    i18n::{self, trf},
+    screen::{self, Screen},
+    sink,
+    state::State,
+    ui,
+    warning,
+};
 
-mod app;
-use self::app::App;
+mod bench;
+use self::bench::BenchArgs;
+mod check;
+use self::check::CheckArgs;
+mod convert;
+use self::convert::ConvertArgs;
+mod doctor;
+use self::doctor::DoctorArgs;
 mod event;
 use self::event::Event;
+mod merge;
+use self::merge::MergeArgs;
+mod mirror;
+use self::mirror::Mirror;
+mod top;
+use self::top::TopArgs;
 
 /// Display ZFS datasets' I/O in real time
 // TODO: shorten the help options so they fit on 80 columns.
 #[derive(Debug, Default, clap::Parser)]
+#[clap(args_conflicts_with_subcommands = true)]
 struct Cli {
     /// only display datasets that have some activity.
     #[clap(short = 'a', long = "auto", verbatim_doc_comment)]
-    auto:     bool,
+    auto:            bool,
+    /// in auto mode, the minimum combined bytes per second for a dataset to
+    /// be considered active.  Suffixes like k, M, Ki, and Mi are accepted.
+    #[clap(long = "auto-threshold", value_parser = config::rate_from_str)]
+    auto_threshold:  Option<f64>,
+    /// in auto mode, if every dataset is idle, show this many of the busiest
+    /// ones anyway.
+    #[clap(long = "auto-min", default_value_t = app::DEFAULT_AUTO_MIN)]
+    auto_min:        usize,
+    /// Show all boot environments as a single ROOT dataset.
+    #[clap(short = 'b', long = "collapse-be")]
+    collapse_be:     bool,
+    /// Read settings from this file instead of the default location.
+    #[clap(long = "config")]
+    config:          Option<PathBuf>,
     /// Include child datasets' stats with their parents'.
     #[clap(short = 'c', long = "children")]
-    children: bool,
+    children:        bool,
     /// display datasets no more than this many levels deep.
     #[clap(short = 'd', long = "depth")]
-    depth:    Option<NonZeroUsize>,
+    depth:           Option<NonZeroUsize>,
     /// only display datasets with names matching filter, as a regex.
     #[clap(short = 'f', value_parser = Regex::new, long = "filter")]
-    filter:   Option<Regex>,
+    filter:          Option<Regex>,
     /// display update interval, in seconds or with the specified unit
-    #[clap(short = 't', value_parser = Cli::duration_from_str, long = "time")]
-    time:     Option<Duration>,
+    #[clap(short = 't', value_parser = config::interval_from_str, long = "time")]
+    time:            Option<Duration>,
+    /// Take samples when the wall clock reaches a multiple of the update
+    /// interval, so output from different hosts lines up.
+    #[clap(long = "align")]
+    align:           bool,
+    /// redraw the screen no more often than this, in seconds or with the
+    /// specified unit.
+    #[clap(long = "redraw", value_parser = config::duration_from_str)]
+    redraw:          Option<Duration>,
+    /// Read the counters this many times per update interval, so that the
+    /// lowest or highest rates in between can be displayed.
+    #[clap(
+        long = "samples",
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..=100)
+    )]
+    samples:         Option<u16>,
+    /// With --samples, display each dataset's mean, lowest (min), or highest
+    /// (max) rates.
+    #[clap(long = "statistic", value_name = "STAT", requires = "samples")]
+    statistic:       Option<Statistic>,
+    /// Collect statistics from the kernel's per-dataset kstats (kstat), or,
+    /// where those can't be read, each pool's from zpool iostat (exec).
+    #[clap(long = "source", value_name = "SOURCE", default_value_t)]
+    source:          Source,
+    /// print the raw counters of this objset, like tank/0x58c, instead of
+    /// the usual display.  The pool may be omitted if there's only one.  May
+    /// be repeated.
+    #[clap(long = "objset", value_parser = Cli::objset_from_str)]
+    objsets:         Vec<(Option<String>, u64)>,
+    /// Show each dataset's objset ID.
+    #[clap(long = "objset-ids")]
+    objset_ids:      bool,
+    /// if more than this many datasets are found at startup, limit the
+    /// depth so that fewer are displayed.  0 disables the limit.
+    #[clap(long = "max-datasets", default_value_t = 5000)]
+    max_datasets:    usize,
+    /// Use plain ASCII and no colors, to save bandwidth on slow links.
+    #[clap(long = "plain")]
+    plain:           bool,
     /// Reverse the sort
     #[clap(short = 'r', long = "reverse")]
-    reverse:  bool,
+    reverse:         bool,
+    /// In children mode, also show each parent's own statistics.
+    #[clap(long = "self-rows")]
+    self_rows:       bool,
+    /// Include mounted snapshots, which are excluded by default.
+    #[clap(long = "snapshots")]
+    snapshots:       bool,
+    /// Show the L2ARC's size and activity above the table.
+    #[clap(long = "l2arc")]
+    l2arc:           bool,
+    /// Show each pool's latency histograms instead of each dataset's
+    /// statistics, like `zpool iostat -w`.
+    #[clap(long = "latency")]
+    latency:         bool,
+    /// Show each vdev's pending and active operations per I/O class instead
+    /// of each dataset's statistics, like `zpool iostat -qv`.
+    #[clap(long = "queues")]
+    queues:          bool,
+    /// Show each vdev's operations and bandwidth instead of each dataset's,
+    /// like `zpool iostat -v`.
+    #[clap(long = "vdevs")]
+    vdevs:           bool,
+    /// Show each pool's size, allocated and free space, capacity, and
+    /// fragmentation above the table.
+    #[clap(long = "capacity")]
+    capacity:        bool,
+    /// Show the size of each pool's dedup table, on disk and in memory, and
+    /// its dedup ratio above the table.
+    #[clap(long = "dedup")]
+    dedup:           bool,
+    /// Show each dataset's used, available, and referenced space.  This
+    /// runs `zfs list` on every update, which is slower than reading the
+    /// kstats.
+    #[clap(long = "space")]
+    space:           bool,
+    /// Show the dbuf cache's size, hits, misses, and evictions above the
+    /// table.
+    #[clap(long = "dbuf")]
+    dbuf:            bool,
+    /// Show the prefetcher's hits, misses, and stream exhaustion above the
+    /// table.
+    #[clap(long = "zfetch")]
+    zfetch:          bool,
+    /// Show each pool's latest transaction group timings above the table.
+    #[clap(long = "txgs")]
+    txgs:            bool,
+    /// Graph the selected dataset's read and write bandwidth, or the
+    /// total of those shown, above the table.
+    #[clap(long = "graph")]
+    graph:           bool,
+    /// Draw the graph with braille characters, or as an image with the
+    /// Kitty graphics protocol or Sixel.  auto uses whichever the terminal
+    /// supports.
+    #[clap(long = "graphics", value_name = "GRAPHICS", default_value_t)]
+    graphics:        Graphics,
+    /// Show the bytes read and written by each dataset since its pool was
+    /// imported.
+    #[clap(long = "totals")]
+    totals:          bool,
+    /// Show whether each dataset is a file system, volume, or snapshot.
+    #[clap(long = "types")]
+    types:           bool,
+    /// Only display datasets of this type: filesystem, volume, or snapshot.
+    #[clap(long = "type-filter", value_name = "TYPE")]
+    type_filter:     Option<DatasetType>,
+    /// Show whether each dataset is encrypted, and whether its key is
+    /// loaded.
+    #[clap(long = "encryption")]
+    encryption:      bool,
+    /// Only display encrypted datasets, or only those that are unlocked and
+    /// mounted: encrypted or unlocked.
+    #[clap(long = "encryption-filter", value_name = "FILTER")]
+    enc_filter:      Option<EncryptionFilter>,
+    /// Only display the datasets that zfs list shows, such as those
+    /// delegated to this jail, zone, or user namespace.
+    #[clap(long = "delegated")]
+    delegated:       bool,
+    /// Name the Docker or LXD container that uses each file system.
+    #[clap(long = "container-names")]
+    container_names: bool,
+    /// Mark file systems that aren't mounted.
+    #[clap(long = "mount-status")]
+    mount_status:    bool,
+    /// Only display mounted file systems and volumes.
+    #[clap(long = "mounted-only")]
+    mounted_only:    bool,
+    /// Display a named set of datasets from the configuration file.
+    #[clap(long = "set")]
+    set:             Option<String>,
+    /// Never run other programs, such as alert hooks.
+    #[clap(long = "no-exec")]
+    no_exec:         bool,
+    /// When quitting, print a summary of each dataset's activity.
+    #[clap(long = "summary")]
+    summary:         bool,
+    /// Keep running if some pools' statistics aren't visible, as in a
+    /// container.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "container")]
+    container:       bool,
+    /// Once started, deny the system calls that ztop never needs, like those
+    /// that run programs or load kernel modules.  Implies --no-exec.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "sandbox")]
+    sandbox:         bool,
+    /// Read ZFS kstats from this directory instead of /proc/spl/kstat/zfs.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "kstat-path")]
+    kstat_path:      Option<PathBuf>,
+    /// Serve a read-only copy of the display to viewers using --attach, on
+    /// HOST:PORT, or on localhost if only a PORT is given.
+    #[clap(long = "mirror", value_name = "ADDR")]
+    mirror:          Option<String>,
+    /// Instead of collecting statistics, display the ztop mirrored on
+    /// HOST:PORT.
+    #[clap(long = "attach", value_name = "ADDR", conflicts_with = "mirror")]
+    attach:          Option<String>,
+    /// Register with the AgentX master agent on this socket, as a path or
+    /// HOST:PORT, to serve per-dataset statistics over SNMP.
+    #[clap(long = "agentx", value_name = "MASTER")]
+    agentx:          Option<String>,
+    /// Send per-dataset gauges to this statsd server, as HOST:PORT.
+    #[clap(long = "statsd")]
+    statsd:          Option<String>,
+    /// Send per-dataset metrics to this Zabbix server, as HOST:PORT.
+    #[clap(long = "zabbix")]
+    zabbix:          Option<String>,
     /// Sort by the named column.  The name should match the column header.
     #[clap(short = 's', long = "sort")]
-    sort:     Option<String>,
+    sort:            Option<String>,
     /// Display these pools and their children
-    pools:    Vec<String>,
-}
-
-impl Cli {
-    fn duration_from_str(s: &str) -> Result<Duration, humanize_rs::ParseError> {
-        if let Ok(fsecs) = s.parse::<f64>() {
-            Ok(Duration::from_secs_f64(fsecs))
-        } else {
-            // Must have units
-            humanize_rs::duration::parse(s)
-        }
-    }
+    pools:           Vec<String>,
+    #[clap(subcommand)]
+    command:         Option<Command>,
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct FilterPopup {
-    new_regex: String,
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    Bench(BenchArgs),
+    Check(CheckArgs),
+    Convert(ConvertArgs),
+    Doctor(DoctorArgs),
+    Merge(MergeArgs),
+    Top(TopArgs),
 }
 
-impl FilterPopup {
-    pub fn on_enter(&mut self) -> Result<Regex, impl Error> {
-        Regex::new(&self.new_regex)
+impl Cli {
+    /// Check that the requested pools exist, unless in container mode.  Then,
+    /// any that aren't visible are reported in the display instead.
+    fn check_pools(&self, pools: &[String]) -> Result<(), Box<dyn Error>> {
+        #[cfg(target_os = "linux")]
+        if self.container {
+            return Ok(());
+        }
+        DataSource::check_pools(pools)
     }
 
-    pub fn on_backspace(&mut self) {
-        self.new_regex.pop();
+    /// Parse an objset ID, optionally preceded by its pool, like tank/0x58c
+    fn objset_from_str(
+        s: &str,
+    ) -> Result<(Option<String>, u64), std::num::ParseIntError> {
+        let (pool, s) = match s.rsplit_once('/') {
+            Some((pool, id)) => (Some(pool.to_owned()), id),
+            None => (None, s),
+        };
+        let s = s.strip_prefix("objset-").unwrap_or(s);
+        let id = match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse::<u64>(),
+        }?;
+        Ok((pool, id))
     }
 
-    pub fn on_char(&mut self, c: char) {
-        self.new_regex.push(c);
+    /// The requested objsets, by pool and ID.  Objset IDs are only unique
+    /// within a pool, so one without a pool is only accepted if there's just
+    /// one pool to look in.
+    fn objset_keys(&self) -> Result<Vec<(String, u64)>, Box<dyn Error>> {
+        let pools = if self.pools.is_empty() {
+            DataSource::available_pools()?
+        } else {
+            self.pools.clone()
+        };
+        self.objsets
+            .iter()
+            .map(|(pool, id)| match (pool, pools.as_slice()) {
+                (Some(pool), _) => Ok((pool.clone(), *id)),
+                (None, [pool]) => Ok((pool.clone(), *id)),
+                (None, _) => Err(format!(
+                    "objset {id:#x} is ambiguous with several pools; name its \
+                     pool, like {}/{id:#x}",
+                    pools.first().map(String::as_str).unwrap_or("tank")
+                )
+                .into()),
+            })
+            .collect()
     }
 }
 
-mod ui {
-    use ratatui::Frame;
-
-    use super::*;
+/// How often to check whether the first refresh or a background command has
+/// finished
+const COLLECTING_POLL: Duration = Duration::from_millis(50);
 
-    // helper function to create a one-line popup box
-    fn popup_layout(x: u16, y: u16, r: Rect) -> Rect {
-        let popup_layout = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(
-                [
-                    Constraint::Max(r.height.saturating_sub(y) / 2),
-                    Constraint::Length(y),
-                    Constraint::Max(r.height.saturating_sub(y) / 2),
-                ]
-                .as_ref(),
-            )
-            .split(r);
-
-        Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints(
-                [
-                    Constraint::Max(r.width.saturating_sub(x) / 2),
-                    Constraint::Length(x),
-                    Constraint::Max(r.width.saturating_sub(x) / 2),
-                ]
-                .as_ref(),
-            )
-            .split(popup_layout[1])[1]
+/// How long until the wall clock next reaches a multiple of `interval`,
+/// measured from the Unix epoch
+fn align_delay(since_epoch: Duration, interval: Duration) -> Duration {
+    let interval = interval.as_nanos();
+    if interval == 0 {
+        return Duration::ZERO;
     }
+    let delay = interval - since_epoch.as_nanos() % interval;
+    Duration::from_nanos(u64::try_from(delay).unwrap_or(u64::MAX))
+}
 
-    pub fn draw(f: &mut Frame, app: &mut App) {
-        let hstyle = Style::default()
-            .fg(Color::LightYellow)
-            .add_modifier(Modifier::BOLD);
-        let sstyle = hstyle.add_modifier(Modifier::REVERSED);
-        let hcells = [
-            Cell::from("   r/s"),
-            Cell::from(" kB/s r"),
-            Cell::from("   w/s"),
-            Cell::from(" kB/s w"),
-            Cell::from("   d/s"),
-            Cell::from("kB/s d"),
-            Cell::from("Dataset"),
-        ]
-        .into_iter()
-        .enumerate()
-        .map(|(i, cell)| {
-            if Some(i) == app.sort_idx() {
-                cell.style(sstyle)
-            } else {
-                cell.style(hstyle)
-            }
-        });
-        let header = Row::new(hcells).style(Style::default().bg(Color::Blue));
-        let rows = app
-            .elements()
-            .into_iter()
-            .map(|elem| {
-                Row::new([
-                    Cell::from(format!("{:>6.0}", elem.ops_r)),
-                    Cell::from(format!("{:>7.0}", elem.r_s / 1024.0)),
-                    Cell::from(format!("{:>6.0}", elem.ops_w)),
-                    Cell::from(format!("{:>7.0}", elem.w_s / 1024.0)),
-                    Cell::from(format!("{:>6.0}", elem.ops_d)),
-                    Cell::from(format!("{:>6.0}", elem.d_s / 1024.0)),
-                    Cell::from(elem.name),
-                ])
-            })
-            .collect::<Vec<_>>();
-        let widths = [
-            Constraint::Length(7),
-            Constraint::Length(8),
-            Constraint::Length(7),
-            Constraint::Length(8),
-            Constraint::Length(7),
-            Constraint::Length(7),
-            Constraint::Min(6),
-        ];
-        let t = Table::new(rows, widths)
-            .header(header)
-            .block(Block::default())
-            .flex(ratatui::layout::Flex::Legacy);
-        f.render_widget(t, f.size());
+/// When to take the next sample, if it isn't already scheduled.  With
+/// `--align`, that's the next wall clock multiple of the interval.
+fn next_sample_time(interval: Duration, align: bool) -> Instant {
+    if align {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Instant::now() + align_delay(since_epoch, interval)
+    } else {
+        Instant::now() + interval
     }
+}
 
-    #[rustfmt::skip]
-    pub fn draw_filter(f: &mut Frame, app: &FilterPopup) {
-        let area = popup_layout(40, 3, f.size());
-        let popup_box = Paragraph::new(app.new_regex.as_str())
-            .block(
-                Block::default()
-                .borders(Borders::ALL)
-                .title("Filter regex")
-            );
-        f.render_widget(Clear, area);
-        f.render_widget(popup_box, area);
-    }
+/// When to take the next of the extra samples between refreshes, if any.
+/// The refresh at `next_sample` counts as the last one, so there's none
+/// within half a step of it.
+fn subsample_time(
+    after: Instant,
+    interval: Duration,
+    app: &App,
+    next_sample: Instant,
+) -> Option<Instant> {
+    let samples = u32::try_from(app.samples()).ok().filter(|&n| n > 1)?;
+    let step = interval / samples;
+    Some(after + step).filter(|&t| t + step / 2 < next_sample)
+}
 
-    // Needs a &String argument to work with Option<String>::as_ref
-    #[allow(clippy::ptr_arg)]
-    pub fn col_idx(col_name: &String) -> Option<usize> {
-        match col_name.trim() {
-            "r/s" => Some(0),
-            "kB/s r" => Some(1),
-            "w/s" => Some(2),
-            "kB/s w" => Some(3),
-            "d/s" => Some(4),
-            "kB/s d" => Some(5),
-            "Dataset" => Some(6),
-            _ => None,
+/// Print the raw counters of the requested objsets, instead of the usual
+/// display
+fn watch_objsets(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let mut data = DataSource::with_objsets(cli.objset_keys()?);
+    let interval = cli.time.unwrap_or(Duration::from_secs(1));
+    data.refresh()?;
+    for (pool, objset) in data.missing_objsets() {
+        eprintln!("objset {pool}/{objset:#x} not found");
+    }
+    loop {
+        let deadline = next_sample_time(interval, cli.align);
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        data.refresh()?;
+        for line in data.raw_report() {
+            println!("{line}");
         }
+        println!();
     }
 }
 
@@ -196,93 +392,304 @@ mod ui {
 #[allow(clippy::or_fun_call)]
 fn main() -> Result<(), Box<dyn Error>> {
     let cli: Cli = Cli::parse();
-    let mut editting_filter = false;
-    let mut tick_rate = cli.time.unwrap_or(Duration::from_secs(1));
-    let col_idx = cli.sort.as_ref().map(ui::col_idx).unwrap_or(None);
-    let mut app = App::new(
-        cli.auto,
-        cli.children,
-        cli.pools,
-        cli.depth,
-        cli.filter,
-        cli.reverse,
-        col_idx,
-    );
-    let mut filter_popup = FilterPopup::default();
+    if cli.no_exec {
+        exec::forbid("--no-exec");
+    }
+    #[cfg(target_os = "linux")]
+    if cli.sandbox {
+        exec::forbid("--sandbox");
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(path) = cli.kstat_path.clone() {
+        app::set_kstat_path(path);
+    }
+    #[cfg(target_os = "linux")]
+    if cli.container {
+        app::set_container_mode();
+    }
+    match cli.command.as_ref() {
+        Some(Command::Bench(args)) => return bench::run(args),
+        Some(Command::Check(args)) => std::process::exit(check::run(args)),
+        Some(Command::Convert(args)) => return convert::run(args),
+        Some(Command::Doctor(args)) => {
+            std::process::exit(doctor::run(args, &cli))
+        }
+        Some(Command::Merge(args)) => return merge::run(args),
+        Some(Command::Top(args)) => return top::run(args),
+        None => (),
+    }
+    if let Some(addr) = cli.attach.as_deref() {
+        let token = env::var("ZTOP_MIRROR_TOKEN").ok();
+        return mirror::attach(addr, token.as_deref());
+    }
+    if let Err(e) = cli.check_pools(&cli.pools) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if !cli.objsets.is_empty() {
+        return watch_objsets(&cli);
+    }
+    let mut config = Config::load(cli.config.as_deref())?;
+    // Pools and filters from the command line override the set's
+    let (pools, filter) = match cli.set.as_deref() {
+        Some(name) => {
+            let set = config.sets.get(name).ok_or_else(|| {
+                format!("No set named {name:?} in the configuration file")
+            })?;
+            // Pools from the command line were already checked
+            let pools = if cli.pools.is_empty() {
+                if let Err(e) = cli.check_pools(&set.pools) {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+                set.pools.clone()
+            } else {
+                cli.pools
+            };
+            (pools, cli.filter.or_else(|| set.filter.clone()))
+        }
+        None => (cli.pools, cli.filter),
+    };
+    let sinks =
+        sink::from_config(&cli.agentx, &cli.statsd, &cli.zabbix, &mut config)?;
+    let mut watcher = config::Watcher::new(cli.config.as_deref());
+    let mirror_token = env::var("ZTOP_MIRROR_TOKEN").ok();
+    let mirror = cli
+        .mirror
+        .as_deref()
+        .map(|addr| Mirror::new(addr, mirror_token.as_deref()))
+        .transpose()?;
+    let mut state = State::load();
+    let tick_rate = cli
+        .time
+        .or(state.interval.map(screen::clamp_interval))
+        .unwrap_or(Duration::from_secs(1));
+    // Headers from the config file override the translation's
+    let mut catalog_headers = HashMap::new();
+    if let Some(lang) = i18n::language(config.lang.as_deref()) {
+        let mut catalog = i18n::Catalog::load(&lang)?;
+        catalog_headers = mem::take(&mut catalog.headers);
+        i18n::set_catalog(catalog);
+    }
+    let mut headers = catalog_headers.clone();
+    headers.extend(mem::take(&mut config.headers));
+    let esc_quits = config.esc_quits;
+    let col_idx = cli
+        .sort
+        .as_ref()
+        .and_then(|col_name| ui::col_idx(col_name, &headers));
+    let options = app::Options {
+        auto: cli.auto,
+        auto_threshold: cli.auto_threshold.unwrap_or(1.0),
+        auto_min: cli.auto_min,
+        children: cli.children,
+        collapse_be: cli.collapse_be,
+        pools,
+        depth: cli.depth,
+        filter,
+        objset_ids: cli.objset_ids,
+        reverse: cli.reverse,
+        self_rows: cli.self_rows,
+        totals: cli.totals,
+        mount_status: cli.mount_status,
+        mounted_only: cli.mounted_only,
+        sort_idx: col_idx,
+        source: cli.source,
+    };
+    let mut app = App::new(options, config, sinks);
+    if cli.depth.is_none() && cli.max_datasets > 0 {
+        app.limit_depth(cli.max_datasets);
+    }
+    if cli.summary {
+        app.record_summary();
+    }
+    if cli.l2arc {
+        app.on_l();
+    }
+    if cli.txgs {
+        app.on_g();
+    }
+    if cli.graph {
+        app.toggle_graph();
+    }
+    if cli.zfetch {
+        app.on_p();
+    }
+    if cli.dbuf {
+        app.toggle_dbuf();
+    }
+    if cli.capacity {
+        app.toggle_capacity();
+    }
+    if cli.dedup {
+        app.toggle_dedup();
+    }
+    if cli.space {
+        app.toggle_space();
+    }
+    if cli.snapshots {
+        app.toggle_snapshots();
+    }
+    if cli.types {
+        app.toggle_types();
+    }
+    if let Some(t) = cli.type_filter {
+        app.set_type_filter(t);
+    }
+    if cli.encryption {
+        app.toggle_encryption();
+    }
+    if let Some(f) = cli.enc_filter {
+        app.set_encryption_filter(f);
+    }
+    if let Some(n) = cli.samples {
+        app.set_samples(n.into());
+    }
+    if let Some(s) = cli.statistic {
+        app.set_statistic(s);
+    }
+    if cli.delegated {
+        app.set_delegated();
+    }
+    if cli.container_names {
+        app.set_containers();
+    }
+    if cli.vdevs {
+        app.toggle_vdevs(tick_rate);
+    }
+    if cli.latency {
+        app.toggle_latency(tick_rate);
+    }
+    if cli.queues {
+        app.toggle_queues(tick_rate);
+    }
+    app.restore_sorts(&state.sort, cli.sort.is_none() && !cli.reverse);
+    let mut screen = Screen::new(headers, esc_quits, cli.plain, tick_rate);
+    screen.set_widths(&state.widths);
+    #[cfg(target_os = "linux")]
+    if cli.sandbox {
+        ztop::sandbox::enter()?;
+    }
     let stdout = io::stdout();
     crossterm::terminal::enable_raw_mode().unwrap();
+    // Detection reads the terminal's replies, so it needs raw mode
+    let mut raster = if cli.plain {
+        None
+    } else {
+        Raster::new(cli.graphics)
+    };
+    screen.set_raster(raster.is_some());
 
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     terminal.clear()?;
+    // Sampling and redrawing run on separate schedules.  The screen is
+    // redrawn after every sample or input event, but no more often than
+    // redraw_rate.  With --samples, the extra samples in between refreshes
+    // run on a third.
+    let redraw_rate = cli.redraw.unwrap_or_default();
+    let mut next_sample = next_sample_time(tick_rate, cli.align);
+    let mut next_subsample = None;
+    let mut next_draw = Instant::now();
+    let mut dirty = true;
     while !app.should_quit() {
-        terminal.draw(|f| {
-            ui::draw(f, &mut app);
-            if editting_filter {
-                ui::draw_filter(f, &filter_popup)
+        if app.poll_first_refresh()
+            | app.poll_jobs()
+            | screen.poll_jobs(&mut app)
+        {
+            dirty = true;
+        }
+        let now = Instant::now();
+        if dirty && now >= next_draw {
+            let warnings = warning::total();
+            let frame =
+                terminal.draw(|f| screen.draw(f, &mut app, warnings))?;
+            if let Some(mirror) = mirror.as_ref() {
+                mirror.publish(frame.buffer);
+            }
+            next_draw = now + redraw_rate;
+            dirty = false;
+            if let Some(raster) = raster.as_mut() {
+                let area = screen.graph_area();
+                let out = terminal.backend_mut();
+                if raster.draw(out, area, app.graph())? {
+                    // A Sixel image can only be erased by redrawing its cells
+                    terminal.clear()?;
+                    dirty = true;
+                }
             }
-        })?;
+        }
 
-        match event::poll(&tick_rate) {
+        let mut deadline = if dirty {
+            next_sample.min(next_draw)
+        } else {
+            next_sample
+        };
+        if let Some(t) = next_subsample {
+            deadline = deadline.min(t);
+        }
+        if app.is_collecting() || app.has_jobs() || screen.has_jobs() {
+            deadline = deadline.min(now + COLLECTING_POLL);
+        }
+        let event = event::poll(&deadline.saturating_duration_since(now));
+        dirty = true;
+        match event {
             Some(Event::Tick) => {
-                app.on_tick();
+                // The timeout may have been for a pending redraw instead
+                let now = Instant::now();
+                if now >= next_sample {
+                    let tick_rate = screen.tick_rate();
+                    app.on_tick();
+                    app.refresh_iostat(tick_rate);
+                    if watcher.changed() {
+                        let r = Config::load(cli.config.as_deref()).and_then(
+                            |mut config| {
+                                let sinks = sink::from_config(
+                                    &cli.agentx,
+                                    &cli.statsd,
+                                    &cli.zabbix,
+                                    &mut config,
+                                )?;
+                                Ok((config, sinks))
+                            },
+                        );
+                        match r {
+                            Ok((mut config, sinks)) => {
+                                let mut headers = catalog_headers.clone();
+                                headers.extend(mem::take(&mut config.headers));
+                                screen.reload_config(headers, config.esc_quits);
+                                app.reload_config(config, sinks);
+                            }
+                            Err(e) => app.set_error(trf(
+                                "Cannot reload the configuration file: {}",
+                                &[&e],
+                            )),
+                        }
+                    }
+                    next_sample = if cli.align {
+                        next_sample_time(tick_rate, true)
+                    } else {
+                        (next_sample + tick_rate).max(now)
+                    };
+                    next_subsample =
+                        subsample_time(now, tick_rate, &app, next_sample);
+                } else if let Some(t) = next_subsample.filter(|&t| now >= t) {
+                    app.on_sample();
+                    next_subsample = subsample_time(
+                        t,
+                        screen.tick_rate(),
+                        &app,
+                        next_sample,
+                    );
+                }
             }
             Some(Event::Key(kev)) => {
-                match kev.code {
-                    KeyCode::Esc if editting_filter => {
-                        editting_filter = false;
-                    }
-                    KeyCode::Enter if editting_filter => {
-                        let filter = filter_popup.on_enter()?;
-                        app.set_filter(filter);
-                        editting_filter = false;
-                    }
-                    KeyCode::Backspace if editting_filter => {
-                        filter_popup.on_backspace();
-                    }
-                    KeyCode::Char(c) if editting_filter => {
-                        filter_popup.on_char(c);
-                    }
-                    KeyCode::Char('+') => {
-                        app.on_plus();
-                    }
-                    KeyCode::Char('-') => {
-                        app.on_minus();
-                    }
-                    KeyCode::Char('<') => {
-                        tick_rate /= 2;
-                    }
-                    KeyCode::Char('>') => {
-                        tick_rate *= 2;
-                    }
-                    KeyCode::Char('a') => {
-                        app.on_a();
-                    }
-                    KeyCode::Char('c') => {
-                        app.on_c()?;
-                    }
-                    KeyCode::Char('D') => {
-                        app.on_d(false);
-                    }
-                    KeyCode::Char('d') => {
-                        app.on_d(true);
-                    }
-                    KeyCode::Char('F') => {
-                        app.clear_filter();
-                    }
-                    KeyCode::Char('f') => {
-                        editting_filter = true;
-                    }
-                    KeyCode::Char('q') => {
-                        app.on_q();
-                    }
-                    KeyCode::Char('r') => {
-                        app.on_r();
-                    }
-                    _ => {
-                        // Ignore unknown keys
-                    }
+                let interval_changed = screen.on_key(&mut app, kev)?;
+                if interval_changed {
+                    next_sample =
+                        next_sample_time(screen.tick_rate(), cli.align);
+                    next_subsample = None;
                 }
             }
             None => {
@@ -294,7 +701,155 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
     }
+    if let Some(raster) = raster.as_mut() {
+        raster.draw(terminal.backend_mut(), None, None)?;
+    }
     terminal.set_cursor(0, crossterm::terminal::size()?.1 - 1)?;
     crossterm::terminal::disable_raw_mode().unwrap();
+    if let Some(summary) = app.take_summary() {
+        println!();
+        print!("{summary}");
+    }
+    if let Some(cmd) = screen.take_command_line() {
+        println!("{cmd}");
+    }
+    let saved = state.clone();
+    state.widths = screen.widths();
+    state.sort = app.sorts();
+    if screen.tick_rate() != tick_rate {
+        state.interval = Some(screen.tick_rate());
+    }
+    if state != saved {
+        if let Err(e) = state.save() {
+            eprintln!("{e}");
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod t {
+    /// Pools named like a subcommand can be given after `--`
+    mod pool_names {
+        use super::super::*;
+
+        fn pools(args: &[&str]) -> Vec<String> {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert!(cli.command.is_none());
+            cli.pools
+        }
+
+        #[test]
+        fn subcommand() {
+            let cli = Cli::try_parse_from(["ztop", "top"]).unwrap();
+            assert!(matches!(cli.command, Some(Command::Top(_))));
+            let cli =
+                Cli::try_parse_from(["ztop", "convert", "--to", "json", "f"])
+                    .unwrap();
+            assert!(matches!(cli.command, Some(Command::Convert(_))));
+            assert!(Cli::try_parse_from(["ztop", "convert"]).is_err());
+            let cli =
+                Cli::try_parse_from(["ztop", "merge", "-o", "m", "a", "b"])
+                    .unwrap();
+            assert!(matches!(cli.command, Some(Command::Merge(_))));
+        }
+
+        #[test]
+        fn separator() {
+            assert_eq!(pools(&["ztop", "--", "top"]), ["top"]);
+            assert_eq!(pools(&["ztop", "-a", "--", "check"]), ["check"]);
+        }
+
+        /// After the first pool, every word is a pool
+        #[test]
+        fn after_pool() {
+            assert_eq!(pools(&["ztop", "tank", "doctor"]), ["tank", "doctor"]);
+        }
+    }
+
+    /// The command line's defaults match the library's, which ztop-agent
+    /// uses
+    mod defaults {
+        use super::super::*;
+
+        #[test]
+        fn auto_min() {
+            let cli = Cli::try_parse_from(["ztop"]).unwrap();
+            assert_eq!(cli.auto_min, app::Options::default().auto_min);
+        }
+
+        #[test]
+        fn source() {
+            let cli = Cli::try_parse_from(["ztop"]).unwrap();
+            assert_eq!(cli.source, app::Options::default().source);
+            let cli =
+                Cli::try_parse_from(["ztop", "--source", "exec"]).unwrap();
+            assert_eq!(cli.source, Source::Exec);
+            assert!(Cli::try_parse_from(["ztop", "--source", "x"]).is_err());
+        }
+    }
+
+    mod objset {
+        use super::super::*;
+
+        #[test]
+        fn parse() {
+            assert_eq!(Cli::objset_from_str("0x58c"), Ok((None, 0x58c)));
+            assert_eq!(
+                Cli::objset_from_str("tank/objset-0x58c"),
+                Ok((Some("tank".to_owned()), 0x58c))
+            );
+            assert_eq!(
+                Cli::objset_from_str("my pool/1420"),
+                Ok((Some("my pool".to_owned()), 1420))
+            );
+            assert!(Cli::objset_from_str("tank/").is_err());
+        }
+
+        /// A bare ID needs a single pool to look in
+        #[test]
+        fn ambiguous() {
+            let cli = Cli {
+                pools: vec!["tank".to_owned(), "zroot".to_owned()],
+                objsets: vec![(None, 0x58c)],
+                ..Default::default()
+            };
+            assert!(cli.objset_keys().is_err());
+            let cli = Cli {
+                pools: vec!["tank".to_owned()],
+                objsets: vec![(None, 0x58c), (Some("zroot".to_owned()), 1)],
+                ..Default::default()
+            };
+            let keys = cli.objset_keys().unwrap();
+            assert_eq!(
+                keys,
+                [("tank".to_owned(), 0x58c), ("zroot".to_owned(), 1)]
+            );
+        }
+    }
+
+    mod align_delay {
+        use super::super::*;
+
+        #[test]
+        fn mid_interval() {
+            let now = Duration::from_millis(1_700_000_012_250);
+            let d = align_delay(now, Duration::from_secs(5));
+            assert_eq!(d, Duration::from_millis(2750));
+        }
+
+        /// Exactly on a boundary, wait for the next one
+        #[test]
+        fn on_boundary() {
+            let now = Duration::from_secs(1_700_000_040);
+            let d = align_delay(now, Duration::from_secs(60));
+            assert_eq!(d, Duration::from_secs(60));
+        }
+
+        #[test]
+        fn zero_interval() {
+            let now = Duration::from_secs(1_700_000_040);
+            assert_eq!(align_delay(now, Duration::ZERO), Duration::ZERO);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// vim: tw=80
+//! Stitch capture files together
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use crate::capture::{self, Format};
+
+/// Merge capture files, in either format and compressed or not, into one
+#[derive(Debug, clap::Args)]
+pub struct MergeArgs {
+    /// the format to write: json or binary
+    #[clap(long = "format", default_value = "json")]
+    format: Format,
+    /// write the merged capture to this file
+    #[clap(short = 'o', long = "output")]
+    output: PathBuf,
+    /// the capture files to merge, in any order
+    #[clap(required = true)]
+    files:  Vec<PathBuf>,
+}
+
+pub fn run(args: &MergeArgs) -> Result<(), Box<dyn Error>> {
+    let same = |path: &PathBuf| {
+        fs::canonicalize(path).ok().is_some_and(|p| {
+            fs::canonicalize(&args.output).is_ok_and(|o| o == p)
+        })
+    };
+    if args.files.iter().any(same) {
+        return Err(format!(
+            "{} is one of the files to merge",
+            args.output.display()
+        )
+        .into());
+    }
+    let file = File::create(&args.output)
+        .map_err(|e| format!("Cannot create {}: {e}", args.output.display()))?;
+    let mut w = BufWriter::new(file);
+    let r = capture::merge(&args.files, args.format, &mut w)
+        .and_then(|_| w.flush().map_err(Into::into));
+    if r.is_err() {
+        // Don't leave a partial capture behind
+        let _ = fs::remove_file(&args.output);
+    }
+    r
+}
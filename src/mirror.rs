@@ -0,0 +1,373 @@
+// vim: tw=80
+//! Share a read-only copy of the display with colleagues.
+//!
+//! `--mirror` serves every frame as it is drawn, as one line of JSON holding
+//! the screen's text and colors, and `--attach` displays those frames.  Only
+//! the host's keyboard controls the session.
+//!
+//! A viewer starts by sending one line with the host's token, which is empty
+//! if the host doesn't require one.
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::{
+    backend::CrosstermBackend,
+    buffer::{Buffer, Cell},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    Terminal,
+};
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    event::{self, Event},
+    i18n::{tr, trf},
+};
+
+/// Don't let a stalled viewer freeze the display for long
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How often an attached viewer checks for new frames
+const POLL: Duration = Duration::from_millis(50);
+
+/// A run of text in the same style
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+struct Run {
+    text:     String,
+    /// Foreground color, as [`Color`] displays it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fg:       Option<String>,
+    /// Background color
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bg:       Option<String>,
+    /// Bits of [`Modifier`]
+    #[serde(default, skip_serializing_if = "is_zero")]
+    modifier: u16,
+}
+
+fn is_zero(n: &u16) -> bool {
+    *n == 0
+}
+
+impl Run {
+    fn new(cell: &Cell) -> Self {
+        let color = |c: Color| (c != Color::Reset).then(|| c.to_string());
+        Run {
+            text:     String::new(),
+            fg:       color(cell.fg),
+            bg:       color(cell.bg),
+            modifier: cell.modifier.bits(),
+        }
+    }
+
+    fn style(&self) -> Style {
+        let color = |c: &Option<String>| c.as_deref()?.parse::<Color>().ok();
+        let mut style = Style::default()
+            .add_modifier(Modifier::from_bits_truncate(self.modifier));
+        style.fg = color(&self.fg);
+        style.bg = color(&self.bg);
+        style
+    }
+}
+
+/// The text and styles of one frame
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+struct Screen {
+    lines: Vec<Vec<Run>>,
+}
+
+impl Screen {
+    fn from_buffer(buf: &Buffer) -> Self {
+        let width = usize::from(buf.area.width);
+        let lines = buf
+            .content
+            .chunks(width.max(1))
+            .map(|row| {
+                let mut runs: Vec<Run> = Vec::new();
+                // The cells covered by a wide character hold placeholders
+                let mut skip = 0;
+                for cell in row {
+                    if skip > 0 {
+                        skip -= 1;
+                        continue;
+                    }
+                    let run = Run::new(cell);
+                    match runs.last_mut() {
+                        Some(last) if last.style() == run.style() => {
+                            last.text.push_str(cell.symbol())
+                        }
+                        _ => runs.push(Run {
+                            text: cell.symbol().to_owned(),
+                            ..run
+                        }),
+                    }
+                    skip = cell.symbol().width().saturating_sub(1);
+                }
+                // Trailing blanks are only worth sending if they're styled
+                if let Some(last) = runs.last_mut() {
+                    if last.style() == Style::default() {
+                        last.text.truncate(last.text.trim_end().len());
+                        if last.text.is_empty() {
+                            runs.pop();
+                        }
+                    }
+                }
+                runs
+            })
+            .collect();
+        Screen { lines }
+    }
+
+    fn text(&self) -> Vec<Line<'_>> {
+        self.lines
+            .iter()
+            .map(|runs| {
+                runs.iter()
+                    .map(|run| Span::styled(run.text.as_str(), run.style()))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// The viewers, and the frame that a new one should see first
+#[derive(Default)]
+struct Shared {
+    viewers: Vec<TcpStream>,
+    last:    String,
+}
+
+/// Read the viewer's token, and check that it matches ours
+fn authenticate(stream: &TcpStream, token: &str) -> io::Result<bool> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(line.trim_end_matches('\n') == token)
+}
+
+/// Serves each frame to any attached viewers
+pub struct Mirror {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Mirror {
+    /// Listen for viewers on `addr`, as HOST:PORT, or on localhost if only a
+    /// PORT is given.  Viewers must present `token`.  Without one, only
+    /// loopback addresses are allowed.
+    pub fn new(
+        addr: &str,
+        token: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let addr = match addr.parse::<u16>() {
+            Ok(port) => format!("localhost:{port}"),
+            Err(_) => addr.to_owned(),
+        };
+        let listener = TcpListener::bind(&addr)
+            .map_err(|e| format!("Cannot listen on {addr}: {e}"))?;
+        if token.is_none() && !listener.local_addr()?.ip().is_loopback() {
+            return Err(format!(
+                "Set ZTOP_MIRROR_TOKEN to mirror on a non-loopback address \
+                 like {addr}"
+            )
+            .into());
+        }
+        let token = token.unwrap_or_default().to_owned();
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let shared2 = shared.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                if !authenticate(&stream, &token).unwrap_or(false) {
+                    continue;
+                }
+                let _ = stream.set_write_timeout(Some(TIMEOUT));
+                let mut shared = shared2.lock().unwrap();
+                if stream.write_all(shared.last.as_bytes()).is_ok() {
+                    shared.viewers.push(stream);
+                }
+            }
+        });
+        Ok(Mirror { shared })
+    }
+
+    /// Send a frame to every viewer.  Viewers that can't keep up are
+    /// disconnected.
+    pub fn publish(&self, buf: &Buffer) {
+        let mut frame =
+            serde_json::to_string(&Screen::from_buffer(buf)).unwrap();
+        frame.push('\n');
+        let mut shared = self.shared.lock().unwrap();
+        shared
+            .viewers
+            .retain_mut(|s| s.write_all(frame.as_bytes()).is_ok());
+        shared.last = frame;
+    }
+}
+
+/// Display another ztop's frames until the user quits or the host goes away
+pub fn attach(addr: &str, token: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut stream = TcpStream::connect(addr)
+        .map_err(|e| format!("Cannot connect to {addr}: {e}"))?;
+    writeln!(stream, "{}", token.unwrap_or_default())?;
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            match serde_json::from_str::<Screen>(&line) {
+                Ok(screen) => {
+                    if tx.send(Some(screen)).is_err() {
+                        return;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        let _ = tx.send(None);
+    });
+
+    crossterm::terminal::enable_raw_mode().unwrap();
+    let backend = CrosstermBackend::new(io::stdout());
+    let mut terminal = Terminal::new(backend)?;
+    terminal.clear()?;
+    let mut screen = Screen::default();
+    let mut connected = true;
+    loop {
+        while let Ok(msg) = rx.try_recv() {
+            match msg {
+                Some(s) => screen = s,
+                None => connected = false,
+            }
+        }
+        terminal.draw(|f| {
+            let mut block = Block::default();
+            if !connected {
+                let msg =
+                    trf("Disconnected from {}; press q to quit", &[&addr]);
+                block = block.title(msg);
+            } else if screen.lines.is_empty() {
+                block = block.title(tr("Waiting for the first frame..."));
+            }
+            let text = screen.text();
+            f.render_widget(Paragraph::new(text).block(block), f.size());
+        })?;
+        match event::poll(&POLL) {
+            Some(Event::Key(kev)) => match kev.code {
+                KeyCode::Char('c')
+                    if kev.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    break
+                }
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                _ => (),
+            },
+            None => break,
+            _ => (),
+        }
+    }
+    terminal.set_cursor(0, crossterm::terminal::size()?.1 - 1)?;
+    crossterm::terminal::disable_raw_mode().unwrap();
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use ratatui::layout::Rect;
+
+    use super::*;
+
+    fn texts(screen: &Screen) -> Vec<String> {
+        screen
+            .lines
+            .iter()
+            .map(|runs| runs.iter().map(|r| r.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn from_buffer() {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 8, 2));
+        buf.set_string(0, 0, "tank", Style::default());
+        buf.set_string(1, 1, "日本", Style::default());
+        let screen = Screen::from_buffer(&buf);
+        assert_eq!(texts(&screen), ["tank", " 日本"]);
+    }
+
+    /// Cells with the same style are sent as one run, and styled blanks
+    /// aren't trimmed
+    #[test]
+    fn styles() {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 8, 1));
+        let header = Style::default().add_modifier(Modifier::REVERSED);
+        let alert = Style::default().fg(Color::Red).bg(Color::Rgb(0, 0, 64));
+        buf.set_string(0, 0, "ab", alert);
+        buf.set_string(2, 0, "cd  ", header);
+        let screen = Screen::from_buffer(&buf);
+        assert_eq!(texts(&screen), ["abcd  "]);
+        let runs = &screen.lines[0];
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].style(), alert);
+        assert_eq!(runs[1].style(), header);
+
+        let json = serde_json::to_string(&screen).unwrap();
+        assert_eq!(serde_json::from_str::<Screen>(&json).unwrap(), screen);
+    }
+
+    fn connect(addr: &str, token: &str) -> BufReader<TcpStream> {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        writeln!(stream, "{token}").unwrap();
+        BufReader::new(stream)
+    }
+
+    /// Frames reach every viewer, including ones that attach later
+    #[test]
+    fn publish() {
+        // Find a free port
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let mirror = Mirror::new(&addr, None).unwrap();
+        let mut buf = Buffer::empty(Rect::new(0, 0, 4, 1));
+        buf.set_string(0, 0, "tank", Style::default());
+        let mut viewer = connect(&addr, "");
+        // Wait for the viewer to be accepted
+        while mirror.shared.lock().unwrap().viewers.is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        mirror.publish(&buf);
+        let mut line = String::new();
+        viewer.read_line(&mut line).unwrap();
+        let screen = serde_json::from_str::<Screen>(&line).unwrap();
+        assert_eq!(texts(&screen), ["tank"]);
+
+        let mut late = connect(&addr, "");
+        let mut line = String::new();
+        late.read_line(&mut line).unwrap();
+        assert_eq!(serde_json::from_str::<Screen>(&line).unwrap(), screen);
+    }
+
+    /// Viewers with the wrong token are disconnected without a frame
+    #[test]
+    fn wrong_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let _mirror = Mirror::new(&addr, Some("secret")).unwrap();
+        let mut viewer = connect(&addr, "guess");
+        let mut line = String::new();
+        assert_eq!(viewer.read_line(&mut line).unwrap(), 0);
+    }
+
+    /// Without a token, only loopback addresses are allowed
+    #[test]
+    fn needs_token() {
+        assert!(Mirror::new("0.0.0.0:0", None).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+// vim: tw=80
+//! Watches for sustained mass deletions, which can silently degrade a pool.
+//!
+//! The `[delete_pressure]` section is shorthand for built-in alert rules, so
+//! delete pressure can be acknowledged and snoozed like any other alert.
+use std::{error::Error, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{
+    alert::{Action, Comparison, Rule},
+    app::Metric,
+    config::{de_duration, de_opt_rate},
+};
+
+/// The name of the built-in alert rules
+pub const RULE_NAME: &str = "delete pressure";
+
+/// The `[delete_pressure]` section of the config file
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PressureConfig {
+    /// Delete B/s at or above which a dataset is under pressure
+    #[serde(default, deserialize_with = "de_opt_rate")]
+    pub rate:     Option<f64>,
+    /// Files unlinked but not yet deleted at or above which a dataset is
+    /// under pressure
+    #[serde(default)]
+    pub pending:  Option<u64>,
+    /// The pressure must last this long before the dataset is marked
+    #[serde(default, deserialize_with = "de_duration")]
+    pub duration: Duration,
+}
+
+impl PressureConfig {
+    /// Check for inconsistencies that serde can't catch
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.rate.is_none() && self.pending.is_none() {
+            let msg = "delete_pressure needs a rate, a pending count, or both";
+            return Err(msg.into());
+        }
+        Ok(())
+    }
+
+    /// An alert rule for each threshold.  Either is enough to highlight a
+    /// dataset.
+    pub fn rules(&self) -> Vec<Rule> {
+        let rule = |metric, threshold| Rule {
+            name: Some(RULE_NAME.to_owned()),
+            datasets: None,
+            metric,
+            op: Comparison::GreaterEqual,
+            threshold,
+            duration: self.duration,
+            actions: vec![Action::Highlight],
+            hook: None,
+        };
+        let rate = self.rate.map(|rate| rule(Metric::DS, rate));
+        let pending = self.pending.map(|n| rule(Metric::PendingD, n as f64));
+        rate.into_iter().chain(pending).collect()
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{
+        alert::Alerts,
+        app::{Counters, Element},
+    };
+
+    fn elem(name: &str, d_s: f64, pending_d: u64) -> Element {
+        Element {
+            d_s,
+            pending_d,
+            ..Element::named(name)
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let s = r#"
+            rate = "100M"
+            pending = 5000
+            duration = "1m"
+        "#;
+        let config: PressureConfig = toml::from_str(s).unwrap();
+        assert_eq!(config.rate, Some(1e8));
+        assert_eq!(config.pending, Some(5000));
+        assert_eq!(config.duration, Duration::from_secs(60));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn no_thresholds() {
+        let config: PressureConfig = toml::from_str("duration = 5").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    /// Either threshold is enough, but only once it's been sustained
+    #[test]
+    fn sustained() {
+        let config = PressureConfig {
+            rate:     Some(1e6),
+            pending:  Some(100),
+            duration: Duration::from_secs(10),
+        };
+        let mut alerts = Alerts::new(config.rules());
+        let t0 = Instant::now();
+        let elems = [
+            elem("tank/a", 2e6, 0),
+            elem("tank/b", 0.0, 500),
+            elem("tank/c", 1e3, 10),
+        ];
+        alerts.evaluate(&elems, t0, Counters::ALL);
+        assert!(!alerts.has_fired(RULE_NAME, "tank/a"));
+        alerts.evaluate(&elems, t0 + Duration::from_secs(10), Counters::ALL);
+        assert!(alerts.has_fired(RULE_NAME, "tank/a"));
+        assert!(alerts.has_fired(RULE_NAME, "tank/b"));
+        assert!(!alerts.has_fired(RULE_NAME, "tank/c"));
+        assert!(alerts.is_highlighted("tank/b"));
+    }
+
+    /// A lull resets the duration
+    #[test]
+    fn lull() {
+        let config = PressureConfig {
+            rate:     Some(1e6),
+            pending:  None,
+            duration: Duration::from_secs(10),
+        };
+        let mut alerts = Alerts::new(config.rules());
+        let t0 = Instant::now();
+        let mut tick = |d_s, secs| {
+            let now = t0 + Duration::from_secs(secs);
+            alerts.evaluate(&[elem("tank/a", d_s, 0)], now, Counters::ALL);
+            alerts.has_fired(RULE_NAME, "tank/a")
+        };
+        tick(2e6, 0);
+        tick(0.0, 5);
+        assert!(!tick(2e6, 10));
+        assert!(tick(2e6, 20));
+    }
+}
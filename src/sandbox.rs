@@ -0,0 +1,257 @@
+// vim: tw=80
+//! Deny ztop the system calls it never needs once it's running, on Linux.
+//!
+//! ztop rediscovers datasets by name with every update, opening new files
+//! under `/proc/spl/kstat/zfs` and, for its exporters, new sockets.  So
+//! rather than allow only what it needs, the seccomp filter denies what a
+//! compromised monitor could use to do more harm as root: running programs,
+//! tracing other processes, mounting, loading kernel modules, changing
+//! credentials, and the like.  Denied calls fail with `EPERM`.
+//!
+//! FreeBSD's Capsicum isn't used, because capability mode forbids looking up
+//! sysctls by name, and that's how ztop finds the kstat.zfs nodes.
+use std::{error::Error, io};
+
+use cfg_if::cfg_if;
+use libc::{c_long, c_uint, sock_filter, sock_fprog};
+
+cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        /// The architecture that the filter checks for, so that a process
+        /// can't evade it with another ABI's system call numbers
+        const AUDIT_ARCH: u32 = 0xc000_003e;
+        /// x32 system calls have this bit set, and the same numbers as the
+        /// x86_64 calls otherwise, so they're denied wholesale
+        const X32_SYSCALL_BIT: Option<u32> = Some(0x4000_0000);
+    } else if #[cfg(target_arch = "aarch64")] {
+        const AUDIT_ARCH: u32 = 0xc000_00b7;
+        const X32_SYSCALL_BIT: Option<u32> = None;
+    }
+}
+
+/// The denied system calls
+const DENIED: &[c_long] = &[
+    // Running programs.  --sandbox implies --no-exec, so ztop doesn't try.
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    // Reading or changing other processes
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    // Changing the file system namespace
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_open_by_handle_at,
+    // Changing the kernel
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    // Changing the system
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_adjtimex,
+    libc::SYS_sethostname,
+    libc::SYS_setdomainname,
+    // Changing credentials
+    libc::SYS_setuid,
+    libc::SYS_setgid,
+    libc::SYS_setreuid,
+    libc::SYS_setregid,
+    libc::SYS_setresuid,
+    libc::SYS_setresgid,
+    libc::SYS_setgroups,
+    libc::SYS_setfsuid,
+    libc::SYS_setfsgid,
+    libc::SYS_capset,
+];
+
+/// Offsets of the fields of `struct seccomp_data` that the filter loads
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+
+fn stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// Build a BPF program that kills the process for any other architecture's
+/// system calls, fails the `denied` ones and any with `x32_bit` set with
+/// `EPERM`, and allows the rest.
+fn filter(
+    arch: u32,
+    x32_bit: Option<u32>,
+    denied: &[c_long],
+) -> Vec<sock_filter> {
+    use libc::{
+        BPF_ABS,
+        BPF_JEQ,
+        BPF_JGE,
+        BPF_JMP,
+        BPF_K,
+        BPF_LD,
+        BPF_RET,
+        BPF_W,
+    };
+
+    let n = denied.len();
+    assert!(n < usize::from(u8::MAX), "too many system calls to deny");
+    let mut prog = vec![
+        stmt(BPF_LD | BPF_W | BPF_ABS, ARCH_OFFSET),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, arch, 1, 0),
+        stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(BPF_LD | BPF_W | BPF_ABS, NR_OFFSET),
+    ];
+    // Each jump to the EPERM return skips the remaining comparisons and the
+    // allow
+    if let Some(bit) = x32_bit {
+        prog.push(jump(BPF_JMP | BPF_JGE | BPF_K, bit, n as u8 + 1, 0));
+    }
+    for (i, nr) in denied.iter().enumerate() {
+        let jt = (n - i) as u8;
+        prog.push(jump(BPF_JMP | BPF_JEQ | BPF_K, *nr as u32, jt, 0));
+    }
+    prog.push(stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW));
+    let eperm = libc::SECCOMP_RET_ERRNO | libc::EPERM as c_uint;
+    prog.push(stmt(BPF_RET | BPF_K, eperm));
+    prog
+}
+
+/// Deny the dangerous system calls to every thread, for the rest of the
+/// process's life
+pub fn enter() -> Result<(), Box<dyn Error>> {
+    cfg_if! {
+        if #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))] {
+            let mut prog = filter(AUDIT_ARCH, X32_SYSCALL_BIT, DENIED);
+            let fprog = sock_fprog {
+                len:    prog.len() as u16,
+                filter: prog.as_mut_ptr(),
+            };
+            // Safety: the arguments are valid for both calls, and fprog
+            // outlives them.
+            let r = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+            if r != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            // Synchronize all threads, including the background jobs' that
+            // are already running, or fail if that isn't possible
+            let r = unsafe {
+                libc::syscall(
+                    libc::SYS_seccomp,
+                    libc::SECCOMP_SET_MODE_FILTER,
+                    libc::SECCOMP_FILTER_FLAG_TSYNC,
+                    &fprog as *const sock_fprog,
+                )
+            };
+            match r {
+                0 => Ok(()),
+                -1 => Err(io::Error::last_os_error().into()),
+                tid => Err(format!(
+                    "thread {tid} couldn't be sandboxed, because it's \
+                    already sandboxed differently"
+                )
+                .into()),
+            }
+        } else {
+            Err("--sandbox isn't supported on this architecture".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    /// Run the filter on a system call, as the kernel would
+    fn run(prog: &[sock_filter], arch: u32, nr: u32) -> u32 {
+        let mut pc = 0;
+        let mut acc = 0;
+        loop {
+            let insn = &prog[pc];
+            let code = u32::from(insn.code);
+            pc += 1;
+            if code == libc::BPF_LD | libc::BPF_W | libc::BPF_ABS {
+                acc = if insn.k == ARCH_OFFSET { arch } else { nr };
+            } else if code == libc::BPF_RET | libc::BPF_K {
+                return insn.k;
+            } else {
+                let taken = if code & 0xf0 == libc::BPF_JGE {
+                    acc >= insn.k
+                } else {
+                    acc == insn.k
+                };
+                pc += usize::from(if taken { insn.jt } else { insn.jf });
+            }
+        }
+    }
+
+    const EPERM: u32 = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+    #[test]
+    fn allowed() {
+        let prog = filter(0xc000_003e, Some(0x4000_0000), &[59, 101]);
+        assert_eq!(run(&prog, 0xc000_003e, 0), libc::SECCOMP_RET_ALLOW);
+        assert_eq!(run(&prog, 0xc000_003e, 60), libc::SECCOMP_RET_ALLOW);
+    }
+
+    #[test]
+    fn denied() {
+        let prog = filter(0xc000_003e, Some(0x4000_0000), &[59, 101]);
+        assert_eq!(run(&prog, 0xc000_003e, 59), EPERM);
+        assert_eq!(run(&prog, 0xc000_003e, 101), EPERM);
+    }
+
+    #[test]
+    fn other_arch() {
+        let prog = filter(0xc000_003e, Some(0x4000_0000), &[59, 101]);
+        let r = run(&prog, 0x4000_0003, 0);
+        assert_eq!(r, libc::SECCOMP_RET_KILL_PROCESS);
+    }
+
+    #[test]
+    fn x32() {
+        let prog = filter(0xc000_003e, Some(0x4000_0000), &[59, 101]);
+        assert_eq!(run(&prog, 0xc000_003e, 0x4000_0000), EPERM);
+        let prog = filter(0xc000_00b7, None, &[221]);
+        let r = run(&prog, 0xc000_00b7, 0x4000_0000);
+        assert_eq!(r, libc::SECCOMP_RET_ALLOW);
+    }
+
+    #[test]
+    fn defaults() {
+        let prog = filter(0, None, DENIED);
+        for nr in DENIED {
+            assert_eq!(run(&prog, 0, *nr as u32), EPERM);
+        }
+        let nr = libc::SYS_openat as u32;
+        assert_eq!(run(&prog, 0, nr), libc::SECCOMP_RET_ALLOW);
+    }
+}
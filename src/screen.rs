@@ -0,0 +1,1017 @@
+// vim: tw=80
+//! The interactive display's state: which popups are open, and how each key
+//! changes that and the App
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    time::Duration,
+};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{layout::Rect, Frame};
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    app::{self, col, App, Job, Property, COLUMNS, HOTNESS},
+    config,
+    ui::{self, Status},
+};
+
+/// Shortest update interval that can be set interactively.  Any shorter, and
+/// ztop would spend more time reading statistics than anything else.
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many lines Page Up and Page Down scroll the properties popup
+const PROPERTIES_PAGE: isize = 10;
+
+/// Longest update interval that can be set interactively
+const MAX_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Keep an interval within the bounds that can be set interactively
+pub fn clamp_interval(interval: Duration) -> Duration {
+    interval.clamp(MIN_INTERVAL, MAX_INTERVAL)
+}
+
+/// Remove the last user-perceived character, which may be several chars
+fn pop_grapheme(s: &mut String) {
+    if let Some((i, _)) = s.grapheme_indices(true).next_back() {
+        s.truncate(i);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FilterPopup {
+    pub(crate) new_regex: String,
+}
+
+impl FilterPopup {
+    pub fn on_enter(&mut self) -> Result<Regex, impl Error> {
+        Regex::new(&self.new_regex)
+    }
+
+    pub fn on_backspace(&mut self) {
+        pop_grapheme(&mut self.new_regex);
+    }
+
+    pub fn on_char(&mut self, c: char) {
+        self.new_regex.push(c);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SetPopup {
+    pub(crate) new_set: String,
+    /// Set if the last set entered doesn't exist
+    pub(crate) invalid: bool,
+}
+
+impl SetPopup {
+    /// Switch to the entered set.  If there's no such set, clear the entry
+    /// and return `false`.
+    pub fn on_enter(&mut self, app: &mut App) -> bool {
+        self.invalid = !app.select_set(self.new_set.trim());
+        self.new_set.clear();
+        !self.invalid
+    }
+
+    pub fn on_backspace(&mut self) {
+        pop_grapheme(&mut self.new_set);
+    }
+
+    pub fn on_char(&mut self, c: char) {
+        self.new_set.push(c);
+    }
+}
+
+/// A scrollable list of the selected dataset's properties
+#[derive(Debug, Default)]
+pub struct PropertiesPopup {
+    pub(crate) dataset: String,
+    /// The properties, once `zfs get` has listed them
+    pub(crate) properties: Option<Vec<Property>>,
+    /// Index of the first property shown
+    pub(crate) scroll: usize,
+    job: Job<Vec<Property>>,
+}
+
+impl PropertiesPopup {
+    /// Start listing the dataset's properties in the background
+    pub fn new(dataset: &str) -> Self {
+        let mut job = Job::default();
+        let name = dataset.to_owned();
+        job.start(move || {
+            app::dataset_properties(&name).map_err(|e| e.to_string())
+        });
+        PropertiesPopup {
+            dataset: dataset.to_owned(),
+            job,
+            ..Default::default()
+        }
+    }
+
+    /// Scroll by `delta` lines, stopping at the first and last properties
+    pub fn scroll(&mut self, delta: isize) {
+        let len = self.properties.as_ref().map_or(0, Vec::len);
+        let last = len.saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(delta).min(last);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IntervalPopup {
+    pub(crate) new_interval: String,
+    /// Why the last interval entered was invalid
+    pub(crate) error:        Option<String>,
+}
+
+impl IntervalPopup {
+    /// Parse the entered interval.  If it's invalid, clear it and return
+    /// `None`.
+    pub fn on_enter(&mut self) -> Option<Duration> {
+        let r = config::interval_from_str(self.new_interval.trim());
+        self.new_interval.clear();
+        match r {
+            Ok(interval) => {
+                self.error = None;
+                Some(interval)
+            }
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    pub fn on_backspace(&mut self) {
+        pop_grapheme(&mut self.new_interval);
+    }
+
+    pub fn on_char(&mut self, c: char) {
+        self.new_interval.push(c);
+    }
+}
+
+/// Asks how long to snooze the selected dataset's alerts
+#[derive(Clone, Debug, Default)]
+pub struct SnoozePopup {
+    pub(crate) new_duration: String,
+    /// Why the last duration entered was invalid
+    pub(crate) error:        Option<String>,
+}
+
+impl SnoozePopup {
+    /// Parse the entered duration.  If it's invalid, clear it and return
+    /// `None`.
+    pub fn on_enter(&mut self) -> Option<Duration> {
+        let r = match config::duration_from_str(self.new_duration.trim()) {
+            Ok(d) if d.is_zero() => Err("the duration must be positive".into()),
+            r => r,
+        };
+        self.new_duration.clear();
+        match r {
+            Ok(duration) => {
+                self.error = None;
+                Some(duration)
+            }
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    pub fn on_backspace(&mut self) {
+        pop_grapheme(&mut self.new_duration);
+    }
+
+    pub fn on_char(&mut self, c: char) {
+        self.new_duration.push(c);
+    }
+}
+/// Everything about the display that isn't part of the App
+#[derive(Debug, Default)]
+pub struct Screen {
+    /// Column headers, by their English names
+    headers:           HashMap<String, String>,
+    /// Quit on Esc, if there's nothing else for it to dismiss
+    esc_quits:         bool,
+    /// Use no colors, only a few text attributes
+    plain:             bool,
+    tick_rate:         Duration,
+    editting_filter:   bool,
+    editting_interval: bool,
+    editting_snooze:   bool,
+    choosing_set:      bool,
+    showing_warnings:  bool,
+    filter_popup:      FilterPopup,
+    interval_popup:    IntervalPopup,
+    snooze_popup:      SnoozePopup,
+    set_popup:         SetPopup,
+    properties:        Option<PropertiesPopup>,
+    /// The last command line shown with x, to print on exit
+    command_line:      Option<String>,
+    showing_command:   bool,
+    /// How much wider or narrower than the default to draw each column
+    widths:            [i16; COLUMNS.len()],
+    /// Leave the history graph blank, for an image to be drawn over it
+    raster:            bool,
+    /// Where the history graph's image goes, as of the last draw, unless a
+    /// popup covers the display
+    graph_area:        Cell<Option<Rect>>,
+}
+
+impl Screen {
+    pub fn new(
+        headers: HashMap<String, String>,
+        esc_quits: bool,
+        plain: bool,
+        tick_rate: Duration,
+    ) -> Self {
+        Screen {
+            headers,
+            esc_quits,
+            plain,
+            tick_rate,
+            ..Default::default()
+        }
+    }
+
+    /// Apply a reloaded configuration file's settings
+    pub fn reload_config(
+        &mut self,
+        headers: HashMap<String, String>,
+        esc_quits: bool,
+    ) {
+        self.headers = headers;
+        self.esc_quits = esc_quits;
+    }
+
+    /// Collect the result of `zfs get`, if the properties popup was waiting
+    /// for it.  Return true if it was.
+    pub fn poll_jobs(&mut self, app: &mut App) -> bool {
+        let Some(popup) = self.properties.as_mut() else {
+            return false;
+        };
+        match popup.job.poll() {
+            None => false,
+            Some(Ok(properties)) => {
+                popup.properties = Some(properties);
+                true
+            }
+            Some(Err(e)) => {
+                self.properties = None;
+                app.set_error(e);
+                true
+            }
+        }
+    }
+
+    /// Is the properties popup waiting for `zfs get`?
+    pub fn has_jobs(&self) -> bool {
+        self.properties.as_ref().is_some_and(|p| p.job.is_running())
+    }
+
+    pub fn tick_rate(&self) -> Duration {
+        self.tick_rate
+    }
+
+    /// Leave the history graph blank, for an image to be drawn over it
+    pub fn set_raster(&mut self, raster: bool) {
+        self.raster = raster;
+    }
+
+    /// Where to draw the history graph's image, if anywhere
+    pub fn graph_area(&self) -> Option<Rect> {
+        self.graph_area.get()
+    }
+
+    /// The last command line shown with x, if any
+    pub fn take_command_line(&mut self) -> Option<String> {
+        self.command_line.take()
+    }
+
+    /// Draw the table, and whichever popup is open on top of it
+    pub fn draw(&self, f: &mut Frame, app: &mut App, warnings: u64) {
+        let status = Status {
+            interval: self.tick_rate,
+            warnings,
+            widths: self.widths,
+        };
+        let graph_area =
+            ui::draw(f, app, &self.headers, self.plain, self.raster, status);
+        // An image would hide the popup
+        let popup = if self.editting_filter {
+            ui::draw_filter(f, &self.filter_popup, self.plain);
+            true
+        } else if self.editting_interval {
+            ui::draw_interval(f, &self.interval_popup, self.plain);
+            true
+        } else if self.editting_snooze {
+            ui::draw_snooze(f, &self.snooze_popup, self.plain);
+            true
+        } else if self.choosing_set {
+            ui::draw_set(f, &self.set_popup, app, self.plain);
+            true
+        } else if self.showing_warnings {
+            ui::draw_warnings(f, self.plain);
+            true
+        } else if let Some(popup) = self.properties.as_ref() {
+            let breakdown = app.breakdown(&popup.dataset);
+            ui::draw_properties(f, popup, &breakdown, self.plain);
+            true
+        } else if let Some(cmd) = self
+            .command_line
+            .as_deref()
+            .filter(|_| self.showing_command)
+        {
+            ui::draw_command_line(f, cmd, self.plain);
+            true
+        } else {
+            false
+        };
+        self.graph_area.set(graph_area.filter(|_| !popup));
+    }
+
+    /// Apply column widths saved by a previous session
+    pub fn set_widths(&mut self, saved: &BTreeMap<String, i16>) {
+        for (i, (name, _)) in COLUMNS.iter().enumerate() {
+            if let Some(&adj) = saved.get(*name) {
+                self.widths[i] = adj.clamp(-6, 40);
+            }
+        }
+    }
+
+    /// The adjusted columns' widths, to save for the next session
+    pub fn widths(&self) -> BTreeMap<String, i16> {
+        COLUMNS
+            .iter()
+            .zip(self.widths)
+            .filter(|(_, adj)| *adj != 0)
+            .map(|((name, _), adj)| ((*name).to_owned(), adj))
+            .collect()
+    }
+
+    /// Widen or narrow the sorted column, or the Dataset column if none is
+    /// sorted
+    fn adjust_width(&mut self, app: &App, delta: i16) {
+        let i = app.sort_idx().filter(|&i| i != HOTNESS);
+        let i = i.unwrap_or(col::DATASET);
+        self.widths[i] = (self.widths[i] + delta).clamp(-6, 40);
+    }
+
+    /// Handle a key press.  Returns `true` if it changed the update interval.
+    pub fn on_key(
+        &mut self,
+        app: &mut App,
+        kev: KeyEvent,
+    ) -> Result<bool, Box<dyn Error>> {
+        let tick_rate = self.tick_rate;
+        match kev.code {
+            KeyCode::Char('c')
+                if kev.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                app.on_q();
+            }
+            KeyCode::Esc if self.editting_filter => {
+                self.editting_filter = false;
+            }
+            KeyCode::Enter if self.editting_filter => {
+                let filter = self.filter_popup.on_enter()?;
+                app.set_filter(filter);
+                self.editting_filter = false;
+            }
+            KeyCode::Backspace if self.editting_filter => {
+                self.filter_popup.on_backspace();
+            }
+            KeyCode::Char(c) if self.editting_filter => {
+                self.filter_popup.on_char(c);
+            }
+            KeyCode::Esc if self.editting_interval => {
+                self.editting_interval = false;
+            }
+            KeyCode::Enter if self.editting_interval => {
+                if let Some(interval) = self.interval_popup.on_enter() {
+                    self.tick_rate = clamp_interval(interval);
+                    self.editting_interval = false;
+                }
+            }
+            KeyCode::Backspace if self.editting_interval => {
+                self.interval_popup.on_backspace();
+            }
+            KeyCode::Char(c) if self.editting_interval => {
+                self.interval_popup.on_char(c);
+            }
+            KeyCode::Esc if self.editting_snooze => {
+                self.editting_snooze = false;
+            }
+            KeyCode::Enter if self.editting_snooze => {
+                if let Some(duration) = self.snooze_popup.on_enter() {
+                    app.snooze_alerts(duration);
+                    self.editting_snooze = false;
+                }
+            }
+            KeyCode::Backspace if self.editting_snooze => {
+                self.snooze_popup.on_backspace();
+            }
+            KeyCode::Char(c) if self.editting_snooze => {
+                self.snooze_popup.on_char(c);
+            }
+            KeyCode::Esc if self.choosing_set => {
+                self.choosing_set = false;
+            }
+            KeyCode::Enter if self.choosing_set => {
+                self.choosing_set = !self.set_popup.on_enter(app);
+            }
+            KeyCode::Backspace if self.choosing_set => {
+                self.set_popup.on_backspace();
+            }
+            KeyCode::Char(c) if self.choosing_set => {
+                self.set_popup.on_char(c);
+            }
+            KeyCode::Esc | KeyCode::Char('w') if self.showing_warnings => {
+                self.showing_warnings = false;
+            }
+            KeyCode::Char('w') => {
+                self.showing_warnings = true;
+            }
+            KeyCode::Esc | KeyCode::Enter if self.properties.is_some() => {
+                self.properties = None;
+            }
+            KeyCode::Up if self.properties.is_some() => {
+                self.properties.as_mut().unwrap().scroll(-1);
+            }
+            KeyCode::Down if self.properties.is_some() => {
+                self.properties.as_mut().unwrap().scroll(1);
+            }
+            KeyCode::PageUp if self.properties.is_some() => {
+                self.properties.as_mut().unwrap().scroll(-PROPERTIES_PAGE);
+            }
+            KeyCode::PageDown if self.properties.is_some() => {
+                self.properties.as_mut().unwrap().scroll(PROPERTIES_PAGE);
+            }
+            KeyCode::Enter => {
+                if let Some(dataset) = app.selected() {
+                    self.properties = Some(PropertiesPopup::new(dataset));
+                }
+            }
+            KeyCode::Up => {
+                app.on_up();
+            }
+            KeyCode::Down => {
+                app.on_down();
+            }
+            KeyCode::Left => {
+                app.on_left();
+            }
+            KeyCode::Right => {
+                app.on_right();
+            }
+            KeyCode::Esc | KeyCode::Char('x') if self.showing_command => {
+                self.showing_command = false;
+            }
+            KeyCode::Char('x') => {
+                self.command_line = Some(app.command_line(tick_rate));
+                self.showing_command = true;
+            }
+            KeyCode::Char('y') => {
+                app.toggle_types();
+            }
+            KeyCode::Char('z') => {
+                self.snooze_popup = SnoozePopup::default();
+                self.editting_snooze = true;
+            }
+            KeyCode::Char('T') => {
+                app.cycle_type_filter();
+            }
+            KeyCode::Char('e') => {
+                app.toggle_encryption();
+            }
+            KeyCode::Char('E') => {
+                app.cycle_encryption_filter();
+            }
+            KeyCode::Char('t') => {
+                app.on_t();
+            }
+            KeyCode::Char('u') => {
+                app.toggle_space();
+            }
+            KeyCode::Char('V') => {
+                app.toggle_vdevs(tick_rate);
+            }
+            KeyCode::Char('v') => {
+                self.choosing_set = true;
+            }
+            KeyCode::Esc if app.error().is_some() => {
+                app.dismiss_error();
+            }
+            KeyCode::Esc if app.selected().is_some() => {
+                app.clear_selection();
+            }
+            KeyCode::Esc if self.esc_quits => {
+                app.on_q();
+            }
+            KeyCode::Char('+') => {
+                app.on_plus();
+            }
+            KeyCode::Char('-') => {
+                app.on_minus();
+            }
+            KeyCode::Char('<') => {
+                self.tick_rate = clamp_interval(tick_rate / 2);
+            }
+            KeyCode::Char('>') => {
+                self.tick_rate = clamp_interval(tick_rate * 2);
+            }
+            KeyCode::Char('(') => {
+                self.adjust_width(app, -1);
+            }
+            KeyCode::Char(')') => {
+                self.adjust_width(app, 1);
+            }
+            KeyCode::Char('@') => {
+                app.toggle_snapshots();
+            }
+            KeyCode::Char('[') => {
+                app.on_lbracket();
+            }
+            KeyCode::Char(']') => {
+                app.on_rbracket();
+            }
+            KeyCode::Char('a') => {
+                app.on_a();
+            }
+            KeyCode::Char('B') => {
+                app.toggle_dbuf();
+            }
+            KeyCode::Char('b') => {
+                app.on_b();
+            }
+            KeyCode::Char('C') => {
+                app.toggle_capacity();
+            }
+            KeyCode::Char('c') => {
+                app.on_c();
+            }
+            KeyCode::Char('D') => {
+                app.on_d(false);
+            }
+            KeyCode::Char('U') => {
+                app.toggle_dedup();
+            }
+            KeyCode::Char('d') => {
+                app.on_d(true);
+            }
+            KeyCode::Char('F') => {
+                app.clear_filter();
+            }
+            KeyCode::Char('f') => {
+                self.editting_filter = true;
+            }
+            KeyCode::Char('g') => {
+                app.on_g();
+            }
+            KeyCode::Char('G') => {
+                app.toggle_graph();
+            }
+            KeyCode::Char('h') => {
+                app.toggle_latency(tick_rate);
+            }
+            KeyCode::Char('i') => {
+                self.interval_popup = IntervalPopup::default();
+                self.editting_interval = true;
+            }
+            KeyCode::Char('k') => {
+                app.ack_alerts();
+            }
+            KeyCode::Char('l') => {
+                app.on_l();
+            }
+            KeyCode::Char('M') => {
+                app.cycle_statistic();
+            }
+            KeyCode::Char('m') => {
+                app.on_m();
+            }
+            KeyCode::Char('o') => {
+                app.on_o();
+            }
+            KeyCode::Char('p') => {
+                app.on_p();
+            }
+            KeyCode::Char('Q') => {
+                app.toggle_queues(tick_rate);
+            }
+            KeyCode::Char('q') => {
+                app.on_q();
+            }
+            KeyCode::Char('r') => {
+                app.on_r();
+            }
+            KeyCode::Char('s') => {
+                app.on_s();
+            }
+            _ => {
+                // Ignore unknown keys
+            }
+        }
+        Ok(self.tick_rate != tick_rate)
+    }
+}
+
+#[cfg(test)]
+mod t {
+    mod pop_grapheme {
+        use super::super::*;
+
+        #[test]
+        fn ascii() {
+            let mut s = "tank".to_owned();
+            pop_grapheme(&mut s);
+            assert_eq!(s, "tan");
+        }
+
+        /// A combining accent should be removed with its base character
+        #[test]
+        fn combining() {
+            let mut s = "cafe\u{301}".to_owned();
+            pop_grapheme(&mut s);
+            assert_eq!(s, "caf");
+        }
+
+        #[test]
+        fn empty() {
+            let mut s = String::new();
+            pop_grapheme(&mut s);
+            assert_eq!(s, "");
+        }
+    }
+
+    mod width {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+        use super::super::*;
+
+        fn press(screen: &mut Screen, app: &mut App, keys: &str) {
+            for c in keys.chars() {
+                let kev = KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+                screen.on_key(app, kev).unwrap();
+            }
+        }
+
+        #[test]
+        fn dataset_by_default() {
+            let mut app = App::default();
+            let mut screen = Screen::default();
+            press(&mut screen, &mut app, "))");
+            assert_eq!(screen.widths[col::DATASET], 2);
+            assert_eq!(screen.widths[0], 0);
+        }
+
+        #[test]
+        fn sorted_column() {
+            let mut app = App::default();
+            let mut screen = Screen::default();
+            press(&mut screen, &mut app, "+(");
+            assert_eq!(screen.widths[0], -1);
+            assert_eq!(screen.widths[col::DATASET], 0);
+        }
+
+        #[test]
+        fn clamped() {
+            let mut app = App::default();
+            let mut screen = Screen::default();
+            press(&mut screen, &mut app, "((((((((((");
+            assert_eq!(screen.widths[col::DATASET], -6);
+        }
+
+        /// Widths are saved by column name
+        #[test]
+        fn saved() {
+            let mut app = App::default();
+            let mut screen = Screen::default();
+            press(&mut screen, &mut app, "))");
+            let saved = screen.widths();
+            assert_eq!(saved.len(), 1);
+            assert_eq!(saved["Dataset"], 2);
+            let mut screen = Screen::default();
+            screen.set_widths(&saved);
+            assert_eq!(screen.widths[col::DATASET], 2);
+        }
+    }
+
+    /// Compare the whole display against golden snapshots in
+    /// `src/snapshots`.  After an intended change to the display, rerun
+    /// with `ZTOP_BLESS=1` to rewrite them, and review the diff.
+    mod snapshot {
+        use std::{env, fs, path::PathBuf, thread};
+
+        use ratatui::{
+            backend::TestBackend,
+            buffer::Buffer,
+            style::{Color, Modifier},
+            Terminal,
+        };
+
+        use super::super::*;
+
+        fn app() -> App {
+            App::with_rates(&[
+                ("tank", [10, 40960, 5, 20480, 0, 0]),
+                ("tank/home", [120, 1048576, 30, 262144, 2, 8192]),
+                ("tank/vm", [3, 12288, 250, 4194304, 0, 0]),
+            ])
+        }
+
+        fn screen() -> Screen {
+            Screen::new(HashMap::new(), false, false, Duration::from_secs(1))
+        }
+
+        /// Press each key in turn, as the main loop would.  `↑`, `↓`, `←`,
+        /// and `→` stand for the arrow keys, `\n` for Enter, and `\x1b` for
+        /// Esc.
+        fn press(screen: &mut Screen, app: &mut App, keys: &str) {
+            for c in keys.chars() {
+                let code = match c {
+                    '↑' => KeyCode::Up,
+                    '↓' => KeyCode::Down,
+                    '←' => KeyCode::Left,
+                    '→' => KeyCode::Right,
+                    '\n' => KeyCode::Enter,
+                    '\x1b' => KeyCode::Esc,
+                    '\x08' => KeyCode::Backspace,
+                    c => KeyCode::Char(c),
+                };
+                let kev = KeyEvent::new(code, KeyModifiers::NONE);
+                screen.on_key(app, kev).unwrap();
+            }
+        }
+
+        /// Render the display as text.  Styled cells are marked in a second
+        /// grid below the first, with a letter for each distinct style, and a
+        /// legend says which style each letter stands for.
+        fn render(
+            screen: &Screen,
+            app: &mut App,
+            width: u16,
+            height: u16,
+        ) -> String {
+            let backend = TestBackend::new(width, height);
+            let mut terminal = Terminal::new(backend).unwrap();
+            let frame = terminal.draw(|f| screen.draw(f, app, 0)).unwrap();
+            dump(frame.buffer)
+        }
+
+        /// Describe a style like "fg=LightYellow BOLD"
+        fn describe(fg: Color, bg: Color, modifier: Modifier) -> String {
+            let mut words = Vec::new();
+            if fg != Color::Reset {
+                words.push(format!("fg={fg}"));
+            }
+            if bg != Color::Reset {
+                words.push(format!("bg={bg}"));
+            }
+            words.extend(modifier.iter_names().map(|(name, _)| name.into()));
+            words.join(" ")
+        }
+
+        fn dump(buf: &Buffer) -> String {
+            let mut text = String::new();
+            let mut marks = String::new();
+            let mut styles = Vec::new();
+            for y in 0..buf.area.height {
+                let mut line = String::new();
+                let mut mark_line = String::new();
+                for x in 0..buf.area.width {
+                    let cell = buf.get(x, y);
+                    line.push_str(cell.symbol());
+                    let style = (cell.fg, cell.bg, cell.modifier);
+                    if style == (Color::Reset, Color::Reset, Modifier::empty())
+                    {
+                        mark_line.push(' ');
+                        continue;
+                    }
+                    let i = styles.iter().position(|s| *s == style);
+                    let i = i.unwrap_or_else(|| {
+                        styles.push(style);
+                        styles.len() - 1
+                    });
+                    mark_line.push(char::from(b'a' + i as u8));
+                }
+                text.push_str(line.trim_end());
+                text.push('\n');
+                marks.push_str(mark_line.trim_end());
+                marks.push('\n');
+            }
+            if !styles.is_empty() {
+                text.push_str("--- styles\n");
+                text.push_str(&marks);
+                for (i, &(fg, bg, modifier)) in styles.iter().enumerate() {
+                    let letter = char::from(b'a' + i as u8);
+                    let style = describe(fg, bg, modifier);
+                    text.push_str(&format!("{letter}: {style}\n"));
+                }
+            }
+            text
+        }
+
+        fn assert_snapshot(name: &str, actual: &str) {
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("src/snapshots")
+                .join(format!("{name}.txt"));
+            if env::var_os("ZTOP_BLESS").is_some() {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, actual).unwrap();
+                return;
+            }
+            let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+                panic!(
+                    "Cannot read {}: {e}.  Rerun with ZTOP_BLESS=1 to create \
+                     it.",
+                    path.display()
+                )
+            });
+            assert!(
+                expected == actual,
+                "The display differs from {}\n--- expected\n{expected}--- \
+                 actual\n{actual}",
+                path.display()
+            );
+        }
+
+        #[test]
+        fn table() {
+            let mut app = app();
+            let out = render(&screen(), &mut app, 80, 5);
+            assert_snapshot("table", &out);
+        }
+
+        /// Too narrow for the optional columns
+        #[test]
+        fn narrow() {
+            let mut app = app();
+            let out = render(&screen(), &mut app, 64, 5);
+            assert_snapshot("narrow", &out);
+        }
+
+        /// So narrow that each dataset takes two lines
+        #[test]
+        fn compact() {
+            let mut app = app();
+            let out = render(&screen(), &mut app, 40, 9);
+            assert_snapshot("compact", &out);
+        }
+
+        /// With the totals shown, the columns don't all fit, so scroll them
+        /// as far as they go.  The Dataset column stays put.
+        #[test]
+        fn panned() {
+            let mut app = app();
+            let mut screen = screen();
+            press(&mut screen, &mut app, "t→→→→");
+            let out = render(&screen, &mut app, 80, 5);
+            assert_snapshot("panned", &out);
+            // Any extra presses were ignored
+            press(&mut screen, &mut app, "←");
+            let out = render(&screen, &mut app, 80, 5);
+            let header = out.lines().nth(1).unwrap();
+            assert!(header.starts_with("   r/s"), "{header}");
+        }
+
+        /// Sort by write bandwidth, descending, and select the top row
+        #[test]
+        fn sorted() {
+            let mut app = app();
+            let mut screen = screen();
+            press(&mut screen, &mut app, "++++r↓");
+            let out = render(&screen, &mut app, 80, 5);
+            assert_snapshot("sorted", &out);
+        }
+
+        /// The graph goes below the table, and stays empty until the next
+        /// update
+        #[test]
+        fn graph() {
+            let mut app = app();
+            let mut screen = screen();
+            press(&mut screen, &mut app, "G");
+            let out = render(&screen, &mut app, 80, 14);
+            assert_snapshot("graph", &out);
+            assert_eq!(screen.graph_area(), None);
+        }
+
+        /// An image is drawn over the graph's blank cells, unless a popup
+        /// covers them
+        #[test]
+        fn graph_raster() {
+            let mut app = app();
+            let mut screen = screen();
+            screen.set_raster(true);
+            press(&mut screen, &mut app, "G");
+            render(&screen, &mut app, 80, 14);
+            let area = screen.graph_area().unwrap();
+            assert_eq!((area.width, area.height), (78, 6));
+            press(&mut screen, &mut app, "f");
+            render(&screen, &mut app, 80, 14);
+            assert_eq!(screen.graph_area(), None);
+        }
+
+        #[test]
+        fn filter_popup() {
+            let mut app = app();
+            let mut screen = screen();
+            press(&mut screen, &mut app, "fhomx\x08e");
+            let out = render(&screen, &mut app, 80, 7);
+            assert_snapshot("filter_popup", &out);
+            press(&mut screen, &mut app, "\n");
+            let out = render(&screen, &mut app, 80, 5);
+            assert_snapshot("filtered", &out);
+        }
+
+        /// An invalid interval leaves the popup open, asking again
+        #[test]
+        fn interval_popup() {
+            let mut app = app();
+            let mut screen = screen();
+            press(&mut screen, &mut app, "i-1\n");
+            let out = render(&screen, &mut app, 80, 7);
+            assert_snapshot("interval_popup", &out);
+        }
+
+        /// Nonsensical intervals are rejected, rather than panicking while
+        /// the terminal is in raw mode
+        #[test]
+        fn interval_invalid() {
+            let mut app = app();
+            let mut screen = screen();
+            for s in ["-1", "0", "nan", "inf", "1e30", "bogus"] {
+                press(&mut screen, &mut app, &format!("i{s}\n\x1b"));
+                assert!(screen.interval_popup.error.is_some(), "{s}");
+                assert_eq!(screen.tick_rate(), Duration::from_secs(1));
+            }
+        }
+
+        #[test]
+        fn interval_keys() {
+            let mut app = app();
+            let mut screen = screen();
+            let kev = KeyEvent::new(KeyCode::Char('<'), KeyModifiers::NONE);
+            assert!(screen.on_key(&mut app, kev).unwrap());
+            assert_eq!(screen.tick_rate(), Duration::from_millis(500));
+            press(&mut screen, &mut app, "i1h\n");
+            assert_eq!(screen.tick_rate(), MAX_INTERVAL);
+            let kev = KeyEvent::new(KeyCode::Char('+'), KeyModifiers::NONE);
+            assert!(!screen.on_key(&mut app, kev).unwrap());
+        }
+
+        /// `zfs get` runs in the background, while the popup says so
+        #[test]
+        fn properties_loading() {
+            let mut app = app();
+            let mut screen = screen();
+            press(&mut screen, &mut app, "↓\n");
+            let out = render(&screen, &mut app, 80, 7);
+            assert_snapshot("properties_loading", &out);
+            while screen.has_jobs() {
+                screen.poll_jobs(&mut app);
+                thread::sleep(Duration::from_millis(10));
+            }
+            // Whether or not this system has a zfs command, it's finished
+            let popup = screen.properties.as_ref();
+            assert!(
+                popup.is_some_and(|p| p.properties.is_some())
+                    || app.error().is_some()
+            );
+        }
+
+        /// A snooze must be for a positive duration
+        #[test]
+        fn snooze_popup() {
+            let mut app = app();
+            let mut screen = screen();
+            press(&mut screen, &mut app, "z0\n");
+            assert!(screen.editting_snooze);
+            assert!(screen.snooze_popup.error.is_some());
+            press(&mut screen, &mut app, "5m\n");
+            assert!(!screen.editting_snooze);
+            assert_eq!(app.notice().as_deref(), Some("No alerts to snooze"));
+            press(&mut screen, &mut app, "k");
+            assert_eq!(
+                app.notice().as_deref(),
+                Some("No alerts to acknowledge")
+            );
+        }
+
+        #[test]
+        fn command_line() {
+            let mut app = app();
+            let mut screen = screen();
+            press(&mut screen, &mut app, "ax");
+            let out = render(&screen, &mut app, 80, 7);
+            assert_snapshot("command_line", &out);
+            press(&mut screen, &mut app, "x");
+            assert_eq!(screen.take_command_line().unwrap(), "ztop -a");
+        }
+    }
+}
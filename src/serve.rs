@@ -0,0 +1,495 @@
+// vim: tw=80
+//! A read-only HTTP API with the latest statistics, for `ztop-agent --serve`.
+//!
+//! `GET /datasets` returns every dataset's statistics from the most recent
+//! update as a JSON array, in the same format as capture files.
+//! `GET /datasets/<name>` returns just one dataset's, and `GET /pools` each
+//! pool's totals.  `GET /history?since=<time>` returns the updates since a
+//! time in seconds since the epoch, as capture files' lines do.
+//!
+//! A WebSocket connection to `/stream` is sent each update as it happens, in
+//! the same format as `/history`'s.  A client that falls behind misses
+//! updates, rather than delaying the others.
+//!
+//! `GET /metrics` returns the most recent update in the OpenMetrics text
+//! format, for Prometheus, or for Performance Co-Pilot through
+//! pmdaopenmetrics.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    error::Error,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    app::Element,
+    capture::{Frame, Row},
+    sink::Sink,
+};
+
+mod openmetrics;
+mod websocket;
+
+pub use openmetrics::{Label, OpenMetricsConfig};
+// The graph sends Kitty images in base64 too
+#[cfg(feature = "tui")]
+pub(crate) use websocket::base64;
+
+/// Give up on clients that don't send a request or read the response promptly
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many updates `/history` remembers
+const HISTORY: usize = 600;
+
+/// A page for browsers, showing the table as it streams in
+#[cfg(feature = "web")]
+const INDEX: &[u8] = include_bytes!("serve/index.html");
+
+/// The recent updates, newest last, and the WebSocket clients waiting for
+/// the next
+#[derive(Default)]
+struct Latest {
+    frames:      VecDeque<Frame>,
+    /// Whether each dataset's statistics already include its children's
+    children:    bool,
+    subscribers: Vec<SyncSender<Arc<str>>>,
+    /// How to name and label `/metrics`
+    openmetrics: OpenMetricsConfig,
+}
+
+/// Shares the recent updates with the listener thread.  Clones share them
+/// too, so the sink survives rebuilding the others when the configuration is
+/// reloaded.
+#[derive(Clone)]
+pub struct Serve {
+    latest: Arc<Mutex<Latest>>,
+    addr:   SocketAddr,
+}
+
+impl Serve {
+    /// Listen on `addr`, which may be a bare port to listen only on localhost.
+    /// `children` says whether datasets' statistics will include their
+    /// children's, so that pools' totals don't count them twice.
+    pub fn new(addr: &str, children: bool) -> Result<Self, Box<dyn Error>> {
+        let addr = match addr.parse::<u16>() {
+            Ok(port) => format!("localhost:{port}"),
+            Err(_) => addr.to_owned(),
+        };
+        let listener = TcpListener::bind(&addr)
+            .map_err(|e| format!("Cannot listen on {addr}: {e}"))?;
+        let addr = listener.local_addr()?;
+        let latest = Arc::new(Mutex::new(Latest {
+            children,
+            ..Default::default()
+        }));
+        let latest2 = latest.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A client that misbehaves only hurts itself
+                let _ = respond(stream, &latest2);
+            }
+        });
+        Ok(Serve { latest, addr })
+    }
+
+    /// The address actually listened on, which tells the port if 0 was given
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Name and label `/metrics` from now on as the configuration says
+    pub fn set_openmetrics(&self, config: OpenMetricsConfig) {
+        self.latest.lock().unwrap().openmetrics = config;
+    }
+}
+
+impl Sink for Serve {
+    fn send(&mut self, elems: &[Element]) -> io::Result<()> {
+        let mut latest = self.latest.lock().unwrap();
+        if latest.frames.len() == HISTORY {
+            latest.frames.pop_front();
+        }
+        let rows = elems.iter().map(Row::from).collect();
+        let frame = Frame::now(Vec::new(), rows);
+        let msg = Arc::<str>::from(serde_json::to_string(&frame).unwrap());
+        // A full channel means that client is still busy with an earlier
+        // update, so it skips this one
+        latest.subscribers.retain(|tx| {
+            !matches!(
+                tx.try_send(msg.clone()),
+                Err(TrySendError::Disconnected(_))
+            )
+        });
+        latest.frames.push_back(frame);
+        Ok(())
+    }
+}
+
+/// Each pool's totals, from the datasets in one update
+fn pools(rows: &[Row], children: bool) -> Vec<Row> {
+    let mut pools = BTreeMap::<&str, Row>::new();
+    for row in rows {
+        let pool = row.name.split('/').next().unwrap_or_default();
+        // The pool's own dataset already includes all the others
+        if children && row.name != pool {
+            continue;
+        }
+        let total = pools.entry(pool).or_insert_with(|| Row {
+            name: pool.to_owned(),
+            ..Default::default()
+        });
+        total.ops_r += row.ops_r;
+        total.r_s += row.r_s;
+        total.ops_w += row.ops_w;
+        total.w_s += row.w_s;
+        total.ops_d += row.ops_d;
+        total.d_s += row.d_s;
+        total.pending_d += row.pending_d;
+    }
+    pools.into_values().collect()
+}
+
+/// The updates newer than the `since` query parameter, or all of them
+fn history<'a>(
+    query: &str,
+    frames: &'a VecDeque<Frame>,
+) -> Option<Vec<&'a Frame>> {
+    let mut since = f64::NEG_INFINITY;
+    for param in query.split('&').filter(|p| !p.is_empty()) {
+        match param.split_once('=') {
+            Some(("since", value)) => since = value.parse().ok()?,
+            _ => return None,
+        }
+    }
+    Some(frames.iter().filter(|f| f.time > since).collect())
+}
+
+/// Choose the status and body for one request line
+fn route(line: &str, latest: &Latest) -> (&'static str, String) {
+    let mut words = line.split_whitespace();
+    let (Some(method), Some(target)) = (words.next(), words.next()) else {
+        return ("400 Bad Request", String::new());
+    };
+    if method != "GET" {
+        return ("405 Method Not Allowed", String::new());
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let rows = latest.frames.back().map_or(&[][..], |f| &f.datasets);
+    if path == "/datasets" {
+        ("200 OK", serde_json::to_string(rows).unwrap())
+    } else if let Some(name) = path.strip_prefix("/datasets/") {
+        match rows.iter().find(|r| r.name == name) {
+            Some(row) => ("200 OK", serde_json::to_string(row).unwrap()),
+            None => ("404 Not Found", String::new()),
+        }
+    } else if path == "/pools" {
+        let pools = pools(rows, latest.children);
+        ("200 OK", serde_json::to_string(&pools).unwrap())
+    } else if path == "/stream" {
+        // Only reached without a WebSocket handshake
+        ("426 Upgrade Required", String::new())
+    } else if path == "/history" {
+        match history(query, &latest.frames) {
+            Some(frames) => ("200 OK", serde_json::to_string(&frames).unwrap()),
+            None => ("400 Bad Request", String::new()),
+        }
+    } else {
+        ("404 Not Found", String::new())
+    }
+}
+
+/// Is this request line a GET of `path`?
+fn is_get(line: &str, path: &str) -> bool {
+    let mut words = line.split_whitespace();
+    words.next() == Some("GET") && words.next() == Some(path)
+}
+
+/// Accept a WebSocket client, and send it each update from now on, starting
+/// with the latest
+fn subscribe(
+    mut stream: TcpStream,
+    key: &str,
+    latest: &Mutex<Latest>,
+) -> io::Result<()> {
+    // Room for one update while the previous is being sent
+    let (tx, rx) = mpsc::sync_channel::<Arc<str>>(1);
+    {
+        let mut latest = latest.lock().unwrap();
+        if let Some(frame) = latest.frames.back() {
+            let msg = serde_json::to_string(frame).unwrap();
+            tx.send(msg.into()).unwrap();
+        }
+        latest.subscribers.push(tx);
+    }
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: \
+         websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket::accept(key)
+    )?;
+    // The write timeout disconnects a client that stops reading entirely
+    thread::spawn(move || {
+        for msg in rx {
+            if websocket::write_text(&mut stream, &msg).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answer one request, then close the connection
+fn respond(stream: TcpStream, latest: &Mutex<Latest>) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    // Skip the headers, except for a WebSocket handshake's key
+    let mut key = None;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Sec-WebSocket-Key") {
+                key = Some(value.trim().to_owned());
+            }
+        }
+        header.clear();
+    }
+    let mut stream = reader.into_inner();
+    if let Some(key) = key.filter(|_| is_get(&line, "/stream")) {
+        return subscribe(stream, &key, latest);
+    }
+    #[cfg(feature = "web")]
+    if is_get(&line, "/") {
+        return reply(&mut stream, "200 OK", "text/html; charset=utf-8", INDEX);
+    }
+    if is_get(&line, "/metrics") {
+        let body = {
+            let latest = latest.lock().unwrap();
+            let rows = latest.frames.back().map_or(&[][..], |f| &f.datasets);
+            openmetrics::format(rows, &latest.openmetrics)
+        };
+        let content_type = openmetrics::CONTENT_TYPE;
+        return reply(&mut stream, "200 OK", content_type, body.as_bytes());
+    }
+    let (status, body) = route(&line, &latest.lock().unwrap());
+    reply(&mut stream, status, "application/json", body.as_bytes())
+}
+
+/// Send a complete response
+fn reply(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: \
+         {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)
+}
+
+#[cfg(test)]
+mod t {
+    use std::io::Read;
+
+    use super::*;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn datasets() {
+        let mut serve = Serve::new("127.0.0.1:0", false).unwrap();
+        let addr = serve.local_addr();
+        let elem = Element::writing("tank/foo", 4.0);
+        serve.send(&[elem]).unwrap();
+
+        let response = get(addr, "/datasets");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let rows = serde_json::from_str::<Vec<Row>>(body).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].w_s, 4.0);
+
+        let response = get(addr, "/datasets/tank/foo");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let response = get(addr, "/datasets/tank/bar");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn metrics() {
+        let mut serve = Serve::new("127.0.0.1:0", false).unwrap();
+        let addr = serve.local_addr();
+        serve.send(&[Element::writing("tank/foo", 4.0)]).unwrap();
+
+        let response = get(addr, "/metrics");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: application/openmetrics-text;"));
+        assert!(
+            body.contains("\nztop_w_s{pool=\"tank\",dataset=\"tank/foo\"} 4\n")
+        );
+        assert!(body.ends_with("# EOF\n"));
+    }
+
+    /// Read one unmasked text message from a WebSocket
+    fn read_message(stream: &mut impl Read) -> String {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[0], 0x81);
+        let len = match header[1] {
+            126 => {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                usize::from(u16::from_be_bytes(len))
+            }
+            len => usize::from(len),
+        };
+        let mut msg = vec![0; len];
+        stream.read_exact(&mut msg).unwrap();
+        String::from_utf8(msg).unwrap()
+    }
+
+    /// A WebSocket client gets the latest update right away, then each new
+    /// one
+    #[test]
+    fn stream() {
+        let mut serve = Serve::new("127.0.0.1:0", false).unwrap();
+        serve.send(&[Element::writing("tank/foo", 4.0)]).unwrap();
+        let mut stream = TcpStream::connect(serve.local_addr()).unwrap();
+        write!(
+            stream,
+            "GET /stream HTTP/1.1\r\nHost: localhost\r\nUpgrade: \
+             websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: \
+             dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .unwrap();
+        // The first message may arrive with the headers, so read both
+        // through the same buffer
+        let mut reader = BufReader::new(stream);
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            lines.push(line);
+        }
+        assert_eq!(lines[0], "HTTP/1.1 101 Switching Protocols\r\n");
+        assert!(lines.contains(
+            &"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"
+                .to_owned()
+        ));
+        let frame =
+            serde_json::from_str::<Frame>(&read_message(&mut reader)).unwrap();
+        assert_eq!(frame.datasets[0].w_s, 4.0);
+        serve.send(&[Element::writing("tank/foo", 8.0)]).unwrap();
+        let frame =
+            serde_json::from_str::<Frame>(&read_message(&mut reader)).unwrap();
+        assert_eq!(frame.datasets[0].w_s, 8.0);
+    }
+
+    /// A client that hasn't taken the last update yet skips the next, and one
+    /// that's gone is forgotten
+    #[test]
+    fn slow_client() {
+        let mut serve = Serve::new("127.0.0.1:0", false).unwrap();
+        let (slow, slow_rx) = mpsc::sync_channel(1);
+        let (gone, _) = mpsc::sync_channel(1);
+        serve.latest.lock().unwrap().subscribers = vec![slow, gone];
+        serve.send(&[Element::writing("tank/foo", 1.0)]).unwrap();
+        serve.send(&[Element::writing("tank/foo", 2.0)]).unwrap();
+        assert_eq!(serve.latest.lock().unwrap().subscribers.len(), 1);
+        let msg = slow_rx.try_recv().unwrap();
+        let frame = serde_json::from_str::<Frame>(&msg).unwrap();
+        assert_eq!(frame.datasets[0].w_s, 1.0);
+        assert!(slow_rx.try_recv().is_err());
+    }
+
+    fn row(name: &str, w_s: f64) -> Row {
+        Row::from(&Element::writing(name, w_s))
+    }
+
+    /// Without children mode, every dataset counts towards its pool
+    #[test]
+    fn pools_sum() {
+        let rows = [row("tank", 1.0), row("tank/a", 2.0), row("zroot", 4.0)];
+        let pools = pools(&rows, false);
+        assert_eq!(pools.len(), 2);
+        assert_eq!((pools[0].name.as_str(), pools[0].w_s), ("tank", 3.0));
+        assert_eq!((pools[1].name.as_str(), pools[1].w_s), ("zroot", 4.0));
+    }
+
+    /// In children mode, the pool's own dataset already has the total
+    #[test]
+    fn pools_children() {
+        let rows = [row("tank", 3.0), row("tank/a", 2.0)];
+        let pools = pools(&rows, true);
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].w_s, 3.0);
+    }
+
+    #[test]
+    fn history_since() {
+        let latest = Latest {
+            frames: (0..3)
+                .map(|i| Frame {
+                    time:     f64::from(i),
+                    alerts:   Vec::new(),
+                    datasets: vec![row("tank", f64::from(i))],
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let (status, body) = route("GET /history?since=0.5 HTTP/1.1", &latest);
+        assert_eq!(status, "200 OK");
+        let frames = serde_json::from_str::<Vec<Frame>>(&body).unwrap();
+        assert_eq!(frames, &latest.frames.as_slices().0[1..]);
+        let (_, body) = route("GET /history HTTP/1.1", &latest);
+        let frames = serde_json::from_str::<Vec<Frame>>(&body).unwrap();
+        assert_eq!(frames.len(), 3);
+        let (status, _) = route("GET /history?since=soon HTTP/1.1", &latest);
+        assert_eq!(status, "400 Bad Request");
+    }
+
+    #[cfg(feature = "web")]
+    #[test]
+    fn index() {
+        let serve = Serve::new("127.0.0.1:0", false).unwrap();
+        let response = get(serve.local_addr(), "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/html"));
+        assert!(response.ends_with("</html>\n"));
+    }
+
+    #[test]
+    fn method() {
+        let latest = Latest::default();
+        assert_eq!(
+            route("POST /datasets HTTP/1.1", &latest).0,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(route("garbage", &latest).0, "400 Bad Request");
+        assert_eq!(route("GET /metrics HTTP/1.1", &latest).0, "404 Not Found");
+    }
+}
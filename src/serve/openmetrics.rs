@@ -0,0 +1,244 @@
+// vim: tw=80
+//! The latest statistics in the OpenMetrics text format, for Prometheus and
+//! for Performance Co-Pilot's pmdaopenmetrics
+use std::{collections::BTreeMap, error::Error, fmt::Write};
+
+use serde::Deserialize;
+
+use crate::{app::Metric, capture::Row};
+
+pub(super) const CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// A label that varies by dataset
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Label {
+    Pool,
+    Dataset,
+    /// How many levels below its pool the dataset is
+    Depth,
+}
+
+impl Label {
+    fn name(self) -> &'static str {
+        match self {
+            Label::Pool => "pool",
+            Label::Dataset => "dataset",
+            Label::Depth => "depth",
+        }
+    }
+
+    fn value(self, dataset: &str) -> String {
+        match self {
+            Label::Pool => {
+                dataset.split('/').next().unwrap_or_default().to_owned()
+            }
+            Label::Dataset => dataset.to_owned(),
+            Label::Depth => dataset.matches('/').count().to_string(),
+        }
+    }
+}
+
+/// The `[openmetrics]` section of the config file
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OpenMetricsConfig {
+    /// How to name each gauge.  `{metric}` will be replaced by the metric
+    /// name.
+    #[serde(default = "OpenMetricsConfig::default_template")]
+    pub template:      String,
+    /// The labels that identify each dataset's sample, in order
+    #[serde(default = "OpenMetricsConfig::default_labels")]
+    pub labels:        Vec<Label>,
+    /// Labels with the same value on every sample
+    #[serde(default)]
+    pub static_labels: BTreeMap<String, String>,
+}
+
+impl Default for OpenMetricsConfig {
+    fn default() -> Self {
+        OpenMetricsConfig {
+            template:      Self::default_template(),
+            labels:        Self::default_labels(),
+            static_labels: BTreeMap::new(),
+        }
+    }
+}
+
+impl OpenMetricsConfig {
+    fn default_template() -> String {
+        "ztop_{metric}".to_owned()
+    }
+
+    fn default_labels() -> Vec<Label> {
+        vec![Label::Pool, Label::Dataset]
+    }
+
+    /// Check that every metric and label will have a valid name
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let name = self.template.replace("{metric}", "x");
+        let valid = name
+            .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            let msg = format!(
+                "Invalid openmetrics template {:?}: names may only contain \
+                 letters, digits, and underscores",
+                self.template
+            );
+            return Err(msg.into());
+        }
+        for label in self.static_labels.keys() {
+            let valid = label.starts_with(|c: char| c.is_ascii_alphabetic())
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(
+                    format!("Invalid openmetrics label {label:?}").into()
+                );
+            }
+            if self.labels.iter().any(|l| l.name() == label) {
+                let msg =
+                    format!("The openmetrics label {label:?} is not static");
+                return Err(msg.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn help(metric: Metric) -> &'static str {
+    match metric {
+        Metric::OpsR => "Read operations per second",
+        Metric::RS => "Bytes read per second",
+        Metric::OpsW => "Write operations per second",
+        Metric::WS => "Bytes written per second",
+        Metric::OpsD => "Files deleted per second",
+        Metric::DS => "Bytes deleted per second",
+        Metric::PendingD => "Files unlinked but not yet deleted",
+    }
+}
+
+/// Quote a label value
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// One gauge per metric, with a sample for each dataset
+pub(super) fn format(rows: &[Row], config: &OpenMetricsConfig) -> String {
+    let mut out = String::new();
+    for metric in Metric::ALL {
+        let name = config.template.replace("{metric}", &metric.to_string());
+        writeln!(out, "# TYPE {name} gauge").unwrap();
+        writeln!(out, "# HELP {name} {}", help(metric)).unwrap();
+        for row in rows {
+            let labels = config
+                .labels
+                .iter()
+                .map(|l| (l.name(), l.value(&row.name)))
+                .chain(
+                    config
+                        .static_labels
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.clone())),
+                )
+                .map(|(k, v)| format!("{k}=\"{}\"", escape(&v)))
+                .collect::<Vec<_>>();
+            if labels.is_empty() {
+                writeln!(out, "{name} {}", row.get(metric)).unwrap();
+            } else {
+                let labels = labels.join(",");
+                writeln!(out, "{name}{{{labels}}} {}", row.get(metric))
+                    .unwrap();
+            }
+        }
+    }
+    out + "# EOF\n"
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn gauges() {
+        let rows = [Row {
+            name: "tank/foo".to_owned(),
+            w_s: 4096.0,
+            pending_d: 2,
+            ..Default::default()
+        }];
+        let text = format(&rows, &OpenMetricsConfig::default());
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3 * Metric::ALL.len() + 1);
+        assert_eq!(lines[0], "# TYPE ztop_ops_r gauge");
+        assert_eq!(lines[1], "# HELP ztop_ops_r Read operations per second");
+        assert_eq!(
+            lines[2],
+            "ztop_ops_r{pool=\"tank\",dataset=\"tank/foo\"} 0"
+        );
+        assert!(lines
+            .contains(&"ztop_w_s{pool=\"tank\",dataset=\"tank/foo\"} 4096"));
+        assert!(lines
+            .contains(&"ztop_pending_d{pool=\"tank\",dataset=\"tank/foo\"} 2"));
+        assert_eq!(lines.last(), Some(&"# EOF"));
+    }
+
+    /// Gauges are named by the template, and labeled as configured
+    #[test]
+    fn configured() {
+        let rows = [Row {
+            name: "tank/foo/bar".to_owned(),
+            r_s: 512.0,
+            ..Default::default()
+        }];
+        let config = toml::from_str::<OpenMetricsConfig>(
+            r#"
+            template = "zfs_dataset_{metric}"
+            labels = ["dataset", "depth"]
+            static_labels = { site = "nyc1" }
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        let text = format(&rows, &config);
+        assert!(text.contains("# TYPE zfs_dataset_r_s gauge\n"));
+        assert!(text.contains(
+            "\nzfs_dataset_r_s{dataset=\"tank/foo/bar\",depth=\"2\",site=\"\
+             nyc1\"} 512\n"
+        ));
+    }
+
+    #[test]
+    fn unlabeled() {
+        let rows = [Row::default()];
+        let config = OpenMetricsConfig {
+            labels: Vec::new(),
+            ..Default::default()
+        };
+        assert!(format(&rows, &config).contains("\nztop_ops_r 0\n"));
+    }
+
+    #[test]
+    fn invalid() {
+        let config = |s: &str| {
+            toml::from_str::<OpenMetricsConfig>(s).unwrap().validate()
+        };
+        assert!(config("").is_ok());
+        assert!(config(r#"template = "ztop.{metric}""#).is_err());
+        assert!(config(r#"template = "9{metric}""#).is_err());
+        assert!(config(r#"static_labels = { "2x" = "y" }"#).is_err());
+        assert!(config(r#"static_labels = { pool = "y" }"#).is_err());
+        assert!(
+            toml::from_str::<OpenMetricsConfig>(r#"labels = ["x"]"#).is_err()
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape(r#"tank/a"b\c"#), r#"tank/a\"b\\c"#);
+        assert_eq!(escape("tank/a\nb"), r"tank/a\nb");
+    }
+}
@@ -0,0 +1,151 @@
+// vim: tw=80
+//! Just enough of RFC 6455 to push text messages to a WebSocket client
+use std::io::{self, Write};
+
+/// Appended to the client's key to prove that the server speaks WebSocket
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The `Sec-WebSocket-Accept` value that answers the client's
+/// `Sec-WebSocket-Key`
+pub(super) fn accept(key: &str) -> String {
+    base64(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+/// Send one unfragmented text message.  Servers don't mask their frames.
+pub(super) fn write_text(w: &mut impl Write, text: &str) -> io::Result<()> {
+    let mut header = vec![0x81];
+    let len = text.len();
+    if len < 126 {
+        header.push(len as u8);
+    } else if let Ok(len) = u16::try_from(len) {
+        header.push(126);
+        header.extend_from_slice(&len.to_be_bytes());
+    } else {
+        header.push(127);
+        header.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    w.write_all(&header)?;
+    w.write_all(text.as_bytes())
+}
+
+/// SHA-1 is long broken, but the handshake requires it, and nothing here
+/// depends on it being secure.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] =
+        [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (hi, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *hi = hi.wrapping_add(x);
+        }
+    }
+    let mut digest = [0; 20];
+    for (out, hi) in digest.chunks_mut(4).zip(h) {
+        out.copy_from_slice(&hi.to_be_bytes());
+    }
+    digest
+}
+
+/// Standard base64, with padding
+pub(crate) fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut s = String::new();
+    for chunk in data.chunks(3) {
+        let mut buf = [0; 3];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let n = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let sextet = (n >> (18 - 6 * i)) & 0x3f;
+                s.push(char::from(ALPHABET[sextet as usize]));
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    /// The example from RFC 6455 section 1.3
+    #[test]
+    fn accept_rfc() {
+        assert_eq!(
+            accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    /// Test vectors from FIPS 180, which cross a block boundary
+    #[test]
+    fn sha1_fips() {
+        let hex = |d: [u8; 20]| {
+            d.iter().map(|b| format!("{b:02x}")).collect::<String>()
+        };
+        assert_eq!(
+            hex(sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+    }
+
+    /// Longer messages need an extended length
+    #[test]
+    fn lengths() {
+        let mut buf = Vec::new();
+        write_text(&mut buf, "hi").unwrap();
+        assert_eq!(buf, b"\x81\x02hi");
+        let mut buf = Vec::new();
+        write_text(&mut buf, &"x".repeat(300)).unwrap();
+        assert_eq!(&buf[..4], [0x81, 126, 1, 44]);
+        assert_eq!(buf.len(), 304);
+    }
+}
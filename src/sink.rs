@@ -0,0 +1,42 @@
+// vim: tw=80
+//! Destinations for every dataset's statistics, besides the display
+use std::{error::Error, io, mem};
+
+use crate::{
+    agentx::Agentx,
+    app::Element,
+    config::Config,
+    statsd::Statsd,
+    zabbix::Zabbix,
+};
+
+/// Something that consumes every dataset's statistics on each update.
+///
+/// All sinks are driven by the same collector, so any number of them may run
+/// alongside the display.
+pub trait Sink {
+    /// Consume the statistics from one update
+    fn send(&mut self, elems: &[Element]) -> io::Result<()>;
+}
+
+/// Build the sinks requested on the command line or in the config file
+pub fn from_config(
+    agentx: &Option<String>,
+    statsd: &Option<String>,
+    zabbix: &Option<String>,
+    config: &mut Config,
+) -> Result<Vec<Box<dyn Sink>>, Box<dyn Error>> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    if let Some(addr) = statsd.as_ref().or(config.statsd.address.as_ref()) {
+        let template = mem::take(&mut config.statsd.template);
+        sinks.push(Box::new(Statsd::new(addr, template)?));
+    }
+    if let Some(server) = zabbix.as_ref().or(config.zabbix.server.as_ref()) {
+        let host = config.zabbix.host.take();
+        sinks.push(Box::new(Zabbix::new(server, host)?));
+    }
+    if let Some(master) = agentx.as_ref().or(config.agentx.master.as_ref()) {
+        sinks.push(Box::new(Agentx::new(master)));
+    }
+    Ok(sinks)
+}
@@ -0,0 +1,153 @@
+// vim: tw=80
+//! Settings that ztop remembers between sessions, as opposed to the
+//! configuration file, which it only reads
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fs,
+    io,
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    config::{format_interval, interval_from_str},
+    warning::warn,
+};
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct State {
+    /// The update interval last set with `<`, `>`, or `i`.  Used unless `-t`
+    /// is given.
+    #[serde(
+        default,
+        deserialize_with = "de_interval",
+        serialize_with = "ser_interval",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub interval: Option<Duration>,
+    /// How much wider or narrower than the default to draw each column, by
+    /// its English name.  Columns at their default width are omitted.
+    #[serde(default)]
+    pub widths:   BTreeMap<String, i16>,
+    /// Each view's sort, by the view's name, like "datasets" or "vdevs".
+    /// Unsorted views are omitted.
+    #[serde(default)]
+    pub sort:     BTreeMap<String, SavedSort>,
+}
+
+/// How one view is sorted
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SavedSort {
+    /// The sort column's English name
+    pub column:  String,
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+impl State {
+    /// `$XDG_STATE_HOME/ztop/state.toml`, or `~/.local/state/ztop/state.toml`
+    pub fn path() -> Option<PathBuf> {
+        let dir =
+            env::var_os("XDG_STATE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    env::var_os("HOME")
+                        .map(|h| PathBuf::from(h).join(".local").join("state"))
+                })?;
+        Some(dir.join("ztop").join("state.toml"))
+    }
+
+    /// Load the saved state.  If there is none, or it can't be read, start
+    /// afresh.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return State::default();
+        };
+        let r = fs::read_to_string(&path)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|s| Ok(toml::from_str(&s)?));
+        match r {
+            Ok(state) => state,
+            Err(e)
+                if e.downcast_ref::<io::Error>()
+                    .is_some_and(|e| e.kind() == io::ErrorKind::NotFound) =>
+            {
+                State::default()
+            }
+            Err(e) => {
+                warn(format!("Cannot load {}: {e}", path.display()));
+                State::default()
+            }
+        }
+    }
+
+    /// Save the state for the next session
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Self::path().ok_or("Cannot find the home directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, toml::to_string(self)?)
+            .map_err(|e| format!("Cannot save {}: {e}", path.display()).into())
+    }
+}
+
+fn de_interval<'de, D>(d: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(d)?;
+    interval_from_str(&s)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn ser_interval<S>(interval: &Option<Duration>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match interval {
+        Some(d) => s.serialize_str(&format_interval(*d)),
+        None => s.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut state = State {
+            interval: Some(Duration::from_millis(250)),
+            ..Default::default()
+        };
+        state.widths.insert("Dataset".to_owned(), 12);
+        state.widths.insert("kB/s w".to_owned(), -2);
+        state.sort.insert(
+            "vdevs".to_owned(),
+            SavedSort {
+                column:  "w/s".to_owned(),
+                reverse: true,
+            },
+        );
+        let s = toml::to_string(&state).unwrap();
+        assert_eq!(toml::from_str::<State>(&s).unwrap(), state);
+    }
+
+    #[test]
+    fn interval() {
+        let state: State = toml::from_str("interval = \"2s\"").unwrap();
+        assert_eq!(state.interval, Some(Duration::from_secs(2)));
+        assert!(toml::from_str::<State>("interval = \"0s\"").is_err());
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(toml::from_str::<State>("").unwrap(), State::default());
+    }
+}
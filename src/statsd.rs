@@ -0,0 +1,158 @@
+// vim: tw=80
+//! Emit per-dataset gauges using the statsd protocol
+use std::{
+    error::Error,
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    app::{Element, Metric},
+    sink::Sink,
+};
+
+/// Keep datagrams small enough to avoid IP fragmentation on most networks
+const MAX_DATAGRAM: usize = 1432;
+
+/// The `[statsd]` section of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatsdConfig {
+    /// Host and UDP port of the statsd server
+    pub address:  Option<String>,
+    /// How to name each gauge.  `{pool}`, `{dataset}`, and `{metric}` will be
+    /// replaced by the pool name, dataset name, and metric name.
+    #[serde(default = "StatsdConfig::default_template")]
+    pub template: String,
+}
+
+impl StatsdConfig {
+    fn default_template() -> String {
+        "ztop.{dataset}.{metric}".to_owned()
+    }
+}
+
+pub struct Statsd {
+    sock:     UdpSocket,
+    template: String,
+}
+
+impl Statsd {
+    pub fn new(
+        address: &str,
+        template: String,
+    ) -> Result<Self, Box<dyn Error>> {
+        let addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+            format!("Cannot resolve statsd address {address}")
+        })?;
+        let sock = if addr.is_ipv4() {
+            UdpSocket::bind("0.0.0.0:0")?
+        } else {
+            UdpSocket::bind("[::]:0")?
+        };
+        sock.connect(addr)?;
+        Ok(Statsd { sock, template })
+    }
+
+    /// Format a gauge's name.
+    ///
+    /// Dots are statsd's hierarchy separator, so they replace the slashes in
+    /// dataset names.  Any dots already in the name become underscores.
+    fn name(&self, elem: &Element, metric: Metric) -> String {
+        let escape = |s: &str| s.replace(['.', ':', '|', '@'], "_");
+        let pool = elem.name.split('/').next().unwrap_or_default();
+        let dataset = elem
+            .name
+            .split('/')
+            .map(escape)
+            .collect::<Vec<_>>()
+            .join(".");
+        self.template
+            .replace("{pool}", &escape(pool))
+            .replace("{dataset}", &dataset)
+            .replace("{metric}", &metric.to_string())
+    }
+}
+
+impl Sink for Statsd {
+    /// Send a gauge for every metric of every dataset
+    fn send(&mut self, elems: &[Element]) -> io::Result<()> {
+        let mut buf = String::new();
+        for elem in elems {
+            for metric in Metric::ALL {
+                let line = format!(
+                    "{}:{}|g\n",
+                    self.name(elem, metric),
+                    elem.get(metric)
+                );
+                if buf.len() + line.len() > MAX_DATAGRAM {
+                    self.sock.send(buf.as_bytes())?;
+                    buf.clear();
+                }
+                buf.push_str(&line);
+            }
+        }
+        if !buf.is_empty() {
+            self.sock.send(buf.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn statsd(template: &str) -> Statsd {
+        Statsd {
+            sock:     UdpSocket::bind("127.0.0.1:0").unwrap(),
+            template: template.to_owned(),
+        }
+    }
+
+    fn elem(name: &str) -> Element {
+        Element {
+            ops_r: 1.0,
+            r_s: 2.0,
+            ops_w: 3.0,
+            w_s: 4.0,
+            ops_d: 5.0,
+            d_s: 6.0,
+            pending_d: 7,
+            ..Element::named(name)
+        }
+    }
+
+    #[test]
+    fn default_name() {
+        let statsd = statsd(&StatsdConfig::default_template());
+        let name = statsd.name(&elem("tank/foo/v1.2"), Metric::WS);
+        assert_eq!(name, "ztop.tank.foo.v1_2.w_s");
+    }
+
+    #[test]
+    fn custom_name() {
+        let statsd = statsd("zfs.{pool}.{metric}.{dataset}");
+        let name = statsd.name(&elem("tank/foo"), Metric::OpsR);
+        assert_eq!(name, "zfs.tank.ops_r.tank.foo");
+    }
+
+    #[test]
+    fn send() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let mut statsd =
+            Statsd::new(&addr, "{dataset}.{metric}".to_owned()).unwrap();
+        statsd.send(&[elem("tank")]).unwrap();
+        let mut buf = [0u8; MAX_DATAGRAM];
+        let len = server.recv(&mut buf).unwrap();
+        let msg = std::str::from_utf8(&buf[..len]).unwrap();
+        assert_eq!(
+            msg,
+            "tank.ops_r:1|g\ntank.r_s:2|g\ntank.ops_w:3|g\ntank.w_s:4|g\ntank.\
+             ops_d:5|g\ntank.d_s:6|g\ntank.pending_d:7|g\n"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+// vim: tw=80
+//! A report of the whole session's activity, printed on exit
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::app::Element;
+
+/// One dataset's activity over the session
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Totals {
+    /// Bytes read, written, and deleted
+    bytes:  [u64; 3],
+    /// Read, write, and delete operations combined
+    ops:    u64,
+    /// Highest read, write, and delete B/s seen in any one update
+    peak:   [f64; 3],
+    /// The dataset's counters as of the last update: bytes read, written,
+    /// and unlinked, and operations
+    counts: [u64; 4],
+}
+
+impl Totals {
+    /// Start counting from `elem`'s counters
+    fn new(elem: &Element) -> Self {
+        Totals {
+            counts: counts(elem),
+            ..Default::default()
+        }
+    }
+}
+
+/// The cumulative counters that `elem` was computed from
+fn counts(elem: &Element) -> [u64; 4] {
+    let nunlinked = elem.total.saturating_sub(elem.nread + elem.nwritten);
+    [elem.nread, elem.nwritten, nunlinked, elem.nops]
+}
+
+/// Accumulates every dataset's activity, for `--summary`
+#[derive(Debug)]
+pub struct Summary {
+    start:    Instant,
+    last:     Instant,
+    datasets: HashMap<String, Totals>,
+}
+
+impl Summary {
+    pub fn new(now: Instant) -> Self {
+        Summary {
+            start:    now,
+            last:     now,
+            datasets: HashMap::new(),
+        }
+    }
+
+    /// Add one update's statistics.  The totals come from differencing the
+    /// kernel's counters, so they're exact no matter how the updates are
+    /// spaced.  A dataset's first update only sets its starting point.
+    pub fn tick(&mut self, elems: &[Element], now: Instant) {
+        self.last = now;
+        for elem in elems.iter().filter(|e| !e.stale && !e.self_only) {
+            let totals = self
+                .datasets
+                .entry(elem.name.clone())
+                .or_insert_with(|| Totals::new(elem));
+            let cur = counts(elem);
+            // If any counter went backwards, the pool was reimported and
+            // they all restarted from zero.
+            let restarted = cur.iter().zip(totals.counts).any(|(c, p)| *c < p);
+            let growth: [u64; 4] = if restarted {
+                cur
+            } else {
+                std::array::from_fn(|i| cur[i] - totals.counts[i])
+            };
+            totals.counts = cur;
+            for (bytes, g) in totals.bytes.iter_mut().zip(growth) {
+                *bytes += g;
+            }
+            totals.ops += growth[3];
+            let rates = [elem.r_s, elem.w_s, elem.d_s];
+            for (i, rate) in rates.into_iter().enumerate() {
+                totals.peak[i] = totals.peak[i].max(rate);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    /// A table of every dataset that had any activity, busiest first
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let elapsed = Duration::from_secs((self.last - self.start).as_secs());
+        writeln!(f, "Session lasted {elapsed:?}")?;
+        let mut rows = self
+            .datasets
+            .iter()
+            .filter(|(_, t)| t.ops > 0 || t.bytes.iter().any(|b| *b > 0))
+            .collect::<Vec<_>>();
+        if rows.is_empty() {
+            return writeln!(f, "No dataset activity");
+        }
+        let total = |t: &Totals| t.bytes.iter().sum::<u64>();
+        rows.sort_by(|(an, at), (bn, bt)| {
+            total(bt).cmp(&total(at)).then_with(|| an.cmp(bn))
+        });
+        writeln!(
+            f,
+            "{:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}  Dataset",
+            "read",
+            "written",
+            "deleted",
+            "ops",
+            "peak r/s",
+            "peak w/s",
+            "peak d/s"
+        )?;
+        for (name, t) in rows {
+            let b = |x: f64| human_bytes(x.round() as u64);
+            writeln!(
+                f,
+                "{:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}  {name}",
+                human_bytes(t.bytes[0]),
+                human_bytes(t.bytes[1]),
+                human_bytes(t.bytes[2]),
+                t.ops,
+                b(t.peak[0]),
+                b(t.peak[1]),
+                b(t.peak[2]),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a byte count with a binary unit suffix, like `1.5G`
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 {
+        format!("{value:.1}{}", UNITS[unit])
+    } else {
+        format!("{value:.0}{}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    /// A dataset that has read `nread` and written `nwritten` bytes, in 1 KiB
+    /// operations, and is currently reading and writing at `r_s` and `w_s`
+    fn elem(
+        name: &str,
+        nread: u64,
+        nwritten: u64,
+        r_s: f64,
+        w_s: f64,
+    ) -> Element {
+        Element {
+            r_s,
+            w_s,
+            nread,
+            nwritten,
+            total: nread + nwritten,
+            nops: (nread + nwritten) / 1024,
+            ..Element::named(name)
+        }
+    }
+
+    /// The totals don't depend on the spacing of the updates
+    #[test]
+    fn accumulate() {
+        let start = Instant::now();
+        let mut summary = Summary::new(start);
+        let t1 = start + Duration::from_secs(2);
+        summary.tick(&[elem("tank/a", 10240, 0, 1024.0, 0.0)], t1);
+        let t2 = t1 + Duration::from_millis(300);
+        summary.tick(&[elem("tank/a", 16384, 1024, 4096.0, 512.0)], t2);
+        let t3 = t2 + Duration::from_secs(5);
+        summary.tick(&[elem("tank/a", 18432, 1024, 2048.0, 0.0)], t3);
+        let a = &summary.datasets["tank/a"];
+        assert_eq!(a.bytes, [8192, 1024, 0]);
+        assert_eq!(a.ops, 9);
+        assert_eq!(a.peak, [4096.0, 512.0, 0.0]);
+    }
+
+    /// Deleted bytes are whatever's left of the total
+    #[test]
+    fn deleted() {
+        let start = Instant::now();
+        let mut summary = Summary::new(start);
+        summary.tick(&[elem("tank/a", 0, 0, 0.0, 0.0)], start);
+        let mut e = elem("tank/a", 1024, 0, 0.0, 0.0);
+        e.total += 4096;
+        summary.tick(&[e], start + Duration::from_secs(1));
+        assert_eq!(summary.datasets["tank/a"].bytes, [1024, 0, 4096]);
+    }
+
+    /// When a pool is reimported, its counters restart from zero
+    #[test]
+    fn restarted() {
+        let start = Instant::now();
+        let mut summary = Summary::new(start);
+        summary.tick(&[elem("tank/a", 10240, 0, 0.0, 0.0)], start);
+        let t1 = start + Duration::from_secs(1);
+        summary.tick(&[elem("tank/a", 12288, 0, 0.0, 0.0)], t1);
+        let t2 = t1 + Duration::from_secs(1);
+        summary.tick(&[elem("tank/a", 1024, 0, 0.0, 0.0)], t2);
+        assert_eq!(summary.datasets["tank/a"].bytes, [3072, 0, 0]);
+    }
+
+    #[test]
+    fn skip_stale() {
+        let start = Instant::now();
+        let mut summary = Summary::new(start);
+        let mut e = elem("tank/a", 1024, 0, 1024.0, 0.0);
+        e.stale = true;
+        summary.tick(&[e], start + Duration::from_secs(1));
+        assert!(summary.datasets.is_empty());
+    }
+
+    #[test]
+    fn human() {
+        assert_eq!(human_bytes(0), "0");
+        assert_eq!(human_bytes(1023), "1023");
+        assert_eq!(human_bytes(1024), "1.0K");
+        assert_eq!(human_bytes(1536), "1.5K");
+        assert_eq!(human_bytes(10 << 20), "10M");
+        assert_eq!(human_bytes(3 << 40), "3.0T");
+        assert_eq!(human_bytes(u64::MAX), "16E");
+    }
+
+    /// Busiest datasets first, and idle ones omitted
+    #[test]
+    fn display() {
+        let start = Instant::now();
+        let mut summary = Summary::new(start);
+        let names = ["tank/idle", "tank/a", "tank/b"];
+        let elems = names.map(|name| elem(name, 0, 0, 0.0, 0.0));
+        summary.tick(&elems, start);
+        let elems = [
+            elem("tank/idle", 0, 0, 0.0, 0.0),
+            elem("tank/a", 61440, 0, 1024.0, 0.0),
+            elem("tank/b", 0, 614400, 0.0, 10240.0),
+        ];
+        summary.tick(&elems, start + Duration::from_secs(60));
+        let s = summary.to_string();
+        let lines = s.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "Session lasted 60s");
+        assert!(lines[2].ends_with("  tank/b"));
+        assert!(lines[3].ends_with("  tank/a"));
+        assert!(lines[3].starts_with("     60K        0"));
+    }
+}
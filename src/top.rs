@@ -0,0 +1,155 @@
+// vim: tw=80
+//! Rank datasets by their activity over a time window, without the TUI
+use std::{cmp::Ordering, error::Error, thread, time::Duration};
+
+use crate::{
+    app::{DataSource, Element, Metric},
+    config,
+};
+
+/// Sample every dataset over a window and print the busiest ones
+#[derive(Debug, clap::Args)]
+pub struct TopArgs {
+    /// Include child datasets' stats with their parents'.
+    #[clap(short = 'c', long = "children")]
+    children: bool,
+    /// how long to sample for, in seconds or with the specified unit
+    #[clap(
+        long = "duration",
+        value_parser = config::duration_from_str,
+        default_value = "60s"
+    )]
+    duration: Duration,
+    /// the metric to rank by: ops_r, r_s, ops_w, w_s, ops_d, d_s, or
+    /// pending_d
+    #[clap(long = "by", default_value = "w_s")]
+    by:       Metric,
+    /// print at most this many datasets
+    #[clap(long = "limit", default_value_t = 10)]
+    limit:    usize,
+    /// the metrics to print, in order, separated by commas
+    #[clap(
+        long = "fields",
+        value_delimiter = ',',
+        default_value = "ops_r,r_s,ops_w,w_s,ops_d,d_s,pending_d"
+    )]
+    fields:   Vec<Metric>,
+    /// Sample these pools and their children
+    pools:    Vec<String>,
+}
+
+/// The `limit` datasets with the highest value of `metric`, highest first.
+/// Ties are broken by name.
+fn rank(mut elems: Vec<Element>, metric: Metric, limit: usize) -> Vec<Element> {
+    elems.sort_by(|x, y| match y.get(metric).total_cmp(&x.get(metric)) {
+        Ordering::Equal => x.name.cmp(&y.name),
+        o => o,
+    });
+    elems.truncate(limit);
+    elems
+}
+
+/// How wide a metric's column is, wide enough for its heading
+fn width(metric: Metric) -> usize {
+    match metric {
+        Metric::OpsR | Metric::OpsW | Metric::OpsD => 8,
+        Metric::RS | Metric::WS | Metric::DS => 12,
+        Metric::PendingD => 9,
+    }
+}
+
+fn format_header(fields: &[Metric]) -> String {
+    let mut s = String::new();
+    for &metric in fields {
+        s += &format!("{:>w$} ", metric.to_string(), w = width(metric));
+    }
+    s + " dataset"
+}
+
+fn format_row(elem: &Element, fields: &[Metric]) -> String {
+    let mut s = String::new();
+    for &metric in fields {
+        s += &format!("{:>w$.0} ", elem.get(metric), w = width(metric));
+    }
+    s + " " + &elem.name
+}
+
+/// Sample for the whole window and print the ranking.
+///
+/// Only two samples are taken, at the start and end of the window, so each
+/// rate is exactly the average over the window.
+pub fn run(args: &TopArgs) -> Result<(), Box<dyn Error>> {
+    DataSource::check_pools(&args.pools)?;
+    let mut data =
+        DataSource::new(args.children, args.pools.clone(), Vec::new());
+    data.refresh()?;
+    thread::sleep(args.duration);
+    data.refresh()?;
+    for failure in data.failures() {
+        eprintln!("{failure}");
+    }
+    let elems = rank(data.iter().collect(), args.by, args.limit);
+    println!("{}", format_header(&args.fields));
+    for elem in elems.iter() {
+        println!("{}", format_row(elem, &args.fields));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn elem(name: &str, w_s: f64, ops_d: f64) -> Element {
+        Element {
+            name: name.to_owned(),
+            w_s,
+            ops_d,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn by_metric() {
+        let elems = vec![
+            elem("tank/a", 10.0, 3.0),
+            elem("tank/b", 30.0, 1.0),
+            elem("tank/c", 20.0, 2.0),
+        ];
+        let ranked = rank(elems.clone(), Metric::WS, 10);
+        let names = ranked.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["tank/b", "tank/c", "tank/a"]);
+        let ranked = rank(elems, Metric::OpsD, 10);
+        assert_eq!(ranked[0].name, "tank/a");
+    }
+
+    #[test]
+    fn limit_and_ties() {
+        let elems = vec![
+            elem("tank/z", 5.0, 0.0),
+            elem("tank/y", 5.0, 0.0),
+            elem("tank/x", 1.0, 0.0),
+        ];
+        let ranked = rank(elems, Metric::WS, 2);
+        let names = ranked.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["tank/y", "tank/z"]);
+    }
+
+    #[test]
+    fn row() {
+        let row = format_row(&elem("tank/a", 1234.4, 2.0), &Metric::ALL);
+        let fields = row.split_whitespace().collect::<Vec<_>>();
+        assert_eq!(fields, ["0", "0", "0", "1234", "2", "0", "0", "tank/a"]);
+        assert_eq!(row.len(), 83);
+    }
+
+    /// Only the selected fields are printed, in the order given
+    #[test]
+    fn fields() {
+        let fields = [Metric::OpsD, Metric::WS];
+        let row = format_row(&elem("tank/a", 1234.4, 2.0), &fields);
+        assert_eq!(row, "       2         1234  tank/a");
+        let header = format_header(&fields);
+        assert_eq!(header, "   ops_d          w_s  dataset");
+    }
+}
@@ -0,0 +1,1312 @@
+// vim: tw=80
+//! Drawing the interactive display
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    symbols::{border, Marker},
+    text::{Line, Span},
+    widgets::{
+        block::Title,
+        Axis,
+        Bar,
+        BarChart,
+        BarGroup,
+        Block,
+        Borders,
+        Cell,
+        Chart,
+        Clear,
+        Dataset,
+        GraphType,
+        Paragraph,
+        Row,
+        Table,
+        TableState,
+        Wrap,
+    },
+    Frame,
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    app::{
+        col,
+        App,
+        DatasetType,
+        Dbuf,
+        Element,
+        Encryption,
+        History,
+        L2arc,
+        PoolCapacity,
+        PoolDedup,
+        Space,
+        Statistic,
+        TxgStats,
+        Zfetch,
+        COLUMNS,
+        HOTNESS,
+    },
+    graph,
+    i18n::{tr, trf},
+    iostat::{Latency, LatencyBucket, Queues, Vdevs, VDEV_COLUMNS},
+    screen::{
+        FilterPopup,
+        IntervalPopup,
+        PropertiesPopup,
+        SetPopup,
+        SnoozePopup,
+    },
+    summary::human_bytes,
+    warning,
+};
+
+/// What the status line shows, besides the App's own error and notice
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+    /// The update interval
+    pub interval: Duration,
+    /// The total number of warnings so far
+    pub warnings: u64,
+    /// How much wider or narrower than the default to draw each column
+    pub widths:   [i16; COLUMNS.len()],
+}
+
+// helper function to create a one-line popup box
+fn popup_layout(x: u16, y: u16, r: Rect) -> Rect {
+    // Keep the popup on screen, even if the terminal shrank
+    let x = x.min(r.width);
+    let y = y.min(r.height);
+    let popup_layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Max(r.height.saturating_sub(y) / 2),
+                Constraint::Length(y),
+                Constraint::Max(r.height.saturating_sub(y) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(r);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Max(r.width.saturating_sub(x) / 2),
+                Constraint::Length(x),
+                Constraint::Max(r.width.saturating_sub(x) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(popup_layout[1])[1]
+}
+
+/// Borders made only of ASCII characters, for plain mode
+const ASCII_BORDER: border::Set = border::Set {
+    top_left:          "+",
+    top_right:         "+",
+    bottom_left:       "+",
+    bottom_right:      "+",
+    vertical_left:     "|",
+    vertical_right:    "|",
+    horizontal_top:    "-",
+    horizontal_bottom: "-",
+};
+
+/// Width of each column
+const WIDTHS: [Constraint; COLUMNS.len()] = [
+    Constraint::Length(7),
+    Constraint::Length(8),
+    Constraint::Length(7),
+    Constraint::Length(8),
+    Constraint::Length(7),
+    Constraint::Length(7),
+    Constraint::Length(7),
+    Constraint::Length(7),
+    Constraint::Length(8),
+    Constraint::Length(7),
+    Constraint::Length(7),
+    Constraint::Length(7),
+    Constraint::Length(5),
+    Constraint::Length(5),
+    Constraint::Min(6),
+];
+
+/// Below this terminal width, hide the optional columns
+pub const FULL_WIDTH: u16 = 72;
+
+/// Below this terminal width, show each dataset on two lines
+pub const COMPACT_WIDTH: u16 = 60;
+
+/// In the compact layout, the pairs of columns that share a cell
+const COMPACT_PAIRS: [(usize, usize); 3] = [
+    (col::OPS_R, col::R_S),
+    (col::OPS_W, col::W_S),
+    (col::OPS_D, col::D_S),
+];
+
+/// The shortest that the history graph may be, including its border
+const GRAPH_HEIGHT: u16 = 8;
+
+/// Draw the main table.
+///
+/// On narrow terminals, hide the optional columns, or on very narrow ones
+/// show each dataset's operations and bandwidth on two lines.  In plain
+/// mode, use no colors, only a few text attributes.
+///
+/// With `raster`, the history graph's cells are left blank for an image.
+/// Returns where the image should go, if the graph is shown.
+pub fn draw(
+    f: &mut Frame,
+    app: &mut App,
+    headers: &HashMap<String, String>,
+    plain: bool,
+    raster: bool,
+    status: Status,
+) -> Option<Rect> {
+    let hstyle = if plain {
+        Style::default().add_modifier(Modifier::BOLD)
+    } else {
+        Style::default()
+            .fg(Color::LightYellow)
+            .add_modifier(Modifier::BOLD)
+    };
+    let sstyle = hstyle.add_modifier(Modifier::REVERSED);
+    let compact = f.size().width < COMPACT_WIDTH;
+    app.set_narrow(f.size().width < FULL_WIDTH);
+    let mut shown = (0..COLUMNS.len())
+        .filter(|&i| !app.is_hidden(i))
+        .collect::<Vec<_>>();
+    let elems = app.elements();
+    let labels = elems
+        .iter()
+        .map(|elem| dataset_label(app, elem))
+        .collect::<Vec<_>>();
+    let pan = if compact {
+        0
+    } else {
+        let longest = labels.iter().map(|l| l.width()).max();
+        let max = max_pan(&shown, &status.widths, longest, f.size().width);
+        app.pan(max)
+    };
+    shown.drain(..pan);
+    let label = |i: usize| {
+        let (name, width) = COLUMNS[i];
+        let label = headers.get(name).map(String::as_str).unwrap_or(name);
+        format!("{label:>width$}")
+    };
+    let sort_style = |sorted: bool| if sorted { sstyle } else { hstyle };
+    let hcells = if compact {
+        COMPACT_PAIRS
+            .iter()
+            .map(|&(i, j)| {
+                let sorted =
+                    app.sort_idx() == Some(i) || app.sort_idx() == Some(j);
+                Cell::from(format!("{}\n{}", label(i), label(j)))
+                    .style(sort_style(sorted))
+            })
+            .chain(std::iter::once(
+                Cell::from(label(col::DATASET))
+                    .style(sort_style(app.sort_idx() == Some(col::DATASET))),
+            ))
+            .collect::<Vec<_>>()
+    } else {
+        shown
+            .iter()
+            .enumerate()
+            .map(|(n, &i)| {
+                let mut text = fit(&label(i), column_width(&status.widths, i));
+                if n == 0 && pan > 0 {
+                    // Show that there are columns scrolled off to the left
+                    text.replace_range(..1, "<");
+                }
+                Cell::from(text).style(sort_style(app.sort_idx() == Some(i)))
+            })
+            .collect::<Vec<_>>()
+    };
+    let height = if compact { 2 } else { 1 };
+    let header = if plain {
+        Row::new(hcells).height(height)
+    } else {
+        Row::new(hcells)
+            .height(height)
+            .style(Style::default().bg(Color::Blue))
+    };
+    let selected = elems
+        .iter()
+        .position(|elem| Some(elem.name.as_str()) == app.selected());
+    let available = (0..COLUMNS.len())
+        .map(|i| app.is_available(i))
+        .collect::<Vec<_>>();
+    let hint = match app.sort_idx() {
+        _ if !app.show_sort_hint() => None,
+        Some(HOTNESS) => Some(tr("Sorting by hotness")),
+        Some(i) => Some(trf("Sorting by {}", &[&label(i).trim()])),
+        None => Some(tr("Not sorted")),
+    };
+    // Only the rates and the totals can be added up
+    let total = app.sort_idx().filter(|&i| i <= col::NWRITTEN).map(|i| {
+        let text = cell_texts(&app.total(&elems), String::new())[i].clone();
+        trf("Total {}: {}", &[&label(i).trim(), &text.trim()])
+    });
+    let rows = elems
+        .into_iter()
+        .zip(labels)
+        .map(|(elem, label)| {
+            let style = if app.is_alerting(&elem.name) {
+                if plain {
+                    Style::default().add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default()
+                        .fg(Color::LightRed)
+                        .add_modifier(Modifier::BOLD)
+                }
+            } else if elem.new && !plain {
+                Style::default().fg(Color::LightGreen)
+            } else {
+                Style::default()
+            };
+            let mut texts = cell_texts(&elem, label);
+            // This kernel doesn't provide the counters for these columns
+            for (text, _) in texts
+                .iter_mut()
+                .zip(available.iter())
+                .filter(|(_, available)| !**available)
+            {
+                *text = format!("{:>6}", "-");
+            }
+            let cells = if compact {
+                COMPACT_PAIRS
+                    .iter()
+                    .map(|&(i, j)| {
+                        Cell::from(format!("{}\n{}", texts[i], texts[j]))
+                    })
+                    .chain(std::iter::once(Cell::from(
+                        texts[col::DATASET].clone(),
+                    )))
+                    .collect::<Vec<_>>()
+            } else {
+                shown
+                    .iter()
+                    .map(|&i| {
+                        Cell::from(fit(
+                            &texts[i],
+                            column_width(&status.widths, i),
+                        ))
+                    })
+                    .collect::<Vec<_>>()
+            };
+            Row::new(cells).height(height).style(style)
+        })
+        .collect::<Vec<_>>();
+    let widths = if compact {
+        COMPACT_PAIRS
+            .iter()
+            .map(|&(i, j)| {
+                // Both columns share the width, so the wider one wins
+                let adj = status.widths[i].max(status.widths[j]);
+                Constraint::Length(7u16.saturating_add_signed(adj).max(1))
+            })
+            .chain(std::iter::once(Constraint::Min(
+                6u16.saturating_add_signed(status.widths[col::DATASET])
+                    .max(1),
+            )))
+            .collect::<Vec<_>>()
+    } else {
+        shown
+            .iter()
+            .map(|&i| column_width(&status.widths, i))
+            .collect::<Vec<_>>()
+    };
+    let t = Table::new(rows, widths)
+        .header(header)
+        .block(status_block(app, status, hint.or(total), plain))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .flex(ratatui::layout::Flex::Legacy);
+    let mut area = f.size();
+    if let Some(l2arc) = app.l2arc() {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(0)])
+            .split(area);
+        f.render_widget(l2arc_panel(l2arc, plain), chunks[0]);
+        area = chunks[1];
+    }
+    if let Some(dbuf) = app.dbuf() {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(0)])
+            .split(area);
+        f.render_widget(dbuf_panel(dbuf, plain), chunks[0]);
+        area = chunks[1];
+    }
+    if let Some(zfetch) = app.zfetch() {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(0)])
+            .split(area);
+        f.render_widget(zfetch_panel(zfetch, plain), chunks[0]);
+        area = chunks[1];
+    }
+    if let Some(capacity) = app.capacity() {
+        let height = u16::try_from(capacity.len() + 2).unwrap_or(u16::MAX);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(height), Constraint::Min(0)])
+            .split(area);
+        f.render_widget(capacity_panel(capacity, plain), chunks[0]);
+        area = chunks[1];
+    }
+    if let Some(dedup) = app.dedup() {
+        let height = u16::try_from(dedup.len() + 2).unwrap_or(u16::MAX);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(height), Constraint::Min(0)])
+            .split(area);
+        f.render_widget(dedup_panel(dedup, plain), chunks[0]);
+        area = chunks[1];
+    }
+    if let Some(txgs) = app.txgs() {
+        let height = u16::try_from(txgs.len() + 2).unwrap_or(u16::MAX);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(height), Constraint::Min(0)])
+            .split(area);
+        f.render_widget(txg_panel(txgs, plain), chunks[0]);
+        area = chunks[1];
+    }
+    let mut graph_area = None;
+    if let Some(history) = app.graph() {
+        let height = (area.height / 3).max(GRAPH_HEIGHT);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(height), Constraint::Min(0)])
+            .split(area);
+        let block = graph_block(history, plain);
+        if raster {
+            graph_area = Some(block.inner(chunks[0]));
+            f.render_widget(block, chunks[0]);
+        } else {
+            draw_graph(f, chunks[0], history, block, plain);
+        }
+        area = chunks[1];
+    }
+    if let Some(latency) = app.latency() {
+        draw_latency(f, area, app, latency, status, plain);
+    } else if let Some(queues) = app.queues() {
+        f.render_widget(queue_table(app, queues, status, plain), area);
+    } else if let Some(vdevs) = app.vdevs() {
+        f.render_widget(vdev_table(app, vdevs, status, plain), area);
+    } else {
+        let mut state = TableState::default().with_selected(selected);
+        f.render_stateful_widget(t, area, &mut state);
+    }
+    graph_area
+}
+
+/// The history graph's border, saying whose rates it shows, which line is
+/// which, and its scale
+fn graph_block(history: &History, plain: bool) -> Block<'static> {
+    let subject = match history.subject() {
+        Some(name) => trf("History of {}", &[&name]),
+        None => tr("History of the datasets shown"),
+    };
+    let (read, write) = if plain {
+        (Style::default(), Style::default())
+    } else {
+        (
+            Style::default().fg(Color::LightBlue),
+            Style::default().fg(Color::LightRed),
+        )
+    };
+    // Without colors, the lines are told apart by their letters
+    let (read_name, write_name) = if plain {
+        (tr("read (r)"), tr("write (w)"))
+    } else {
+        (tr("read"), tr("write"))
+    };
+    let legend = Line::from(vec![
+        Span::styled(read_name, read),
+        Span::raw(" "),
+        Span::styled(write_name, write),
+    ]);
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(subject)
+        .title(Title::from(legend).alignment(Alignment::Center));
+    let samples = history.samples();
+    if let (Some(first), Some(last)) = (samples.front(), samples.back()) {
+        let peak = samples
+            .iter()
+            .map(|&(_, r, w)| r.max(w))
+            .fold(0.0, f64::max);
+        let span = Duration::from_secs((last.0 - first.0).as_secs());
+        let scale = trf(
+            "peak {}/s over {}",
+            &[&human_bytes(peak as u64), &format_args!("{span:?}")],
+        );
+        block = block.title(Title::from(scale).alignment(Alignment::Right));
+    }
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    block
+}
+
+/// Draw the history graph with braille characters, oldest on the left
+fn draw_graph(
+    f: &mut Frame,
+    area: Rect,
+    history: &History,
+    block: Block<'static>,
+    plain: bool,
+) {
+    let samples = history.samples();
+    let Some(&(last, ..)) = samples.back() else {
+        f.render_widget(block, area);
+        return;
+    };
+    if plain {
+        // Braille isn't ASCII, so plot one point per cell instead
+        let inner = block.inner(area);
+        let pixmap = graph::plot(
+            samples,
+            usize::from(inner.width),
+            usize::from(inner.height),
+        );
+        let lines = graph::ascii(&pixmap)
+            .into_iter()
+            .map(Line::from)
+            .collect::<Vec<_>>();
+        f.render_widget(Paragraph::new(lines).block(block), area);
+        return;
+    }
+    // Seconds before the latest sample
+    let x = |t: Instant| -(last - t).as_secs_f64();
+    let reads = samples
+        .iter()
+        .map(|&(t, r, _)| (x(t), r))
+        .collect::<Vec<_>>();
+    let writes = samples
+        .iter()
+        .map(|&(t, _, w)| (x(t), w))
+        .collect::<Vec<_>>();
+    let oldest = reads[0].0.min(-1.0);
+    let peak = samples
+        .iter()
+        .map(|&(_, r, w)| r.max(w))
+        .fold(1.0, f64::max);
+    let datasets = vec![
+        Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::LightBlue))
+            .data(&reads),
+        Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::LightRed))
+            .data(&writes),
+    ];
+    let chart = Chart::new(datasets)
+        .block(block)
+        .x_axis(Axis::default().bounds([oldest, 0.0]))
+        .y_axis(Axis::default().bounds([0.0, peak]));
+    f.render_widget(chart, area);
+}
+
+/// Each pool's read and write latency histograms, side by side
+fn draw_latency(
+    f: &mut Frame,
+    area: Rect,
+    app: &App,
+    latency: &Latency,
+    status: Status,
+    plain: bool,
+) {
+    let histograms = latency.latest();
+    let hint = histograms
+        .is_empty()
+        .then(|| tr("Collecting statistics..."));
+    let block = status_block(app, status, hint, plain);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    if histograms.is_empty() {
+        return;
+    }
+    let n = u32::try_from(histograms.len()).unwrap_or(u32::MAX);
+    let pool_areas = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Ratio(1, n); histograms.len()])
+        .split(inner);
+    for ((pool, buckets), pool_area) in histograms.iter().zip(pool_areas.iter())
+    {
+        let halves = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)])
+            .split(*pool_area);
+        let title = trf("{} read latency", &[pool]);
+        let read = histogram(buckets, title, |b| b.read, plain);
+        f.render_widget(read, halves[0]);
+        let title = trf("{} write latency", &[pool]);
+        let write = histogram(buckets, title, |b| b.write, plain);
+        f.render_widget(write, halves[1]);
+    }
+}
+
+/// A horizontal bar chart of one latency histogram, omitting the empty
+/// buckets at either end
+fn histogram(
+    buckets: &[LatencyBucket],
+    title: String,
+    count: fn(&LatencyBucket) -> u64,
+    plain: bool,
+) -> BarChart<'static> {
+    let first = buckets.iter().position(|b| b.read > 0 || b.write > 0);
+    let last = buckets.iter().rposition(|b| b.read > 0 || b.write > 0);
+    let shown = match (first, last) {
+        (Some(first), Some(last)) => &buckets[first..=last],
+        _ => &[],
+    };
+    let bars = shown
+        .iter()
+        .map(|b| {
+            Bar::default()
+                .label(Line::from(format!("{:>5}", b.label)))
+                .value(count(b))
+        })
+        .collect::<Vec<_>>();
+    let mut block = Block::default().borders(Borders::ALL).title(title);
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    let mut chart = BarChart::default()
+        .direction(Direction::Horizontal)
+        .bar_width(1)
+        .bar_gap(0)
+        .data(BarGroup::default().bars(&bars))
+        .block(block);
+    if !plain {
+        chart = chart.bar_style(Style::default().fg(Color::LightBlue));
+    }
+    chart
+}
+
+/// A short header for an I/O class from `zpool iostat -q`
+fn queue_label(class: &str) -> String {
+    let label = match class {
+        "syncq_read" => "sync r",
+        "syncq_write" => "sync w",
+        "asyncq_read" => "async r",
+        "asyncq_write" => "async w",
+        "scrubq_read" => "scrub",
+        "trimq_write" => "trim",
+        "rebuildq_write" => "rebuild",
+        _ => class,
+    };
+    tr(label)
+}
+
+/// Each vdev's pending and active operations per I/O class, nested below
+/// its pool like `zpool iostat -qv`
+fn queue_table(
+    app: &App,
+    queues: &Queues,
+    status: Status,
+    plain: bool,
+) -> Table<'static> {
+    let report = queues.latest();
+    let hstyle = if plain {
+        Style::default().add_modifier(Modifier::BOLD)
+    } else {
+        Style::default()
+            .fg(Color::LightYellow)
+            .add_modifier(Modifier::BOLD)
+            .bg(Color::Blue)
+    };
+    let header = report
+        .classes
+        .iter()
+        .map(|c| Cell::from(format!("{:>9}", queue_label(c))))
+        .chain(std::iter::once(Cell::from(tr("Vdev"))))
+        .collect::<Row>()
+        .style(hstyle);
+    let rows = report
+        .rows
+        .iter()
+        .map(|r| {
+            r.queues
+                .iter()
+                .map(|q| {
+                    let text = match q {
+                        Some((pend, activ)) => format!("{pend}/{activ}"),
+                        None => "-".to_owned(),
+                    };
+                    Cell::from(format!("{text:>9}"))
+                })
+                .chain(std::iter::once(Cell::from(format!(
+                    "{:1$}{2}",
+                    "",
+                    2 * r.depth,
+                    r.name
+                ))))
+                .collect::<Row>()
+        })
+        .collect::<Vec<_>>();
+    let hint = if report.rows.is_empty() {
+        Some(tr("Collecting statistics..."))
+    } else {
+        Some(tr("Pending/active operations"))
+    };
+    let widths = report
+        .classes
+        .iter()
+        .map(|_| Constraint::Length(10))
+        .chain(std::iter::once(Constraint::Min(6)))
+        .collect::<Vec<_>>();
+    Table::new(rows, widths)
+        .header(header)
+        .block(status_block(app, status, hint, plain))
+}
+
+/// Each vdev's operations and bandwidth, nested below its pool like
+/// `zpool iostat -v`
+fn vdev_table(
+    app: &App,
+    vdevs: &Vdevs,
+    status: Status,
+    plain: bool,
+) -> Table<'static> {
+    let hstyle = if plain {
+        Style::default().add_modifier(Modifier::BOLD)
+    } else {
+        Style::default()
+            .fg(Color::LightYellow)
+            .add_modifier(Modifier::BOLD)
+            .bg(Color::Blue)
+    };
+    let header = VDEV_COLUMNS
+        .into_iter()
+        .enumerate()
+        .map(|(i, h)| {
+            let cell = Cell::from(format!("{:>6}", tr(h)));
+            if app.vdev_sort() == Some(i) {
+                cell.style(Style::default().add_modifier(Modifier::REVERSED))
+            } else {
+                cell
+            }
+        })
+        .chain(std::iter::once(Cell::from(tr("Vdev"))))
+        .collect::<Row>()
+        .style(hstyle);
+    let rate = |x: Option<f64>, scale: f64| match x {
+        Some(x) => format!("{:>6.0}", x / scale),
+        None => format!("{:>6}", "-"),
+    };
+    let bytes = |x: Option<u64>| match x {
+        Some(x) => format!("{:>6}", human_bytes(x)),
+        None => format!("{:>6}", "-"),
+    };
+    let rows = app
+        .vdev_rows()
+        .iter()
+        .map(|r| {
+            let style = if r.class {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            Row::new([
+                Cell::from(bytes(r.alloc)),
+                Cell::from(bytes(r.free)),
+                Cell::from(rate(r.ops_r, 1.0)),
+                Cell::from(rate(r.r_s, 1024.0)),
+                Cell::from(rate(r.ops_w, 1.0)),
+                Cell::from(rate(r.w_s, 1024.0)),
+                Cell::from(format!("{:1$}{2}", "", 2 * r.depth, r.name)),
+            ])
+            .style(style)
+        })
+        .collect::<Vec<_>>();
+    let hint = vdevs
+        .latest()
+        .is_empty()
+        .then(|| tr("Collecting statistics..."));
+    let widths = [
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Min(6),
+    ];
+    Table::new(rows, widths)
+        .header(header)
+        .block(status_block(app, status, hint, plain))
+}
+
+/// A rounded rate for a panel, or `-` if ZFS doesn't report it
+fn per_sec(rate: Option<f64>) -> String {
+    rate.map_or_else(|| "-".to_owned(), |r| r.round().to_string())
+}
+
+/// A size for a panel, or `-` if ZFS doesn't report it
+fn opt_bytes(bytes: Option<u64>) -> String {
+    bytes.map_or_else(|| "-".to_owned(), human_bytes)
+}
+
+/// The percentage of lookups that hit, or `-` if there were none
+fn hit_ratio(hits: Option<f64>, misses: Option<f64>) -> String {
+    match (hits, misses) {
+        (Some(h), Some(m)) if h + m > 0.0 => {
+            format!("{:.0}%", 100.0 * h / (h + m))
+        }
+        _ => "-".to_owned(),
+    }
+}
+
+/// A bordered line summarizing the dbuf cache's activity
+fn dbuf_panel(dbuf: &Dbuf, plain: bool) -> Paragraph<'static> {
+    let text = match dbuf.rates() {
+        None => tr("Collecting statistics..."),
+        Some(r) => trf(
+            "size {}  hits {}/s  misses {}/s  hit ratio {}  evicts {}/s",
+            &[
+                &opt_bytes(r.size),
+                &per_sec(r.hits),
+                &per_sec(r.misses),
+                &hit_ratio(r.hits, r.misses),
+                &per_sec(r.evicts),
+            ],
+        ),
+    };
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(tr("dbuf cache"));
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    Paragraph::new(text).block(block)
+}
+
+/// A bordered line summarizing the prefetcher's activity
+fn zfetch_panel(zfetch: &Zfetch, plain: bool) -> Paragraph<'static> {
+    let text = match zfetch.rates() {
+        None => tr("Collecting statistics..."),
+        Some(r) => trf(
+            "hits {}/s  misses {}/s  hit ratio {}  streams exhausted {}/s",
+            &[
+                &per_sec(r.hits),
+                &per_sec(r.misses),
+                &hit_ratio(r.hits, r.misses),
+                &per_sec(r.max_streams),
+            ],
+        ),
+    };
+    let mut block =
+        Block::default().borders(Borders::ALL).title(tr("Prefetch"));
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    Paragraph::new(text).block(block)
+}
+
+/// A duration short enough to fit in a panel, like `21ms`
+fn short_duration(d: Duration) -> String {
+    if d < Duration::from_millis(1) {
+        format!("{}us", d.as_micros())
+    } else if d < Duration::from_secs(1) {
+        format!("{}ms", d.as_millis())
+    } else {
+        format!("{:.1}s", d.as_secs_f64())
+    }
+}
+
+/// One line per pool, with its space usage
+fn capacity_panel(
+    capacity: &[PoolCapacity],
+    plain: bool,
+) -> Paragraph<'static> {
+    let lines = capacity
+        .iter()
+        .map(|p| {
+            let frag = match p.frag {
+                Some(frag) => format!("{frag}%"),
+                None => "-".to_owned(),
+            };
+            Line::from(trf(
+                "{}: size {}  alloc {}  free {}  cap {}%  frag {}",
+                &[
+                    &p.name,
+                    &human_bytes(p.size),
+                    &human_bytes(p.alloc),
+                    &human_bytes(p.free),
+                    &p.cap,
+                    &frag,
+                ],
+            ))
+        })
+        .collect::<Vec<_>>();
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(tr("Pool capacity"));
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    Paragraph::new(lines).block(block)
+}
+
+/// One line per pool, with the size of its dedup table
+fn dedup_panel(dedup: &[PoolDedup], plain: bool) -> Paragraph<'static> {
+    let lines = dedup
+        .iter()
+        .map(|p| {
+            let ratio = match p.ratio {
+                Some(ratio) => format!("{ratio:.2}x"),
+                None => "-".to_owned(),
+            };
+            if p.entries == 0 {
+                return Line::from(trf(
+                    "{}: no DDT entries  ratio {}",
+                    &[&p.name, &ratio],
+                ));
+            }
+            Line::from(trf(
+                "{}: entries {}  on disk {}  in core {}  ratio {}",
+                &[
+                    &p.name,
+                    &p.entries,
+                    &human_bytes(p.disk),
+                    &human_bytes(p.core),
+                    &ratio,
+                ],
+            ))
+        })
+        .collect::<Vec<_>>();
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(tr("Dedup tables"));
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    Paragraph::new(lines).block(block)
+}
+
+/// One line per pool, with the timings of its latest committed txg
+fn txg_panel(
+    txgs: &[(String, Result<TxgStats, String>)],
+    plain: bool,
+) -> Paragraph<'static> {
+    let lines = txgs
+        .iter()
+        .map(|(pool, r)| {
+            let text = match r {
+                Ok(s) => trf(
+                    "{}: txg {}  dirty {}  written {}  open {}  quiesce {}  \
+                     wait {}  sync {}",
+                    &[
+                        pool,
+                        &s.txg,
+                        &human_bytes(s.ndirty),
+                        &human_bytes(s.nwritten),
+                        &short_duration(s.otime),
+                        &short_duration(s.qtime),
+                        &short_duration(s.wtime),
+                        &short_duration(s.stime),
+                    ],
+                ),
+                Err(e) => format!("{pool}: {e}"),
+            };
+            Line::from(text)
+        })
+        .collect::<Vec<_>>();
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(tr("Latest committed TXGs"));
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    Paragraph::new(lines).block(block)
+}
+
+/// A bordered line summarizing the L2ARC's activity
+fn l2arc_panel(l2arc: &L2arc, plain: bool) -> Paragraph<'static> {
+    let text = match l2arc.rates() {
+        None => tr("Collecting statistics..."),
+        Some(r) => trf(
+            "size {}  hits {}/s  misses {}/s  hit ratio {}  read {}/s  \
+             written {}/s",
+            &[
+                &opt_bytes(r.size),
+                &per_sec(r.hits),
+                &per_sec(r.misses),
+                &hit_ratio(r.hits, r.misses),
+                &opt_bytes(r.read.map(|x| x as u64)),
+                &opt_bytes(r.written.map(|x| x as u64)),
+            ],
+        ),
+    };
+    let mut block = Block::default().borders(Borders::ALL).title("L2ARC");
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    Paragraph::new(text).block(block)
+}
+
+/// A column's width, after the user's adjustments
+fn column_width(widths: &[i16], i: usize) -> Constraint {
+    let adj = widths[i];
+    match WIDTHS[i] {
+        Constraint::Length(n) => {
+            Constraint::Length(n.saturating_add_signed(adj).max(1))
+        }
+        Constraint::Min(n) => {
+            Constraint::Min(n.saturating_add_signed(adj).max(1))
+        }
+        c => c,
+    }
+}
+
+/// How many of the shown columns must be scrolled off the left of the table
+/// for the rest to fit in `width`, including the longest Dataset label.  At
+/// least one column is always left besides the Dataset column, which never
+/// scrolls.
+fn max_pan(
+    shown: &[usize],
+    widths: &[i16],
+    longest: Option<usize>,
+    width: u16,
+) -> usize {
+    let need = |cols: &[usize]| {
+        cols.iter()
+            .map(|&i| match column_width(widths, i) {
+                Constraint::Length(n) => usize::from(n),
+                Constraint::Min(n) => usize::from(n).max(longest.unwrap_or(0)),
+                _ => 0,
+            })
+            .sum::<usize>()
+            // The gaps between columns
+            + cols.len().saturating_sub(1)
+    };
+    let last = shown.len().saturating_sub(2);
+    (0..last)
+        .find(|&k| need(&shown[k..]) <= usize::from(width))
+        .unwrap_or(last)
+}
+
+/// Right-align a cell's text within a fixed-width column, dropping
+/// leading spaces if the column has been narrowed.  Like the defaults,
+/// leave one space for the gap between columns.
+fn fit(text: &str, width: Constraint) -> String {
+    match width {
+        Constraint::Length(n) => {
+            let w = usize::from(n).saturating_sub(1);
+            format!("{:>w$}", text.trim_start())
+        }
+        _ => text.to_owned(),
+    }
+}
+
+/// The text of each of an element's cells, given its Dataset label
+fn cell_texts(elem: &Element, label: String) -> [String; COLUMNS.len()] {
+    let space = |f: fn(&Space) -> u64| match elem.space.as_ref() {
+        Some(s) => format!("{:>6}", human_bytes(f(s))),
+        None => format!("{:>6}", "-"),
+    };
+    [
+        format!("{:>6.0}", elem.ops_r),
+        format!("{:>7.0}", elem.r_s / 1024.0),
+        format!("{:>6.0}", elem.ops_w),
+        format!("{:>7.0}", elem.w_s / 1024.0),
+        format!("{:>6.0}", elem.ops_d),
+        format!("{:>6.0}", elem.d_s / 1024.0),
+        format!("{:>6}", elem.pending_d),
+        format!("{:>6}", human_bytes(elem.nread)),
+        format!("{:>7}", human_bytes(elem.nwritten)),
+        space(|s| s.used),
+        space(|s| s.avail),
+        space(|s| s.refer),
+        format!("{:>4}", elem.kind.abbrev()),
+        format!("{:>4}", elem.encryption.map_or("-", Encryption::abbrev)),
+        label,
+    ]
+}
+
+/// A title-only block, showing the update interval, any error or notice,
+/// a summary of the sorted column, and the number of warnings
+fn status_block(
+    app: &App,
+    status: Status,
+    summary: Option<String>,
+    plain: bool,
+) -> Block<'static> {
+    let mut block = Block::default()
+        .title(trf("Every {}", &[&format_args!("{:?}", status.interval)]));
+    if app.samples() > 1 {
+        let n = app.samples();
+        block = block.title(match app.statistic() {
+            Statistic::Mean => trf("Mean of {} samples", &[&n]),
+            Statistic::Min => trf("Lowest of {} samples", &[&n]),
+            Statistic::Max => trf("Highest of {} samples", &[&n]),
+        });
+    }
+    if app.is_capturing() {
+        block = block.title(tr("Capturing"));
+    }
+    if let Some(error) = app.error() {
+        let style = if plain {
+            Style::default().add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+                .fg(Color::LightRed)
+                .add_modifier(Modifier::BOLD)
+        };
+        let msg = trf("{} (Esc to dismiss)", &[&error]);
+        block = block.title(Line::styled(msg, style));
+    } else if let Some(notice) = app.notice() {
+        block = block.title(notice.into_owned());
+    }
+    if let Some(summary) = summary {
+        block = block.title(Title::from(summary).alignment(Alignment::Center));
+    }
+    if status.warnings > 0 {
+        let msg = trf("{} warnings; press w to view", &[&status.warnings]);
+        block = block.title(Title::from(msg).alignment(Alignment::Right));
+    }
+    block
+}
+
+/// Draw a popup listing recent warnings
+pub fn draw_warnings(f: &mut Frame, plain: bool) {
+    let recent = warning::recent();
+    let lines = if recent.is_empty() {
+        vec![Line::from(tr("No warnings"))]
+    } else {
+        recent
+            .into_iter()
+            .map(|(msg, count)| Line::from(format!("{count:>6} {msg}")))
+            .collect::<Vec<_>>()
+    };
+    let height = (lines.len() as u16).saturating_add(2);
+    let width = f.size().width.saturating_sub(4).min(100);
+    let area = popup_layout(width, height.min(f.size().height), f.size());
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(tr("Warnings, newest first"));
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// Draw a popup listing a dataset's properties, like `zfs get all`
+pub fn draw_properties(
+    f: &mut Frame,
+    popup: &PropertiesPopup,
+    breakdown: &[Element],
+    plain: bool,
+) {
+    let mut lines = breakdown_lines(breakdown);
+    lines.extend(match popup.properties.as_ref() {
+        None => vec![Line::from(tr("Listing properties..."))],
+        Some(properties) => {
+            let name_width = properties
+                .iter()
+                .map(|p| p.name.width())
+                .max()
+                .unwrap_or_default();
+            let value_width = properties
+                .iter()
+                .map(|p| p.value.width())
+                .max()
+                .unwrap_or_default();
+            properties
+                .iter()
+                .map(|p| {
+                    Line::from(format!(
+                        "{:name_width$}  {:value_width$}  {}",
+                        p.name, p.value, p.source
+                    ))
+                })
+                .collect::<Vec<_>>()
+        }
+    });
+    let height = (lines.len() as u16).saturating_add(2);
+    let width = f.size().width.saturating_sub(4).min(100);
+    let area = popup_layout(width, height.min(f.size().height), f.size());
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(trf("Properties of {}", &[&popup.dataset]));
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    let scroll = u16::try_from(popup.scroll).unwrap_or(u16::MAX);
+    let paragraph = Paragraph::new(lines).block(block).scroll((scroll, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(paragraph, area);
+}
+
+/// Each child's share of a dataset's total bandwidth, followed by a blank
+/// line, or nothing if there are no children to break it down among
+fn breakdown_lines(breakdown: &[Element]) -> Vec<Line<'static>> {
+    if breakdown.is_empty() {
+        return Vec::new();
+    }
+    let bandwidth = |e: &Element| e.r_s + e.w_s + e.d_s;
+    let total = breakdown.iter().map(bandwidth).sum::<f64>();
+    let mut lines = vec![Line::from(tr("Share of the total bandwidth:"))];
+    lines.extend(breakdown.iter().map(|e| {
+        let share = if total > 0.0 {
+            format!("{:.0}%", 100.0 * bandwidth(e) / total)
+        } else {
+            "-".to_owned()
+        };
+        let name = if e.self_only {
+            trf("{} (self)", &[&e.name])
+        } else {
+            e.name.clone()
+        };
+        Line::from(format!(
+            "{share:>5}  {:>6}/s  {name}",
+            human_bytes(bandwidth(e) as u64)
+        ))
+    }));
+    lines.push(Line::default());
+    lines
+}
+
+/// Show the command line that would reproduce the current state
+pub fn draw_command_line(f: &mut Frame, command: &str, plain: bool) {
+    let width = f.size().width.saturating_sub(4).min(100);
+    let lines = command.width() / usize::from(width.saturating_sub(2).max(1));
+    let height = u16::try_from(lines + 3).unwrap_or(u16::MAX);
+    let area = popup_layout(width, height.min(f.size().height), f.size());
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(tr("Equivalent command line"));
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    let paragraph = Paragraph::new(command)
+        .block(block)
+        .wrap(Wrap { trim: false });
+    f.render_widget(Clear, area);
+    f.render_widget(paragraph, area);
+}
+
+#[rustfmt::skip]
+pub fn draw_filter(f: &mut Frame, app: &FilterPopup, plain: bool) {
+    draw_prompt(f, &tr("Filter regex"), &app.new_regex, plain);
+}
+
+pub fn draw_interval(f: &mut Frame, app: &IntervalPopup, plain: bool) {
+    let title = match app.error.as_deref() {
+        Some(e) => trf("Invalid interval: {}", &[&e]),
+        None => tr("Update interval"),
+    };
+    draw_prompt(f, &title, &app.new_interval, plain);
+}
+
+pub fn draw_snooze(f: &mut Frame, popup: &SnoozePopup, plain: bool) {
+    let title = match popup.error.as_deref() {
+        Some(e) => trf("Invalid duration: {}", &[&e]),
+        None => tr("Snooze alerts for"),
+    };
+    draw_prompt(f, &title, &popup.new_duration, plain);
+}
+
+pub fn draw_set(f: &mut Frame, popup: &SetPopup, app: &App, plain: bool) {
+    let title = if popup.invalid {
+        tr("Unknown set; try again")
+    } else {
+        let names = app.set_names().collect::<Vec<_>>();
+        trf("Dataset set ({})", &[&names.join(", ")])
+    };
+    draw_prompt(f, &title, &popup.new_set, plain);
+}
+
+/// Draw a one-line popup box for entering text, widening it if the text
+/// doesn't fit
+#[rustfmt::skip]
+fn draw_prompt(f: &mut Frame, title: &str, text: &str, plain: bool) {
+    // Wide enough for the title, too
+    let width = (text.width() + 3).max(title.width() + 2);
+    let width = u16::try_from(width).unwrap_or(u16::MAX);
+    let area = popup_layout(width.max(40), 3, f.size());
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title(title);
+    if plain {
+        block = block.border_set(ASCII_BORDER);
+    }
+    let popup_box = Paragraph::new(text)
+        .block(block);
+    f.render_widget(Clear, area);
+    f.render_widget(popup_box, area);
+}
+
+/// The text of an element's Dataset cell
+fn dataset_label(app: &App, elem: &Element) -> String {
+    let mut label = app.label(&elem.name).into_owned();
+    if elem.self_only {
+        label.push_str(" [self]");
+    }
+    if elem.stale {
+        label.push_str(" [stale]");
+    }
+    if elem.implausible {
+        label.push_str(" [implausible]");
+    }
+    if elem.new {
+        label.push_str(" [new]");
+    }
+    if elem.is_receiving() {
+        label.push_str(" [receiving]");
+    }
+    if app.is_delete_pressured(&elem.name) {
+        label.push_str(" [delete pressure]");
+    }
+    if app.mount_status() && app.is_unmounted(&elem.name) {
+        label.push_str(" [unmounted]");
+    }
+    // Volumes are only recognized when their type is shown or filtered
+    if elem.kind == DatasetType::Volume {
+        if let Some(vm) = app.vm(&elem.name) {
+            label.push_str(&format!(" [vm {vm}]"));
+        }
+    }
+    if let Some(container) = app.container(&elem.name) {
+        label.push_str(&format!(" [container {container}]"));
+    }
+    match (app.objset_ids(), elem.objset) {
+        (false, _) => label,
+        (true, 0) => format!("{:>8} {label}", "-"),
+        (true, objset) => format!("{objset:>#8x} {label}"),
+    }
+}
+
+/// Find a column by either its canonical name or its custom header
+pub fn col_idx(
+    col_name: &str,
+    headers: &HashMap<String, String>,
+) -> Option<usize> {
+    let col_name = col_name.trim();
+    if col_name == "hotness" {
+        return Some(HOTNESS);
+    }
+    COLUMNS.iter().position(|(name, _)| {
+        *name == col_name
+            || headers
+                .get(*name)
+                .map(|h| h.trim() == col_name)
+                .unwrap_or(false)
+    })
+}
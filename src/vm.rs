@@ -0,0 +1,129 @@
+// vim: tw=80
+//! Name the virtual machines that use each volume.
+//!
+//! A VM's configuration, whether a libvirt domain's XML or a bhyve
+//! configuration file, names its disks by their device nodes, like
+//! `/dev/zvol/tank/vm/web`.  Scanning the configurations for those paths is
+//! enough to map volumes back to their VMs, without asking the hypervisor.
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Where libvirt keeps its QEMU domains' configurations
+#[cfg(target_os = "freebsd")]
+const DEFAULT_DIRS: &[&str] = &["/usr/local/etc/libvirt/qemu"];
+#[cfg(not(target_os = "freebsd"))]
+const DEFAULT_DIRS: &[&str] = &["/etc/libvirt/qemu"];
+
+/// The configuration directories to scan, if the configuration file doesn't
+/// say
+pub fn default_dirs() -> Vec<PathBuf> {
+    DEFAULT_DIRS.iter().map(PathBuf::from).collect()
+}
+
+/// Find the VM's name and the volumes it uses in one configuration file.
+/// A libvirt domain has a `<name>` element, and a bhyve configuration is
+/// named after its VM.
+fn parse(text: &str, path: &Path) -> (String, Vec<String>) {
+    let name = text
+        .split_once("<name>")
+        .and_then(|(_, rest)| rest.split_once("</name>"))
+        .map(|(name, _)| name.trim().to_owned())
+        .unwrap_or_else(|| {
+            let stem = path.file_stem().unwrap_or_default();
+            stem.to_string_lossy().into_owned()
+        });
+    let volumes = text
+        .split("/dev/zvol/")
+        .skip(1)
+        .filter_map(|rest| {
+            let end = rest
+                .find(|c: char| "'\"<> \t\r\n".contains(c))
+                .unwrap_or(rest.len());
+            (end > 0).then(|| rest[..end].to_owned())
+        })
+        .collect();
+    (name, volumes)
+}
+
+/// The configuration files in `dir`, and in its subdirectories, where
+/// vm-bhyve keeps each VM's
+fn config_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files = Vec::new();
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.is_dir() {
+            if let Ok(sub) = fs::read_dir(&path) {
+                files.extend(sub.flatten().map(|e| e.path()));
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    files.retain(|p| {
+        p.extension()
+            .is_some_and(|ext| ext == "xml" || ext == "conf")
+    });
+    files
+}
+
+/// Map each volume used by a VM configured in `dirs` to that VM's name.
+/// Directories and files that can't be read are skipped, since most hosts
+/// have no VMs at all.
+pub fn scan(dirs: &[PathBuf]) -> HashMap<String, String> {
+    let mut vms = HashMap::new();
+    for path in dirs.iter().flat_map(|d| config_files(d)) {
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let (name, volumes) = parse(&text, &path);
+        for volume in volumes {
+            vms.insert(volume, name.clone());
+        }
+    }
+    vms
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn libvirt() {
+        let xml = "<domain type='kvm'>
+  <name>web01</name>
+  <devices>
+    <disk type='block' device='disk'>
+      <source dev='/dev/zvol/tank/vm/web01'/>
+    </disk>
+    <disk type=\"block\" device=\"disk\">
+      <source dev=\"/dev/zvol/tank/vm/web01-data\"/>
+    </disk>
+  </devices>
+</domain>";
+        let (name, volumes) = parse(xml, Path::new("/etc/libvirt/qemu/x.xml"));
+        assert_eq!(name, "web01");
+        assert_eq!(volumes, ["tank/vm/web01", "tank/vm/web01-data"]);
+    }
+
+    #[test]
+    fn bhyve() {
+        let conf = "loader=\"bhyveload\"
+disk0_type=\"virtio-blk\"
+disk0_dev=\"custom\"
+disk0_name=\"/dev/zvol/zroot/guests/db\"
+";
+        let (name, volumes) = parse(conf, Path::new("/vm/db/db.conf"));
+        assert_eq!(name, "db");
+        assert_eq!(volumes, ["zroot/guests/db"]);
+    }
+
+    #[test]
+    fn missing_dir() {
+        assert!(scan(&[PathBuf::from("/nonexistent/ztop/vms")]).is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+// vim: tw=80
+//! Collect warnings from the backends, instead of printing them over the TUI
+use std::{
+    collections::{BTreeSet, VecDeque},
+    sync::Mutex,
+};
+
+/// Remember this many distinct warnings
+const CAPACITY: usize = 20;
+
+static WARNINGS: Mutex<Warnings> = Mutex::new(Warnings::new());
+
+#[derive(Debug)]
+struct Warnings {
+    /// Total number of warnings, including duplicates
+    total:  u64,
+    /// Recent distinct warnings and how often each occurred, oldest first
+    recent: VecDeque<(String, u64)>,
+    /// Keys of the warnings that should only be given once
+    once:   BTreeSet<String>,
+}
+
+impl Warnings {
+    const fn new() -> Self {
+        Warnings {
+            total:  0,
+            recent: VecDeque::new(),
+            once:   BTreeSet::new(),
+        }
+    }
+
+    fn add_once(&mut self, key: &str, msg: impl FnOnce() -> String) {
+        if !self.once.contains(key) {
+            self.once.insert(key.to_owned());
+            self.add(msg());
+        }
+    }
+
+    fn add(&mut self, msg: String) {
+        self.total += 1;
+        if let Some(i) = self.recent.iter().position(|(m, _)| *m == msg) {
+            let (msg, count) = self.recent.remove(i).unwrap();
+            self.recent.push_back((msg, count + 1));
+        } else {
+            if self.recent.len() >= CAPACITY {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((msg, 1));
+        }
+    }
+}
+
+/// Record a warning.  Duplicates of a recent warning are only counted.
+pub fn warn(msg: String) {
+    WARNINGS.lock().unwrap().add(msg);
+}
+
+/// Record a warning, unless one was already recorded with the same `key`.
+/// For problems that would otherwise be reported on every update.
+pub fn warn_once(key: &str, msg: impl FnOnce() -> String) {
+    WARNINGS.lock().unwrap().add_once(key, msg);
+}
+
+/// The total number of warnings so far, including duplicates
+pub fn total() -> u64 {
+    WARNINGS.lock().unwrap().total
+}
+
+/// Recent distinct warnings and how often each occurred, newest first
+pub fn recent() -> Vec<(String, u64)> {
+    WARNINGS
+        .lock()
+        .unwrap()
+        .recent
+        .iter()
+        .rev()
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn duplicates() {
+        let mut w = Warnings::new();
+        w.add("foo".to_owned());
+        w.add("bar".to_owned());
+        w.add("foo".to_owned());
+        assert_eq!(w.total, 3);
+        assert_eq!(w.recent, [("bar".to_owned(), 1), ("foo".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn once() {
+        let mut w = Warnings::new();
+        w.add_once("foo", || "Unknown foo".to_owned());
+        w.add_once("foo", || "Unknown foo again".to_owned());
+        w.add_once("bar", || "Unknown bar".to_owned());
+        assert_eq!(w.total, 2);
+        assert_eq!(w.recent[0].0, "Unknown foo");
+    }
+
+    #[test]
+    fn capacity() {
+        let mut w = Warnings::new();
+        for i in 0..=CAPACITY {
+            w.add(i.to_string());
+        }
+        assert_eq!(w.total, CAPACITY as u64 + 1);
+        assert_eq!(w.recent.len(), CAPACITY);
+        assert_eq!(w.recent[0].0, "1");
+    }
+}
@@ -0,0 +1,267 @@
+// vim: tw=80
+//! Push per-dataset metrics to a Zabbix server, using the sender protocol
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    app::{Element, Metric},
+    sink::Sink,
+    warning::warn,
+};
+
+/// Don't let an unresponsive server hold up the queue for long
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Updates waiting to be sent.  When the server falls further behind than
+/// this, newer updates are dropped.
+const QUEUE: usize = 2;
+
+/// Key of the low-level discovery rule that enumerates datasets
+const DISCOVERY_KEY: &str = "ztop.datasets";
+
+/// The `[zabbix]` section of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZabbixConfig {
+    /// Host and trapper port of the Zabbix server or proxy
+    pub server: Option<String>,
+    /// Name of the monitored host, as configured in Zabbix.  Defaults to the
+    /// system's hostname.
+    pub host:   Option<String>,
+}
+
+/// Sends updates from a worker thread, so a slow server can't delay the
+/// display
+pub struct Zabbix {
+    tx: SyncSender<Vec<Element>>,
+}
+
+impl Zabbix {
+    pub fn new(
+        server: &str,
+        host: Option<String>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let addr = server
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("Cannot resolve Zabbix server {server}"))?;
+        let host = match host {
+            Some(h) => h,
+            None => nix::unistd::gethostname()?.to_string_lossy().into_owned(),
+        };
+        let mut sender = Sender {
+            addr,
+            host,
+            discovered: BTreeSet::new(),
+        };
+        let (tx, rx) = mpsc::sync_channel::<Vec<Element>>(QUEUE);
+        // Exits once the Zabbix is dropped, as on a configuration reload
+        thread::spawn(move || {
+            for elems in rx {
+                if let Err(e) = sender.send(&elems) {
+                    warn(format!("Cannot send to Zabbix server {addr}: {e}"));
+                }
+            }
+        });
+        Ok(Zabbix { tx })
+    }
+}
+
+impl Sink for Zabbix {
+    /// Queue every metric of every dataset to be sent
+    fn send(&mut self, elems: &[Element]) -> io::Result<()> {
+        match self.tx.try_send(elems.to_vec()) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                warn("Zabbix server is too slow; dropping updates".to_owned());
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(io::Error::other("Zabbix sender exited"))
+            }
+        }
+    }
+}
+
+/// Speaks the sender protocol to one server
+struct Sender {
+    addr:       SocketAddr,
+    host:       String,
+    /// Datasets most recently reported through low-level discovery
+    discovered: BTreeSet<String>,
+}
+
+impl Sender {
+    /// Build a low-level discovery request for these datasets
+    fn discovery(&self, names: &BTreeSet<String>) -> Value {
+        let lld = names
+            .iter()
+            .map(|name| {
+                let pool = name.split('/').next().unwrap_or_default();
+                json!({"{#DATASET}": name, "{#POOL}": pool})
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "request": "sender data",
+            "data": [{
+                "host": self.host,
+                "key": DISCOVERY_KEY,
+                "value": Value::Array(lld).to_string(),
+            }]
+        })
+    }
+
+    /// Build a request containing every metric of every dataset
+    fn data(&self, elems: &[Element]) -> Value {
+        let data = elems
+            .iter()
+            .flat_map(|elem| {
+                Metric::ALL.into_iter().map(move |metric| {
+                    json!({
+                        "host": self.host,
+                        "key": Self::key(metric, &elem.name),
+                        "value": format!("{:.3}", elem.get(metric)),
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({"request": "sender data", "data": data})
+    }
+
+    /// Format an item key, like `ztop.w_s["tank/foo"]`
+    fn key(metric: Metric, dataset: &str) -> String {
+        let quoted = dataset.replace('"', "\\\"");
+        format!("ztop.{metric}[\"{quoted}\"]")
+    }
+
+    /// Frame a message with the Zabbix protocol header
+    fn frame(body: &Value) -> Vec<u8> {
+        let body = body.to_string();
+        let mut buf = Vec::with_capacity(13 + body.len());
+        buf.extend_from_slice(b"ZBXD\x01");
+        buf.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        buf.extend_from_slice(body.as_bytes());
+        buf
+    }
+
+    fn request(&self, body: &Value) -> io::Result<()> {
+        let mut stream = TcpStream::connect_timeout(&self.addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.write_all(&Self::frame(body))?;
+        // The server's response only reports how many items it accepted.
+        // Read it so the server doesn't see a reset connection.
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        Ok(())
+    }
+
+    /// Send every metric of every dataset.
+    ///
+    /// Whenever the set of datasets changes, first send a low-level
+    /// discovery update so Zabbix can create items for new datasets.
+    fn send(&mut self, elems: &[Element]) -> io::Result<()> {
+        let names = elems.iter().map(|e| e.name.clone()).collect();
+        if names != self.discovered {
+            self.request(&self.discovery(&names))?;
+            self.discovered = names;
+        }
+        self.request(&self.data(elems))
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    fn elem(name: &str) -> Element {
+        Element {
+            ops_r: 1.0,
+            r_s: 2.0,
+            ops_w: 3.0,
+            w_s: 4.0,
+            ops_d: 5.0,
+            d_s: 6.0,
+            ..Element::named(name)
+        }
+    }
+
+    #[test]
+    fn key() {
+        assert_eq!(
+            Sender::key(Metric::WS, "tank/foo"),
+            "ztop.w_s[\"tank/foo\"]"
+        );
+    }
+
+    #[test]
+    fn frame() {
+        let framed = Sender::frame(&json!({}));
+        assert_eq!(&framed[..], b"ZBXD\x01\x02\0\0\0\0\0\0\0{}");
+    }
+
+    /// The first send should include a discovery request, but the second
+    /// shouldn't.
+    #[test]
+    fn send() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for _ in 0..3 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut header = [0u8; 13];
+                stream.read_exact(&mut header).unwrap();
+                let len = u64::from_le_bytes(header[5..].try_into().unwrap());
+                let mut body = vec![0u8; len as usize];
+                stream.read_exact(&mut body).unwrap();
+                bodies.push(serde_json::from_slice::<Value>(&body).unwrap());
+            }
+            bodies
+        });
+        let mut zabbix = Zabbix::new(&server, Some("h".to_owned())).unwrap();
+        let elems = [elem("tank")];
+        zabbix.send(&elems).unwrap();
+        zabbix.send(&elems).unwrap();
+        let bodies = handle.join().unwrap();
+
+        let lld = &bodies[0]["data"][0];
+        assert_eq!(lld["key"], DISCOVERY_KEY);
+        let value: Value =
+            serde_json::from_str(lld["value"].as_str().unwrap()).unwrap();
+        assert_eq!(value, json!([{"{#DATASET}": "tank", "{#POOL}": "tank"}]));
+        for body in &bodies[1..] {
+            let data = body["data"].as_array().unwrap();
+            assert_eq!(data.len(), 7);
+            assert_eq!(data[3]["host"], "h");
+            assert_eq!(data[3]["key"], "ztop.w_s[\"tank\"]");
+            assert_eq!(data[3]["value"], "4.000");
+        }
+    }
+
+    /// When the worker falls behind, updates are dropped instead of blocking
+    #[test]
+    fn full() {
+        let (tx, rx) = mpsc::sync_channel(QUEUE);
+        let mut zabbix = Zabbix { tx };
+        let elems = [elem("tank")];
+        for _ in 0..QUEUE + 1 {
+            zabbix.send(&elems).unwrap();
+        }
+        assert_eq!(rx.try_iter().count(), QUEUE);
+        drop(rx);
+        assert!(zabbix.send(&elems).is_err());
+    }
+}